@@ -0,0 +1,55 @@
+//! Records the commit, compiler and features of the build for the `build_info` view, so a
+//! deployed farm can be matched to a tagged release.
+//!
+//! Builds are reproducible by default: the commit and compiler are only read from git and
+//! rustc with `BUILD_INFO=1` set, and "unknown" otherwise. Release pipelines can pass them
+//! in as `BUILD_GIT_COMMIT` and `BUILD_RUSTC_VERSION` instead, so rebuilding the same
+//! inputs yields the same wasm.
+use std::env;
+use std::process::Command;
+
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(|output| output.trim().to_string())
+}
+
+/// `name` as given to the build, else read with `read` if `BUILD_INFO` opted in.
+fn metadata(name: &str, read: impl FnOnce() -> Option<String>) -> String {
+    println!("cargo:rerun-if-env-changed={}", name);
+    env::var(name)
+        .ok()
+        .or_else(|| if env::var_os("BUILD_INFO").is_some() { read() } else { None })
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn main() {
+    let commit = metadata("BUILD_GIT_COMMIT", || {
+        let commit = output("git", &["rev-parse", "HEAD"])?;
+        let status = output("git", &["status", "--porcelain", "--untracked-files=no"]);
+        let dirty = matches!(status, Some(status) if !status.is_empty());
+        Some(format!("{}{}", commit, if dirty { "-dirty" } else { "" }))
+    });
+    let rustc_version = metadata("BUILD_RUSTC_VERSION", || {
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        output(&rustc, &["--version"])
+    });
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BUILD_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-env-changed=BUILD_INFO");
+    if env::var_os("BUILD_INFO").is_some() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/index");
+    }
+}
@@ -1,5 +1,11 @@
 #!/bin/bash
 set -e
 cd "`dirname $0`"
-cargo build --all --target wasm32-unknown-unknown --release
-cp target/wasm32-unknown-unknown/release/*.wasm ./res/
+if [ "$1" == "minimal" ]; then
+  # core farm only, without the optional subsystems
+  cargo build --target wasm32-unknown-unknown --release --no-default-features
+  cp target/wasm32-unknown-unknown/release/obs_farm.wasm ./res/obs_farm_minimal.wasm
+else
+  cargo build --all --target wasm32-unknown-unknown --release
+  cp target/wasm32-unknown-unknown/release/*.wasm ./res/
+fi
@@ -0,0 +1,126 @@
+//! Account-facing views and settings.
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+/// maximum length of an account label, in bytes.
+pub const MAX_LABEL_LEN: usize = 64;
+
+#[near_bindgen]
+impl Farm {
+    /// Sets or clears the predecessor's label. The label is charged to its storage deposit.
+    pub fn set_label(&mut self, label: Option<String>) {
+        if let Some(label) = &label {
+            assert!(label.len() <= MAX_LABEL_LEN, "Label must be at most 64 bytes");
+        }
+        let (account_id_hash, mut account) =
+            self.get_registered_account(&env::predecessor_account_id());
+        account.label = label;
+        self.save_account(&account_id_hash, &account);
+    }
+
+    pub fn get_account(&self, account_id: ValidAccountId) -> Option<AccountView> {
+        self.account_view(account_id.as_ref())
+    }
+}
+
+impl Farm {
+    pub(crate) fn account_view(&self, account_id: &AccountId) -> Option<AccountView> {
+        let frozen = self.is_frozen(account_id);
+        self.get_internal_account(account_id).1.map(|mut account| {
+            if !frozen {
+                self.split_closed_periods(&mut account);
+            }
+            let warm_until = Some(account.warm_until).filter(|warm_until| *warm_until > self.now());
+            AccountView {
+                obs_balance: account.obs_balance.into(),
+                reward_balance: if self.rewards_expired() {
+                    U128(0)
+                } else if frozen {
+                    account.reward_balance.into()
+                } else {
+                    account
+                        .reward_balance
+                        .saturating_add(self.pending_reward(&account))
+                        .saturating_add(self.staker_fees_owed(&account))
+                        .into()
+                },
+                reward_claimed: account.reward_claimed.into(),
+                reward_multiplier_bps: self.multiplier_bps(&account, self.now()),
+                reward_expires_at: self.claims_expire_at().map(TimestampView::from),
+                warming_balance: U128(if warm_until.is_some() {
+                    account.warming_balance
+                } else {
+                    0
+                }),
+                warm_until: warm_until.map(TimestampView::from),
+                streak_epochs: self.streak_epochs(&account, self.now()).into(),
+                auto_compound: account.preferences.auto_compound,
+                campaign: account.campaign,
+                preferences: account.preferences,
+                label: account.label,
+                total_staked: account.total_staked.into(),
+                total_withdrawn: account.total_withdrawn.into(),
+                stake_count: account.stake_count.into(),
+                large_unstake_threshold: account.large_unstake_threshold.map(U128),
+                large_unstake_confirmable_at: account
+                    .large_unstake_requested_at
+                    .map(|requested_at| (requested_at + self.large_unstake_delay).into()),
+                first_stake_time: if account.stake_count > 0 {
+                    Some(account.first_stake_time.into())
+                } else {
+                    None
+                },
+            }
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_set_label() {
+        let mut context = get_context(alice(), 0, 100);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+        contract.storage_deposit(None, None);
+        contract.set_label(Some("DAO treasury".to_string()));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.label, Some("DAO treasury".to_string()));
+    }
+
+    #[test]
+    fn test_lifetime_stats() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.total_staked.0, 1000);
+        assert_eq!(account.total_withdrawn.0, 1000);
+        assert_eq!(account.stake_count.0, 1);
+        assert_eq!(account.first_stake_time, Some(TimestampView::from(0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage deposit")]
+    fn test_label_is_storage_charged() {
+        let mut context = get_context(alice(), 0, 100);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+        contract.set_label(Some("DAO treasury".to_string()));
+    }
+}
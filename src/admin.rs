@@ -0,0 +1,843 @@
+//! Machine-readable description of the admin surface, for DAO and multisig proposal
+//! builders. Admin methods never require an attached deposit, so they can be called as
+//! plain function-call actions with the gas listed here.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::{json, Value};
+use near_sdk::Gas;
+
+use crate::bots::BotScope;
+use crate::streak::MAX_STREAK_BONUS_BPS;
+use crate::vip_auction::MAX_VIP_BOOST_BPS;
+use crate::*;
+
+/// gas to attach to admin methods that only change the farm's state.
+pub const GAS_FOR_ADMIN_CALL: Gas = 2 * BASE_GAS;
+
+/// gas to attach to methods working through a list of accounts; whatever the gas doesn't
+/// cover is left for a further call.
+pub const GAS_FOR_ACCOUNT_BATCH: Gas = 40 * BASE_GAS;
+
+#[cfg(feature = "bonus")]
+/// gas to attach to bonus distributions, longer lists continuing in further calls.
+pub const GAS_FOR_BONUS_DISTRIBUTION: Gas = 40 * BASE_GAS;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AdminMethod {
+    pub method_name: String,
+    /// "owner", "treasurer", "operator" or "guardian", the owner being able to call all of
+    /// them, see `roles`.
+    pub role: String,
+    /// bots with this scope can call the method as well, see `bots`.
+    pub bot_scope: Option<BotScope>,
+    /// JSON schema of the arguments object.
+    pub args: Value,
+    pub gas: U64,
+    pub deposit: U128,
+}
+
+fn method(method_name: &str, role: &str, properties: Value, gas: Gas) -> AdminMethod {
+    let required: Vec<&String> = properties
+        .as_object()
+        .unwrap()
+        .iter()
+        .filter(|(_, schema)| schema.get("nullable").is_none())
+        .map(|(name, _)| name)
+        .collect();
+    AdminMethod {
+        method_name: method_name.to_string(),
+        role: role.to_string(),
+        args: json!({ "type": "object", "properties": properties, "required": required }),
+        gas: gas.into(),
+        deposit: U128(0),
+        bot_scope: None,
+    }
+}
+
+/// a method the owner and bots with `scope` can call.
+fn bot_method(method_name: &str, scope: BotScope, properties: Value, gas: Gas) -> AdminMethod {
+    AdminMethod { bot_scope: Some(scope), ..method(method_name, "owner", properties, gas) }
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn get_admin_interface(&self) -> Vec<AdminMethod> {
+        let account_id = json!({ "type": "string" });
+        let amount = json!({ "type": "string", "description": "integer as a string" });
+        let string_list = json!({ "type": "array", "items": { "type": "string" } });
+        let optional_string = json!({ "type": "string", "nullable": true });
+        let optional_amount =
+            json!({ "type": "string", "description": "integer as a string", "nullable": true });
+        let role = json!({ "enum": ["Treasurer", "Operator", "Guardian"] });
+        // optional features add their own methods at the end
+        #[allow(unused_mut)]
+        let mut methods = vec![
+            method(
+                "add_guardian",
+                "owner",
+                json!({ "account_id": account_id }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "remove_guardian",
+                "owner",
+                json!({ "account_id": account_id }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_treasury",
+                "owner",
+                json!({ "treasury_id": account_id }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_harvest_bounty",
+                "owner",
+                json!({
+                    "bounty_bps": { "type": "integer", "maximum": 100 },
+                    "effective_at": optional_amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_gas_profiling",
+                "operator",
+                json!({ "enabled": { "type": "boolean" } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_funding_bounds",
+                "treasurer",
+                json!({ "bounds": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": { "min_tokens": amount, "max_tokens": amount },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_token_gas",
+                "operator",
+                json!({
+                    "token_id": account_id,
+                    "gas": {
+                        "type": "object",
+                        "nullable": true,
+                        "properties": {
+                            "ft_transfer": amount,
+                            "ft_transfer_call": amount,
+                            "ft_balance_of": amount,
+                        },
+                    },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_compounding_frequency",
+                "owner",
+                json!({ "frequency": {
+                    "oneOf": [
+                        { "enum": ["Continuous", "Daily"] },
+                        {
+                            "type": "object",
+                            "properties": { "PerHarvest": {
+                                "type": "object",
+                                "properties": { "interval": amount },
+                            } },
+                        },
+                    ],
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_emission_schedule",
+                "treasurer",
+                json!({
+                    "schedule": {
+                        "oneOf": [
+                            { "enum": ["Constant"] },
+                            {
+                                "type": "object",
+                                "properties": { "LinearDecay": {
+                                    "type": "object",
+                                    "properties": {
+                                        "start": amount,
+                                        "end": amount,
+                                        "initial_rate": amount,
+                                        "final_rate": amount,
+                                    },
+                                } },
+                            },
+                        ],
+                    },
+                    "effective_at": optional_amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_rate_controller",
+                "treasurer",
+                json!({ "controller": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": {
+                        "oracle_id": account_id,
+                        "target_apr_bps": { "type": "integer" },
+                        "min_rate": amount,
+                        "max_rate": amount,
+                        "epoch": amount,
+                    },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method("cancel_pending_changes", "owner", json!({}), GAS_FOR_ADMIN_CALL),
+            method(
+                "set_partner_boost",
+                "owner",
+                json!({ "boost": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": {
+                        "token_id": account_id,
+                        "tiers": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "min_balance": amount,
+                                    "multiplier_bps": { "type": "integer" },
+                                },
+                            },
+                        },
+                        "max_staleness": amount,
+                    },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_cliff_scope",
+                "owner",
+                json!({ "scope": {
+                    "type": "object",
+                    "properties": {
+                        "rewards": { "type": "boolean" },
+                        "principal": { "type": "boolean" },
+                    },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_reward_cliff",
+                "owner",
+                json!({
+                    "reward_cliff": optional_amount,
+                    "retroactive": { "type": "boolean" },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_large_unstake_delay",
+                "owner",
+                json!({ "delay": amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_farm_metadata",
+                "operator",
+                json!({ "metadata": {
+                    "type": "object",
+                    "properties": {
+                        "title": { "type": "string" },
+                        "description": optional_string,
+                        "icon": optional_string,
+                        "website": optional_string,
+                        "social_links": string_list,
+                        "tags": string_list,
+                    },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_registration_fee",
+                "owner",
+                json!({ "fee": { "type": "string", "nullable": true } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_stake_warm_up",
+                "treasurer",
+                json!({ "warm_up": amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_max_positions",
+                "operator",
+                json!({ "max_positions": amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method("set_min_claim", "operator", json!({ "min_claim": amount }), GAS_FOR_ADMIN_CALL),
+            method(
+                "set_history_archive",
+                "operator",
+                json!({ "archive_id": optional_string }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_claim_manager",
+                "operator",
+                json!({ "manager_id": account_id, "approved": { "type": "boolean" } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_claim_sponsorship",
+                "operator",
+                json!({ "sponsorship": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": {
+                        "relayer_id": account_id,
+                        "monthly_claims": { "type": "integer" },
+                    },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "withdraw_storage_sponsorship",
+                "owner",
+                json!({ "amount": optional_amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_sponsored_accounts_limit",
+                "operator",
+                json!({ "limit": amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_runway_alert",
+                "treasurer",
+                json!({ "alert": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": { "threshold": amount, "ops_account_id": optional_string },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            bot_method("take_snapshot", BotScope::Snapshot, json!({}), GAS_FOR_ADMIN_CALL),
+            bot_method(
+                "harvest_all",
+                BotScope::Harvest,
+                json!({ "account_ids": string_list }),
+                GAS_FOR_ACCOUNT_BATCH,
+            ),
+            bot_method(
+                "update_reward_rate",
+                BotScope::RewardRate,
+                json!({}),
+                2 * BASE_GAS + GAS_FOR_RESOLVE_TRANSFER,
+            ),
+            bot_method("health_check", BotScope::Health, json!({}), self.health_check_gas()),
+            method(
+                "set_claim_window",
+                "owner",
+                json!({ "window": optional_amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_accrual_bound",
+                "owner",
+                json!({ "bound": optional_amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method("reset_breaker", "owner", json!({}), GAS_FOR_ADMIN_CALL),
+            method(
+                "set_guardian_threshold",
+                "owner",
+                json!({ "threshold": { "type": "integer" } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_vip_auction",
+                "owner",
+                json!({ "config": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": {
+                        "slots": { "type": "integer" },
+                        "epoch_length": amount,
+                        "multiplier_bps": { "type": "integer", "maximum": MAX_VIP_BOOST_BPS },
+                        "min_bid": amount,
+                    },
+                    "required": ["slots", "epoch_length", "multiplier_bps", "min_bid"],
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_streak_bonus",
+                "owner",
+                json!({ "bonus": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": {
+                        "epoch_length": amount,
+                        "bonus_bps_per_epoch": { "type": "integer" },
+                        "max_bonus_bps": { "type": "integer", "maximum": MAX_STREAK_BONUS_BPS },
+                    },
+                    "required": ["epoch_length", "bonus_bps_per_epoch", "max_bonus_bps"],
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_promise_gas_margin",
+                "operator",
+                json!({ "margin": amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_strategy",
+                "owner",
+                json!({
+                    "strategy_id": account_id,
+                    "limits": {
+                        "type": "object",
+                        "nullable": true,
+                        "properties": {
+                            "max_deployed": amount,
+                            "max_deployed_bps": { "type": "integer", "maximum": BPS_DENOM as u64 },
+                        },
+                        "required": ["max_deployed", "max_deployed_bps"],
+                    },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "deploy_to_strategy",
+                "treasurer",
+                json!({ "strategy_id": account_id, "amount": amount }),
+                self.deploy_gas(),
+            ),
+            bot_method(
+                "report",
+                BotScope::Reconcile,
+                json!({ "strategy_id": account_id }),
+                self.report_gas(),
+            ),
+            method(
+                "switch_reward_token",
+                "owner",
+                json!({ "token_id": account_id, "claims_expire_at": optional_amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "sweep_reward_period",
+                "treasurer",
+                json!({ "period": amount }),
+                BASE_GAS + GAS_FOR_ON_TRANSFER + GAS_FOR_RESOLVE_TRANSFER,
+            ),
+            method(
+                "set_claim_route",
+                "owner",
+                json!({
+                    "token_id": account_id,
+                    "route": {
+                        "type": "object",
+                        "nullable": true,
+                        "properties": { "dex_id": account_id, "pool_id": { "type": "integer" } },
+                        "required": ["dex_id", "pool_id"],
+                    },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_bot",
+                "owner",
+                json!({
+                    "bot_id": account_id,
+                    "config": {
+                        "type": "object",
+                        "nullable": true,
+                        "properties": {
+                            "scopes": {
+                                "type": "array",
+                                "items": { "enum": [
+                                    "Harvest",
+                                    "Snapshot",
+                                    "RetirePool",
+                                    "Reconcile",
+                                    "RewardRate",
+                                    "Health",
+                                ] },
+                            },
+                            "min_interval": amount,
+                        },
+                        "required": ["scopes", "min_interval"],
+                    },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_reject_contracts",
+                "operator",
+                json!({ "enabled": { "type": "boolean" } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_contract_listing",
+                "operator",
+                json!({
+                    "account_id": account_id,
+                    "listing": {
+                        "type": "string",
+                        "enum": ["Denied", "Approved"],
+                        "nullable": true,
+                    },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_health_watchdog",
+                "operator",
+                json!({ "watchdog": {
+                    "type": "object",
+                    "nullable": true,
+                    "properties": { "max_shortfall_bps": { "type": "integer" } },
+                } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method("resume_deposits", "operator", json!({}), GAS_FOR_ADMIN_CALL),
+            method(
+                "set_fee_on_transfer",
+                "operator",
+                json!({ "token_id": account_id, "enabled": { "type": "boolean" } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_drip_schedule",
+                "treasurer",
+                json!({
+                    "schedule": {
+                        "type": "object",
+                        "properties": { "epoch_length": amount, "amount_per_epoch": amount },
+                    },
+                    "effective_at": optional_amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "grant_role",
+                "owner",
+                json!({ "account_id": account_id, "role": role }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "revoke_role",
+                "owner",
+                json!({ "account_id": account_id, "role": role }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_fee_destination",
+                "owner",
+                json!({
+                    "destination": { "enum": ["Treasury", "Burn", "Reserve", "Stakers"] },
+                    "effective_at": amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_exit_fee_destination",
+                "owner",
+                json!({
+                    "destination": { "enum": ["Treasury", "Burn", "Reserve", "Stakers"] },
+                    "effective_at": amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_entry_fee",
+                "owner",
+                json!({
+                    "fee_bps": { "type": "integer", "maximum": 1000 },
+                    "effective_at": optional_amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "set_exit_fee",
+                "owner",
+                json!({
+                    "fee_bps": { "type": "integer", "maximum": 1000 },
+                    "effective_at": optional_amount,
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method("sweep_fees", "treasurer", json!({}), GAS_FOR_ADMIN_CALL),
+            method(
+                "set_dust_policy",
+                "owner",
+                json!({
+                    "policy": {
+                        "type": "object",
+                        "nullable": true,
+                        "properties": {
+                            "max_obs": amount,
+                            "max_reward": amount,
+                            "dormant_for": amount,
+                        },
+                    },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "force_settle",
+                "owner",
+                json!({ "account_id": account_id }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method("schedule_decommission", "owner", json!({}), GAS_FOR_ADMIN_CALL),
+            method("cancel_decommission", "owner", json!({}), GAS_FOR_ADMIN_CALL),
+            method("decommission", "owner", json!({}), GAS_FOR_ADMIN_CALL),
+            method("sweep_reserve", "owner", json!({}), self.sweep_gas()),
+            bot_method(
+                "retire_accounts",
+                BotScope::RetirePool,
+                json!({ "from_index": optional_amount, "limit": amount }),
+                GAS_FOR_ACCOUNT_BATCH,
+            ),
+            method(
+                "freeze_account",
+                "guardian",
+                json!({ "account_id": account_id, "reason": { "type": "string" } }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "unfreeze_account",
+                "guardian",
+                json!({
+                    "account_id": account_id,
+                    "restore_accrual": { "type": "boolean", "nullable": true },
+                }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "confirm_action",
+                "guardian",
+                json!({ "action_id": amount }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "recall_from_strategy",
+                "guardian",
+                json!({ "strategy_id": account_id, "amount": optional_amount }),
+                self.recall_gas(),
+            ),
+        ];
+        #[cfg(feature = "grants")]
+        methods.push(method(
+            "set_grant_signer",
+            "owner",
+            json!({ "public_key": optional_string }),
+            GAS_FOR_ADMIN_CALL,
+        ));
+        #[cfg(feature = "liens")]
+        methods.extend(vec![
+            method(
+                "add_lienholder",
+                "owner",
+                json!({ "account_id": account_id }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+            method(
+                "remove_lienholder",
+                "owner",
+                json!({ "account_id": account_id }),
+                GAS_FOR_ADMIN_CALL,
+            ),
+        ]);
+        #[cfg(feature = "bonus")]
+        methods.extend(vec![
+            method(
+                "distribute_bonus",
+                "treasurer",
+                json!({ "recipients": {
+                    "type": "array",
+                    "items": { "type": "array", "items": [account_id, amount] },
+                } }),
+                GAS_FOR_BONUS_DISTRIBUTION,
+            ),
+            method(
+                "continue_bonus_distribution",
+                "treasurer",
+                json!({}),
+                GAS_FOR_BONUS_DISTRIBUTION,
+            ),
+        ]);
+        methods
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_admin_interface() {
+        testing_env!(get_context(alice(), 0, 0));
+        let contract = new_farm();
+        let methods = contract.get_admin_interface();
+        assert!(methods.iter().all(|m| m.deposit.0 == 0));
+        let unfreeze = methods.iter().find(|m| m.method_name == "unfreeze_account").unwrap();
+        assert_eq!(unfreeze.args["required"], json!(["account_id"]));
+        let sweep = methods.iter().find(|m| m.method_name == "sweep_reserve").unwrap();
+        assert_eq!(sweep.gas.0, 3 * BASE_GAS + GAS_FOR_ON_TRANSFER + GAS_FOR_RESOLVE_TRANSFER);
+    }
+
+    /// every object argument lists its fields, down to nested ones.
+    #[test]
+    fn test_object_args_have_properties() {
+        fn assert_properties(method_name: &str, schema: &Value) {
+            if schema.get("type") == Some(&json!("object")) {
+                assert!(schema.get("properties").is_some(), "{}", method_name);
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for nested in properties.into_iter().flat_map(|properties| properties.values()) {
+                assert_properties(method_name, nested);
+            }
+            if let Some(items) = schema.get("items") {
+                assert_properties(method_name, items);
+            }
+        }
+        testing_env!(get_context(alice(), 0, 0));
+        for method in new_farm().get_admin_interface() {
+            assert_properties(&method.method_name, &method.args);
+        }
+    }
+
+    /// every method gated by a role or a bot scope is listed with it.
+    #[test]
+    fn test_gated_methods_are_listed() {
+        testing_env!(get_context(alice(), 0, 0));
+        let owner = vec![
+            "add_guardian",
+            "remove_guardian",
+            "set_treasury",
+            "set_harvest_bounty",
+            "set_compounding_frequency",
+            "cancel_pending_changes",
+            "set_partner_boost",
+            "set_cliff_scope",
+            "set_reward_cliff",
+            "set_large_unstake_delay",
+            "set_registration_fee",
+            "withdraw_storage_sponsorship",
+            "set_claim_window",
+            "set_accrual_bound",
+            "reset_breaker",
+            "set_guardian_threshold",
+            "set_vip_auction",
+            "set_streak_bonus",
+            "set_strategy",
+            "switch_reward_token",
+            "set_claim_route",
+            "set_bot",
+            "grant_role",
+            "revoke_role",
+            "set_fee_destination",
+            "set_exit_fee_destination",
+            "set_entry_fee",
+            "set_exit_fee",
+            "set_dust_policy",
+            "force_settle",
+            "schedule_decommission",
+            "cancel_decommission",
+            "decommission",
+            "sweep_reserve",
+            #[cfg(feature = "grants")]
+            "set_grant_signer",
+            #[cfg(feature = "liens")]
+            "add_lienholder",
+            #[cfg(feature = "liens")]
+            "remove_lienholder",
+        ];
+        let treasurer = vec![
+            "set_funding_bounds",
+            "set_emission_schedule",
+            "set_rate_controller",
+            "set_stake_warm_up",
+            "set_runway_alert",
+            "deploy_to_strategy",
+            "sweep_reward_period",
+            "set_drip_schedule",
+            "sweep_fees",
+            #[cfg(feature = "bonus")]
+            "distribute_bonus",
+            #[cfg(feature = "bonus")]
+            "continue_bonus_distribution",
+        ];
+        let operator = vec![
+            "set_gas_profiling",
+            "set_token_gas",
+            "set_farm_metadata",
+            "set_max_positions",
+            "set_min_claim",
+            "set_history_archive",
+            "set_claim_manager",
+            "set_claim_sponsorship",
+            "set_sponsored_accounts_limit",
+            "set_promise_gas_margin",
+            "set_reject_contracts",
+            "set_contract_listing",
+            "set_health_watchdog",
+            "resume_deposits",
+            "set_fee_on_transfer",
+        ];
+        let guardian =
+            vec!["freeze_account", "unfreeze_account", "confirm_action", "recall_from_strategy"];
+        let bots = [
+            ("take_snapshot", BotScope::Snapshot),
+            ("harvest_all", BotScope::Harvest),
+            ("update_reward_rate", BotScope::RewardRate),
+            ("health_check", BotScope::Health),
+            ("report", BotScope::Reconcile),
+            ("retire_accounts", BotScope::RetirePool),
+        ];
+        let mut expected: Vec<(&str, &str, Option<BotScope>)> = vec![];
+        for (role, names) in [
+            ("owner", owner),
+            ("treasurer", treasurer),
+            ("operator", operator),
+            ("guardian", guardian),
+        ] {
+            expected.extend(names.into_iter().map(|name| (name, role, None)));
+        }
+        expected.extend(bots.iter().map(|(name, scope)| (*name, "owner", Some(*scope))));
+        expected.sort_by_key(|(name, _, _)| *name);
+
+        let methods = new_farm().get_admin_interface();
+        let mut listed: Vec<(&str, &str, Option<BotScope>)> = methods
+            .iter()
+            .map(|m| (m.method_name.as_str(), m.role.as_str(), m.bot_scope))
+            .collect();
+        listed.sort_by_key(|(name, _, _)| *name);
+        assert_eq!(listed, expected);
+    }
+
+    /// admin methods must work as plain function calls without an attached deposit.
+    #[test]
+    fn test_admin_calls_without_deposit() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 0;
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.set_harvest_bounty(10, None);
+        contract.schedule_decommission();
+        contract.cancel_decommission();
+        contract.set_large_unstake_delay(U64(0));
+    }
+}
@@ -0,0 +1,160 @@
+//! APR and APY views. The APR is what accrual pays a stake over the coming year under the
+//! emission schedule, in reward token units per staked obs unit, without boosts. The APY
+//! compounds it at the frequency configured by the operator so the figures match how often
+//! rewards are actually reinvested.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// fixed point scale of the compounding math.
+const APR_SCALE: u128 = 1_000_000_000_000_000_000;
+
+/// largest APR that is compounded, in basis points. Above it `get_apy` saturates.
+const MAX_COMPOUNDED_APR_BPS: u128 = 200_000;
+
+/// one year in nanoseconds, the unit of `reward_interval` and of harvest intervals.
+pub const YEAR_NANOS: u64 = 365 * 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum CompoundingFrequency {
+    Continuous,
+    Daily,
+    /// rewards are reinvested on every harvest, `interval` nanoseconds apart.
+    PerHarvest {
+        interval: U64,
+    },
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn set_compounding_frequency(&mut self, frequency: CompoundingFrequency) {
+        self.assert_owner();
+        if let CompoundingFrequency::PerHarvest { interval } = &frequency {
+            assert!(interval.0 > 0 && interval.0 <= YEAR_NANOS, "Invalid harvest interval");
+        }
+        log!("Compounding frequency set to {:?}", frequency);
+        self.compounding_frequency = frequency;
+    }
+
+    pub fn get_compounding_frequency(&self) -> CompoundingFrequency {
+        self.compounding_frequency.clone()
+    }
+
+    /// Simple annual rate over the coming year, in basis points. Under a non-linear reward
+    /// weight it is the rate of a stake weighted as itself, see `get_apr_at_stake`.
+    pub fn get_apr(&self) -> U128 {
+        let now = self.accrual_time();
+        self.apr_of(self.emission_between(now, now + YEAR_NANOS)).into()
+    }
+
+    /// Annual rate compounded at the configured frequency, in basis points, `u128::MAX`
+    /// for rates too high to compound.
+    pub fn get_apy(&self) -> U128 {
+        compounded(self.get_apr().0, &self.compounding_frequency).into()
+    }
+}
+
+impl Farm {
+    /// APR of a stake weighted as itself that earns `yearly_emission` over a year, in basis
+    /// points. It accrues like any stake, so a stake of `BPS_DENOM` earns the APR itself.
+    pub(crate) fn apr_of(&self, yearly_emission: U256) -> Balance {
+        self.accrued(U256::from(BPS_DENOM), yearly_emission, U256::from(BPS_DENOM))
+    }
+}
+
+/// `apr` compounded at `frequency`, both in basis points.
+fn compounded(apr: u128, frequency: &CompoundingFrequency) -> u128 {
+    if apr > MAX_COMPOUNDED_APR_BPS {
+        return u128::MAX;
+    }
+    let apr = apr * APR_SCALE / BPS_DENOM;
+    let growth = match frequency {
+        CompoundingFrequency::Continuous => fixed_exp(apr),
+        CompoundingFrequency::Daily => compound(apr, 365),
+        CompoundingFrequency::PerHarvest { interval } => compound(apr, YEAR_NANOS / interval.0),
+    };
+    (growth - APR_SCALE) * BPS_DENOM / APR_SCALE
+}
+
+fn fixed_mul(a: u128, b: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(APR_SCALE)).as_u128()
+}
+
+/// growth factor of `apr` compounded `periods` times a year.
+fn compound(apr: u128, periods: u64) -> u128 {
+    let mut base = APR_SCALE + apr / u128::from(periods);
+    let mut exponent = periods;
+    let mut growth = APR_SCALE;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            growth = fixed_mul(growth, base);
+        }
+        base = fixed_mul(base, base);
+        exponent >>= 1;
+    }
+    growth
+}
+
+/// e^x by its Taylor series, for the rates a farm can realistically have.
+fn fixed_exp(x: u128) -> u128 {
+    let mut term = APR_SCALE;
+    let mut sum = APR_SCALE;
+    for n in 1..64u128 {
+        term = fixed_mul(term, x) / n;
+        if term == 0 {
+            break;
+        }
+        sum += term;
+    }
+    sum
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_apr_matches_accrual() {
+        let contract = staked_farm();
+        let start = contract.cliff_time + 1;
+        testing_env!(get_context(alice(), env::storage_usage(), start));
+        let apr = contract.get_apr().0;
+        let before = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        testing_env!(get_context(alice(), env::storage_usage(), start + YEAR_NANOS));
+        let after = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        // alice stakes 1000
+        let observed = (after.0 - before.0) * BPS_DENOM / 1000;
+        assert!(observed.abs_diff(apr) <= BPS_DENOM, "{} {}", observed, apr);
+        assert_eq!(apr, 1800 * OBS_PER_REWARD_DENOM * BPS_DENOM);
+    }
+
+    #[test]
+    fn test_apy() {
+        assert_eq!(compounded(1800, &CompoundingFrequency::Daily), 1971);
+        assert_eq!(compounded(1800, &CompoundingFrequency::Continuous), 1972);
+        let interval = U64(YEAR_NANOS / 12);
+        assert_eq!(compounded(1800, &CompoundingFrequency::PerHarvest { interval }), 1956);
+
+        testing_env!(get_context(alice(), 0, 0));
+        // a token unit earns far more than 2000% of itself at the default rate
+        assert_eq!(new_farm().get_apy().0, u128::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid harvest interval")]
+    fn test_invalid_harvest_interval() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        let interval = U64(0);
+        contract.set_compounding_frequency(CompoundingFrequency::PerHarvest { interval });
+    }
+}
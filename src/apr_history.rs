@@ -0,0 +1,154 @@
+//! Time series of the APR, so analytics can chart yield straight from the contract. A point
+//! is recorded on every emission change and at most once a day otherwise, when accounts
+//! interact or anyone calls `checkpoint_apr`. The series keeps the latest
+//! `MAX_APR_HISTORY` points, dropping the oldest.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// points kept in the series, two years of daily checkpoints.
+pub const MAX_APR_HISTORY: u64 = 730;
+
+/// time between checkpoints, in nanoseconds.
+pub const APR_CHECKPOINT_INTERVAL: Timestamp = 24 * 60 * 60 * 1_000_000_000;
+
+/// most points `get_apr_history` returns at once.
+const MAX_APR_HISTORY_PAGE: u64 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AprPoint {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub recorded_at: Timestamp,
+    /// in basis points.
+    pub apr: U128,
+    /// whether an emission change, rather than a daily checkpoint, recorded it.
+    pub config_change: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AprHistory {
+    /// points by their index modulo `MAX_APR_HISTORY`.
+    points: LookupMap<u64, AprPoint>,
+    /// points ever recorded.
+    recorded: u64,
+    last_recorded_at: Option<Timestamp>,
+}
+
+impl AprHistory {
+    pub fn new() -> Self {
+        Self { points: LookupMap::new(StorageKey::AprHistory), recorded: 0, last_recorded_at: None }
+    }
+
+    fn push(&mut self, point: AprPoint) {
+        self.last_recorded_at = Some(point.recorded_at);
+        self.points.insert(&(self.recorded % MAX_APR_HISTORY), &point);
+        self.recorded += 1;
+    }
+
+    /// index of the oldest point kept.
+    fn first(&self) -> u64 {
+        self.recorded.saturating_sub(MAX_APR_HISTORY)
+    }
+
+    fn get(&self, index: u64) -> AprPoint {
+        self.points.get(&(index % MAX_APR_HISTORY)).expect("APR point missing")
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Records the APR if the last point is a day old. Interactions do this on their own;
+    /// this lets keepers fill the series while nobody calls the farm.
+    pub fn checkpoint_apr(&mut self) {
+        self.record_apr(false);
+    }
+
+    /// Up to `limit` points recorded at or after `from`, oldest first.
+    pub fn get_apr_history(&self, from: Option<U64>, limit: Option<u64>) -> Vec<AprPoint> {
+        let history = &self.apr_history;
+        let from = from.map_or(0, |from| from.0);
+        let limit = limit.unwrap_or(MAX_APR_HISTORY_PAGE).min(MAX_APR_HISTORY_PAGE);
+        // points are recorded in time order, so the first one not before `from` is found by
+        // bisection
+        let (mut low, mut high) = (history.first(), history.recorded);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if history.get(middle).recorded_at < from {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        (low..history.recorded.min(low + limit)).map(|index| history.get(index)).collect()
+    }
+}
+
+impl Farm {
+    /// Adds the current APR to the series, unless it is a checkpoint within a day of the
+    /// last point.
+    pub(crate) fn record_apr(&mut self, config_change: bool) {
+        let now = self.now();
+        let due = !matches!(
+            self.apr_history.last_recorded_at,
+            Some(recorded_at) if now < recorded_at + APR_CHECKPOINT_INTERVAL
+        );
+        if config_change || due {
+            let point = AprPoint { recorded_at: now, apr: self.get_apr(), config_change };
+            self.apr_history.push(point);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::apr::YEAR_NANOS;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_apr_history() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.checkpoint_apr();
+        contract.checkpoint_apr();
+        assert_eq!(contract.get_apr_history(None, None).len(), 1);
+
+        testing_env!(get_context(alice(), 0, 10));
+        let schedule = EmissionSchedule::LinearDecay {
+            start: U64(0),
+            end: U64(1),
+            initial_rate: U128(900),
+            final_rate: U128(900),
+        };
+        contract.set_emission_schedule(schedule, None);
+        testing_env!(get_context(alice(), 0, APR_CHECKPOINT_INTERVAL + 10));
+        contract.checkpoint_apr();
+
+        let history = contract.get_apr_history(Some(U64(10)), None);
+        assert_eq!(history.len(), 2);
+        assert!(history[0].config_change);
+        // half the initial rate of 1800
+        let initial = contract.get_apr_history(None, Some(1))[0].apr.0;
+        assert_eq!(history[0].apr.0, initial / 2);
+        assert_eq!(initial, contract.apr_of(U256::from(1800) * U256::from(YEAR_NANOS)));
+    }
+
+    #[test]
+    fn test_apr_history_is_bounded() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        for _ in 0..MAX_APR_HISTORY + 5 {
+            contract.record_apr(true);
+        }
+        assert_eq!(contract.apr_history.first(), 5);
+        assert_eq!(contract.get_apr_history(None, Some(MAX_APR_HISTORY)).len(), 100);
+    }
+}
@@ -0,0 +1,158 @@
+//! Automatic restaking of rewards. In a farm whose reward token is the obs token, accounts
+//! can opt in to have keepers restake their rewards through `harvest_all`, rather than let
+//! them pile up as a claimable balance. Restaked rewards count as claimed and add to the
+//! stake without restarting its cliff. The farm keeps the set of accounts opted in, so
+//! keepers can batch them. Keepers run as harvest bots, see `bots`.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Gas};
+
+use crate::bots::BotScope;
+use crate::events::emit_event;
+use crate::*;
+
+/// upper bound of the gas restaking the rewards of a single account takes.
+const GAS_PER_COMPOUND: Gas = BASE_GAS;
+
+/// gas kept back to finish a call once it stops restaking.
+const GAS_FOR_COMPOUND_WRAPUP: Gas = BASE_GAS;
+
+#[near_bindgen]
+impl Farm {
+    /// Opts the predecessor in or out of having its rewards restaked.
+    pub fn set_auto_compound(&mut self, enabled: bool) {
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        self.set_auto_compounder(&account_id, enabled);
+        account.preferences.auto_compound = enabled;
+        self.save_account(&account_id_hash, &account);
+        emit_event("set_auto_compound", json!({ "account_id": account_id, "enabled": enabled }));
+    }
+
+    /// Accounts opted in to restaking, for keepers to batch.
+    pub fn get_auto_compounders(
+        &self,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<AccountId> {
+        let from_index = from_index.map_or(0, |from_index| from_index.0);
+        let limit = limit.map_or(self.auto_compounders.len(), |limit| limit.0);
+        self.auto_compounders.iter().skip(from_index as usize).take(limit as usize).collect()
+    }
+
+    /// Restakes the rewards of the listed accounts that opted in. The owner and harvest bots
+    /// can call it; frozen accounts and those that opted out are skipped. Returns how many
+    /// accounts were compounded, which may stop short of the list when gas runs low.
+    pub fn harvest_all(&mut self, account_ids: Vec<ValidAccountId>) -> u32 {
+        self.assert_automation(BotScope::Harvest);
+        self.assert_not_decommissioned();
+        let mut compounded = 0;
+        let mut restaked = 0;
+        for account_id in account_ids {
+            let gas_left = env::prepaid_gas() - env::used_gas();
+            if gas_left < GAS_PER_COMPOUND + GAS_FOR_COMPOUND_WRAPUP {
+                break;
+            }
+            let account_id: AccountId = account_id.into();
+            if !self.auto_compounders.contains(&account_id) || self.is_frozen(&account_id) {
+                continue;
+            }
+            let amount = self.internal_compound(&account_id);
+            if amount > 0 {
+                compounded += 1;
+                restaked += amount;
+            }
+        }
+        self.check_runway();
+        emit_event("harvest_all", json!({ "compounded": compounded, "restaked": U128(restaked) }));
+        compounded
+    }
+}
+
+impl Farm {
+    /// Moves the rewards of `account_id` into its stake, returning the amount restaked.
+    /// Rewards that would have to wait in the claim queue stay claimable.
+    fn internal_compound(&mut self, account_id: &AccountId) -> Balance {
+        let (account_id_hash, mut account) = self.get_mut_account(account_id);
+        let amount = account.reward_balance;
+        if amount == 0 || account.obs_balance == 0 || self.must_queue_claim(amount) {
+            // keeps the accrual, and any settlement of closed reward periods
+            self.save_account(&account_id_hash, &account);
+            return 0;
+        }
+        self.checkpoint_balance(account_id, &account);
+        account.reward_balance = 0;
+        account.reward_claimed += amount;
+        account.obs_balance += amount;
+        account.total_staked += amount;
+        self.ledger.post(LedgerAccount::Reserve, LedgerAccount::Claimed, amount);
+        self.ledger.post(LedgerAccount::Deposits, LedgerAccount::Staked, amount);
+        self.save_account(&account_id_hash, &account);
+        self.rank_staker(account_id, account.obs_balance);
+        let token_id = self.reward_token_account_id.clone();
+        self.record_claim(account_id, amount, token_id, None, None);
+        self.notify_position_hook(account_id, &account, PositionEvent::RewardClaimed, amount);
+        self.notify_position_hook(account_id, &account, PositionEvent::Staked, amount);
+        amount
+    }
+
+    /// Adds `account_id` to the accounts keepers restake for, or removes it.
+    pub(crate) fn set_auto_compounder(&mut self, account_id: &AccountId, enabled: bool) {
+        if enabled {
+            assert!(self.is_single_token(), "Rewards can only be restaked as obs");
+            self.auto_compounders.insert(account_id);
+        } else {
+            self.auto_compounders.remove(account_id);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, obs, staked_farm};
+    use std::convert::TryInto;
+
+    /// farm paying rewards in obs, with alice staking 1000 and a reserve of 10**30.
+    fn compounding_farm() -> Farm {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = Farm::new(FarmConfig::new(alice().try_into().unwrap(), obs(), obs()));
+        contract.register_account();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(10u128.pow(30)), FUND_RESERVE_MSG.to_string());
+        contract
+    }
+
+    #[test]
+    fn test_harvest_all_restakes() {
+        let mut contract = compounding_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_auto_compound(true);
+        assert_eq!(contract.get_auto_compounders(None, None), vec![alice()]);
+
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let before = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(before.reward_balance.0 > 0);
+        let accounts = vec![alice().try_into().unwrap(), bob().try_into().unwrap()];
+        assert_eq!(contract.harvest_all(accounts), 1);
+        let after = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(after.reward_balance.0, 0);
+        assert_eq!(after.obs_balance.0, before.obs_balance.0 + before.reward_balance.0);
+        assert!(after.auto_compound);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rewards can only be restaked as obs")]
+    fn test_auto_compound_needs_single_token() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_auto_compound(true);
+    }
+}
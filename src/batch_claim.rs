@@ -0,0 +1,158 @@
+//! Custodian batch claims. Exchanges staking client funds hold one account per client, so
+//! an operator approves claim managers, and an account that names a manager in its
+//! preferences lets it claim with `batch_claim`. Each account's rewards go to its own claim
+//! receiver, never to the manager. Accounts that didn't delegate to the caller, are frozen
+//! or have too little to claim are skipped, so one account can't fail the batch. A batch
+//! stops when gas runs low and returns the index to continue from.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Gas};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[near_bindgen]
+impl Farm {
+    /// Approves `manager_id` to claim for the accounts delegating to it, or withdraws that.
+    pub fn set_claim_manager(&mut self, manager_id: ValidAccountId, approved: bool) {
+        self.assert_role(Role::Operator);
+        if approved {
+            self.claim_managers.insert(manager_id.as_ref());
+        } else {
+            self.claim_managers.remove(manager_id.as_ref());
+        }
+        emit_event("set_claim_manager", json!({ "manager_id": manager_id, "approved": approved }));
+    }
+
+    pub fn get_claim_managers(&self) -> Vec<AccountId> {
+        self.claim_managers.to_vec()
+    }
+
+    /// Claims for the accounts of `account_ids` from `from_index` on. Returns the index to
+    /// continue from, `None` once the list is done.
+    pub fn batch_claim(
+        &mut self,
+        account_ids: Vec<ValidAccountId>,
+        from_index: Option<U64>,
+    ) -> Option<U64> {
+        let manager_id = env::predecessor_account_id();
+        assert!(
+            self.claim_managers.contains(&manager_id),
+            "Only approved claim managers can batch claims"
+        );
+        let gas_per_claim: Gas = BASE_GAS
+            + self.token_gas(&self.reward_token_account_id).ft_transfer.0
+            + GAS_FOR_RESOLVE_TRANSFER
+            + self.payout_check_gas();
+        let from_index = from_index.map_or(0, |from_index| from_index.0) as usize;
+        let mut claimed = 0;
+        for (index, account_id) in account_ids.iter().enumerate().skip(from_index) {
+            if env::prepaid_gas() - env::used_gas() < gas_per_claim + BASE_GAS {
+                emit_event("batch_claim", json!({ "manager_id": manager_id, "claimed": claimed }));
+                return Some(U64(index as u64));
+            }
+            let account_id: AccountId = account_id.clone().into();
+            if let Some(amount) = self.manager_claimable(&manager_id, &account_id) {
+                self.internal_claim(&account_id);
+                claimed += 1;
+                emit_event(
+                    "manager_claim",
+                    json!({
+                        "manager_id": manager_id,
+                        "account_id": account_id,
+                        "receiver_id": self.reward_receiver(&account_id),
+                        "amount": U128(amount),
+                    }),
+                );
+            }
+        }
+        emit_event("batch_claim", json!({ "manager_id": manager_id, "claimed": claimed }));
+        None
+    }
+}
+
+impl Farm {
+    /// Rewards `manager_id` can claim for `account_id`, `None` if it can't claim them.
+    fn manager_claimable(
+        &mut self,
+        manager_id: &AccountId,
+        account_id: &AccountId,
+    ) -> Option<Balance> {
+        let delegate_id = self
+            .get_internal_account(account_id)
+            .1
+            .and_then(|account| account.preferences.claim_manager)
+            .map(AccountId::from);
+        if delegate_id.as_ref() != Some(manager_id)
+            || self.is_frozen(account_id)
+            || self.refuses_contract(account_id)
+        {
+            return None;
+        }
+        let (account_id_hash, account) = self.get_mut_account(account_id);
+        // keeps the accrual, like a claim would
+        self.save_account(&account_id_hash, &account);
+        let amount = account.reward_balance;
+        if amount == 0 || amount < self.min_claim {
+            return None;
+        }
+        Some(amount)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::preferences::Preferences;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn delegated_farm() -> Farm {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_preferences(Preferences {
+            claim_manager: Some(bob().try_into().unwrap()),
+            ..Default::default()
+        });
+        contract.set_claim_manager(bob().try_into().unwrap(), true);
+        contract
+    }
+
+    #[test]
+    fn test_batch_claim() {
+        let mut contract = delegated_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), contract.cliff_time + 1));
+        let account_ids = vec![alice().try_into().unwrap(), bob().try_into().unwrap()];
+        assert_eq!(contract.batch_claim(account_ids, None), None);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, 0);
+        assert!(account.reward_claimed.0 > 0);
+    }
+
+    #[test]
+    fn test_batch_claim_continues() {
+        let mut contract = delegated_farm();
+        let mut context = get_context(bob(), env::storage_usage(), contract.cliff_time + 1);
+        context.prepaid_gas = BASE_GAS;
+        testing_env!(context);
+        let account_ids = vec![alice().try_into().unwrap()];
+        assert_eq!(contract.batch_claim(account_ids, None), Some(U64(0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only approved claim managers can batch claims")]
+    fn test_unapproved_manager() {
+        let mut contract = delegated_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.batch_claim(vec![alice().try_into().unwrap()], None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Storage and gas measurements with budgets. Each test reports its numbers with
+//! `cargo test bench -- --nocapture` and fails once a number goes over its budget.
+//!
+//! Gas is measured in the mocked blockchain, which meters host functions and the gas
+//! attached to outgoing promises but not wasm execution, so the numbers are lower bounds
+//! of on-chain usage.
+use std::convert::TryInto;
+
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::U128;
+use near_sdk::{testing_env, Gas, MockedBlockchain, StorageUsage};
+
+use crate::account::MAX_LABEL_LEN;
+use crate::campaigns::MAX_CAMPAIGN_TAG_LEN;
+use crate::preferences::MAX_LOCALE_LEN;
+use crate::tests::{alice, bob, get_context, new_farm, staked_farm};
+use crate::*;
+
+/// storage of a freshly registered account, the minimum storage deposit.
+const BARE_ACCOUNT_BUDGET: StorageUsage = 592;
+
+/// storage of an account with every optional field set to its largest value.
+const FULL_ACCOUNT_BUDGET: StorageUsage = 1024;
+
+/// gas of the heaviest single-account paths, a third of the transaction limit.
+const ACCOUNT_PATH_GAS_BUDGET: Gas = 100_000_000_000_000;
+
+fn max_len_account_id(name: &str) -> AccountId {
+    format!("{}{}.near", name, "x".repeat(64 - name.len() - 5))
+}
+
+#[test]
+fn bench_account_storage() {
+    // the longest id takes the most to index
+    let account_id = max_len_account_id("staker");
+    let mut context = get_context(account_id.clone(), 0, 0);
+    context.attached_deposit = 10u128.pow(24);
+    testing_env!(context);
+    let mut contract = new_farm();
+    let before = env::storage_usage();
+    contract.register_account();
+    // the write is logged for indexers, in a slot the farm pays for
+    let logged = env::storage_usage();
+    contract.account_log.record(&(&bob()).into(), None, None);
+    let bare = logged - before - (env::storage_usage() - logged);
+    assert_eq!(contract.get_internal_account(&account_id).1.unwrap().storage_used(), bare);
+
+    let mut account = contract.get_internal_account(&account_id).1.unwrap();
+    account.label = Some("x".repeat(MAX_LABEL_LEN));
+    account.preferences = Preferences {
+        auto_compound: true,
+        claim_receiver: Some(max_len_account_id("receiver").try_into().unwrap()),
+        claim_manager: Some(max_len_account_id("manager").try_into().unwrap()),
+        notification_hook: Some(max_len_account_id("hook").try_into().unwrap()),
+        locale: Some("x".repeat(MAX_LOCALE_LEN)),
+    };
+    account.approved_lienholder = Some(max_len_account_id("lender"));
+    account.large_unstake_threshold = Some(Balance::MAX);
+    account.large_unstake_requested_at = Some(Timestamp::MAX);
+    account.campaign = Some("x".repeat(MAX_CAMPAIGN_TAG_LEN));
+    account.sponsored_claims = Some(SponsoredClaims { month: u64::MAX, claims: u32::MAX });
+    let full = account.storage_used();
+
+    println!("account storage: bare {} bytes, full {} bytes", bare, full);
+    assert!(bare <= BARE_ACCOUNT_BUDGET, "Bare account takes {} bytes", bare);
+    assert!(full <= FULL_ACCOUNT_BUDGET, "Full account takes {} bytes", full);
+}
+
+fn gas_of(operation: &str, call: impl FnOnce()) -> Gas {
+    let before = env::used_gas();
+    call();
+    let gas = env::used_gas() - before;
+    println!("{}: {} gas", operation, gas);
+    gas
+}
+
+#[test]
+fn bench_account_paths_gas() {
+    let mut contract = staked_farm();
+    let mut context = get_context(alice(), env::storage_usage(), 0);
+    context.attached_deposit = 10u128.pow(24);
+    testing_env!(context);
+    contract.storage_deposit(None, None);
+    contract.set_position_hook(Some(bob().try_into().unwrap()));
+    contract.set_harvest_bounty(100, None);
+
+    let mut gas = vec![];
+    testing_env!(get_context(alice(), env::storage_usage(), 0));
+    gas.push(gas_of("stake_my_obs", || contract.stake_my_obs(U128(1000))));
+    let mut context = get_context(bob(), env::storage_usage(), contract.cliff_time + 1);
+    context.account_balance = 10u128.pow(25);
+    testing_env!(context);
+    gas.push(gas_of("harvest_for", || {
+        contract.harvest_for(alice().try_into().unwrap());
+    }));
+    let mut context = get_context(alice(), env::storage_usage(), 2 * contract.cliff_time + 2);
+    context.account_balance = 10u128.pow(25);
+    testing_env!(context);
+    gas.push(gas_of("claim_all", || {
+        contract.claim_all();
+    }));
+    gas.push(gas_of("exit", || {
+        contract.exit(Some(true));
+    }));
+    let heaviest = gas.into_iter().max().unwrap();
+    assert!(heaviest <= ACCOUNT_PATH_GAS_BUDGET, "Heaviest account path takes {} gas", heaviest);
+}
+
+#[cfg(feature = "bonus")]
+#[test]
+fn bench_bonus_distribution_gas() {
+    const RECIPIENTS: usize = 50;
+    /// gas per recipient of `distribute_bonus`.
+    const BONUS_RECIPIENT_GAS_BUDGET: Gas = 1_000_000_000_000;
+    let mut contract = staked_farm();
+    testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+    let recipients = vec![(alice().try_into().unwrap(), U128(1)); RECIPIENTS];
+    let gas = gas_of("distribute_bonus", || {
+        contract.distribute_bonus(recipients);
+    });
+    let per_recipient = gas / RECIPIENTS as Gas;
+    assert!(
+        per_recipient <= BONUS_RECIPIENT_GAS_BUDGET,
+        "Bonus distribution takes {} gas per recipient",
+        per_recipient
+    );
+}
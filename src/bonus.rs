@@ -0,0 +1,146 @@
+//! Bonus distribution for community contests: the owner credits rewards decided off-chain
+//! to a list of accounts, from the reserve, through the same reward balances accrual uses.
+//! Recipients a call has no gas left for stay queued for `continue_bonus_distribution`.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{env, log, near_bindgen, Gas};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// upper bound of the gas crediting a single recipient takes.
+const GAS_PER_BONUS_RECIPIENT: Gas = BASE_GAS;
+
+/// gas kept back to finish a call once it stops crediting.
+const GAS_FOR_BONUS_WRAPUP: Gas = BASE_GAS;
+
+#[near_bindgen]
+impl Farm {
+    /// Credits each recipient's reward balance with its bonus, returning the number of
+    /// recipients left for `continue_bonus_distribution`. Unregistered recipients are
+    /// skipped.
+    pub fn distribute_bonus(&mut self, recipients: Vec<(ValidAccountId, U128)>) -> U64 {
+        self.assert_role(Role::Treasurer);
+        assert!(self.bonus_queue.is_empty(), "A bonus distribution is in progress");
+        let total: Balance = recipients.iter().map(|(_, amount)| amount.0).sum();
+        // queued claims are owed already
+        self.assert_reserve_covers(self.claim_queue.outstanding() + total);
+        // queued last to first, so the next recipient is always popped
+        for (account_id, amount) in recipients.into_iter().rev() {
+            self.bonus_queue.push(&(account_id.into(), amount.0));
+        }
+        self.internal_distribute_bonus()
+    }
+
+    /// Credits the next queued recipients of a distribution that ran out of gas.
+    pub fn continue_bonus_distribution(&mut self) -> U64 {
+        self.assert_role(Role::Treasurer);
+        assert!(!self.bonus_queue.is_empty(), "No bonus distribution is in progress");
+        self.internal_distribute_bonus()
+    }
+
+    pub fn get_pending_bonus_recipients(&self) -> U64 {
+        self.bonus_queue.len().into()
+    }
+}
+
+impl Farm {
+    fn internal_distribute_bonus(&mut self) -> U64 {
+        let (mut credited, mut distributed) = (0u64, 0);
+        loop {
+            let gas_left = env::prepaid_gas() - env::used_gas();
+            if credited > 0 && gas_left < GAS_PER_BONUS_RECIPIENT + GAS_FOR_BONUS_WRAPUP {
+                break;
+            }
+            let (account_id, amount) = match self.bonus_queue.pop() {
+                Some(recipient) => recipient,
+                None => break,
+            };
+            credited += 1;
+            let (account_id_hash, account) = self.get_internal_account(&account_id);
+            let mut account = match account {
+                Some(account) => account,
+                None => {
+                    log!("Skipped bonus of {} to unregistered @{}", amount, account_id);
+                    continue;
+                }
+            };
+            self.assert_reserve_covers(self.claim_queue.outstanding() + distributed + amount);
+            if !self.is_frozen(&account_id) {
+                self.settle_reward_periods(&account_id_hash, &mut account);
+                self.touch(&mut account);
+            }
+            account.reward_balance += amount;
+            self.save_account(&account_id_hash, &account);
+            self.total_reward_farmed += amount;
+            distributed += amount;
+        }
+        let remaining = self.bonus_queue.len();
+        emit_event(
+            "distribute_bonus",
+            json!({
+                "recipients": credited,
+                "amount": U128(distributed),
+                "remaining": remaining,
+            }),
+        );
+        remaining.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_distribute_bonus() {
+        let mut contract = staked_farm();
+        let reward_balance =
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        let remaining = contract.distribute_bonus(vec![
+            (alice().try_into().unwrap(), U128(500)),
+            (bob().try_into().unwrap(), U128(700)),
+        ]);
+        assert_eq!(remaining.0, 0);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, reward_balance + 500);
+        assert!(contract.get_account(bob().try_into().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_distribute_bonus_continues() {
+        let mut contract = staked_farm();
+        let reward_balance =
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.prepaid_gas = GAS_PER_BONUS_RECIPIENT + GAS_FOR_BONUS_WRAPUP;
+        testing_env!(context);
+        let recipients = vec![
+            (alice().try_into().unwrap(), U128(500)),
+            (alice().try_into().unwrap(), U128(700)),
+        ];
+        assert_eq!(contract.distribute_bonus(recipients).0, 1);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, reward_balance + 500);
+
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.prepaid_gas = GAS_PER_BONUS_RECIPIENT + GAS_FOR_BONUS_WRAPUP;
+        testing_env!(context);
+        assert_eq!(contract.continue_bonus_distribution().0, 0);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, reward_balance + 1200);
+    }
+
+    #[test]
+    #[should_panic(expected = "Not enough rewards in the reserve")]
+    fn test_bonus_beyond_reserve() {
+        let mut contract = staked_farm();
+        contract.distribute_bonus(vec![(alice().try_into().unwrap(), U128(10u128.pow(31)))]);
+    }
+}
@@ -0,0 +1,158 @@
+//! Automation accounts. The owner registers bots with the scopes they may act in, so keeper
+//! jobs run without the owner key: restaking (`harvest_all`), snapshots (`take_snapshot`),
+//! retiring accounts of a decommissioned farm (`retire_accounts`), reconciling strategies
+//! (`report`), pricing the reward rate (`update_reward_rate`) and checking the token
+//! contracts (`health_check`). Each bot may call at most once per `min_interval`, and the
+//! farm counts its calls. The owner can always call these entry points itself.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum BotScope {
+    Harvest,
+    Snapshot,
+    RetirePool,
+    Reconcile,
+    RewardRate,
+    Health,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BotConfig {
+    pub scopes: Vec<BotScope>,
+    /// least time between two calls of the bot, in nanoseconds.
+    pub min_interval: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Bot {
+    pub config: BotConfig,
+    pub calls: u64,
+    pub last_call_at: Option<Timestamp>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BotView {
+    pub bot_id: AccountId,
+    pub config: BotConfig,
+    pub calls: U64,
+    pub last_call_at: Option<TimestampView>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Registers `bot_id` with `config`, or removes it. Updating a bot keeps its counters.
+    pub fn set_bot(&mut self, bot_id: ValidAccountId, config: Option<BotConfig>) {
+        self.assert_owner();
+        match &config {
+            Some(config) => {
+                assert!(!config.scopes.is_empty(), "Bot needs at least one scope");
+                let bot = match self.bots.get(bot_id.as_ref()) {
+                    Some(bot) => Bot { config: config.clone(), ..bot },
+                    None => Bot { config: config.clone(), calls: 0, last_call_at: None },
+                };
+                self.bots.insert(bot_id.as_ref(), &bot);
+            }
+            None => {
+                self.bots.remove(bot_id.as_ref());
+            }
+        }
+        emit_event("set_bot", json!({ "bot_id": bot_id, "config": config }));
+    }
+
+    pub fn get_bots(&self) -> Vec<BotView> {
+        self.bots
+            .iter()
+            .map(|(bot_id, bot)| BotView {
+                bot_id,
+                config: bot.config,
+                calls: bot.calls.into(),
+                last_call_at: bot.last_call_at.map(TimestampView::from),
+            })
+            .collect()
+    }
+}
+
+impl Farm {
+    /// Lets the owner through, and bots with `scope` whose last call is at least their
+    /// `min_interval` old, counting the call.
+    pub(crate) fn assert_automation(&mut self, scope: BotScope) {
+        let predecessor_id = env::predecessor_account_id();
+        if predecessor_id == self.owner_id {
+            return;
+        }
+        let mut bot = match self.bots.get(&predecessor_id) {
+            Some(bot) if bot.config.scopes.contains(&scope) => bot,
+            _ => env::panic(
+                format!("Only the owner or a bot with scope {:?} can call this method", scope)
+                    .as_bytes(),
+            ),
+        };
+        let now = self.now();
+        if let Some(last_call_at) = bot.last_call_at {
+            assert!(now >= last_call_at + bot.config.min_interval.0, "Bot is rate limited");
+        }
+        bot.calls += 1;
+        bot.last_call_at = Some(now);
+        self.bots.insert(&predecessor_id, &bot);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm};
+    use std::convert::TryInto;
+
+    fn bot_farm() -> Farm {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        let config = BotConfig { scopes: vec![BotScope::Snapshot], min_interval: U64(100) };
+        contract.set_bot(bob().try_into().unwrap(), Some(config));
+        contract
+    }
+
+    #[test]
+    fn test_bot_takes_snapshots() {
+        let mut contract = bot_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.take_snapshot();
+        testing_env!(get_context(bob(), env::storage_usage(), 100));
+        contract.take_snapshot();
+        let bots = contract.get_bots();
+        assert_eq!(bots[0].calls.0, 2);
+        assert_eq!(bots[0].last_call_at, Some(TimestampView::from(100)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Bot is rate limited")]
+    fn test_bot_rate_limit() {
+        let mut contract = bot_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.take_snapshot();
+        testing_env!(get_context(bob(), env::storage_usage(), 99));
+        contract.take_snapshot();
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or a bot with scope Harvest can call this method")]
+    fn test_bot_out_of_scope() {
+        let mut contract = bot_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.harvest_all(vec![]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Circuit breaker on accrual. If a single accrual would credit more than the owner's
+//! bound, e.g. after a clock or math bug, the breaker trips instead: nothing accrues for
+//! any account until the owner resets it. Pending accrual is kept, so the bound has to be
+//! raised or the cause fixed before resetting. A top-up while the breaker is tripped keeps
+//! what the position had pending, credited with the rest once the breaker is reset.
+use near_sdk::json_types::U128;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BreakerStatus {
+    pub accrual_bound: Option<U128>,
+    pub tripped_at: Option<TimestampView>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the largest reward a single accrual may credit, `None` disabling the breaker.
+    pub fn set_accrual_bound(&mut self, bound: Option<U128>) {
+        self.assert_owner();
+        emit_event("set_accrual_bound", json!({ "bound": bound }));
+        self.accrual_bound = bound.map(|bound| bound.0);
+    }
+
+    /// Resumes accrual after the breaker tripped.
+    pub fn reset_breaker(&mut self) {
+        self.assert_owner();
+        assert!(self.breaker_tripped_at.take().is_some(), "Breaker is not tripped");
+        emit_event("reset_breaker", json!({}));
+    }
+
+    pub fn get_breaker_status(&self) -> BreakerStatus {
+        BreakerStatus {
+            accrual_bound: self.accrual_bound.map(U128),
+            tripped_at: self.breaker_tripped_at.map(TimestampView::from),
+        }
+    }
+}
+
+impl Farm {
+    /// Whether `amount` may be credited, tripping the breaker if it is beyond the bound.
+    pub(crate) fn accrual_allowed(&mut self, amount: Balance) -> bool {
+        if self.breaker_tripped_at.is_some() {
+            return false;
+        }
+        match self.accrual_bound {
+            Some(bound) if amount > bound => {
+                self.breaker_tripped_at = Some(self.now());
+                emit_event(
+                    "breaker_tripped",
+                    json!({ "amount": U128(amount), "bound": U128(bound) }),
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_breaker_pauses_accrual() {
+        let mut contract = staked_farm();
+        let now = contract.cliff_time + 1;
+        let mut context = get_context(alice(), env::storage_usage(), now);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context.clone());
+        let reward = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        contract.set_accrual_bound(Some(U128(reward.0 - 1)));
+        contract.stake_my_obs(U128(1000));
+        assert!(contract.get_breaker_status().tripped_at.is_some());
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!((account.obs_balance.0, account.reward_balance.0), (2000, 0));
+
+        contract.set_accrual_bound(None);
+        contract.reset_breaker();
+        context.block_timestamp = 2 * now;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(account.reward_balance.0 > reward.0);
+    }
+
+    #[test]
+    fn test_top_up_while_tripped_keeps_pending() {
+        let mut contract = staked_farm();
+        let now = contract.cliff_time + 1;
+        testing_env!(get_context(alice(), env::storage_usage(), now));
+        let reward = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        contract.set_accrual_bound(Some(U128(reward.0 - 1)));
+        contract.stake_my_obs(U128(1000));
+        assert!(contract.get_breaker_status().tripped_at.is_some());
+
+        contract.set_accrual_bound(None);
+        contract.reset_breaker();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance, reward);
+    }
+}
@@ -0,0 +1,87 @@
+//! Build metadata recorded by `build.rs`, and NEP-330 source metadata, so users can check
+//! a deployed farm against a tagged release of this crate.
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BuildInfo {
+    pub version: String,
+    /// commit the wasm was built from, suffixed with "-dirty" for uncommitted changes, or
+    /// "unknown" unless the build recorded it, see `build.rs`.
+    pub git_commit: String,
+    pub rustc_version: String,
+    pub features: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Standard {
+    pub standard: String,
+    pub version: String,
+}
+
+/// NEP-330 contract source metadata.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractSourceMetadata {
+    pub version: Option<String>,
+    pub link: Option<String>,
+    pub standards: Vec<Standard>,
+}
+
+fn standard(standard: &str, version: &str) -> Standard {
+    Standard { standard: standard.to_string(), version: version.to_string() }
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn build_info(&self) -> BuildInfo {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: env!("BUILD_GIT_COMMIT").to_string(),
+            rustc_version: env!("BUILD_RUSTC_VERSION").to_string(),
+            features: env!("BUILD_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    pub fn contract_source_metadata(&self) -> ContractSourceMetadata {
+        let repository = env!("CARGO_PKG_REPOSITORY");
+        let version = match env!("BUILD_GIT_COMMIT") {
+            "unknown" => env!("CARGO_PKG_VERSION").to_string(),
+            commit => format!("{}+{}", env!("CARGO_PKG_VERSION"), commit),
+        };
+        ContractSourceMetadata {
+            version: Some(version),
+            link: if repository.is_empty() { None } else { Some(repository.to_string()) },
+            standards: vec![
+                standard("nep145", "1.0.0"),
+                standard("nep297", "1.0.0"),
+                standard("nep330", "1.1.0"),
+            ],
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_build_info() {
+        testing_env!(get_context(alice(), 0, 0));
+        let info = new_farm().build_info();
+        assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!info.git_commit.is_empty());
+        assert_eq!(info.features.contains(&"liens".to_string()), cfg!(feature = "liens"));
+    }
+}
@@ -0,0 +1,134 @@
+//! Campaign attribution. A stake msg ending with `@<campaign>`, like `Stake@spring-launch`
+//! or `Stake#lp@spring-launch`, tags the deposit with the campaign, and the campaign's
+//! stats add it up, so growth teams see the stake each campaign brought without joining
+//! logs off-chain. An account is attributed to the campaign of its first tagged deposit;
+//! later deposits count towards the campaign they are tagged with. Like a label, the tag
+//! takes storage the account's deposit must cover.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+/// maximum length of a campaign tag, in bytes.
+pub const MAX_CAMPAIGN_TAG_LEN: usize = 32;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct CampaignStats {
+    pub deposits: u64,
+    /// accounts whose first tagged deposit was tagged with the campaign.
+    pub stakers: u64,
+    pub total_staked: Balance,
+    pub first_deposit_at: Timestamp,
+    pub last_deposit_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CampaignStatsView {
+    pub deposits: U64,
+    pub stakers: U64,
+    pub total_staked: U128,
+    pub first_deposit_at: TimestampView,
+    pub last_deposit_at: TimestampView,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn get_campaign_stats(&self, tag: String) -> Option<CampaignStatsView> {
+        self.campaign_stats.get(&tag).map(|stats| CampaignStatsView {
+            deposits: stats.deposits.into(),
+            stakers: stats.stakers.into(),
+            total_staked: stats.total_staked.into(),
+            first_deposit_at: stats.first_deposit_at.into(),
+            last_deposit_at: stats.last_deposit_at.into(),
+        })
+    }
+}
+
+impl Farm {
+    /// Adds the deposit of `amount` by `account_id` to the stats of `campaign`.
+    pub(crate) fn record_campaign(
+        &mut self,
+        account_id: &AccountId,
+        account: &mut Account,
+        amount: Balance,
+        campaign: Option<String>,
+    ) {
+        let campaign = match campaign {
+            Some(campaign) => campaign,
+            None => return,
+        };
+        assert!(
+            !campaign.is_empty()
+                && campaign.len() <= MAX_CAMPAIGN_TAG_LEN
+                && campaign.bytes().all(|c| c.is_ascii_alphanumeric() || b"-_.".contains(&c)),
+            "Invalid campaign tag"
+        );
+        let now = self.now();
+        let mut stats = self
+            .campaign_stats
+            .get(&campaign)
+            .unwrap_or(CampaignStats { first_deposit_at: now, ..Default::default() });
+        stats.deposits += 1;
+        stats.total_staked += amount;
+        stats.last_deposit_at = now;
+        if account.campaign.is_none() {
+            stats.stakers += 1;
+            account.campaign = Some(campaign.clone());
+        }
+        self.campaign_stats.insert(&campaign, &stats);
+        emit_event(
+            "campaign_deposit",
+            json!({ "account_id": account_id, "campaign": campaign, "amount": U128(amount) }),
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, obs, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_campaign_stats() {
+        let mut contract = staked_farm();
+        // the tag is stored with the account, so it takes storage
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(22);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        testing_env!(get_context(obs().into(), env::storage_usage(), 100));
+        contract.ft_on_transfer(alice(), U128(300), format!("{}@spring", STAKE_MSG));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 200));
+        contract.ft_on_transfer(alice(), U128(200), format!("{}@spring", STAKE_MSG));
+        contract.ft_on_transfer(alice(), U128(50), format!("{}@autumn", STAKE_MSG));
+
+        let stats = contract.get_campaign_stats("spring".to_string()).unwrap();
+        assert_eq!((stats.deposits.0, stats.stakers.0, stats.total_staked.0), (2, 1, 500));
+        assert_eq!(stats.first_deposit_at.nanoseconds.0, 100);
+        let stats = contract.get_campaign_stats("autumn".to_string()).unwrap();
+        assert_eq!((stats.deposits.0, stats.stakers.0), (1, 0));
+        assert!(contract.get_campaign_stats("winter".to_string()).is_none());
+
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 1550);
+        assert_eq!(account.campaign.as_deref(), Some("spring"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid campaign tag")]
+    fn test_campaign_tag_is_bounded() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        let tag = "a".repeat(MAX_CAMPAIGN_TAG_LEN + 1);
+        contract.ft_on_transfer(alice(), U128(300), format!("{}@{}", STAKE_MSG, tag));
+    }
+}
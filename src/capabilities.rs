@@ -0,0 +1,255 @@
+//! What the owner key can do, for DAOs auditing the role before taking a farm over. Each
+//! admin method is classified by the money it puts at risk, and `simulate_admin_call`
+//! checks a proposed call against the admin interface without executing it. The check
+//! covers the method, its role and the shape of its arguments; conditions on the farm's
+//! state are only checked by the call itself.
+use near_sdk::json_types::U64;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::{self, Value};
+
+use crate::*;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum MoneyAtRisk {
+    /// parameters and operations that move no tokens.
+    None,
+    /// can move, redirect or cut reward tokens not paid out yet.
+    Rewards,
+    /// can lock or delay the withdrawal of staked principal.
+    Principal,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnerCapability {
+    pub method_name: String,
+    /// role the method is declared for; the owner holds every role.
+    pub role: String,
+    pub money_at_risk: MoneyAtRisk,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AdminCallCheck {
+    pub valid: bool,
+    pub role: Option<String>,
+    pub money_at_risk: Option<MoneyAtRisk>,
+    pub gas: Option<U64>,
+    pub errors: Vec<String>,
+}
+
+/// Money `method_name` puts at risk, `None` for methods that aren't admin methods.
+fn money_at_risk(method_name: &str) -> Option<MoneyAtRisk> {
+    Some(match method_name {
+        "set_treasury"
+        | "set_harvest_bounty"
+        | "set_emission_schedule"
+        | "set_rate_controller"
+        | "cancel_pending_changes"
+        | "set_partner_boost"
+        | "set_compounding_frequency"
+        | "set_claim_window"
+        | "set_accrual_bound"
+        | "set_vip_auction"
+        | "set_streak_bonus"
+        | "set_strategy"
+        | "deploy_to_strategy"
+        | "switch_reward_token"
+        | "sweep_reward_period"
+        | "set_claim_route"
+        | "set_fee_on_transfer"
+        | "set_drip_schedule"
+        | "set_min_claim"
+        | "set_stake_warm_up"
+        | "set_fee_destination"
+        | "set_exit_fee_destination"
+        | "set_reward_cliff"
+        | "set_reject_contracts"
+        | "set_contract_listing"
+        | "sweep_fees"
+        | "decommission"
+        | "sweep_reserve"
+        | "retire_accounts"
+        | "update_reward_rate"
+        | "set_grant_signer"
+        | "distribute_bonus"
+        | "continue_bonus_distribution" => MoneyAtRisk::Rewards,
+        "add_guardian"
+        | "set_guardian_threshold"
+        | "freeze_account"
+        | "confirm_action"
+        | "set_cliff_scope"
+        | "set_large_unstake_delay"
+        | "set_registration_fee"
+        | "add_lienholder"
+        | "grant_role"
+        | "force_settle"
+        | "set_entry_fee"
+        | "set_exit_fee" => MoneyAtRisk::Principal,
+        "remove_guardian"
+        | "set_gas_profiling"
+        | "set_funding_bounds"
+        | "set_token_gas"
+        | "set_farm_metadata"
+        | "set_sponsored_accounts_limit"
+        | "withdraw_storage_sponsorship"
+        | "set_runway_alert"
+        | "take_snapshot"
+        | "harvest_all"
+        | "health_check"
+        | "report"
+        | "reset_breaker"
+        | "set_promise_gas_margin"
+        | "set_max_positions"
+        | "set_bot"
+        | "schedule_decommission"
+        | "cancel_decommission"
+        | "unfreeze_account"
+        | "recall_from_strategy"
+        | "remove_lienholder"
+        | "revoke_role"
+        | "set_dust_policy"
+        | "set_claim_sponsorship"
+        | "set_claim_manager"
+        | "set_history_archive"
+        | "set_health_watchdog"
+        | "resume_deposits" => MoneyAtRisk::None,
+        _ => return None,
+    })
+}
+
+/// Collects where `value` doesn't match `schema`, as a subset of JSON schema: `type`,
+/// `enum`, `nullable` and the `properties` and `required` of objects.
+fn check_value(path: &str, schema: &Value, value: &Value, errors: &mut Vec<String>) {
+    if value.is_null() {
+        if schema.get("nullable").is_none() {
+            errors.push(format!("{} must not be null", path));
+        }
+        return;
+    }
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        if !values.contains(value) {
+            errors.push(format!("{} must be one of {}", path, Value::from(values.clone())));
+        }
+    }
+    let matches = match schema.get("type").and_then(Value::as_str) {
+        Some("string") => value.is_string(),
+        Some("boolean") => value.is_boolean(),
+        Some("integer") => value.is_u64() || value.is_i64(),
+        Some("array") => value.is_array(),
+        Some("object") => value.is_object(),
+        _ => true,
+    };
+    if !matches {
+        errors.push(format!("{} must be of type {}", path, schema["type"]));
+        return;
+    }
+    if let (Some(properties), Some(object)) =
+        (schema.get("properties").and_then(Value::as_object), value.as_object())
+    {
+        for name in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+            let name = name.as_str().unwrap_or_default();
+            if !object.contains_key(name) {
+                errors.push(format!("{}.{} is required", path, name));
+            }
+        }
+        for (name, value) in object {
+            match properties.get(name) {
+                Some(schema) => check_value(&format!("{}.{}", path, name), schema, value, errors),
+                None => errors.push(format!("{}.{} is not an argument", path, name)),
+            }
+        }
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Every admin method with the money it puts at risk. The owner can call all of them,
+    /// guardian methods included.
+    pub fn get_owner_capabilities(&self) -> Vec<OwnerCapability> {
+        self.get_admin_interface()
+            .into_iter()
+            .map(|method| OwnerCapability {
+                money_at_risk: money_at_risk(&method.method_name).unwrap_or(MoneyAtRisk::Rewards),
+                method_name: method.method_name,
+                role: method.role,
+            })
+            .collect()
+    }
+
+    /// Checks a call of the admin method `method_name` with the JSON `args` against the
+    /// admin interface, without executing it.
+    pub fn simulate_admin_call(&self, method_name: String, args: String) -> AdminCallCheck {
+        let method =
+            self.get_admin_interface().into_iter().find(|method| method.method_name == method_name);
+        let method = match method {
+            Some(method) => method,
+            None => {
+                return AdminCallCheck {
+                    valid: false,
+                    role: None,
+                    money_at_risk: None,
+                    gas: None,
+                    errors: vec![format!("{} is not an admin method", method_name)],
+                }
+            }
+        };
+        let mut errors = vec![];
+        match serde_json::from_str::<Value>(&args) {
+            Ok(args) if args.is_object() => check_value("args", &method.args, &args, &mut errors),
+            _ => errors.push("args must be a JSON object".to_string()),
+        }
+        AdminCallCheck {
+            valid: errors.is_empty(),
+            money_at_risk: money_at_risk(&method.method_name),
+            role: Some(method.role),
+            gas: Some(method.gas),
+            errors,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_every_admin_method_is_classified() {
+        testing_env!(get_context(alice(), 0, 0));
+        let contract = new_farm();
+        for method in contract.get_admin_interface() {
+            assert!(money_at_risk(&method.method_name).is_some(), "{}", method.method_name);
+        }
+        let capabilities = contract.get_owner_capabilities();
+        let sweep = capabilities.iter().find(|c| c.method_name == "sweep_reserve").unwrap();
+        assert_eq!(sweep.money_at_risk, MoneyAtRisk::Rewards);
+    }
+
+    #[test]
+    fn test_simulate_admin_call() {
+        testing_env!(get_context(alice(), 0, 0));
+        let contract = new_farm();
+        let check = contract.simulate_admin_call(
+            "set_harvest_bounty".to_string(),
+            r#"{ "bounty_bps": 10, "effective_at": null }"#.to_string(),
+        );
+        assert!(check.valid, "{:?}", check.errors);
+        assert_eq!(check.role.unwrap(), "owner");
+
+        let check = contract.simulate_admin_call(
+            "set_harvest_bounty".to_string(),
+            r#"{ "bounty_bps": "10", "fee": 1 }"#.to_string(),
+        );
+        assert_eq!(
+            check.errors,
+            vec!["args.bounty_bps must be of type \"integer\"", "args.fee is not an argument"]
+        );
+        assert!(!contract.simulate_admin_call("claim_all".to_string(), "{}".to_string()).valid);
+    }
+}
@@ -0,0 +1,180 @@
+//! Announced parameter changes. Admin setters take an optional `effective_at` timestamp;
+//! the change is recorded on-chain right away and the farm switches to it at that time on
+//! its own, so stakers get notice that is enforced by the contract.
+//!
+//! Emission changes are kept once in effect, because accounts that weren't touched since
+//! still need the previous schedule to accrue up to the switch.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+
+use crate::drip::DripSchedule;
+use crate::events::emit_event;
+use crate::fee_destination::FeeDestination;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ParameterChange {
+    EmissionSchedule(EmissionSchedule),
+    HarvestBounty(u32),
+    DripSchedule(DripSchedule),
+    FeeDestination(FeeDestination),
+    EntryFee(u32),
+    ExitFee(u32),
+    ExitFeeDestination(FeeDestination),
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ScheduledChange {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub effective_at: Timestamp,
+    pub change: ParameterChange,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Announced changes that are not in effect yet, in the order they take effect.
+    pub fn get_pending_changes(&self) -> Vec<ScheduledChange> {
+        let now = self.now();
+        self.scheduled_changes.iter().filter(|change| change.effective_at > now).cloned().collect()
+    }
+
+    /// Withdraws every announced change that is not in effect yet.
+    pub fn cancel_pending_changes(&mut self) {
+        self.assert_owner();
+        let now = self.now();
+        self.scheduled_changes.retain(|change| change.effective_at <= now);
+        emit_event("cancel_pending_changes", json!({}));
+    }
+}
+
+impl Farm {
+    /// Records `change` to take effect at `effective_at`, now if `None`, returning that time.
+    pub(crate) fn schedule_change(
+        &mut self,
+        effective_at: Option<U64>,
+        change: ParameterChange,
+    ) -> Timestamp {
+        self.apply_due_changes();
+        let now = self.now();
+        let effective_at = effective_at.map_or(now, |effective_at| effective_at.0);
+        assert!(effective_at >= now, "Changes can't take effect in the past");
+        let index = self
+            .scheduled_changes
+            .iter()
+            .take_while(|scheduled| scheduled.effective_at <= effective_at)
+            .count();
+        self.scheduled_changes.insert(index, ScheduledChange { effective_at, change });
+        self.apply_due_changes();
+        effective_at
+    }
+
+    /// Folds changes in effect into the farm's parameters, except emission changes, and
+    /// records the versions they make, see `config_history`.
+    pub(crate) fn apply_due_changes(&mut self) {
+        let now = self.now();
+        let recorded_emission_changes = self.config_history.emission_changes;
+        let mut emission_changes = 0;
+        let mut due = vec![];
+        self.scheduled_changes.retain(|scheduled| {
+            if scheduled.effective_at > now {
+                return true;
+            }
+            if let ParameterChange::EmissionSchedule(_) = scheduled.change {
+                emission_changes += 1;
+                if emission_changes > recorded_emission_changes {
+                    due.push(scheduled.clone());
+                }
+                return true;
+            }
+            due.push(scheduled.clone());
+            false
+        });
+        self.config_history.emission_changes = emission_changes.max(recorded_emission_changes);
+        for (i, scheduled) in due.iter().enumerate() {
+            match &scheduled.change {
+                ParameterChange::EmissionSchedule(_) => {}
+                ParameterChange::HarvestBounty(bounty_bps) => self.harvest_bounty_bps = *bounty_bps,
+                ParameterChange::FeeDestination(destination) => self.fee_destination = *destination,
+                ParameterChange::ExitFeeDestination(destination) => {
+                    self.exit_fee_destination = *destination
+                }
+                ParameterChange::EntryFee(fee_bps) => self.entry_fee_bps = *fee_bps,
+                ParameterChange::ExitFee(fee_bps) => self.exit_fee_bps = *fee_bps,
+                ParameterChange::DripSchedule(schedule) => {
+                    self.apply_drip_change(schedule.clone(), scheduled.effective_at)
+                }
+            }
+            // changes taking effect together make a single version
+            if !matches!(due.get(i + 1), Some(next) if next.effective_at == scheduled.effective_at)
+            {
+                self.record_config_version(scheduled.effective_at);
+            }
+        }
+    }
+
+    /// Emission changes with the time each takes effect, in order.
+    pub(crate) fn emission_changes(&self) -> impl Iterator<Item = (Timestamp, &EmissionSchedule)> {
+        self.scheduled_changes.iter().filter_map(|scheduled| match &scheduled.change {
+            ParameterChange::EmissionSchedule(schedule) => Some((scheduled.effective_at, schedule)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_announced_rate_change() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        let schedule = EmissionSchedule::LinearDecay {
+            start: U64(0),
+            end: U64(1),
+            initial_rate: U128(900),
+            final_rate: U128(900),
+        };
+        contract.set_emission_schedule(schedule.clone(), Some(U64(100)));
+        assert_eq!(contract.get_pending_changes().len(), 1);
+        assert_eq!(contract.get_current_reward_rate().0, 1800);
+        // 1800·100 before the change and 900·100 after it
+        assert_eq!(contract.emission_between(0, 200), U256::from(180_000 + 90_000));
+
+        testing_env!(get_context(alice(), 0, 100));
+        assert!(contract.get_pending_changes().is_empty());
+        assert_eq!(contract.get_emission_schedule(), schedule);
+    }
+
+    #[test]
+    fn test_announced_bounty_change() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_harvest_bounty(50, Some(U64(100)));
+        contract.apply_due_changes();
+        assert_eq!(contract.harvest_bounty_bps, 0);
+
+        testing_env!(get_context(alice(), 0, 100));
+        contract.apply_due_changes();
+        assert_eq!(contract.harvest_bounty_bps, 50);
+        assert!(contract.scheduled_changes.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Changes can't take effect in the past")]
+    fn test_change_in_the_past() {
+        testing_env!(get_context(alice(), 0, 100));
+        let mut contract = new_farm();
+        contract.set_harvest_bounty(50, Some(U64(99)));
+    }
+}
@@ -0,0 +1,232 @@
+//! Itemized reward payouts per account, so stakers can produce tax reports straight from
+//! the contract rather than from an archival indexer. A payout is recorded once its transfer
+//! resolved, in the token it was paid in. Each account keeps its latest
+//! `MAX_CLAIM_RECORDS` payouts, dropping the oldest, unless they were moved to the history
+//! archive before, see `history_archive`.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// payouts kept per account, years of daily claims.
+pub const MAX_CLAIM_RECORDS: u64 = 1_000;
+
+/// most payouts `get_claims_report` returns at once.
+const MAX_CLAIMS_REPORT_PAGE: u64 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimRecord {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub claimed_at: Timestamp,
+    pub amount: U128,
+    pub token_id: AccountId,
+    /// token the payout was swapped into on the way, see `conversion`.
+    pub converted_into: Option<AccountId>,
+    /// operation the payout settled, see `operations`.
+    pub op_id: Option<U64>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimsReport {
+    pub claims: Vec<ClaimRecord>,
+    /// `from_index` of the next page, if the range holds more payouts.
+    pub next_index: Option<U64>,
+    /// payouts before this index are only in the history archive, see `history_archive`.
+    pub archived_before: Option<U64>,
+    pub archive_id: Option<AccountId>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ClaimHistory {
+    /// payouts by account and index modulo `MAX_CLAIM_RECORDS`.
+    records: LookupMap<(ShortAccountHash, u64), ClaimRecord>,
+    /// payouts ever recorded by account.
+    recorded: LookupMap<ShortAccountHash, u64>,
+    /// payouts moved to the history archive by account, the oldest first.
+    archived: LookupMap<ShortAccountHash, u64>,
+}
+
+impl ClaimHistory {
+    pub fn new() -> Self {
+        Self {
+            records: LookupMap::new(StorageKey::ClaimRecords),
+            recorded: LookupMap::new(StorageKey::ClaimRecordCounts),
+            archived: LookupMap::new(StorageKey::ArchivedClaimCounts),
+        }
+    }
+
+    fn push(&mut self, account_id_hash: ShortAccountHash, record: ClaimRecord) {
+        let recorded = self.recorded.get(&account_id_hash).unwrap_or(0);
+        self.records.insert(&(account_id_hash.clone(), recorded % MAX_CLAIM_RECORDS), &record);
+        self.recorded.insert(&account_id_hash, &(recorded + 1));
+    }
+
+    pub(crate) fn get(&self, account_id_hash: &ShortAccountHash, index: u64) -> ClaimRecord {
+        self.records
+            .get(&(account_id_hash.clone(), index % MAX_CLAIM_RECORDS))
+            .expect("Claim record missing")
+    }
+
+    pub(crate) fn recorded(&self, account_id_hash: &ShortAccountHash) -> u64 {
+        self.recorded.get(account_id_hash).unwrap_or(0)
+    }
+
+    pub(crate) fn archived(&self, account_id_hash: &ShortAccountHash) -> u64 {
+        self.archived.get(account_id_hash).unwrap_or(0)
+    }
+
+    /// index of the account's oldest payout still held here.
+    pub(crate) fn first_local(&self, account_id_hash: &ShortAccountHash) -> u64 {
+        self.recorded(account_id_hash)
+            .saturating_sub(MAX_CLAIM_RECORDS)
+            .max(self.archived(account_id_hash))
+    }
+
+    /// Drops the account's payouts before `through_index`, once they are archived.
+    pub(crate) fn drop_archived(&mut self, account_id_hash: &ShortAccountHash, through_index: u64) {
+        for index in self.first_local(account_id_hash)..through_index {
+            self.records.remove(&(account_id_hash.clone(), index % MAX_CLAIM_RECORDS));
+        }
+        if through_index > self.archived(account_id_hash) {
+            self.archived.insert(account_id_hash, &through_index);
+        }
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Payouts to `account_id` from `from_ts` included to `to_ts` excluded, oldest first.
+    /// Pages start at `from_index`, as returned in `next_index`, and hold up to `limit`
+    /// payouts.
+    pub fn get_claims_report(
+        &self,
+        account_id: ValidAccountId,
+        from_ts: U64,
+        to_ts: U64,
+        from_index: Option<U64>,
+        limit: Option<u64>,
+    ) -> ClaimsReport {
+        let history = &self.claim_history;
+        let account_id_hash = ShortAccountHash::from(account_id.as_ref());
+        let recorded = history.recorded(&account_id_hash);
+        let limit = limit.unwrap_or(MAX_CLAIMS_REPORT_PAGE).min(MAX_CLAIMS_REPORT_PAGE);
+        let archived = history.archived(&account_id_hash);
+        let archived_before = if archived > 0 { Some(U64(archived)) } else { None };
+        let archive_id = archived_before.and(self.history_archive.clone());
+        // payouts are recorded in time order, so the first one not before `from_ts` is found
+        // by bisection
+        let (mut low, mut high) = (history.first_local(&account_id_hash), recorded);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if history.get(&account_id_hash, middle).claimed_at < from_ts.0 {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let mut index = from_index.map_or(low, |from_index| from_index.0.max(low));
+        let mut claims = vec![];
+        while index < recorded {
+            let record = history.get(&account_id_hash, index);
+            if record.claimed_at >= to_ts.0 {
+                break;
+            }
+            if claims.len() as u64 == limit {
+                return ClaimsReport {
+                    claims,
+                    next_index: Some(index.into()),
+                    archived_before,
+                    archive_id,
+                };
+            }
+            claims.push(record);
+            index += 1;
+        }
+        ClaimsReport { claims, next_index: None, archived_before, archive_id }
+    }
+}
+
+impl Farm {
+    /// Records a resolved payout of `amount` in `token_id` to `account_id`.
+    pub(crate) fn record_claim(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+        token_id: AccountId,
+        converted_into: Option<AccountId>,
+        op_id: Option<u64>,
+    ) {
+        if amount == 0 {
+            return;
+        }
+        let record = ClaimRecord {
+            claimed_at: self.now(),
+            amount: amount.into(),
+            token_id,
+            converted_into,
+            op_id: op_id.map(U64),
+        };
+        self.claim_history.push(account_id.into(), record);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_claims_report() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        for day in 1..=5u64 {
+            testing_env!(get_context(alice(), 0, day * 100));
+            contract.record_claim(&alice(), day as u128, "reward.near".to_string(), None, None);
+        }
+        let report = contract.get_claims_report(
+            alice().try_into().unwrap(),
+            U64(200),
+            U64(500),
+            None,
+            Some(2),
+        );
+        assert_eq!(report.claims.len(), 2);
+        assert_eq!(report.claims[0].amount.0, 2);
+        let next = contract.get_claims_report(
+            alice().try_into().unwrap(),
+            U64(200),
+            U64(500),
+            report.next_index,
+            Some(2),
+        );
+        assert_eq!(next.claims.len(), 1);
+        assert_eq!(next.claims[0].amount.0, 4);
+        assert!(next.next_index.is_none());
+    }
+
+    #[test]
+    fn test_claim_history_is_bounded() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        for i in 0..MAX_CLAIM_RECORDS + 5 {
+            // fresh gas for every batch of writes
+            if i % 100 == 0 {
+                testing_env!(get_context(alice(), env::storage_usage(), 0));
+            }
+            contract.record_claim(&alice(), 1, "reward.near".to_string(), None, None);
+        }
+        let report =
+            contract.get_claims_report(alice().try_into().unwrap(), U64(0), U64(1), None, None);
+        assert_eq!(report.claims.len(), 100);
+        assert_eq!(report.next_index, Some(U64(105)));
+    }
+}
@@ -0,0 +1,281 @@
+//! First-come first-served payouts while the reserve is short. A claim the reserve can't
+//! cover, or any claim while others are waiting, is queued instead of failing. Queued
+//! claims are paid in order as soon as the reserve covers them: on every funding as far as
+//! its gas goes, and by anyone calling `process_claim_queue`. Every payout from the reserve
+//! goes through `pay_or_queue_reward`, so none can jump the queue. The claimant's storage
+//! deposit pays for its queue entry until the claim is paid.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Gas, Promise, StorageUsage};
+
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct QueuedClaim {
+    pub account_id: AccountId,
+    pub amount: Balance,
+    /// total queued before this claim, since the queue was created.
+    pub queued_before: Balance,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ClaimQueue {
+    claims: LookupMap<u64, QueuedClaim>,
+    /// position of each queued account's claim.
+    positions: LookupMap<AccountId, u64>,
+    head: u64,
+    tail: u64,
+    total_queued: Balance,
+    total_paid: Balance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct QueuedClaimView {
+    /// claims to be paid before this one.
+    pub position: U64,
+    pub amount: U128,
+    /// reward funding still needed before this claim is paid.
+    pub shortfall: U128,
+}
+
+impl ClaimQueue {
+    pub fn new() -> Self {
+        Self {
+            claims: LookupMap::new(StorageKey::ClaimQueue),
+            positions: LookupMap::new(StorageKey::ClaimQueuePositions),
+            head: 0,
+            tail: 0,
+            total_queued: 0,
+            total_paid: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head == self.tail
+    }
+
+    pub fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    /// rewards owed to queued claims.
+    pub fn outstanding(&self) -> Balance {
+        self.total_queued - self.total_paid
+    }
+
+    fn push(&mut self, account_id: &AccountId, amount: Balance) -> u64 {
+        assert!(self.positions.get(account_id).is_none(), "A claim is already queued");
+        let claim = QueuedClaim {
+            account_id: account_id.clone(),
+            amount,
+            queued_before: self.total_queued,
+        };
+        self.claims.insert(&self.tail, &claim);
+        self.positions.insert(account_id, &self.tail);
+        self.total_queued += amount;
+        self.tail += 1;
+        self.tail - 1 - self.head
+    }
+
+    fn peek(&self) -> Option<QueuedClaim> {
+        if self.is_empty() {
+            return None;
+        }
+        self.claims.get(&self.head)
+    }
+
+    fn pop(&mut self) -> Option<QueuedClaim> {
+        let claim = self.claims.remove(&self.head)?;
+        self.positions.remove(&claim.account_id);
+        self.total_paid += claim.amount;
+        self.head += 1;
+        Some(claim)
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Pays queued claims the reserve covers, returning how many were paid.
+    pub fn process_claim_queue(&mut self) -> U64 {
+        self.pay_queued_claims().into()
+    }
+
+    pub fn get_claim_queue_length(&self) -> U64 {
+        self.claim_queue.len().into()
+    }
+
+    pub fn get_queued_claim(&self, account_id: ValidAccountId) -> Option<QueuedClaimView> {
+        let index = self.claim_queue.positions.get(account_id.as_ref())?;
+        let claim = self.claim_queue.claims.get(&index)?;
+        let owed = claim.queued_before + claim.amount - self.claim_queue.total_paid;
+        Some(QueuedClaimView {
+            position: (index - self.claim_queue.head).into(),
+            amount: claim.amount.into(),
+            shortfall: owed.saturating_sub(self.reward_reserve()).into(),
+        })
+    }
+}
+
+impl Farm {
+    /// Whether a claim of `amount` has to wait in the queue.
+    pub(crate) fn must_queue_claim(&self, amount: Balance) -> bool {
+        !self.claim_queue.is_empty() || amount > self.reward_reserve()
+    }
+
+    /// Pays `amount` of rewards out to `account_id` with `pay`, or queues the payout if it
+    /// has to wait. Returns the payout's promise, `None` once queued.
+    pub(crate) fn pay_or_queue_reward(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+        pay: impl FnOnce(&mut Self) -> Promise,
+    ) -> Option<Promise> {
+        if self.must_queue_claim(amount) {
+            self.queue_claim(account_id, amount);
+            return None;
+        }
+        Some(pay(self))
+    }
+
+    fn queue_claim(&mut self, account_id: &AccountId, amount: Balance) {
+        let storage_before = env::storage_usage();
+        let position = self.claim_queue.push(account_id, amount);
+        self.charge_queue_storage(account_id, env::storage_usage() - storage_before, true);
+        emit_event(
+            "queue_claim",
+            json!({ "account_id": account_id, "amount": U128(amount), "position": U64(position) }),
+        );
+    }
+
+    /// Pays queued claims in order while the reserve covers them and gas lasts.
+    pub(crate) fn pay_queued_claims(&mut self) -> u64 {
+        let gas_per_payout: Gas = BASE_GAS
+            + self.token_gas(&self.reward_token_account_id).ft_transfer.0
+            + GAS_FOR_RESOLVE_TRANSFER
+            + self.payout_check_gas();
+        let mut paid = 0;
+        while let Some(claim) = self.claim_queue.peek() {
+            if claim.amount > self.reward_reserve()
+                || env::prepaid_gas() - env::used_gas() < gas_per_payout + BASE_GAS
+            {
+                break;
+            }
+            let storage_before = env::storage_usage();
+            self.claim_queue.pop();
+            let freed = storage_before - env::storage_usage();
+            self.charge_queue_storage(&claim.account_id, freed, false);
+            self.internal_send_reward(&claim.account_id, claim.amount);
+            paid += 1;
+        }
+        if paid > 0 {
+            emit_event(
+                "pay_queued_claims",
+                json!({ "paid": paid, "remaining": self.claim_queue.len() }),
+            );
+        }
+        paid
+    }
+
+    /// Charges the storage of a queue entry to `account_id`, or releases it once paid.
+    fn charge_queue_storage(&mut self, account_id: &AccountId, bytes: StorageUsage, charge: bool) {
+        // claimants are registered, and accounts with a queued claim can't unregister
+        let (account_id_hash, mut account) = match self.get_internal_account(account_id) {
+            (account_id_hash, Some(account)) => (account_id_hash, account),
+            _ => return,
+        };
+        if charge {
+            account.storage_usage += bytes;
+        } else {
+            account.storage_usage -= bytes.min(account.storage_usage);
+        }
+        self.save_account(&account_id_hash, &account);
+    }
+
+    pub(crate) fn has_queued_claim(&self, account_id: &AccountId) -> bool {
+        self.claim_queue.positions.contains_key(account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, reward, staked_farm};
+    use std::convert::TryInto;
+
+    /// a farm whose reserve covers half of alice's rewards, with `storage` deposited on top
+    /// of her registration.
+    fn short_farm(storage: Balance) -> (Farm, Balance) {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.attached_deposit = storage;
+        testing_env!(context);
+        if storage > 0 {
+            contract.storage_deposit(None, None);
+        }
+        let pending = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        let reserve = contract.reward_reserve();
+        contract.ledger.post(LedgerAccount::Reserve, LedgerAccount::Claimed, reserve - pending / 2);
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.attached_deposit = 1;
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        (contract, pending)
+    }
+
+    #[test]
+    fn test_short_reserve_queues_claims() {
+        let (mut contract, pending) = short_farm(10u128.pow(22));
+        let available = contract.storage_balance_of(alice().try_into().unwrap()).unwrap().available;
+        contract.claim_all();
+        let claim = contract.get_queued_claim(alice().try_into().unwrap()).unwrap();
+        assert_eq!((claim.position.0, claim.amount.0), (0, pending));
+        assert_eq!(claim.shortfall.0, pending - pending / 2);
+        // the claimant pays for its queue entry
+        let storage = contract.storage_balance_of(alice().try_into().unwrap()).unwrap();
+        assert!(storage.available.0 < available.0);
+
+        let now = contract.cliff_time + 1;
+        testing_env!(get_context(reward().into(), env::storage_usage(), now));
+        contract.ft_on_transfer(alice(), U128(pending), String::new());
+        assert!(contract.get_queued_claim(alice().try_into().unwrap()).is_none());
+        assert_eq!(contract.reward_reserve(), pending / 2);
+        let storage = contract.storage_balance_of(alice().try_into().unwrap()).unwrap();
+        assert_eq!(storage.available, available);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage deposit")]
+    fn test_queue_entry_needs_storage() {
+        let (mut contract, _) = short_farm(0);
+        contract.claim_all();
+    }
+
+    #[test]
+    fn test_exit_queues_rewards() {
+        let (mut contract, pending) = short_farm(10u128.pow(22));
+        contract.exit(Some(true));
+        let claim = contract.get_queued_claim(alice().try_into().unwrap()).unwrap();
+        assert_eq!(claim.amount.0, pending);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 0);
+    }
+
+    #[test]
+    fn test_harvest_queues_rewards() {
+        let (mut contract, pending) = short_farm(10u128.pow(22));
+        let reserve = contract.reward_reserve();
+        contract.harvest_for(alice().try_into().unwrap());
+        let claim = contract.get_queued_claim(alice().try_into().unwrap()).unwrap();
+        assert_eq!(claim.amount.0, pending);
+        assert_eq!(contract.reward_reserve(), reserve);
+    }
+}
@@ -0,0 +1,171 @@
+//! Gasless claims. An operator names a relayer, funded by the operator, that claims for
+//! accounts which opted in with `set_sponsored_claims`, so stakers holding only the reward
+//! token don't need NEAR for gas. Each account gets at most `monthly_claims` sponsored
+//! claims per `SPONSORSHIP_MONTH`, counted on the account; the rewards always go to the
+//! account, or to the owner of a position.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// period sponsored claims are capped over, 30 days.
+pub const SPONSORSHIP_MONTH: Timestamp = 30 * 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimSponsorship {
+    pub relayer_id: ValidAccountId,
+    /// sponsored claims each account gets per month.
+    pub monthly_claims: u32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct SponsoredClaims {
+    /// month of `claims`, counted in `SPONSORSHIP_MONTH` since the epoch.
+    pub month: u64,
+    pub claims: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimSponsorshipView {
+    pub sponsorship: ClaimSponsorship,
+    /// sponsored claims ever made.
+    pub total_claims: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SponsoredClaimsView {
+    pub claims_this_month: u32,
+    pub remaining: u32,
+    pub month_ends_at: TimestampView,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Names the relayer that claims for opted-in accounts, `None` to stop sponsoring.
+    pub fn set_claim_sponsorship(&mut self, sponsorship: Option<ClaimSponsorship>) {
+        self.assert_role(Role::Operator);
+        self.claim_sponsorship = sponsorship.clone();
+        emit_event("set_claim_sponsorship", json!({ "sponsorship": sponsorship }));
+    }
+
+    pub fn get_claim_sponsorship(&self) -> Option<ClaimSponsorshipView> {
+        self.claim_sponsorship.clone().map(|sponsorship| ClaimSponsorshipView {
+            sponsorship,
+            total_claims: self.sponsored_claims.into(),
+        })
+    }
+
+    /// Lets the relayer claim for the predecessor, or stops it.
+    #[payable]
+    pub fn set_sponsored_claims(&mut self, enabled: bool) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        if enabled != account.sponsored_claims.is_some() {
+            account.sponsored_claims = if enabled { Some(Default::default()) } else { None };
+            self.save_account(&account_id_hash, &account);
+        }
+        emit_event("set_sponsored_claims", json!({ "account_id": account_id, "enabled": enabled }));
+    }
+
+    /// Sponsored claims the account used this month, if it opted in.
+    pub fn get_sponsored_claims(&self, account_id: ValidAccountId) -> Option<SponsoredClaimsView> {
+        let account = self.get_internal_account(account_id.as_ref()).1?;
+        let sponsored = account.sponsored_claims?;
+        let month = self.now() / SPONSORSHIP_MONTH;
+        let claims = if sponsored.month == month { sponsored.claims } else { 0 };
+        let monthly_claims = self.claim_sponsorship.as_ref().map_or(0, |s| s.monthly_claims);
+        Some(SponsoredClaimsView {
+            claims_this_month: claims,
+            remaining: monthly_claims.saturating_sub(claims),
+            month_ends_at: ((month + 1) * SPONSORSHIP_MONTH).into(),
+        })
+    }
+
+    /// Claims all of the rewards of `account_id` for it, from the relayer.
+    pub fn claim_for(&mut self, account_id: ValidAccountId) -> PromiseOrValue<U128> {
+        let sponsorship = self.claim_sponsorship.clone().expect("Claims aren't sponsored");
+        assert_eq!(
+            &env::predecessor_account_id(),
+            sponsorship.relayer_id.as_ref(),
+            "Only the relayer can claim for others"
+        );
+        let account_id: AccountId = account_id.into();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        let sponsored = account.sponsored_claims.as_mut().expect("The account didn't opt in");
+        let month = self.now() / SPONSORSHIP_MONTH;
+        if sponsored.month != month {
+            *sponsored = SponsoredClaims { month, claims: 0 };
+        }
+        assert!(
+            sponsored.claims < sponsorship.monthly_claims,
+            "The account used its sponsored claims for the month"
+        );
+        sponsored.claims += 1;
+        self.save_account(&account_id_hash, &account);
+        self.sponsored_claims += 1;
+        emit_event("claim_for", json!({ "account_id": account_id }));
+        let promise = self.internal_claim(&account_id);
+        self.flush_gas_profile("claim_for");
+        promise
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn sponsored_farm() -> Farm {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_claim_sponsorship(Some(ClaimSponsorship {
+            relayer_id: bob().try_into().unwrap(),
+            monthly_claims: 1,
+        }));
+        contract.set_sponsored_claims(true);
+        contract
+    }
+
+    #[test]
+    fn test_claim_for() {
+        let mut contract = sponsored_farm();
+        let mut context = get_context(bob(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.claim_for(alice().try_into().unwrap());
+        let claims = contract.get_sponsored_claims(alice().try_into().unwrap()).unwrap();
+        assert_eq!((claims.claims_this_month, claims.remaining), (1, 0));
+        assert_eq!(contract.get_claim_sponsorship().unwrap().total_claims.0, 1);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account used its sponsored claims for the month")]
+    fn test_claim_for_is_capped() {
+        let mut contract = sponsored_farm();
+        let mut context = get_context(bob(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.claim_for(alice().try_into().unwrap());
+        testing_env!(get_context(bob(), env::storage_usage(), contract.cliff_time + 2));
+        contract.claim_for(alice().try_into().unwrap());
+    }
+}
@@ -0,0 +1,271 @@
+//! Which parts of a stake the cliff locks. By default both the principal and the rewards
+//! stay locked for `cliff_time` after a stake from nothing; either lock can be lifted on its
+//! own. Top-ups don't restart the cliff.
+//!
+//! The rewards can have a cliff of their own with `set_reward_cliff`, shorter or longer
+//! than the principal's. Rewards earned during a retroactive reward cliff are credited once
+//! it ends, those earned before a top-up included; otherwise the stake only earns from the
+//! end of the cliff. Changes of the cliff apply to existing stakes at their next accrual,
+//! while whether it is retroactive can only change with nobody staked, as it decides what
+//! stakes in their cliff already earned.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CliffScope {
+    /// rewards can't be accrued or claimed before the cliff.
+    pub rewards: bool,
+    /// principal can't be unstaked before the cliff.
+    pub principal: bool,
+}
+
+impl Default for CliffScope {
+    fn default() -> Self {
+        Self { rewards: true, principal: true }
+    }
+}
+
+/// An account's rewards split the way staking UIs display them.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardBreakdown {
+    /// rewards that can be claimed now.
+    pub claimable: U128,
+    /// rewards earned during the cliff, claimable once it ends.
+    pub vesting: U128,
+    pub vesting_unlocks_at: Option<TimestampView>,
+    /// rewards lost by unstaking now, i.e. the vesting ones.
+    pub forfeited_if_exited: U128,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn get_reward_breakdown(&self, account_id: ValidAccountId) -> Option<RewardBreakdown> {
+        let frozen = self.is_frozen(account_id.as_ref());
+        let mut account = self.get_internal_account(account_id.as_ref()).1?;
+        if !frozen {
+            self.split_closed_periods(&mut account);
+        }
+        let now = self.accrual_time();
+        if frozen || !self.rewards_locked(&account, now) {
+            let pending = if frozen { 0 } else { self.pending_reward(&account) };
+            return Some(RewardBreakdown {
+                claimable: (account.reward_balance + pending).into(),
+                vesting: U128(0),
+                vesting_unlocks_at: None,
+                forfeited_if_exited: U128(0),
+            });
+        }
+        let vesting = self.vesting_reward(&account, now);
+        Some(RewardBreakdown {
+            claimable: account.reward_balance.into(),
+            vesting: vesting.into(),
+            vesting_unlocks_at: Some((self.reward_cliff_ends_at(&account) + 1).into()),
+            forfeited_if_exited: vesting.into(),
+        })
+    }
+
+    pub fn set_cliff_scope(&mut self, scope: CliffScope) {
+        self.assert_owner();
+        log!("Cliff scope set to {:?}", scope);
+        self.apply_due_changes();
+        self.cliff_scope = scope;
+        self.record_config_version(self.now());
+    }
+
+    pub fn get_cliff_scope(&self) -> CliffScope {
+        self.cliff_scope.clone()
+    }
+
+    /// Sets the cliff of the rewards in nanoseconds, `None` to follow `cliff_time`, and
+    /// whether rewards earned during it are credited once it ends.
+    pub fn set_reward_cliff(&mut self, reward_cliff: Option<U64>, retroactive: bool) {
+        self.assert_owner();
+        assert!(self.cliff_scope.rewards, "The cliff doesn't lock rewards, see set_cliff_scope");
+        assert!(
+            retroactive == self.reward_cliff_retroactive || self.active_stakers == 0,
+            "Retroactive credit can only change while nobody is staked"
+        );
+        if let Some(reward_cliff) = reward_cliff {
+            assert!(
+                reward_cliff.0 < self.reward_interval,
+                "Cliff must be shorter than the reward interval"
+            );
+        }
+        self.apply_due_changes();
+        self.reward_cliff = reward_cliff.map(|cliff| cliff.0);
+        self.reward_cliff_retroactive = retroactive;
+        self.record_config_version(self.now());
+        emit_event(
+            "set_reward_cliff",
+            json!({ "reward_cliff": reward_cliff, "retroactive": retroactive }),
+        );
+    }
+}
+
+impl Farm {
+    /// whether the account's rewards are still locked by the cliff at `time`.
+    pub(crate) fn rewards_locked(&self, account: &Account, time: Timestamp) -> bool {
+        self.cliff_scope.rewards && time <= self.reward_cliff_ends_at(account)
+    }
+
+    pub(crate) fn reward_cliff_ends_at(&self, account: &Account) -> Timestamp {
+        account.deposit_time + self.reward_cliff.unwrap_or(self.cliff_time)
+    }
+
+    /// rewards of the account still locked by the reward cliff at `time`.
+    pub(crate) fn vesting_reward(&self, account: &Account, time: Timestamp) -> Balance {
+        self.reward_earned(account, time).saturating_add(account.vesting_reward)
+    }
+
+    /// Keeps what the account's stake earned so far during a retroactive reward cliff, or
+    /// while the breaker is tripped, as a top-up restarts its accrual.
+    pub(crate) fn settle_vesting_reward(&self, account: &mut Account) {
+        let now = self.accrual_time();
+        if self.rewards_locked(account, now) || self.breaker_tripped_at.is_some() {
+            let earned = self.reward_earned(account, now);
+            account.vesting_reward = account.vesting_reward.saturating_add(earned);
+        }
+    }
+
+    /// time the account's stake earns from, the end of the reward cliff unless rewards
+    /// earned during it are credited.
+    pub(crate) fn accrual_start(&self, account: &Account) -> Timestamp {
+        if self.cliff_scope.rewards && !self.reward_cliff_retroactive {
+            account.last_accrual_time.max(self.reward_cliff_ends_at(account))
+        } else {
+            account.last_accrual_time
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_rewards_exempt_from_cliff() {
+        let mut contract = staked_farm();
+        contract.set_cliff_scope(CliffScope { rewards: false, principal: true });
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time / 2));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(account.reward_balance.0 > 0);
+        contract.claim_all();
+    }
+
+    #[test]
+    fn test_reward_breakdown() {
+        let contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time / 2));
+        let breakdown = contract.get_reward_breakdown(alice().try_into().unwrap()).unwrap();
+        assert_eq!(breakdown.claimable.0, 0);
+        assert!(breakdown.vesting.0 > 0);
+        assert_eq!(breakdown.forfeited_if_exited, breakdown.vesting);
+        assert_eq!(
+            breakdown.vesting_unlocks_at,
+            Some(TimestampView::from(contract.cliff_time + 1))
+        );
+
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let breakdown = contract.get_reward_breakdown(alice().try_into().unwrap()).unwrap();
+        assert_eq!(breakdown.vesting.0, 0);
+        assert_eq!(
+            breakdown.claimable,
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance
+        );
+    }
+
+    #[test]
+    fn test_reward_cliff() {
+        let mut contract = staked_farm();
+        let reward_cliff = contract.cliff_time / 2;
+        contract.set_reward_cliff(Some(U64(reward_cliff)), true);
+        testing_env!(get_context(alice(), env::storage_usage(), reward_cliff * 3 / 2));
+        let retroactive = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(retroactive.reward_balance.0 > 0);
+        contract.claim_all();
+
+        // the principal stays locked for the whole cliff
+        assert!(!contract.is_unlocked(&contract.get_registered_account(&alice()).1));
+    }
+
+    #[test]
+    fn test_reward_cliff_without_credit() {
+        let mut contract = staked_farm();
+        let reward_cliff = contract.cliff_time / 2;
+        contract.set_reward_cliff(Some(U64(reward_cliff)), true);
+        testing_env!(get_context(alice(), env::storage_usage(), reward_cliff * 2));
+        let retroactive = contract.get_account(alice().try_into().unwrap()).unwrap();
+
+        // as if the stake had landed in a farm without retroactive credit
+        contract.reward_cliff_retroactive = false;
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        // the first half isn't credited, up to the rounding of each half
+        let credited = account.reward_balance.0 * 2;
+        assert!(credited.abs_diff(retroactive.reward_balance.0) <= 2 * OBS_PER_REWARD_DENOM);
+    }
+
+    #[test]
+    fn test_top_up_keeps_cliff() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time / 2));
+        let breakdown = contract.get_reward_breakdown(alice().try_into().unwrap()).unwrap();
+        contract.stake_my_obs(U128(500));
+        let after = contract.get_reward_breakdown(alice().try_into().unwrap()).unwrap();
+        assert_eq!(after.vesting, breakdown.vesting);
+
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        // the first half, earned before the top-up, is credited with the second
+        assert!(account.reward_balance.0 > 2 * breakdown.vesting.0);
+        assert!(contract.is_unlocked(&contract.get_registered_account(&alice()).1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Retroactive credit can only change while nobody is staked")]
+    fn test_retroactive_change_with_stakers() {
+        let mut contract = staked_farm();
+        contract.set_reward_cliff(None, false);
+    }
+
+    #[test]
+    #[should_panic(expected = "The cliff doesn't lock rewards, see set_cliff_scope")]
+    fn test_reward_cliff_out_of_scope() {
+        let mut contract = staked_farm();
+        contract.set_cliff_scope(CliffScope { rewards: false, principal: true });
+        contract.set_reward_cliff(Some(U64(contract.cliff_time / 2)), true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Stake is still locked by the cliff")]
+    fn test_principal_still_locked() {
+        let mut contract = staked_farm();
+        contract.set_cliff_scope(CliffScope { rewards: false, principal: true });
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time / 2));
+        contract.unstake_my_obs();
+    }
+
+    #[test]
+    fn test_principal_exempt_from_cliff() {
+        let mut contract = staked_farm();
+        contract.set_cliff_scope(CliffScope { rewards: true, principal: false });
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time / 2);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!((account.obs_balance.0, account.reward_claimed.0), (0, 0));
+    }
+}
@@ -0,0 +1,292 @@
+//! Farm configuration given at init, and registration of the farm on its token contracts.
+//! Registering is a separate payable step, since `new` can't attach the NEAR the token
+//! contracts ask for storage. The configuration is validated up front, and updates of
+//! the same parameters are held to the same bounds.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Promise, PromiseResult};
+
+use crate::apr::YEAR_NANOS;
+use crate::events::emit_event;
+use crate::fee_destination::{FeeDestination, MAX_FEE_BPS};
+use crate::partner_boost::MAX_PARTNER_BOOST_BPS;
+use crate::streak::MAX_STREAK_BONUS_BPS;
+use crate::vip_auction::MAX_VIP_BOOST_BPS;
+use crate::*;
+
+pub const DEFAULT_REWARD_RATE: Balance = 1800;
+/// 10 days, in nanoseconds like every timestamp it is compared with.
+pub const DEFAULT_CLIFF_TIME: Timestamp = 10 * 24 * 3600 * 1_000_000_000;
+/// 365 days, in nanoseconds.
+pub const DEFAULT_REWARD_INTERVAL: Timestamp = YEAR_NANOS;
+
+/// shortest reward interval, a day in nanoseconds.
+pub const MIN_REWARD_INTERVAL: Timestamp = 24 * 3600 * 1_000_000_000;
+
+/// largest total reward weight the accrual is bounded for, in obs units.
+pub const MAX_ACCRUAL_WEIGHT: Balance = 1_000_000_000_000;
+
+/// longest accrual the bound holds for, in reward intervals. Longer ones saturate.
+pub const MAX_ACCRUAL_INTERVALS: Balance = 100;
+
+/// largest multiplier an accrual can get: the partner boost, the streak bonus and the VIP
+/// slot on top of the base rate.
+const MAX_ACCRUAL_BOOST_BPS: Balance = MAX_PARTNER_BOOST_BPS as Balance
+    + MAX_STREAK_BONUS_BPS as Balance
+    + (MAX_VIP_BOOST_BPS as Balance - BPS_DENOM);
+
+/// largest reward rate for which `reward_earned`, i.e. weight × rate × intervals ×
+/// `OBS_PER_REWARD_DENOM` × boost, fits in u128 for the accrual bounds above.
+pub const MAX_REWARD_RATE: Balance = u128::MAX
+    / (MAX_ACCRUAL_WEIGHT * MAX_ACCRUAL_INTERVALS * OBS_PER_REWARD_DENOM)
+    / MAX_ACCRUAL_BOOST_BPS
+    * BPS_DENOM;
+
+/// Economic parameters left out take their defaults.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmConfig {
+    pub owner_id: ValidAccountId,
+    pub obs_token_account_id: ValidAccountId,
+    pub reward_token_account_id: ValidAccountId,
+    pub reward_rate: Option<U128>,
+    /// fees on stakes and unstakes in basis points, none by default.
+    pub entry_fee_bps: Option<u32>,
+    pub exit_fee_bps: Option<u32>,
+    /// in nanoseconds, like `reward_interval`.
+    pub cliff_time: Option<U64>,
+    pub reward_interval: Option<U64>,
+    pub reward_weight: Option<RewardWeight>,
+    pub fee_destination: Option<FeeDestination>,
+}
+
+impl FarmConfig {
+    pub fn new(
+        owner_id: ValidAccountId,
+        obs_token_account_id: ValidAccountId,
+        reward_token_account_id: ValidAccountId,
+    ) -> Self {
+        Self {
+            owner_id,
+            obs_token_account_id,
+            reward_token_account_id,
+            reward_rate: None,
+            entry_fee_bps: None,
+            exit_fee_bps: None,
+            cliff_time: None,
+            reward_interval: None,
+            reward_weight: None,
+            fee_destination: None,
+        }
+    }
+
+    pub fn reward_rate(&self) -> Balance {
+        self.reward_rate.map_or(DEFAULT_REWARD_RATE, |rate| rate.0)
+    }
+
+    pub fn entry_fee_bps(&self) -> u32 {
+        self.entry_fee_bps.unwrap_or(0)
+    }
+
+    pub fn exit_fee_bps(&self) -> u32 {
+        self.exit_fee_bps.unwrap_or(0)
+    }
+
+    pub fn cliff_time(&self) -> Timestamp {
+        self.cliff_time.map_or(DEFAULT_CLIFF_TIME, |time| time.0)
+    }
+
+    pub fn reward_interval(&self) -> Timestamp {
+        self.reward_interval.map_or(DEFAULT_REWARD_INTERVAL, |interval| interval.0)
+    }
+
+    pub fn reward_weight(&self) -> RewardWeight {
+        self.reward_weight.clone().unwrap_or_default()
+    }
+
+    pub fn fee_destination(&self) -> FeeDestination {
+        self.fee_destination.unwrap_or(FeeDestination::Treasury)
+    }
+
+    pub fn validate(&self) {
+        let farm_id = env::current_account_id();
+        for token_id in &[&self.obs_token_account_id, &self.reward_token_account_id] {
+            assert_ne!(token_id.as_ref(), &farm_id, "The farm can't be its own token");
+            assert_ne!(token_id, &&self.owner_id, "The owner can't be a token contract");
+        }
+        assert_reward_rate(self.reward_rate());
+        for fee_bps in &[self.entry_fee_bps(), self.exit_fee_bps()] {
+            assert!(*fee_bps <= MAX_FEE_BPS, "Fee is above {} bps", MAX_FEE_BPS);
+        }
+        assert!(
+            self.reward_interval() >= MIN_REWARD_INTERVAL,
+            "Reward interval must be at least a day"
+        );
+        assert!(
+            self.cliff_time() < self.reward_interval(),
+            "Cliff must be shorter than the reward interval"
+        );
+        self.reward_weight().validate();
+        assert!(
+            self.fee_destination()
+                .allowed(self.obs_token_account_id == self.reward_token_account_id),
+            "Fees are only recycled as rewards when the obs token is the reward token"
+        );
+    }
+}
+
+pub(crate) fn assert_reward_rate(rate: Balance) {
+    assert!(rate <= MAX_REWARD_RATE, "Reward rate is above {}", MAX_REWARD_RATE);
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Registers the farm on the obs and reward token contracts, splitting the attached
+    /// NEAR between them. Anyone can pay for it; whatever the tokens refund stays with the
+    /// farm. Returns whether every registration went through.
+    #[payable]
+    pub fn register_on_tokens(&mut self) -> Promise {
+        let mut token_ids = vec![self.obs_token_account_id.clone()];
+        if !self.is_single_token() {
+            token_ids.push(self.reward_token_account_id.clone());
+        }
+        let deposit = env::attached_deposit() / token_ids.len() as Balance;
+        assert!(deposit > 0, "Attach NEAR for the token storage deposits");
+        let register = |token_id: &AccountId| {
+            ext_storage_management::storage_deposit(
+                Some(env::current_account_id()),
+                Some(true),
+                token_id,
+                deposit,
+                GAS_FOR_ACCOUNT_REGISTRATION,
+            )
+        };
+        let promise = token_ids
+            .iter()
+            .skip(1)
+            .fold(register(&token_ids[0]), |promise, token_id| promise.and(register(token_id)));
+        promise.then(ext_self::on_register_on_tokens(
+            token_ids,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    #[private]
+    pub fn on_register_on_tokens(&mut self, token_ids: Vec<AccountId>) -> bool {
+        let failed: Vec<AccountId> = token_ids
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| {
+                !matches!(env::promise_result(*i as u64), PromiseResult::Successful(_))
+            })
+            .map(|(_, token_id)| token_id)
+            .collect();
+        emit_event("register_on_tokens", json!({ "failed": failed }));
+        failed.is_empty()
+    }
+}
+
+#[ext_contract(ext_storage_management)]
+pub trait TokenStorageManagement {
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance;
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, obs, reward};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_register_on_tokens() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 2 * 10u128.pow(22);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_on_tokens();
+        // both storage deposits and the callback
+        assert_eq!(get_created_receipts().len(), 3);
+
+        let results = vec![PromiseResult::Successful(vec![]), PromiseResult::Failed];
+        let context = get_context(env::current_account_id(), env::storage_usage(), 0);
+        testing_env!(context, Default::default(), Default::default(), Default::default(), results);
+        assert!(!contract.on_register_on_tokens(vec![obs().into(), "reward.near".to_string()]));
+    }
+
+    #[test]
+    #[should_panic(expected = "The owner can't be a token contract")]
+    fn test_owner_is_not_a_token() {
+        testing_env!(get_context(alice(), 0, 0));
+        Farm::new(FarmConfig::new(obs(), obs(), alice().try_into().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cliff must be shorter than the reward interval")]
+    fn test_cliff_within_reward_interval() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut config = FarmConfig::new(alice().try_into().unwrap(), obs(), obs());
+        config.cliff_time = Some(U64(DEFAULT_REWARD_INTERVAL));
+        Farm::new(config);
+    }
+
+    #[test]
+    #[should_panic(expected = "Reward interval must be at least a day")]
+    fn test_reward_interval_in_nanoseconds() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut config = FarmConfig::new(alice().try_into().unwrap(), obs(), obs());
+        config.cliff_time = Some(U64(0));
+        config.reward_interval = Some(U64(24 * 3600));
+        Farm::new(config);
+    }
+
+    #[test]
+    fn test_max_rate_accrues() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut config = FarmConfig::new(alice().try_into().unwrap(), obs(), reward());
+        config.reward_rate = Some(U128(MAX_REWARD_RATE));
+        let mut contract = Farm::new(config);
+        contract.register_account();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(MAX_ACCRUAL_WEIGHT));
+
+        let horizon = MAX_ACCRUAL_INTERVALS as Timestamp * DEFAULT_REWARD_INTERVAL;
+        testing_env!(get_context(alice(), env::storage_usage(), horizon));
+        let earned = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        assert!(earned > 0 && earned < u128::MAX);
+
+        // past the bounds the accrual saturates instead of failing
+        testing_env!(get_context(alice(), env::storage_usage(), horizon));
+        contract.stake_my_obs(U128(10u128.pow(30)));
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * horizon));
+        let earned = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        assert_eq!(earned, u128::MAX);
+    }
+
+    #[test]
+    #[should_panic(expected = "Reward rate is above")]
+    fn test_decay_rate_in_range() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_emission_schedule(
+            EmissionSchedule::LinearDecay {
+                start: U64(0),
+                end: U64(1),
+                initial_rate: U128(MAX_REWARD_RATE + 1),
+                final_rate: U128(0),
+            },
+            None,
+        );
+    }
+}
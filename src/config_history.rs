@@ -0,0 +1,141 @@
+//! Versions of the reward parameters, so how rewards were computed over any past period can
+//! be reconstructed from chain state, and disputes about a rate changing under a staker can
+//! be settled without an archival indexer. A version holds every parameter that weighs in
+//! accrual and fees, with the time it took effect: announced changes at their
+//! `effective_at`, other parameters when they were set. Every version is kept.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// most versions `get_config_history` returns at once.
+const MAX_CONFIG_HISTORY_PAGE: u64 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardConfig {
+    pub reward_rate: U128,
+    pub emission_schedule: EmissionSchedule,
+    pub reward_interval: U64,
+    pub reward_weight: RewardWeight,
+    pub cliff_time: U64,
+    pub cliff_scope: CliffScope,
+    pub reward_cliff: Option<U64>,
+    pub reward_cliff_retroactive: bool,
+    pub stake_warm_up: U64,
+    pub harvest_bounty_bps: u32,
+    pub entry_fee_bps: u32,
+    pub exit_fee_bps: u32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ConfigVersion {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub activated_at: Timestamp,
+    pub config: RewardConfig,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ConfigHistory {
+    versions: Vector<ConfigVersion>,
+    /// emission changes in effect that are recorded. They stay among the scheduled changes,
+    /// see `changes`.
+    pub(crate) emission_changes: u64,
+}
+
+impl ConfigHistory {
+    pub fn new() -> Self {
+        Self { versions: Vector::new(StorageKey::ConfigVersions), emission_changes: 0 }
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Up to `limit` versions from the one in effect at `from` on, oldest first.
+    pub fn get_config_history(&self, from: Option<U64>, limit: Option<u64>) -> Vec<ConfigVersion> {
+        let versions = &self.config_history.versions;
+        let from = from.map_or(0, |from| from.0);
+        let limit = limit.unwrap_or(MAX_CONFIG_HISTORY_PAGE).min(MAX_CONFIG_HISTORY_PAGE);
+        // versions are recorded in time order, so the first one after `from` is found by
+        // bisection, and the one before it was in effect at `from`
+        let (mut low, mut high) = (0, versions.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if versions.get(middle).unwrap().activated_at <= from {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        let first = low.saturating_sub(1);
+        (first..versions.len().min(first + limit))
+            .map(|index| versions.get(index).unwrap())
+            .collect()
+    }
+}
+
+impl Farm {
+    /// Records the parameters in effect at `activated_at`, unless they didn't change.
+    /// Setters apply the changes due before changing a parameter, so that their versions
+    /// are recorded first.
+    pub(crate) fn record_config_version(&mut self, activated_at: Timestamp) {
+        let config = RewardConfig {
+            reward_rate: self.reward_rate.into(),
+            emission_schedule: self.emission_schedule_at(activated_at).clone(),
+            reward_interval: self.reward_interval.into(),
+            reward_weight: self.reward_weight.clone(),
+            cliff_time: self.cliff_time.into(),
+            cliff_scope: self.cliff_scope.clone(),
+            reward_cliff: self.reward_cliff.map(U64),
+            reward_cliff_retroactive: self.reward_cliff_retroactive,
+            stake_warm_up: self.stake_warm_up.into(),
+            harvest_bounty_bps: self.harvest_bounty_bps,
+            entry_fee_bps: self.entry_fee_bps,
+            exit_fee_bps: self.exit_fee_bps,
+        };
+        let versions = &mut self.config_history.versions;
+        if !versions.is_empty() && versions.get(versions.len() - 1).unwrap().config == config {
+            return;
+        }
+        versions.push(&ConfigVersion { activated_at, config });
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::changes::ParameterChange;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_config_history() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_harvest_bounty(50, Some(U64(100)));
+        let schedule = EmissionSchedule::Controlled { rate: U128(900) };
+        let change = ParameterChange::EmissionSchedule(schedule.clone());
+        contract.schedule_change(Some(U64(200)), change);
+        testing_env!(get_context(alice(), env::storage_usage(), 300));
+        contract.set_stake_warm_up(U64(1_000));
+        contract.set_stake_warm_up(U64(1_000));
+
+        let history = contract.get_config_history(None, None);
+        let activations: Vec<Timestamp> =
+            history.iter().map(|version| version.activated_at).collect();
+        assert_eq!(activations, vec![0, 100, 200, 300]);
+        assert_eq!(history[1].config.harvest_bounty_bps, 50);
+        assert_eq!(history[1].config.emission_schedule, EmissionSchedule::Constant);
+        assert_eq!(history[2].config.emission_schedule, schedule);
+
+        // the version in effect at 150 comes first
+        let history = contract.get_config_history(Some(U64(150)), Some(2));
+        assert_eq!(history.iter().map(|v| v.activated_at).collect::<Vec<_>>(), vec![100, 200]);
+    }
+}
@@ -0,0 +1,185 @@
+//! Human-held campaigns. With `reject_contracts` on, stakes and claims are refused for
+//! accounts acting through a contract. The farm can't read another account's code, so the
+//! check is a heuristic: the account holding the stake, the owner for a position, must have
+//! signed the transaction itself. A contract staking or claiming for itself, a stake for a
+//! beneficiary and a relayed claim or harvest are all refused, on every path paying out
+//! rewards. Operators can deny accounts by name
+//! and approve integrations such as DAOs and custodians, which pass whoever signed.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ContractListing {
+    /// refused even when signing for itself.
+    Denied,
+    /// allowed whoever signed, for approved integrations.
+    Approved,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Turns the refusal of stakes and claims from contract accounts on or off.
+    pub fn set_reject_contracts(&mut self, enabled: bool) {
+        self.assert_role(Role::Operator);
+        self.reject_contracts = enabled;
+        emit_event("set_reject_contracts", json!({ "enabled": enabled }));
+    }
+
+    /// Denies or approves `account_id`, `None` to leave it to the signer check.
+    pub fn set_contract_listing(
+        &mut self,
+        account_id: ValidAccountId,
+        listing: Option<ContractListing>,
+    ) {
+        self.assert_role(Role::Operator);
+        match listing {
+            Some(listing) => self.contract_listings.insert(account_id.as_ref(), &listing),
+            None => self.contract_listings.remove(account_id.as_ref()),
+        };
+        emit_event("set_contract_listing", json!({ "account_id": account_id, "listing": listing }));
+    }
+
+    pub fn get_contract_listings(&self) -> Vec<(AccountId, ContractListing)> {
+        self.contract_listings.to_vec()
+    }
+}
+
+impl Farm {
+    /// Fails with `CONTRACT_ACCOUNT` if `account_id` can't stake or claim in this pool.
+    pub(crate) fn assert_not_contract(&self, account_id: &AccountId) {
+        if self.refuses_contract(account_id) {
+            let holder_id = self.payout_receiver(account_id);
+            fail(ErrorCode::ContractAccount, json!({ "account_id": holder_id }));
+        }
+    }
+
+    pub(crate) fn refuses_contract(&self, account_id: &AccountId) -> bool {
+        if !self.reject_contracts {
+            return false;
+        }
+        let holder_id = self.payout_receiver(account_id);
+        match self.contract_listings.get(&holder_id) {
+            Some(ContractListing::Approved) => false,
+            Some(ContractListing::Denied) => true,
+            None => holder_id != env::signer_account_id(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use near_sdk::json_types::{U128, U64};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_contract_listings() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_reject_contracts(true);
+        // bob signs in the test context, so bob and bob's positions pass
+        contract.assert_not_contract(&bob());
+        contract.assert_not_contract(&format!("{}#vault", bob()));
+        contract.set_contract_listing(alice().try_into().unwrap(), Some(ContractListing::Approved));
+        contract.assert_not_contract(&alice());
+        assert_eq!(contract.get_contract_listings(), vec![(alice(), ContractListing::Approved)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_denied_account_is_refused() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_reject_contracts(true);
+        contract.set_contract_listing(bob().try_into().unwrap(), Some(ContractListing::Denied));
+        contract.assert_not_contract(&bob());
+    }
+
+    /// a farm refusing contracts, called by alice, who didn't sign.
+    fn gated_farm() -> Farm {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_reject_contracts(true);
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_contract_claim_is_refused() {
+        let mut contract = gated_farm();
+        contract.claim_all();
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_contract_claim_and_stake_is_refused() {
+        let mut contract = gated_farm();
+        contract.claim_and_stake_into("farm2.near".try_into().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_relayed_harvest_is_refused() {
+        let mut contract = gated_farm();
+        contract.harvest_for(alice().try_into().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_contract_converted_claim_is_refused() {
+        let mut contract = gated_farm();
+        contract.claim_converted("wrap.near".try_into().unwrap(), U128(1));
+    }
+
+    #[cfg(feature = "grants")]
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_contract_granted_claim_is_refused() {
+        let mut contract = gated_farm();
+        contract.claim_granted(U128(500), U64(1), U64(100), vec![0; 64].into());
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_contract_period_claim_is_refused() {
+        let mut contract = gated_farm();
+        contract.claim_period_rewards(U64(0));
+    }
+
+    #[test]
+    fn test_contract_unstake_keeps_rewards() {
+        let mut contract = gated_farm();
+        let reward_balance =
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        assert!(reward_balance.0 > 0);
+        contract.unstake_my_obs();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 0);
+        assert_eq!(account.reward_balance, reward_balance);
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_ACCOUNT")]
+    fn test_contract_exit_is_refused() {
+        let mut contract = gated_farm();
+        contract.exit(None);
+    }
+}
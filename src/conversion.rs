@@ -0,0 +1,214 @@
+//! Claims paid out in another token. The owner configures a DEX pool per output token, such
+//! as wNEAR or USDC, and `claim_converted` sends the claimed rewards to the DEX through
+//! `ft_transfer_call` with a swap request for the claimer, bounded by the minimum output
+//! the claimer passes. Rewards the DEX doesn't take, all of them if the swap fails, are paid
+//! out in the reward token instead.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, near_bindgen, Gas, PromiseResult};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// most output tokens with a route at once.
+pub const MAX_CLAIM_ROUTES: u64 = 4;
+
+/// DEX pool swapping reward tokens into an output token.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimRoute {
+    pub dex_id: AccountId,
+    pub pool_id: u64,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Routes claims into `token_id` through `route`, `None` removing the route.
+    pub fn set_claim_route(&mut self, token_id: ValidAccountId, route: Option<ClaimRoute>) {
+        self.assert_owner();
+        match &route {
+            Some(route) => {
+                assert!(env::is_valid_account_id(route.dex_id.as_bytes()), "Invalid DEX account");
+                assert!(
+                    self.claim_routes.get(token_id.as_ref()).is_some()
+                        || self.claim_routes.len() < MAX_CLAIM_ROUTES,
+                    "Too many claim routes"
+                );
+                self.claim_routes.insert(token_id.as_ref(), route);
+            }
+            None => {
+                self.claim_routes.remove(token_id.as_ref());
+            }
+        }
+        emit_event("set_claim_route", json!({ "token_id": token_id, "route": route }));
+    }
+
+    pub fn get_claim_routes(&self) -> Vec<(AccountId, ClaimRoute)> {
+        self.claim_routes.to_vec()
+    }
+
+    /// Claims all of the predecessor's rewards and swaps them into `token_id`, receiving at
+    /// least `min_amount_out`. Rewards the swap doesn't take are paid in the reward token,
+    /// as is a claim that has to wait in the queue.
+    #[payable]
+    pub fn claim_converted(
+        &mut self,
+        token_id: ValidAccountId,
+        min_amount_out: U128,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_frozen(&account_id);
+        self.assert_not_contract(&account_id);
+        let route = self.claim_routes.get(token_id.as_ref()).expect("No claim route to the token");
+        let (account_id_hash, mut account) = self.get_mut_account(&account_id);
+        let amount = account.reward_balance;
+        if amount == 0 {
+            fail(ErrorCode::NothingToClaim, json!({}));
+        }
+        self.assert_min_claim(amount);
+        account.reward_balance = 0;
+        account.reward_claimed += amount;
+        self.save_account(&account_id_hash, &account);
+        self.notify_position_hook(&account_id, &account, PositionEvent::RewardClaimed, amount);
+        let promise = self.pay_or_queue_reward(&account_id, amount, |farm| {
+            farm.start_reward_payout(amount);
+            farm.assert_prepaid_gas(
+                farm.token_gas(&farm.reward_token_account_id).ft_transfer_call.0
+                    + farm.conversion_callback_gas(),
+            );
+            emit_event(
+                "claim_converted",
+                json!({
+                    "account_id": account_id,
+                    "amount": U128(amount),
+                    "token_id": token_id,
+                    "min_amount_out": min_amount_out,
+                }),
+            );
+
+            let gas_mark = farm.gas_mark();
+            let swap = json!({
+                "pool_id": route.pool_id,
+                "token_in": farm.reward_token_account_id,
+                "token_out": token_id,
+                "min_amount_out": min_amount_out,
+                "receiver_id": account_id,
+            });
+            let promise = ext_fungible_token::ft_transfer_call(
+                route.dex_id,
+                amount.into(),
+                None,
+                swap.to_string(),
+                &farm.reward_token_account_id,
+                1,
+                farm.token_gas(&farm.reward_token_account_id).ft_transfer_call.0,
+            )
+            .then(ext_self::on_claim_conversion(
+                account_id.clone(),
+                amount.into(),
+                token_id.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                farm.conversion_callback_gas(),
+            ));
+            farm.record_gas(GasPhase::Promises, gas_mark);
+            promise
+        });
+        self.flush_gas_profile("claim_converted");
+        promise.map_or(PromiseOrValue::Value(U128(0)), PromiseOrValue::Promise)
+    }
+
+    /// Resolves `claim_converted`, paying out in the reward token whatever the DEX didn't
+    /// swap. Returns the amount swapped.
+    #[private]
+    pub fn on_claim_conversion(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        token_id: AccountId,
+    ) -> U128 {
+        self.resolve_reward_payout();
+        let swapped = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).map_or(0, |used| used.0)
+            }
+            _ => 0,
+        };
+        let swapped = swapped.min(amount.0);
+        let unswapped = amount.0 - swapped;
+        let reward_token_id = self.reward_token_account_id.clone();
+        self.record_claim(&account_id, swapped, reward_token_id, Some(token_id), None);
+        if unswapped > 0 {
+            self.ledger.post(LedgerAccount::Claimed, LedgerAccount::Reserve, unswapped);
+            emit_event(
+                "claim_conversion_failed",
+                json!({ "account_id": account_id, "amount": U128(unswapped) }),
+            );
+            self.internal_send_reward(&account_id, unswapped);
+        }
+        swapped.into()
+    }
+}
+
+impl Farm {
+    /// gas of the `on_claim_conversion` callback, including the fallback payout.
+    fn conversion_callback_gas(&self) -> Gas {
+        GAS_FOR_RESOLVE_TRANSFER
+            + self.token_gas(&self.reward_token_account_id).ft_transfer.0
+            + GAS_FOR_RESOLVE_TRANSFER
+            + self.payout_check_gas()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn wnear() -> ValidAccountId {
+        "wrap.near".try_into().unwrap()
+    }
+
+    fn routed_farm() -> Farm {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_claim_route(wnear(), Some(ClaimRoute { dex_id: bob(), pool_id: 7 }));
+        contract
+    }
+
+    #[test]
+    fn test_failed_conversion_pays_native() {
+        let mut contract = routed_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let reserve = contract.reward_reserve();
+        contract.claim_converted(wnear(), U128(1));
+        let claimed = reserve - contract.reward_reserve();
+        assert!(claimed > 0);
+
+        testing_env!(
+            get_context(env::current_account_id(), env::storage_usage(), contract.cliff_time + 1),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&U128(0)).unwrap())]
+        );
+        assert_eq!(contract.on_claim_conversion(alice(), U128(claimed), wnear().into()).0, 0);
+        // paid out again, in the reward token
+        assert_eq!(contract.reward_reserve(), reserve - claimed);
+        assert_eq!(contract.get_stats().total_reward_claimed.0, claimed);
+    }
+
+    #[test]
+    #[should_panic(expected = "No claim route to the token")]
+    fn test_claim_without_route() {
+        let mut contract = routed_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.claim_converted("usdc.near".try_into().unwrap(), U128(1));
+    }
+}
@@ -0,0 +1,175 @@
+//! Everything a status page needs in one view call, so static dashboards render from a
+//! single RPC request. The farm doesn't index its accounts, so it keeps a short leaderboard
+//! of the largest stakes instead, updated whenever a stake changes. Events are only logged,
+//! never stored, so the dashboard has no event history.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::breaker::BreakerStatus;
+use crate::decommission::DecommissionStatus;
+use crate::fee_destination::FeeDestination;
+use crate::rate_controller::RateController;
+use crate::*;
+
+/// length of the top stakers leaderboard, small enough to keep the farm state cheap to load.
+pub const MAX_TOP_STAKERS: usize = 10;
+
+/// the farm's staking parameters.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DashboardConfig {
+    pub obs_token_account_id: AccountId,
+    pub reward_token_account_id: AccountId,
+    pub entry_fee_bps: u32,
+    pub exit_fee_bps: u32,
+    pub cliff_time: U64,
+    pub cliff_scope: CliffScope,
+    pub reward_cliff: U64,
+    pub reward_cliff_retroactive: bool,
+    pub reward_rate: U128,
+    pub reward_weight: RewardWeight,
+    pub emission_schedule: EmissionSchedule,
+    pub rate_controller: Option<RateController>,
+    pub large_unstake_delay: U64,
+    pub harvest_bounty_bps: u32,
+    pub min_claim: U128,
+    pub stake_warm_up: U64,
+    pub fee_destination: FeeDestination,
+    pub exit_fee_destination: FeeDestination,
+    pub registration_fee: Option<U128>,
+    pub reject_contracts: bool,
+    pub deposits_paused: bool,
+    pub decommissioned: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmHealth {
+    pub reward_reserve: U128,
+    pub runway: Option<U64>,
+    pub breaker: BreakerStatus,
+    pub queued_claims: U64,
+    pub decommission: DecommissionStatus,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PublicDashboard {
+    pub metadata: Option<FarmMetadata>,
+    pub config: DashboardConfig,
+    pub stats: FarmStats,
+    pub health: FarmHealth,
+    pub apr: U128,
+    pub apy: U128,
+    /// largest stakes, highest first.
+    pub top_stakers: Vec<(AccountId, U128)>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Reads only the farm state and a few fixed-size records, so it stays well within the
+    /// gas of a view call.
+    pub fn get_public_dashboard(&self) -> PublicDashboard {
+        PublicDashboard {
+            metadata: self.get_farm_metadata(),
+            config: self.dashboard_config(),
+            stats: self.get_stats(),
+            health: FarmHealth {
+                reward_reserve: self.reward_reserve().into(),
+                runway: self.get_runway(),
+                breaker: self.get_breaker_status(),
+                queued_claims: self.claim_queue.len().into(),
+                decommission: self.get_decommission_status(),
+            },
+            apr: self.get_apr(),
+            apy: self.get_apy(),
+            top_stakers: self.get_top_stakers(),
+        }
+    }
+
+    pub fn get_top_stakers(&self) -> Vec<(AccountId, U128)> {
+        self.top_stakers
+            .iter()
+            .map(|(account_id, stake)| (account_id.clone(), U128(*stake)))
+            .collect()
+    }
+}
+
+impl Farm {
+    fn dashboard_config(&self) -> DashboardConfig {
+        DashboardConfig {
+            obs_token_account_id: self.obs_token_account_id.clone(),
+            reward_token_account_id: self.reward_token_account_id.clone(),
+            entry_fee_bps: self.entry_fee_bps,
+            exit_fee_bps: self.exit_fee_bps,
+            cliff_time: self.cliff_time.into(),
+            cliff_scope: self.cliff_scope.clone(),
+            reward_cliff: self.reward_cliff.unwrap_or(self.cliff_time).into(),
+            reward_cliff_retroactive: self.reward_cliff_retroactive,
+            reward_rate: self.get_current_reward_rate(),
+            reward_weight: self.reward_weight.clone(),
+            emission_schedule: self.get_emission_schedule(),
+            rate_controller: self.rate_controller.clone(),
+            large_unstake_delay: self.large_unstake_delay.into(),
+            harvest_bounty_bps: self.harvest_bounty_bps,
+            min_claim: self.min_claim.into(),
+            stake_warm_up: self.stake_warm_up.into(),
+            fee_destination: self.fee_destination,
+            exit_fee_destination: self.exit_fee_destination,
+            registration_fee: self.registration_fee.map(U128),
+            reject_contracts: self.reject_contracts,
+            deposits_paused: self.deposits_paused_at.is_some(),
+            decommissioned: self.decommissioned_at.is_some(),
+        }
+    }
+
+    /// Moves `account_id` to its place in the leaderboard after its stake changed.
+    pub(crate) fn rank_staker(&mut self, account_id: &AccountId, stake: Balance) {
+        self.top_stakers.retain(|(ranked_id, _)| ranked_id != account_id);
+        if stake == 0 {
+            return;
+        }
+        let position = self.top_stakers.iter().position(|(_, ranked)| stake > *ranked);
+        match position {
+            Some(position) => self.top_stakers.insert(position, (account_id.clone(), stake)),
+            None => self.top_stakers.push((account_id.clone(), stake)),
+        }
+        self.top_stakers.truncate(MAX_TOP_STAKERS);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm, staked_farm};
+
+    #[test]
+    fn test_public_dashboard() {
+        let contract = staked_farm();
+        let dashboard = contract.get_public_dashboard();
+        assert_eq!(dashboard.top_stakers, vec![(alice(), U128(1000))]);
+        assert_eq!(dashboard.stats.active_stakers.0, 1);
+        assert_eq!(dashboard.config.obs_token_account_id, "obs.near");
+    }
+
+    #[test]
+    fn test_rank_staker() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        for i in 0..MAX_TOP_STAKERS as u128 + 2 {
+            contract.rank_staker(&format!("staker{}.near", i), 100 + i);
+        }
+        contract.rank_staker(&bob(), 150);
+        let top_stakers = contract.get_top_stakers();
+        assert_eq!(top_stakers.len(), MAX_TOP_STAKERS);
+        assert_eq!(top_stakers[0], (bob(), U128(150)));
+        assert_eq!(top_stakers[1].1, U128(111));
+
+        contract.rank_staker(&bob(), 0);
+        assert!(contract.get_top_stakers().iter().all(|(account_id, _)| *account_id != bob()));
+    }
+}
@@ -0,0 +1,225 @@
+//! Token decimals cached from the tokens' `ft_metadata`, and views returning amounts
+//! already scaled by them as decimal strings, e.g. "1234.5678", for frontends and bots.
+//!
+//! The cached reward decimals also back the funding guard: with bounds set, reward token
+//! deposits outside them, typically scaled for the wrong number of decimals, are refused.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, log, near_bindgen, Promise};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+
+use crate::*;
+
+/// largest number of decimals whose scale fits a `Balance`.
+pub const MAX_DECIMALS: u8 = 38;
+
+/// expected size of a single reserve funding, in whole reward tokens.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FundingBounds {
+    pub min_tokens: U128,
+    pub max_tokens: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HumanAccountView {
+    pub obs_balance: String,
+    pub reward_balance: String,
+    pub reward_claimed: String,
+    pub total_staked: String,
+    pub total_withdrawn: String,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HumanFarmStats {
+    pub total_obs_balance: String,
+    pub total_reward_claimed: String,
+    pub total_reward_received: String,
+    pub reward_reserve: String,
+}
+
+/// `amount` in units of `10^decimals`, without trailing zeros.
+pub fn format_amount(amount: Balance, decimals: u8) -> String {
+    let scale = 10u128.pow(decimals.into());
+    let (whole, fraction) = (amount / scale, amount % scale);
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = decimals as usize);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Caches the decimals of the obs and reward tokens from their metadata.
+    pub fn refresh_token_decimals(&mut self) -> Promise {
+        let fetch = |token_id: &AccountId| {
+            ext_fungible_token::ft_metadata(token_id, NO_DEPOSIT, BASE_GAS).then(
+                ext_self::on_token_metadata(
+                    token_id.clone(),
+                    &env::current_account_id(),
+                    NO_DEPOSIT,
+                    BASE_GAS,
+                ),
+            )
+        };
+        if self.is_single_token() {
+            return fetch(&self.obs_token_account_id);
+        }
+        fetch(&self.obs_token_account_id).and(fetch(&self.reward_token_account_id))
+    }
+
+    #[private]
+    pub fn on_token_metadata(
+        &mut self,
+        token_id: AccountId,
+        #[callback] metadata: FungibleTokenMetadata,
+    ) {
+        assert!(metadata.decimals <= MAX_DECIMALS, "Token decimals are out of range");
+        if token_id == self.obs_token_account_id {
+            self.obs_decimals = Some(metadata.decimals);
+        }
+        if token_id == self.reward_token_account_id {
+            self.reward_decimals = Some(metadata.decimals);
+        }
+        log!("Cached {} decimals of @{}", metadata.decimals, token_id);
+    }
+
+    /// Decimals of the obs and reward tokens, once cached.
+    pub fn get_token_decimals(&self) -> (Option<u8>, Option<u8>) {
+        (self.obs_decimals, self.reward_decimals)
+    }
+
+    /// Sets the accepted size of reward fundings, `None` accepting any amount.
+    pub fn set_funding_bounds(&mut self, bounds: Option<FundingBounds>) {
+        self.assert_role(Role::Treasurer);
+        if let Some(bounds) = &bounds {
+            assert!(bounds.min_tokens.0 <= bounds.max_tokens.0, "Invalid funding bounds");
+        }
+        emit_event("set_funding_bounds", json!({ "bounds": bounds }));
+        self.funding_bounds = bounds;
+    }
+
+    pub fn get_funding_bounds(&self) -> Option<FundingBounds> {
+        self.funding_bounds.clone()
+    }
+
+    pub fn get_account_human(&self, account_id: ValidAccountId) -> Option<HumanAccountView> {
+        let (obs, reward) = self.cached_decimals();
+        self.get_account(account_id).map(|account| HumanAccountView {
+            obs_balance: format_amount(account.obs_balance.0, obs),
+            reward_balance: format_amount(account.reward_balance.0, reward),
+            reward_claimed: format_amount(account.reward_claimed.0, reward),
+            total_staked: format_amount(account.total_staked.0, obs),
+            total_withdrawn: format_amount(account.total_withdrawn.0, obs),
+        })
+    }
+
+    pub fn get_stats_human(&self) -> HumanFarmStats {
+        let (obs, reward) = self.cached_decimals();
+        let stats = self.get_stats();
+        HumanFarmStats {
+            total_obs_balance: format_amount(stats.total_obs_balance.0, obs),
+            total_reward_claimed: format_amount(stats.total_reward_claimed.0, reward),
+            total_reward_received: format_amount(stats.total_reward_received.0, reward),
+            reward_reserve: format_amount(self.reward_reserve(), reward),
+        }
+    }
+}
+
+impl Farm {
+    /// Refuses a reserve funding of `amount` outside the funding bounds.
+    pub fn assert_funding_in_bounds(&self, amount: Balance) {
+        let bounds = match &self.funding_bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let decimals = self
+            .reward_decimals
+            .expect("Reward token decimals are not cached, call refresh_token_decimals");
+        let scale = 10u128.pow(decimals.into());
+        let min = bounds.min_tokens.0.saturating_mul(scale);
+        let max = bounds.max_tokens.0.saturating_mul(scale);
+        assert!(
+            (min..=max).contains(&amount),
+            "Funding of {} reward tokens is outside the expected {} to {}, check its decimals",
+            format_amount(amount, decimals),
+            bounds.min_tokens.0,
+            bounds.max_tokens.0
+        );
+    }
+
+    fn cached_decimals(&self) -> (u8, u8) {
+        match (self.obs_decimals, self.reward_decimals) {
+            (Some(obs), Some(reward)) => (obs, reward),
+            _ => env::panic(b"Token decimals are not cached, call refresh_token_decimals"),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, obs, reward, staked_farm};
+    use std::convert::TryInto;
+
+    fn metadata(decimals: u8) -> FungibleTokenMetadata {
+        FungibleTokenMetadata {
+            spec: "ft-1.0.0".to_string(),
+            name: "Token".to_string(),
+            symbol: "TKN".to_string(),
+            icon: None,
+            reference: None,
+            reference_hash: None,
+            decimals,
+        }
+    }
+
+    #[test]
+    fn test_format_amount() {
+        assert_eq!(format_amount(12_345_678, 4), "1234.5678");
+        assert_eq!(format_amount(12_340_000, 4), "1234");
+        assert_eq!(format_amount(5, 3), "0.005");
+        assert_eq!(format_amount(7, 0), "7");
+    }
+
+    #[test]
+    fn test_account_human() {
+        let mut contract = staked_farm();
+        contract.on_token_metadata(obs().into(), metadata(2));
+        contract.on_token_metadata(reward().into(), metadata(24));
+        let account = contract.get_account_human(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance, "10");
+        assert_eq!(contract.get_stats_human().reward_reserve, "1000000");
+    }
+
+    #[test]
+    #[should_panic(expected = "Funding of 0.000001 reward tokens is outside the expected")]
+    fn test_mis_scaled_funding() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.on_token_metadata(reward().into(), metadata(24));
+        contract.set_funding_bounds(Some(FundingBounds {
+            min_tokens: U128(100),
+            max_tokens: U128(10u128.pow(9)),
+        }));
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(10u128.pow(18)), String::new());
+    }
+
+    #[test]
+    #[should_panic(expected = "Token decimals are not cached")]
+    fn test_human_views_need_decimals() {
+        testing_env!(get_context(alice(), 0, 0));
+        new_farm().get_stats_human();
+    }
+}
@@ -0,0 +1,432 @@
+//! Terminal shutdown of the farm. The owner schedules it, and once the timelock passes
+//! `decommission` stops all accrual, unlocks every stake regardless of the cliff and
+//! leaves the farm withdraw-only. Rewards stay claimable until the optional claim window
+//! closes; once it has and the grace period has passed, the remaining reserve can be swept
+//! to the treasury. Without a claim window rewards never expire and the reserve is kept.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, is_promise_success, log, near_bindgen, Gas, Promise, Timestamp};
+
+use crate::bots::BotScope;
+use crate::events::emit_event;
+use crate::*;
+
+/// delay between scheduling and executing the decommission, in nanoseconds.
+pub const DECOMMISSION_TIMELOCK: Timestamp = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// time stakers have to withdraw before the reserve can be swept, in nanoseconds.
+pub const DECOMMISSION_GRACE_PERIOD: Timestamp = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DecommissionStatus {
+    pub scheduled_at: Option<TimestampView>,
+    pub executable_at: Option<TimestampView>,
+    pub decommissioned_at: Option<TimestampView>,
+    pub sweepable_at: Option<TimestampView>,
+    pub claims_expire_at: Option<TimestampView>,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn set_treasury(&mut self, treasury_id: ValidAccountId) {
+        self.assert_owner();
+        self.treasury_id = treasury_id.into();
+        emit_event("set_treasury", json!({ "treasury_id": self.treasury_id }));
+    }
+
+    pub fn schedule_decommission(&mut self) {
+        self.assert_owner();
+        self.assert_not_decommissioned();
+        assert!(self.decommission_scheduled_at.is_none(), "Decommission is already scheduled");
+        let now = self.now();
+        self.decommission_scheduled_at = Some(now);
+        let executable_at = now + DECOMMISSION_TIMELOCK;
+        emit_event(
+            "schedule_decommission",
+            json!({
+                "executable_at": U64(executable_at),
+                "claims_expire_after": self.claim_window.map(|window| U64(executable_at + window)),
+            }),
+        );
+    }
+
+    pub fn cancel_decommission(&mut self) {
+        self.assert_owner();
+        self.assert_not_decommissioned();
+        assert!(self.decommission_scheduled_at.take().is_some(), "Decommission is not scheduled");
+        emit_event("cancel_decommission", json!({}));
+    }
+
+    /// Sets how long after the decommission rewards can be claimed, `None` keeping them
+    /// claimable for good, so the reserve is never swept. Fixed once the farm is
+    /// decommissioned.
+    pub fn set_claim_window(&mut self, window: Option<U64>) {
+        self.assert_owner();
+        self.assert_not_decommissioned();
+        let window = window.map(|window| window.0);
+        if let Some(window) = window {
+            assert!(
+                window <= DECOMMISSION_GRACE_PERIOD,
+                "Claim window can't outlast the grace period"
+            );
+        }
+        emit_event("set_claim_window", json!({ "window": window.map(U64) }));
+        self.claim_window = window;
+    }
+
+    /// Stops the farm for good. Only possible once the timelock has passed.
+    pub fn decommission(&mut self) {
+        self.assert_owner();
+        self.assert_not_decommissioned();
+        let scheduled_at = self.decommission_scheduled_at.expect("Decommission is not scheduled");
+        let now = self.now();
+        assert!(
+            now >= scheduled_at + DECOMMISSION_TIMELOCK,
+            "Decommission timelock has not passed"
+        );
+        self.decommissioned_at = Some(now);
+        emit_event(
+            "decommission",
+            json!({
+                "sweepable_at": U64(now + DECOMMISSION_GRACE_PERIOD),
+                "claims_expire_at": self.claims_expire_at().map(U64),
+            }),
+        );
+    }
+
+    /// Sends whatever is left in the reserve to the treasury after the grace period, once
+    /// unclaimed rewards have expired. The farm's actual reward token balance is checked
+    /// first, so the sweep can never take staked principal when the obs token is also the
+    /// reward token.
+    pub fn sweep_reserve(&mut self) -> Promise {
+        self.assert_owner();
+        self.assert_grace_period_passed();
+        assert!(self.rewards_expired(), "Rewards can still be claimed");
+        assert!(self.reward_reserve() > 0, "Reward reserve is empty");
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id(),
+            &self.reward_token_account_id,
+            NO_DEPOSIT,
+            self.token_gas(&self.reward_token_account_id).ft_balance_of.0,
+        )
+        .then(ext_self::on_sweep_balance(
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            self.sweep_callback_gas(),
+        ))
+    }
+
+    #[private]
+    pub fn on_sweep_balance(&mut self, #[callback] balance: U128) -> Promise {
+        let (principal, fees) = self.principal_held(&self.reward_token_account_id.clone());
+        // queued claims are owed already
+        let amount = self.reward_reserve().saturating_sub(self.claim_queue.outstanding());
+        assert!(amount + principal + fees <= balance.0, "Sweep would dip into staked principal");
+        // the treasury doesn't claim as a staker: its account and receiver are left alone
+        self.start_reward_payout(amount);
+        let treasury_id = self.treasury_id.clone();
+        emit_event("sweep_reserve", json!({ "treasury_id": treasury_id, "amount": U128(amount) }));
+        ext_fungible_token::ft_transfer(
+            treasury_id,
+            amount.into(),
+            None,
+            &self.reward_token_account_id,
+            1,
+            self.token_gas(&self.reward_token_account_id).ft_transfer.0,
+        )
+        .then(ext_self::on_sweep_transfer(
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Returns a sweep that failed to reach the treasury to the reserve.
+    #[private]
+    pub fn on_sweep_transfer(&mut self, amount: U128) {
+        self.resolve_reward_payout();
+        if !is_promise_success() {
+            self.ledger.post(LedgerAccount::Claimed, LedgerAccount::Reserve, amount.0);
+            log!("Sweep of {} to the treasury failed, returned to the reserve", amount.0);
+        }
+    }
+
+    /// Unregisters accounts left with nothing to withdraw once the grace period has passed
+    /// and refunds their storage deposits. Checks at most `limit` registered accounts from
+    /// `from_index` on and returns the index to continue from, `None` once all were checked.
+    /// Accounts with a stake, rewards still claimable or a queued claim are kept, as are
+    /// frozen and liened ones. Unregistering moves the last account into the freed index,
+    /// so an account can land behind the cursor between pages and wait for a later pass.
+    /// The owner and retire-pool bots can call it.
+    pub fn retire_accounts(&mut self, from_index: Option<U64>, limit: U64) -> Option<U64> {
+        self.assert_automation(BotScope::RetirePool);
+        self.assert_grace_period_passed();
+        let mut index = from_index.map_or(0, |from_index| from_index.0);
+        let mut retired = 0;
+        for _ in 0..limit.0 {
+            let account_id = match self.registered_accounts.as_vector().get(index) {
+                Some(account_id) => account_id,
+                None => break,
+            };
+            if let Some((account_id_hash, account)) = self.retirable_account(&account_id) {
+                self.internal_unregister(&account_id, &account_id_hash, &account);
+                retired += 1;
+            } else {
+                index += 1;
+            }
+        }
+        let next_index = Some(index).filter(|index| *index < self.registered_accounts.len());
+        emit_event(
+            "retire_accounts",
+            json!({ "retired": retired, "next_index": next_index.map(U64) }),
+        );
+        next_index.map(U64)
+    }
+
+    pub fn get_decommission_status(&self) -> DecommissionStatus {
+        DecommissionStatus {
+            scheduled_at: self.decommission_scheduled_at.map(TimestampView::from),
+            executable_at: self
+                .decommission_scheduled_at
+                .map(|t| (t + DECOMMISSION_TIMELOCK).into()),
+            decommissioned_at: self.decommissioned_at.map(TimestampView::from),
+            sweepable_at: self.decommissioned_at.map(|t| (t + DECOMMISSION_GRACE_PERIOD).into()),
+            claims_expire_at: self.claims_expire_at().map(TimestampView::from),
+        }
+    }
+}
+
+impl Farm {
+    /// `on_sweep_balance` runs itself and schedules the sweep transfer with its callback.
+    fn sweep_callback_gas(&self) -> Gas {
+        BASE_GAS
+            + self.token_gas(&self.reward_token_account_id).ft_transfer.0
+            + GAS_FOR_RESOLVE_TRANSFER
+    }
+
+    /// gas to attach to `sweep_reserve`.
+    pub(crate) fn sweep_gas(&self) -> Gas {
+        BASE_GAS
+            + self.token_gas(&self.reward_token_account_id).ft_balance_of.0
+            + self.sweep_callback_gas()
+    }
+
+    /// The account of `account_id` with its rewards settled, if it has nothing left to
+    /// withdraw nor claim.
+    fn retirable_account(&mut self, account_id: &AccountId) -> Option<(ShortAccountHash, Account)> {
+        if self.is_frozen(account_id)
+            || self.is_liened(account_id)
+            || self.has_queued_claim(account_id)
+        {
+            return None;
+        }
+        let (account_id_hash, account) = self.get_mut_account(account_id);
+        if account.obs_balance > 0 || account.reward_balance > 0 {
+            return None;
+        }
+        Some((account_id_hash, account))
+    }
+
+    fn assert_grace_period_passed(&self) {
+        let decommissioned_at = self.decommissioned_at.expect("Farm is not decommissioned");
+        assert!(
+            self.now() >= decommissioned_at + DECOMMISSION_GRACE_PERIOD,
+            "Grace period has not passed"
+        );
+    }
+
+    pub(crate) fn claims_expire_at(&self) -> Option<Timestamp> {
+        Some(self.decommissioned_at? + self.claim_window?)
+    }
+
+    pub(crate) fn rewards_expired(&self) -> bool {
+        matches!(self.claims_expire_at(), Some(expires_at) if self.now() >= expires_at)
+    }
+
+    /// Forfeits the rewards of `account` once the claim window has closed. They stay in the
+    /// reserve, which goes to the treasury with the sweep.
+    pub(crate) fn expire_rewards(&mut self, account_id: &AccountId, account: &mut Account) {
+        if account.reward_balance == 0 || !self.rewards_expired() {
+            return;
+        }
+        emit_event(
+            "reward_expired",
+            json!({ "account_id": account_id, "amount": U128(account.reward_balance) }),
+        );
+        account.reward_balance = 0;
+    }
+
+    pub(crate) fn assert_not_decommissioned(&self) {
+        assert!(self.decommissioned_at.is_none(), "Farm is decommissioned");
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::{MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn decommissioned_farm() -> Farm {
+        let mut contract = staked_farm();
+        contract.schedule_decommission();
+        testing_env!(get_context(alice(), env::storage_usage(), DECOMMISSION_TIMELOCK));
+        contract.decommission();
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Decommission timelock has not passed")]
+    fn test_decommission_timelock() {
+        let mut contract = staked_farm();
+        contract.schedule_decommission();
+        testing_env!(get_context(alice(), env::storage_usage(), DECOMMISSION_TIMELOCK - 1));
+        contract.decommission();
+    }
+
+    #[test]
+    fn test_unclaimed_rewards_expire() {
+        let mut contract = staked_farm();
+        // rewards unlock before the decommission
+        contract.cliff_time = DECOMMISSION_TIMELOCK / 2;
+        contract.set_claim_window(Some(U64(DECOMMISSION_TIMELOCK)));
+        contract.schedule_decommission();
+        testing_env!(get_context(alice(), env::storage_usage(), DECOMMISSION_TIMELOCK));
+        contract.decommission();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(account.reward_balance.0 > 0);
+        assert_eq!(account.reward_expires_at, Some((2 * DECOMMISSION_TIMELOCK).into()));
+
+        let mut context = get_context(alice(), env::storage_usage(), 2 * DECOMMISSION_TIMELOCK);
+        context.attached_deposit = 1;
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        assert_eq!(contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0, 0);
+        let reserve = contract.reward_reserve();
+        contract.unstake_my_obs();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 0);
+        assert_eq!(account.reward_claimed.0, 0);
+        assert_eq!(contract.reward_reserve(), reserve);
+    }
+
+    #[test]
+    #[should_panic(expected = "Claim window can't outlast the grace period")]
+    fn test_claim_window_within_grace_period() {
+        let mut contract = staked_farm();
+        contract.set_claim_window(Some(U64(DECOMMISSION_GRACE_PERIOD + 1)));
+    }
+
+    #[test]
+    fn test_decommission_stops_accrual() {
+        let contract = decommissioned_farm();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * DECOMMISSION_TIMELOCK));
+        assert_eq!(
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance,
+            account.reward_balance
+        );
+        assert!(contract.is_unlocked(&contract.get_internal_account(&alice()).1.unwrap()));
+    }
+
+    /// decommissioned farm past its grace period, with bob registered without a stake.
+    fn retirable_farm() -> Farm {
+        let mut contract = decommissioned_farm();
+        let mut context = get_context(bob(), env::storage_usage(), DECOMMISSION_TIMELOCK);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.register_account();
+        let now = DECOMMISSION_TIMELOCK + DECOMMISSION_GRACE_PERIOD;
+        let mut context = get_context(alice(), env::storage_usage(), now);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract
+    }
+
+    #[test]
+    fn test_retire_accounts() {
+        let mut contract = retirable_farm();
+        // alice is still staking
+        assert_eq!(contract.retire_accounts(None, U64(1)), Some(U64(1)));
+        assert!(contract.account_exists(alice().try_into().unwrap()));
+        assert_eq!(contract.retire_accounts(Some(U64(1)), U64(1)), None);
+        assert!(!contract.account_exists(bob().try_into().unwrap()));
+        assert_eq!(contract.registered_accounts.to_vec(), vec![alice()]);
+    }
+
+    #[test]
+    fn test_retire_keeps_claimable_rewards() {
+        let mut contract = retirable_farm();
+        let (account_id_hash, account) = contract.get_internal_account(&bob());
+        let mut account = account.unwrap();
+        account.reward_balance = 100;
+        contract.save_account(&account_id_hash, &account);
+        assert_eq!(contract.retire_accounts(None, U64(10)), None);
+        assert!(contract.account_exists(bob().try_into().unwrap()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Grace period has not passed")]
+    fn test_retire_accounts_before_grace_period() {
+        let mut contract = decommissioned_farm();
+        contract.retire_accounts(None, U64(10));
+    }
+
+    #[test]
+    fn test_failed_sweep_returns_to_reserve() {
+        let mut contract = decommissioned_farm();
+        let reserve = contract.reward_reserve();
+        // the treasury is alice, who is also a registered staker
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        testing_env!(get_context(alice(), env::storage_usage(), DECOMMISSION_TIMELOCK));
+        contract.on_sweep_balance(U128(10u128.pow(31)));
+        assert_eq!(contract.reward_reserve(), 0);
+
+        testing_env!(
+            get_context(alice(), env::storage_usage(), DECOMMISSION_TIMELOCK),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_sweep_transfer(U128(reserve));
+        assert_eq!(contract.reward_reserve(), reserve);
+        let restored = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(restored.reward_balance, account.reward_balance);
+        assert_eq!(restored.reward_claimed, account.reward_claimed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Rewards can still be claimed")]
+    fn test_sweep_keeps_claimable_rewards() {
+        let mut contract = decommissioned_farm();
+        let now = DECOMMISSION_TIMELOCK + DECOMMISSION_GRACE_PERIOD;
+        testing_env!(get_context(alice(), env::storage_usage(), now));
+        contract.sweep_reserve();
+    }
+
+    #[test]
+    fn test_sweep_after_claims_expire() {
+        let mut contract = staked_farm();
+        contract.set_claim_window(Some(U64(DECOMMISSION_GRACE_PERIOD)));
+        contract.schedule_decommission();
+        testing_env!(get_context(alice(), env::storage_usage(), DECOMMISSION_TIMELOCK));
+        contract.decommission();
+        let now = DECOMMISSION_TIMELOCK + DECOMMISSION_GRACE_PERIOD;
+        testing_env!(get_context(alice(), env::storage_usage(), now));
+        contract.sweep_reserve();
+    }
+
+    #[test]
+    #[should_panic(expected = "Farm is decommissioned")]
+    fn test_decommissioned_farm_rejects_stakes() {
+        let mut contract = decommissioned_farm();
+        contract.stake_my_obs(U128(1000));
+    }
+}
@@ -0,0 +1,180 @@
+//! Reward programs funded up front and released on a schedule. Treasurers fund a locked
+//! reserve with `FUND_LOCKED_MSG`, and every `epoch_length` the drip moves
+//! `amount_per_epoch` of it to the reserve rewards are paid from, so only the current
+//! allocation can be distributed. An epoch is only released once the locked reserve covers
+//! its whole allocation; epochs it fell short of are released as soon as it is topped up.
+//! Nothing else takes tokens out of the locked reserve, the
+//! owner included. The schedule can only change with `DRIP_CHANGE_NOTICE` announced ahead,
+//! see `changes`, so stakers see any slowdown coming.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+
+use crate::changes::ParameterChange;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// notice drip changes are announced with once a drip is running, a week.
+pub const DRIP_CHANGE_NOTICE: Timestamp = 7 * 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DripSchedule {
+    pub epoch_length: U64,
+    pub amount_per_epoch: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Drip {
+    pub schedule: DripSchedule,
+    pub started_at: Timestamp,
+    /// epochs since `started_at` whose allocation was released.
+    pub released_epochs: u64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DripView {
+    pub schedule: DripSchedule,
+    pub started_at: TimestampView,
+    pub locked: U128,
+    pub next_release_at: TimestampView,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Drips the locked reserve by `schedule` from `effective_at`. The first schedule
+    /// starts right away; later ones need `DRIP_CHANGE_NOTICE`.
+    pub fn set_drip_schedule(&mut self, schedule: DripSchedule, effective_at: Option<U64>) {
+        self.assert_role(Role::Treasurer);
+        assert!(schedule.epoch_length.0 > 0, "Drip epochs can't be empty");
+        assert!(schedule.amount_per_epoch.0 > 0, "Drip must release something");
+        if self.drip.is_some() {
+            let notice_ends = self.now() + DRIP_CHANGE_NOTICE;
+            assert!(
+                matches!(effective_at, Some(effective_at) if effective_at.0 >= notice_ends),
+                "Drip changes must be announced a week ahead"
+            );
+        }
+        let effective_at =
+            self.schedule_change(effective_at, ParameterChange::DripSchedule(schedule.clone()));
+        emit_event(
+            "set_drip_schedule",
+            json!({ "schedule": schedule, "effective_at": U64(effective_at) }),
+        );
+    }
+
+    pub fn get_drip(&self) -> Option<DripView> {
+        self.drip.as_ref().map(|drip| DripView {
+            schedule: drip.schedule.clone(),
+            started_at: drip.started_at.into(),
+            locked: self.ledger.balance(LedgerAccount::Locked).into(),
+            next_release_at: (drip.started_at
+                + (drip.released_epochs + 1) * drip.schedule.epoch_length.0)
+                .into(),
+        })
+    }
+
+    /// Releases the allocations of the epochs passed. Accounts being touched release them
+    /// too; this lets keepers top up the reserve while nobody calls the farm.
+    pub fn release_drip(&mut self) -> U128 {
+        self.apply_due_changes();
+        let released = self.release_drip_until(self.now());
+        self.pay_queued_claims();
+        released.into()
+    }
+}
+
+impl Farm {
+    /// Moves the allocations of the epochs completed by `time` the locked reserve covers to
+    /// the reserve, returning the amount moved.
+    pub(crate) fn release_drip_until(&mut self, time: Timestamp) -> Balance {
+        let drip = match &mut self.drip {
+            Some(drip) => drip,
+            None => return 0,
+        };
+        let epochs = time.saturating_sub(drip.started_at) / drip.schedule.epoch_length.0;
+        if epochs <= drip.released_epochs {
+            return 0;
+        }
+        let amount_per_epoch = drip.schedule.amount_per_epoch.0;
+        let covered = self.ledger.balance(LedgerAccount::Locked) / amount_per_epoch;
+        // no more than the epochs due, which fit in a u64
+        let paid_epochs = u128::from(epochs - drip.released_epochs).min(covered);
+        drip.released_epochs += paid_epochs as u64;
+        let amount = paid_epochs * amount_per_epoch;
+        self.ledger.post(LedgerAccount::Locked, LedgerAccount::Reserve, amount);
+        if amount > 0 {
+            emit_event("release_drip", json!({ "amount": U128(amount) }));
+        }
+        amount
+    }
+
+    /// Switches the drip to `schedule` at `effective_at`, releasing what the previous
+    /// schedule owed until then.
+    pub(crate) fn apply_drip_change(&mut self, schedule: DripSchedule, effective_at: Timestamp) {
+        self.release_drip_until(effective_at);
+        self.drip = Some(Drip { schedule, started_at: effective_at, released_epochs: 0 });
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, reward};
+
+    fn schedule(amount_per_epoch: Balance) -> DripSchedule {
+        DripSchedule { epoch_length: U64(100), amount_per_epoch: U128(amount_per_epoch) }
+    }
+
+    fn dripping_farm() -> Farm {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_drip_schedule(schedule(10), None);
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(25), FUND_LOCKED_MSG.to_string());
+        contract
+    }
+
+    #[test]
+    fn test_drip_releases_by_epoch() {
+        let mut contract = dripping_farm();
+        assert_eq!(contract.reward_reserve(), 0);
+        testing_env!(get_context(alice(), env::storage_usage(), 250));
+        assert_eq!(contract.release_drip().0, 20);
+        assert_eq!(contract.reward_reserve(), 20);
+        // the rest covers no whole epoch
+        testing_env!(get_context(alice(), env::storage_usage(), 1_000));
+        assert_eq!(contract.release_drip().0, 0);
+        assert_eq!(contract.get_drip().unwrap().locked.0, 5);
+    }
+
+    #[test]
+    fn test_drip_catches_up_after_top_up() {
+        let mut contract = dripping_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 1_000));
+        assert_eq!(contract.release_drip().0, 20);
+        assert_eq!(contract.get_drip().unwrap().next_release_at.nanoseconds.0, 300);
+
+        testing_env!(get_context(reward().into(), env::storage_usage(), 1_000));
+        contract.ft_on_transfer(alice(), U128(100), FUND_LOCKED_MSG.to_string());
+        testing_env!(get_context(alice(), env::storage_usage(), 1_000));
+        // the epochs the locked reserve fell short of are owed still
+        assert_eq!(contract.release_drip().0, 80);
+        assert_eq!(contract.get_drip().unwrap().locked.0, 25);
+    }
+
+    #[test]
+    #[should_panic(expected = "Drip changes must be announced a week ahead")]
+    fn test_drip_change_needs_notice() {
+        let mut contract = dripping_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_drip_schedule(schedule(1_000), Some(U64(100)));
+    }
+}
@@ -0,0 +1,121 @@
+//! Settling dormant dust accounts. Long-lived farms collect abandoned accounts holding a few
+//! tokens, each keeping its storage. Under the owner's `DustPolicy`, `force_settle` pays out
+//! such an account's stake and rewards to it, or to its owner for a position, and removes
+//! it once the transfers went through, refunding its storage deposit. Only accounts holding
+//! no more than the policy's amounts and inactive for longer than `dormant_for` qualify;
+//! an account counts as active when it stakes or accrues rewards.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+/// shortest inactivity `DustPolicy` accepts, about three months.
+pub const MIN_DORMANT_FOR: Timestamp = 90 * 24 * 3600 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DustPolicy {
+    /// largest stake of a dust account.
+    pub max_obs: U128,
+    /// largest reward balance of a dust account, including rewards not accrued yet.
+    pub max_reward: U128,
+    /// inactivity after which an account is dormant, in nanoseconds.
+    pub dormant_for: U64,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn set_dust_policy(&mut self, policy: Option<DustPolicy>) {
+        self.assert_owner();
+        if let Some(policy) = &policy {
+            assert!(policy.dormant_for.0 >= MIN_DORMANT_FOR, "Dormancy is too short");
+        }
+        emit_event("set_dust_policy", json!({ "policy": policy }));
+        self.dust_policy = policy;
+    }
+
+    pub fn get_dust_policy(&self) -> Option<DustPolicy> {
+        self.dust_policy.clone()
+    }
+
+    /// Pays out and removes the dormant dust account `account_id`.
+    pub fn force_settle(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        let policy = self.dust_policy.clone().expect("No dust policy is set");
+        let account_id: AccountId = account_id.into();
+        let account = self.account_view(&account_id).expect("Account is not registered");
+        let (account_id_hash, stored) = self.get_registered_account(&account_id);
+        let last_active_at = stored.deposit_time.max(stored.last_accrual_time);
+        assert!(self.now() >= last_active_at + policy.dormant_for.0, "The account is not dormant");
+        assert!(
+            account.obs_balance.0 <= policy.max_obs.0
+                && account.reward_balance.0 <= policy.max_reward.0,
+            "The account holds more than dust"
+        );
+        emit_event(
+            "force_settle",
+            json!({
+                "account_id": account_id,
+                "obs_balance": account.obs_balance,
+                "reward_balance": account.reward_balance,
+                "storage_refund": U128(stored.storage_deposit - stored.storage_sponsored),
+                "last_active_at": U64(last_active_at),
+                "settled_by": env::predecessor_account_id(),
+            }),
+        );
+        if account.obs_balance.0 == 0 && account.reward_balance.0 == 0 {
+            self.internal_unregister(&account_id, &account_id_hash, &stored);
+        } else {
+            self.internal_exit(&account_id, true);
+        }
+        self.flush_gas_profile("force_settle");
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, reward, staked_farm};
+    use std::convert::TryInto;
+
+    fn dust_farm() -> Farm {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), MIN_DORMANT_FOR));
+        let pending = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), pending, String::new());
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_dust_policy(Some(DustPolicy {
+            max_obs: U128(1000),
+            max_reward: pending,
+            dormant_for: U64(MIN_DORMANT_FOR),
+        }));
+        contract
+    }
+
+    #[test]
+    fn test_force_settle_dormant_dust() {
+        let mut contract = dust_farm();
+        let mut context = get_context(alice(), env::storage_usage(), MIN_DORMANT_FOR);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.force_settle(alice().try_into().unwrap());
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 0);
+        assert_eq!(account.reward_balance.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not dormant")]
+    fn test_force_settle_needs_dormancy() {
+        let mut contract = dust_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), MIN_DORMANT_FOR - 1));
+        contract.force_settle(alice().try_into().unwrap());
+    }
+}
@@ -0,0 +1,180 @@
+//! Reward emission schedules. By default every staked obs earns `reward_rate` per
+//! `reward_interval`; a linear decay instead moves the rate from an initial to a final
+//! value between two timestamps. Accrual integrates the rate over the accrual period
+//! exactly, so the result doesn't depend on how often an account is touched. Announced
+//! schedule changes, see `changes`, split the integral at the time they take effect.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+
+use crate::changes::ParameterChange;
+use crate::config::assert_reward_rate;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub enum EmissionSchedule {
+    /// `reward_rate` at all times.
+    Constant,
+    /// `initial_rate` until `start`, `final_rate` from `end`, linear in between.
+    LinearDecay { start: U64, end: U64, initial_rate: U128, final_rate: U128 },
+    /// `rate` at all times, set by the reward rate controller, see `rate_controller`.
+    Controlled { rate: U128 },
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Switches to `schedule` at `effective_at`, right away if `None`.
+    pub fn set_emission_schedule(&mut self, schedule: EmissionSchedule, effective_at: Option<U64>) {
+        self.assert_role(Role::Treasurer);
+        assert!(
+            !matches!(schedule, EmissionSchedule::Controlled { .. }),
+            "Controlled rates are set by the rate controller"
+        );
+        schedule.assert_valid();
+        let effective_at =
+            self.schedule_change(effective_at, ParameterChange::EmissionSchedule(schedule.clone()));
+        emit_event(
+            "set_emission_schedule",
+            json!({ "schedule": schedule, "effective_at": U64(effective_at) }),
+        );
+        self.record_apr(true);
+    }
+
+    /// Schedule in effect now.
+    pub fn get_emission_schedule(&self) -> EmissionSchedule {
+        self.emission_schedule_at(self.accrual_time()).clone()
+    }
+
+    /// Reward rate in effect now, per `reward_interval`.
+    pub fn get_current_reward_rate(&self) -> U128 {
+        self.reward_rate_at(self.accrual_time()).into()
+    }
+}
+
+impl EmissionSchedule {
+    pub(crate) fn assert_valid(&self) {
+        match self {
+            EmissionSchedule::Constant => {}
+            EmissionSchedule::LinearDecay { start, end, initial_rate, final_rate } => {
+                assert!(start.0 < end.0, "Emission must end after it starts");
+                assert!(initial_rate.0 >= final_rate.0, "Emission rate must decay");
+                assert_reward_rate(initial_rate.0);
+            }
+            EmissionSchedule::Controlled { rate } => assert_reward_rate(rate.0),
+        }
+    }
+
+    pub(crate) fn rate_at(&self, reward_rate: Balance, time: Timestamp) -> Balance {
+        match self {
+            EmissionSchedule::Constant => reward_rate,
+            EmissionSchedule::LinearDecay { start, end, initial_rate, final_rate } => {
+                let time = time.clamp(start.0, end.0);
+                let decay = U256::from(initial_rate.0 - final_rate.0) * U256::from(time - start.0)
+                    / U256::from(end.0 - start.0);
+                initial_rate.0 - decay.as_u128()
+            }
+            EmissionSchedule::Controlled { rate } => rate.0,
+        }
+    }
+
+    pub(crate) fn emission_between(
+        &self,
+        reward_rate: Balance,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> U256 {
+        match self {
+            EmissionSchedule::Constant => U256::from(reward_rate) * U256::from(to - from),
+            EmissionSchedule::LinearDecay { start, end, initial_rate, final_rate } => {
+                let (start, end) = (start.0, end.0);
+                let before = to.min(start).saturating_sub(from);
+                let after = to.saturating_sub(from.max(end));
+                let (t0, t1) = (from.clamp(start, end), to.clamp(start, end));
+                // r0·(t1 - t0) - (r0 - r1)·((t1 - s)² - (t0 - s)²) / 2(e - s)
+                let squares = U256::from(t1 - start) * U256::from(t1 - start)
+                    - U256::from(t0 - start) * U256::from(t0 - start);
+                let decayed = U256::from(initial_rate.0 - final_rate.0) * squares
+                    / U256::from(2 * (end - start));
+                U256::from(initial_rate.0) * U256::from(before + t1 - t0) - decayed
+                    + U256::from(final_rate.0) * U256::from(after)
+            }
+            EmissionSchedule::Controlled { rate } => U256::from(rate.0) * U256::from(to - from),
+        }
+    }
+}
+
+impl Farm {
+    pub(crate) fn emission_schedule_at(&self, time: Timestamp) -> &EmissionSchedule {
+        self.emission_changes()
+            .take_while(|(effective_at, _)| *effective_at <= time)
+            .last()
+            .map_or(&self.emission_schedule, |(_, schedule)| schedule)
+    }
+
+    pub(crate) fn reward_rate_at(&self, time: Timestamp) -> Balance {
+        self.emission_schedule_at(time).rate_at(self.reward_rate, time)
+    }
+
+    /// Integral of the reward rate over `[from, to]`, i.e. rate × nanoseconds.
+    pub(crate) fn emission_between(&self, from: Timestamp, to: Timestamp) -> U256 {
+        let mut emission = U256::zero();
+        let mut start = from;
+        for (effective_at, _) in self.emission_changes() {
+            if effective_at > start && effective_at < to {
+                emission += self.emission_schedule_at(start).emission_between(
+                    self.reward_rate,
+                    start,
+                    effective_at,
+                );
+                start = effective_at;
+            }
+        }
+        emission + self.emission_schedule_at(start).emission_between(self.reward_rate, start, to)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+
+    fn decaying_farm() -> Farm {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_emission_schedule(
+            EmissionSchedule::LinearDecay {
+                start: U64(100),
+                end: U64(200),
+                initial_rate: U128(3000),
+                final_rate: U128(1000),
+            },
+            None,
+        );
+        contract
+    }
+
+    #[test]
+    fn test_linear_decay_rate() {
+        let contract = decaying_farm();
+        assert_eq!(contract.reward_rate_at(50), 3000);
+        assert_eq!(contract.reward_rate_at(150), 2000);
+        assert_eq!(contract.reward_rate_at(250), 1000);
+    }
+
+    #[test]
+    fn test_linear_decay_integral() {
+        let contract = decaying_farm();
+        // 3000·100 before the decay, (3000 + 1000)/2·100 during it and 1000·100 after
+        assert_eq!(contract.emission_between(0, 300), U256::from(300_000 + 200_000 + 100_000));
+        let split = contract.emission_between(0, 130) + contract.emission_between(130, 300);
+        assert_eq!(split, contract.emission_between(0, 300));
+    }
+}
@@ -0,0 +1,157 @@
+//! Machine-readable errors for wallets. Account-facing failures panic with a JSON payload
+//! holding a stable code, the English message and the parameters a wallet needs to word
+//! the error in its own language, e.g.
+//! `{"code":"CLIFF_NOT_REACHED","message":"...","unlock_at":"..."}`.
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::{json, Value};
+
+use crate::*;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    AccountNotRegistered,
+    AccountFrozen,
+    PositionLocked,
+    CliffNotReached,
+    NothingToClaim,
+    NothingToHarvest,
+    NothingToWithdraw,
+    InsufficientReserve,
+    InsufficientGas,
+    BelowMinClaim,
+    ContractAccount,
+}
+
+impl ErrorCode {
+    const ALL: [ErrorCode; 11] = [
+        ErrorCode::AccountNotRegistered,
+        ErrorCode::AccountFrozen,
+        ErrorCode::PositionLocked,
+        ErrorCode::CliffNotReached,
+        ErrorCode::NothingToClaim,
+        ErrorCode::NothingToHarvest,
+        ErrorCode::NothingToWithdraw,
+        ErrorCode::InsufficientReserve,
+        ErrorCode::InsufficientGas,
+        ErrorCode::BelowMinClaim,
+        ErrorCode::ContractAccount,
+    ];
+
+    pub fn message(self) -> &'static str {
+        match self {
+            ErrorCode::AccountNotRegistered => "Account is not registered",
+            ErrorCode::AccountFrozen => "Account is frozen",
+            ErrorCode::PositionLocked => "Position is locked by a lien",
+            ErrorCode::CliffNotReached => "Stake is still locked by the cliff",
+            ErrorCode::NothingToClaim => "Nothing to claim",
+            ErrorCode::NothingToHarvest => "Nothing to harvest",
+            ErrorCode::NothingToWithdraw => "Nothing to withdraw",
+            ErrorCode::InsufficientReserve => "Not enough rewards in the reserve",
+            ErrorCode::InsufficientGas => "Not enough gas attached",
+            ErrorCode::BelowMinClaim => "Rewards are below the minimum claim",
+            ErrorCode::ContractAccount => "Contract accounts can't stake or claim in this pool",
+        }
+    }
+
+    /// names of the parameters sent along with the code.
+    pub fn params(self) -> &'static [&'static str] {
+        match self {
+            ErrorCode::AccountNotRegistered
+            | ErrorCode::AccountFrozen
+            | ErrorCode::ContractAccount => &["account_id"],
+            ErrorCode::PositionLocked => &["account_id", "lienholder"],
+            ErrorCode::CliffNotReached => &["unlock_at"],
+            ErrorCode::InsufficientReserve => &["amount", "reserve"],
+            ErrorCode::InsufficientGas => &["required", "prepaid"],
+            ErrorCode::BelowMinClaim => &["claimable", "min_claim"],
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ErrorInfo {
+    pub code: ErrorCode,
+    pub message: &'static str,
+    pub params: &'static [&'static str],
+}
+
+/// Panics with the payload of `code`, `params` being an object with its parameters.
+pub(crate) fn fail(code: ErrorCode, params: Value) -> ! {
+    let mut payload = json!({ "code": code, "message": code.message() });
+    if let (Some(payload), Value::Object(params)) = (payload.as_object_mut(), params) {
+        payload.extend(params);
+    }
+    panic!("{}", payload)
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn get_error_catalog(&self) -> Vec<ErrorInfo> {
+        ErrorCode::ALL
+            .iter()
+            .map(|code| ErrorInfo { code: *code, message: code.message(), params: code.params() })
+            .collect()
+    }
+}
+
+impl Farm {
+    /// loads a registered account, failing with `ACCOUNT_NOT_REGISTERED` otherwise.
+    pub(crate) fn get_registered_account(
+        &self,
+        account_id: &AccountId,
+    ) -> (ShortAccountHash, Account) {
+        match self.get_internal_account(account_id) {
+            (account_id_hash, Some(account)) => (account_id_hash, account),
+            _ => fail(ErrorCode::AccountNotRegistered, json!({ "account_id": account_id })),
+        }
+    }
+
+    pub(crate) fn assert_reserve_covers(&self, amount: Balance) {
+        let reserve = self.reward_reserve();
+        if amount > reserve {
+            fail(
+                ErrorCode::InsufficientReserve,
+                json!({ "amount": U128(amount), "reserve": U128(reserve) }),
+            );
+        }
+    }
+
+    pub(crate) fn assert_cliff_reached(&self, account: &Account) {
+        if !self.is_unlocked(account) {
+            let unlock_at = account.deposit_time + self.cliff_time;
+            fail(ErrorCode::CliffNotReached, json!({ "unlock_at": U64(unlock_at) }));
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, staked_farm};
+
+    #[test]
+    fn test_error_catalog() {
+        testing_env!(get_context(alice(), 0, 0));
+        let catalog = new_farm().get_error_catalog();
+        assert_eq!(catalog.len(), ErrorCode::ALL.len());
+        assert_eq!(json!(catalog[3].code), json!("CLIFF_NOT_REACHED"));
+    }
+
+    #[test]
+    #[should_panic(expected = r#"{"code":"CLIFF_NOT_REACHED","message":"#)]
+    fn test_structured_error() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 1);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.unstake_my_obs();
+    }
+}
@@ -0,0 +1,16 @@
+//! NEP-297 events logged by the farm, so indexers can follow admin and account actions.
+use near_sdk::log;
+use near_sdk::serde_json::{json, Value};
+
+pub const EVENT_STANDARD: &str = "obs-farm";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+pub(crate) fn emit_event(event: &str, data: Value) {
+    let event = json!({
+        "standard": EVENT_STANDARD,
+        "version": EVENT_VERSION,
+        "event": event,
+        "data": [data],
+    });
+    log!("EVENT_JSON:{}", event);
+}
@@ -0,0 +1,265 @@
+//! One-call exit: withdraws the whole stake, claims every reward and optionally unregisters
+//! the account once both transfers went through.
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, log, near_bindgen, PromiseResult};
+
+use crate::*;
+
+#[near_bindgen]
+impl Farm {
+    /// Unstakes the predecessor's balance and pays out its rewards. With `unregister` the
+    /// account is also removed and its storage deposit refunded after both transfers succeed.
+    /// Accounts refused by the contract gate can still `unstake_my_obs`. Rewards that have to
+    /// wait are queued, and the account then stays registered.
+    #[payable]
+    pub fn exit(&mut self, unregister: Option<bool>) -> PromiseOrValue<()> {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_contract(&account_id);
+        let promise = self.internal_exit(&account_id, unregister.unwrap_or(false));
+        self.flush_gas_profile("exit");
+        promise
+    }
+
+    /// Restores whatever failed to transfer, or unregisters the account if all went through.
+    /// `principal` is the stake sent, after the exit fee, which is kept either way.
+    #[private]
+    pub fn on_exit(
+        &mut self,
+        account_id: AccountId,
+        principal: U128,
+        reward: U128,
+        unregister: bool,
+    ) {
+        let mut results = (0..env::promise_results_count()).map(env::promise_result);
+        let mut transferred = |amount: U128| {
+            amount.0 == 0 || matches!(results.next(), Some(PromiseResult::Successful(_)))
+        };
+        let principal_sent = transferred(principal);
+        let reward_sent = transferred(reward);
+        if reward.0 > 0 {
+            self.resolve_reward_payout();
+        }
+
+        if !reward_sent {
+            self.ledger.post(LedgerAccount::Claimed, LedgerAccount::Reserve, reward.0);
+        }
+        if !principal_sent {
+            self.ledger.post(LedgerAccount::Withdrawals, LedgerAccount::Staked, principal.0);
+        }
+        if reward_sent {
+            let token_id = self.reward_token_account_id.clone();
+            self.record_claim(&account_id, reward.0, token_id, None, None);
+        }
+        let (account_id_hash, account) = self.get_internal_account(&account_id);
+        let mut account = match account {
+            Some(account) => account,
+            None => {
+                log!("@{} unregistered before its exit resolved", account_id);
+                return;
+            }
+        };
+        if principal_sent && reward_sent {
+            if !unregister {
+                return;
+            }
+            // a stake, rewards or a queued claim may have come in while the transfers were
+            // in flight
+            if account.obs_balance > 0
+                || account.reward_balance > 0
+                || self.has_queued_claim(&account_id)
+            {
+                log!("@{} holds a balance again, left registered", account_id);
+            } else {
+                self.internal_unregister(&account_id, &account_id_hash, &account);
+            }
+            return;
+        }
+        if !principal_sent {
+            self.checkpoint_balance(&account_id, &account);
+            if account.obs_balance == 0 {
+                self.active_stakers += 1;
+            }
+            // the account held no stake while the unstake was in flight
+            account.staker_fee_index = self.staker_fee_index;
+            account.obs_balance += principal.0;
+            self.rank_staker(&account_id, account.obs_balance);
+            account.total_withdrawn -= principal.0;
+            log!("Unstake of {} by @{} failed, stake restored", principal.0, account_id);
+        }
+        if !reward_sent {
+            account.reward_balance += reward.0;
+            account.reward_claimed -= reward.0;
+            log!("Reward transfer of {} to @{} failed, balance restored", reward.0, account_id);
+        }
+        self.save_account(&account_id_hash, &account);
+    }
+}
+
+impl Farm {
+    /// Unstakes the balance of `account_id` and pays out its rewards, to the owner for a
+    /// position, unregistering the account with `unregister` unless the rewards are queued.
+    pub(crate) fn internal_exit(
+        &mut self,
+        account_id: &AccountId,
+        unregister: bool,
+    ) -> PromiseOrValue<()> {
+        self.assert_not_frozen(account_id);
+        self.assert_not_liened(account_id);
+        let (account_id_hash, mut account) = self.get_mut_account(account_id);
+        let principal = account.obs_balance;
+        let reward = account.reward_balance;
+        if principal == 0 && reward == 0 {
+            fail(ErrorCode::NothingToWithdraw, json!({}));
+        }
+        if principal > 0 {
+            self.assert_cliff_reached(&account);
+        }
+        self.check_large_unstake(&mut account, principal);
+        self.checkpoint_balance(account_id, &account);
+        self.apply_due_changes();
+        let exit_fee = fee_of(principal, self.exit_fee_bps);
+        let payout = principal - exit_fee;
+
+        if principal > 0 {
+            self.active_stakers -= 1;
+        }
+        account.obs_balance = 0;
+        account.warming_balance = 0;
+        self.rank_staker(account_id, 0);
+        account.total_withdrawn += principal;
+        account.reward_balance = 0;
+        account.reward_claimed += reward;
+        self.ledger.post(LedgerAccount::Staked, LedgerAccount::Withdrawals, principal);
+        self.charge_exit_fee(exit_fee);
+        self.save_account(&account_id_hash, &account);
+        if principal > 0 {
+            self.notify_position_hook(account_id, &account, PositionEvent::Unstaked, principal);
+        }
+        if reward > 0 {
+            self.notify_position_hook(account_id, &account, PositionEvent::RewardClaimed, reward);
+        }
+
+        let mut promises = GAS_FOR_RESOLVE_TRANSFER;
+        if principal > 0 {
+            promises += self.token_gas(&self.obs_token_account_id).ft_transfer.0;
+        }
+        if reward > 0 {
+            promises += self.token_gas(&self.reward_token_account_id).ft_transfer.0;
+        }
+        self.assert_prepaid_gas(promises);
+
+        let gas_mark = self.gas_mark();
+        let receiver_id = self.payout_receiver(account_id);
+        let withdraw = |farm: &Self, amount: Balance, token_id: &AccountId| {
+            ext_fungible_token::ft_transfer(
+                receiver_id.clone(),
+                amount.into(),
+                None,
+                token_id,
+                1,
+                farm.token_gas(token_id).ft_transfer.0,
+            )
+        };
+        let reward_transfer = if reward > 0 {
+            self.pay_or_queue_reward(account_id, reward, |farm| {
+                farm.start_reward_payout(reward);
+                withdraw(farm, reward, &farm.reward_token_account_id)
+            })
+        } else {
+            None
+        };
+        let sent_reward = if reward_transfer.is_some() { reward } else { 0 };
+        let principal_transfer = if principal > 0 {
+            Some(withdraw(self, payout, &self.obs_token_account_id))
+        } else {
+            None
+        };
+        let transfers = match (principal_transfer, reward_transfer) {
+            (Some(principal), Some(reward)) => principal.and(reward),
+            (Some(transfer), None) | (None, Some(transfer)) => transfer,
+            (None, None) => {
+                self.record_gas(GasPhase::Promises, gas_mark);
+                return PromiseOrValue::Value(());
+            }
+        };
+        let promise = transfers.then(ext_self::on_exit(
+            account_id.clone(),
+            payout.into(),
+            sent_reward.into(),
+            unregister && sent_reward == reward,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+        self.record_gas(GasPhase::Promises, gas_mark);
+        promise.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn resolve_exit(contract: &mut Farm, results: Vec<PromiseResult>, reward: U128) {
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context, Default::default(), Default::default(), Default::default(), results);
+        contract.on_exit(alice(), U128(1000), reward, true);
+    }
+
+    #[test]
+    fn test_exit_and_unregister() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.exit(Some(true));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 0);
+        assert_eq!(account.reward_balance.0, 0);
+
+        let results = vec![PromiseResult::Successful(vec![]), PromiseResult::Successful(vec![])];
+        resolve_exit(&mut contract, results, account.reward_claimed);
+        assert!(!contract.account_exists(alice().try_into().unwrap()));
+    }
+
+    #[test]
+    fn test_stake_during_exit_keeps_account() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.exit(Some(true));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        contract.stake_my_obs(U128(500));
+
+        let results = vec![PromiseResult::Successful(vec![]), PromiseResult::Successful(vec![])];
+        resolve_exit(&mut contract, results, account.reward_claimed);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 500);
+    }
+
+    #[test]
+    fn test_failed_exit_is_restored() {
+        let mut contract = staked_farm();
+        let staked = contract.ledger.balance(LedgerAccount::Staked);
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.exit(Some(true));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+
+        let results = vec![PromiseResult::Failed, PromiseResult::Successful(vec![])];
+        resolve_exit(&mut contract, results, account.reward_claimed);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 1000);
+        assert_eq!(account.reward_balance.0, 0);
+        assert_eq!(contract.ledger.balance(LedgerAccount::Staked), staked);
+    }
+}
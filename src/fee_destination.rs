@@ -0,0 +1,325 @@
+//! Entry and exit fees, and where they go. A stake pays `entry_fee_bps` of the deposit
+//! and an unstake `exit_fee_bps` of the principal, each set independently; raising either
+//! fee needs an announced change at least `FEE_DESTINATION_NOTICE` ahead, see `changes`,
+//! since the exit fee binds stakes made before and the entry fee deposits already in
+//! flight. Each fee is routed to its own destination, entry fees
+//! to the one chosen in the config, exit fees to the treasury by default, and changed only
+//! through an announced change at least `FEE_DESTINATION_NOTICE` ahead:
+//! - `Treasury`: fees are kept until `sweep_fees` sends them to the treasury, along with
+//!   registration fees.
+//! - `Burn`: fees are set aside for good. NEP-141 has no burn, so they stay in the farm's
+//!   balance as `BurnedFees`, out of every payout's reach.
+//! - `Reserve`: fees join the reward reserve and pay future emissions.
+//! - `Stakers`: fees join the reward reserve and are credited to the other stakers by stake,
+//!   through a fee index each account catches up with when touched.
+//!
+//! The last two need a single-token farm, where fees are paid in the reward token.
+//! `get_fees` tells the rates, destinations and fees charged in each direction.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, is_promise_success, log, near_bindgen, Promise};
+
+use crate::changes::ParameterChange;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// notice changes of the fee destination are announced with, a week.
+pub const FEE_DESTINATION_NOTICE: Timestamp = 7 * 24 * 3600 * 1_000_000_000;
+
+/// highest entry or exit fee, in basis points, 10%.
+pub const MAX_FEE_BPS: u32 = 1_000;
+
+/// scale of `staker_fee_index`, fees per staked obs.
+const FEE_INDEX_SCALE: u128 = 1_000_000_000_000;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FeeDestination {
+    Treasury,
+    Burn,
+    Reserve,
+    Stakers,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeesView {
+    pub entry_fee_bps: u32,
+    pub exit_fee_bps: u32,
+    pub entry_fee_destination: FeeDestination,
+    pub exit_fee_destination: FeeDestination,
+    /// fees ever charged on stakes and unstakes.
+    pub entry_fees: U128,
+    pub exit_fees: U128,
+}
+
+impl FeeDestination {
+    /// whether fees can go there in a farm whose obs token is the reward token or not.
+    pub(crate) fn allowed(self, single_token: bool) -> bool {
+        single_token || matches!(self, FeeDestination::Treasury | FeeDestination::Burn)
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Routes entry fees to `destination` from `effective_at`, which must be at least
+    /// `FEE_DESTINATION_NOTICE` ahead.
+    pub fn set_fee_destination(&mut self, destination: FeeDestination, effective_at: U64) {
+        self.assert_owner();
+        self.assert_fee_destination(destination, effective_at);
+        let effective_at =
+            self.schedule_change(Some(effective_at), ParameterChange::FeeDestination(destination));
+        emit_event(
+            "set_fee_destination",
+            json!({ "destination": destination, "effective_at": U64(effective_at) }),
+        );
+    }
+
+    /// Routes exit fees to `destination` from `effective_at`, which must be at least
+    /// `FEE_DESTINATION_NOTICE` ahead.
+    pub fn set_exit_fee_destination(&mut self, destination: FeeDestination, effective_at: U64) {
+        self.assert_owner();
+        self.assert_fee_destination(destination, effective_at);
+        let change = ParameterChange::ExitFeeDestination(destination);
+        let effective_at = self.schedule_change(Some(effective_at), change);
+        emit_event(
+            "set_exit_fee_destination",
+            json!({ "destination": destination, "effective_at": U64(effective_at) }),
+        );
+    }
+
+    /// Sets the entry fee at `effective_at`, right away if `None`. A higher fee must be
+    /// announced at least `FEE_DESTINATION_NOTICE` ahead.
+    pub fn set_entry_fee(&mut self, fee_bps: u32, effective_at: Option<U64>) {
+        self.assert_owner();
+        assert!(fee_bps <= MAX_FEE_BPS, "Fee is above {} bps", MAX_FEE_BPS);
+        self.apply_due_changes();
+        if fee_bps > self.entry_fee_bps {
+            assert!(
+                effective_at.map_or(0, |at| at.0) >= self.now() + FEE_DESTINATION_NOTICE,
+                "Entry fee increases must be announced a week ahead"
+            );
+        }
+        let effective_at = self.schedule_change(effective_at, ParameterChange::EntryFee(fee_bps));
+        emit_event(
+            "set_entry_fee",
+            json!({ "fee_bps": fee_bps, "effective_at": U64(effective_at) }),
+        );
+    }
+
+    /// Sets the exit fee at `effective_at`, right away if `None`. A higher fee must be
+    /// announced at least `FEE_DESTINATION_NOTICE` ahead.
+    pub fn set_exit_fee(&mut self, fee_bps: u32, effective_at: Option<U64>) {
+        self.assert_owner();
+        assert!(fee_bps <= MAX_FEE_BPS, "Fee is above {} bps", MAX_FEE_BPS);
+        self.apply_due_changes();
+        if fee_bps > self.exit_fee_bps {
+            assert!(
+                effective_at.map_or(0, |at| at.0) >= self.now() + FEE_DESTINATION_NOTICE,
+                "Exit fee increases must be announced a week ahead"
+            );
+        }
+        let effective_at = self.schedule_change(effective_at, ParameterChange::ExitFee(fee_bps));
+        emit_event(
+            "set_exit_fee",
+            json!({ "fee_bps": fee_bps, "effective_at": U64(effective_at) }),
+        );
+    }
+
+    pub fn get_fees(&self) -> FeesView {
+        FeesView {
+            entry_fee_bps: self.entry_fee_bps,
+            exit_fee_bps: self.exit_fee_bps,
+            entry_fee_destination: self.fee_destination,
+            exit_fee_destination: self.exit_fee_destination,
+            entry_fees: self.entry_fees.into(),
+            exit_fees: self.exit_fees.into(),
+        }
+    }
+
+    /// Sends the entry, exit and registration fees kept by the farm to the treasury.
+    pub fn sweep_fees(&mut self) -> Promise {
+        self.assert_role(Role::Treasurer);
+        let amount = self.ledger.balance(LedgerAccount::Fees);
+        assert!(amount > 0, "No fees to sweep");
+        self.ledger.post(LedgerAccount::Fees, LedgerAccount::SweptFees, amount);
+        let treasury_id = self.treasury_id.clone();
+        emit_event("sweep_fees", json!({ "treasury_id": treasury_id, "amount": U128(amount) }));
+        ext_fungible_token::ft_transfer(
+            treasury_id,
+            amount.into(),
+            None,
+            &self.obs_token_account_id,
+            1,
+            self.token_gas(&self.obs_token_account_id).ft_transfer.0,
+        )
+        .then(ext_self::on_fees_swept(
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Keeps fees that failed to reach the treasury.
+    #[private]
+    pub fn on_fees_swept(&mut self, amount: U128) {
+        if !is_promise_success() {
+            self.ledger.post(LedgerAccount::SweptFees, LedgerAccount::Fees, amount.0);
+            log!("Sweep of {} fees to the treasury failed", amount.0);
+        }
+    }
+}
+
+impl Farm {
+    fn assert_fee_destination(&self, destination: FeeDestination, effective_at: U64) {
+        assert!(
+            destination.allowed(self.is_single_token()),
+            "Fees are only recycled as rewards when the obs token is the reward token"
+        );
+        assert!(
+            effective_at.0 >= self.now() + FEE_DESTINATION_NOTICE,
+            "Fee destination changes must be announced a week ahead"
+        );
+    }
+
+    /// Sends a fee of `fee` to `destination`. `own_stake` is the stake of the account that
+    /// paid it, which gets no share of it.
+    pub(crate) fn route_fee(
+        &mut self,
+        fee: Balance,
+        own_stake: Balance,
+        destination: FeeDestination,
+    ) {
+        if fee == 0 {
+            return;
+        }
+        let others = self.ledger.balance(LedgerAccount::Staked) - own_stake;
+        match destination {
+            FeeDestination::Treasury => {}
+            FeeDestination::Burn => {
+                self.ledger.post(LedgerAccount::Fees, LedgerAccount::BurnedFees, fee)
+            }
+            FeeDestination::Stakers if others > 0 => {
+                self.ledger.post(LedgerAccount::Fees, LedgerAccount::Reserve, fee);
+                let increment = U256::from(fee) * U256::from(FEE_INDEX_SCALE) / U256::from(others);
+                // a fee too large for the index stays in the reserve
+                if increment <= U256::from(u128::MAX - self.staker_fee_index) {
+                    self.staker_fee_index += increment.as_u128();
+                }
+            }
+            FeeDestination::Reserve | FeeDestination::Stakers => {
+                self.ledger.post(LedgerAccount::Fees, LedgerAccount::Reserve, fee)
+            }
+        }
+    }
+
+    /// Keeps the exit fee `fee` out of an unstake whose principal was withdrawn, and routes
+    /// it to the exit fee destination.
+    pub(crate) fn charge_exit_fee(&mut self, fee: Balance) {
+        self.ledger.post(LedgerAccount::Withdrawals, LedgerAccount::Fees, fee);
+        self.exit_fees += fee;
+        self.route_fee(fee, 0, self.exit_fee_destination);
+    }
+
+    /// fees owed to the account by stake since it last caught up with the fee index.
+    pub(crate) fn staker_fees_owed(&self, account: &Account) -> Balance {
+        (U256::from(account.obs_balance)
+            * U256::from(self.staker_fee_index - account.staker_fee_index)
+            / U256::from(FEE_INDEX_SCALE))
+        .as_u128()
+    }
+}
+
+/// Fee of `fee_bps` basis points on `amount`.
+pub(crate) fn fee_of(amount: Balance, fee_bps: u32) -> Balance {
+    (U256::from(amount) * U256::from(fee_bps) / U256::from(BPS_DENOM)).as_u128()
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::config::FarmConfig;
+    use crate::tests::{alice, bob, get_context, obs, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_fees_go_to_stakers() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut config = FarmConfig::new(alice().try_into().unwrap(), obs(), obs());
+        config.fee_destination = Some(FeeDestination::Stakers);
+        config.entry_fee_bps = Some(235);
+        let mut contract = Farm::new(config);
+        contract.set_registration_fee(Some(U128(10)));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        // 1024 deposited after the registration fee, 24 of it as the entry fee
+        contract.ft_on_transfer(alice(), U128(1034), STAKE_MSG.to_string());
+        contract.ft_on_transfer(bob(), U128(1034), STAKE_MSG.to_string());
+
+        let fee = 24;
+        assert_eq!(contract.get_fees().entry_fees.0, 2 * fee);
+        assert_eq!(contract.ledger.balance(LedgerAccount::Fees), 20);
+        assert_eq!(contract.reward_reserve(), 2 * fee);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, fee);
+        let account = contract.get_account(bob().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fee destination changes must be announced a week ahead")]
+    fn test_fee_destination_needs_notice() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = crate::tests::new_farm();
+        contract.set_fee_destination(FeeDestination::Burn, U64(FEE_DESTINATION_NOTICE - 1));
+    }
+
+    #[test]
+    fn test_exit_fee_is_kept() {
+        let mut contract = staked_farm();
+        contract.exit_fee_bps = 100;
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let fees = contract.get_fees();
+        assert_eq!((fees.exit_fee_bps, fees.exit_fees.0, fees.entry_fees.0), (100, 10, 0));
+        assert_eq!(contract.ledger.balance(LedgerAccount::Fees), 10);
+        assert_eq!(contract.ledger.balance(LedgerAccount::Withdrawals), 990);
+    }
+
+    #[test]
+    #[should_panic(expected = "Exit fee increases must be announced a week ahead")]
+    fn test_exit_fee_increase_needs_notice() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_exit_fee(100, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Entry fee increases must be announced a week ahead")]
+    fn test_entry_fee_increase_needs_notice() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_entry_fee(100, None);
+    }
+
+    #[test]
+    fn test_announced_entry_fee_increase() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_entry_fee(100, Some(U64(FEE_DESTINATION_NOTICE)));
+        assert_eq!(contract.get_fees().entry_fee_bps, 0);
+        testing_env!(get_context(alice(), env::storage_usage(), FEE_DESTINATION_NOTICE));
+        contract.apply_due_changes();
+        assert_eq!(contract.get_fees().entry_fee_bps, 100);
+    }
+}
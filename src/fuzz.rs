@@ -0,0 +1,167 @@
+//! Model-based fuzzing of the farm's state machine. Random sequences of stakes, unstakes,
+//! claims, harvests, time jumps and config changes run against the contract and a simple
+//! reference model, from fixed seeds so failures replay. After every operation the ledger
+//! must balance, every principal must match the model, the reward tokens must be conserved
+//! and claimed totals may only grow.
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+use near_sdk::json_types::{U128, U64};
+use near_sdk::{testing_env, MockedBlockchain};
+
+use crate::cliff::CliffScope;
+use crate::tests::{alice, bob, get_context, new_farm, reward};
+use crate::*;
+
+const SEEDS: u64 = 16;
+const STEPS: usize = 150;
+const FUNDING: Balance = 1_000_000_000_000_000_000_000_000_000_000;
+
+/// xorshift64, enough to drive the operations deterministically.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+/// what the farm should hold, independently of how rewards accrue.
+#[derive(Default)]
+struct Model {
+    principal: HashMap<AccountId, Balance>,
+    reward_claimed: HashMap<AccountId, Balance>,
+    total_claimed: Balance,
+}
+
+fn set_context(predecessor_id: AccountId, now: Timestamp) {
+    let mut context = get_context(predecessor_id, env::storage_usage(), now);
+    context.account_balance = 10u128.pow(25);
+    testing_env!(context);
+}
+
+fn reward_balance(contract: &Farm, account_id: &AccountId) -> Balance {
+    contract.get_account(account_id.clone().try_into().unwrap()).unwrap().reward_balance.0
+}
+
+fn run(seed: u64) {
+    let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) + 1);
+    let accounts = vec![alice(), bob(), "carol.near".to_string()];
+    // every run starts from empty storage
+    env::take_blockchain_interface();
+    testing_env!(get_context(alice(), 0, 0));
+    let mut contract = new_farm();
+    for account_id in &accounts {
+        let mut context = get_context(account_id.clone(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.register_account();
+    }
+    set_context(reward().into(), 0);
+    contract.ft_on_transfer(alice(), U128(FUNDING), String::new());
+
+    let mut model = Model::default();
+    let mut now: Timestamp = 0;
+    for _ in 0..STEPS {
+        let account_id = accounts[rng.below(accounts.len() as u64) as usize].clone();
+        match rng.below(6) {
+            0 => {
+                let amount = 1 + rng.below(10_000) as Balance;
+                set_context(account_id.clone(), now);
+                contract.stake_my_obs(U128(amount));
+                *model.principal.entry(account_id).or_default() += amount;
+            }
+            1 => {
+                set_context(account_id.clone(), now);
+                let (_, account) = contract.get_internal_account(&account_id);
+                if !matches!(account, Some(account) if contract.is_unlocked(&account)) {
+                    continue;
+                }
+                let reward = reward_balance(&contract, &account_id);
+                contract.unstake_my_obs();
+                model.principal.insert(account_id.clone(), 0);
+                *model.reward_claimed.entry(account_id).or_default() += reward;
+                model.total_claimed += reward;
+            }
+            2 => {
+                set_context(account_id.clone(), now);
+                let reward = reward_balance(&contract, &account_id);
+                if reward == 0 {
+                    continue;
+                }
+                contract.claim_all();
+                *model.reward_claimed.entry(account_id).or_default() += reward;
+                model.total_claimed += reward;
+            }
+            3 => {
+                let caller_id = accounts[rng.below(accounts.len() as u64) as usize].clone();
+                set_context(caller_id.clone(), now);
+                let reward = reward_balance(&contract, &account_id);
+                if reward == 0 {
+                    continue;
+                }
+                contract.harvest_for(account_id.clone().try_into().unwrap());
+                let bounty = if caller_id == account_id {
+                    0
+                } else {
+                    reward * Balance::from(contract.harvest_bounty_bps) / BPS_DENOM
+                };
+                *model.reward_claimed.entry(account_id).or_default() += reward - bounty;
+                model.total_claimed += reward;
+            }
+            4 => now += rng.below(2 * contract.cliff_time),
+            _ => {
+                set_context(alice(), now);
+                match rng.below(3) {
+                    0 => contract.set_harvest_bounty(rng.below(101) as u32, None),
+                    1 => {
+                        let initial_rate = 1 + rng.below(3600) as Balance;
+                        let schedule = if rng.below(2) == 0 {
+                            EmissionSchedule::Constant
+                        } else {
+                            EmissionSchedule::LinearDecay {
+                                start: U64(now),
+                                end: U64(now + 1 + rng.below(contract.cliff_time)),
+                                initial_rate: U128(initial_rate),
+                                final_rate: U128(initial_rate / 2),
+                            }
+                        };
+                        contract.set_emission_schedule(schedule, None);
+                    }
+                    _ => contract.set_cliff_scope(CliffScope {
+                        rewards: rng.below(2) == 0,
+                        principal: rng.below(2) == 0,
+                    }),
+                }
+            }
+        }
+        check_invariants(&contract, &model, &accounts, seed);
+    }
+}
+
+fn check_invariants(contract: &Farm, model: &Model, accounts: &[AccountId], seed: u64) {
+    contract.assert_ledger_reconciled();
+    let mut staked = 0;
+    for account_id in accounts {
+        let account = contract.get_internal_account(account_id).1.unwrap();
+        let principal = model.principal.get(account_id).copied().unwrap_or(0);
+        assert_eq!(account.obs_balance, principal, "seed {}: principal of @{}", seed, account_id);
+        let claimed = model.reward_claimed.get(account_id).copied().unwrap_or(0);
+        assert_eq!(account.reward_claimed, claimed, "seed {}: claimed by @{}", seed, account_id);
+        staked += principal;
+    }
+    assert_eq!(contract.ledger.balance(LedgerAccount::Staked), staked, "seed {}: staked", seed);
+    let claimed = contract.ledger.balance(LedgerAccount::Claimed);
+    assert_eq!(claimed, model.total_claimed, "seed {}: total claimed", seed);
+    assert_eq!(contract.reward_reserve() + claimed, FUNDING, "seed {}: rewards conserved", seed);
+}
+
+#[test]
+fn fuzz_farm_state_machine() {
+    for seed in 0..SEEDS {
+        run(seed);
+    }
+}
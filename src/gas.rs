@@ -0,0 +1,274 @@
+//! Optional gas instrumentation. When the owner turns profiling on, every mutating call
+//! logs the gas it spent per internal phase and the farm keeps the last
+//! `GAS_PROFILE_SIZE` measurements for `get_gas_profile`.
+//!
+//! Gas attached to calls into token contracts can also be overridden per token, for tokens
+//! that run cross-contract hooks of their own. Calls scheduling transfers and their
+//! callbacks check up front that the attached gas covers them plus a configurable margin,
+//! rather than letting a later promise run out of gas.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, log, near_bindgen, Gas};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+
+use crate::*;
+
+/// number of operations remembered by `get_gas_profile`.
+pub const GAS_PROFILE_SIZE: u64 = 20;
+
+/// bounds of the gas configured for a token call.
+pub const MIN_TOKEN_GAS: Gas = BASE_GAS;
+pub const MAX_TOKEN_GAS: Gas = 20 * BASE_GAS;
+
+/// gas kept on top of the scheduled promises for the rest of the call.
+pub const DEFAULT_PROMISE_GAS_MARGIN: Gas = BASE_GAS;
+pub const MAX_PROMISE_GAS_MARGIN: Gas = 20 * BASE_GAS;
+
+/// gas attached to each call into a token contract.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenGas {
+    pub ft_transfer: U64,
+    pub ft_transfer_call: U64,
+    pub ft_balance_of: U64,
+}
+
+impl Default for TokenGas {
+    fn default() -> Self {
+        Self {
+            ft_transfer: GAS_FOR_ON_TRANSFER.into(),
+            ft_transfer_call: GAS_FOR_FT_TRANSFER_CALL.into(),
+            ft_balance_of: BASE_GAS.into(),
+        }
+    }
+}
+
+pub enum GasPhase {
+    Accrual,
+    Storage,
+    Promises,
+}
+
+/// gas spent by the current call so far, never persisted.
+#[derive(Default)]
+pub struct GasMeter {
+    accrual: Gas,
+    storage: Gas,
+    promises: Gas,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GasProfile {
+    pub operation: String,
+    pub block_index: U64,
+    pub accrual: U64,
+    pub storage: U64,
+    pub promises: U64,
+    pub total: U64,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn set_gas_profiling(&mut self, enabled: bool) {
+        self.assert_role(Role::Operator);
+        self.gas_profiling = enabled;
+        log!("Gas profiling enabled: {}", enabled);
+    }
+
+    /// Overrides the gas of calls into `token_id`, `None` restoring the defaults.
+    pub fn set_token_gas(&mut self, token_id: ValidAccountId, gas: Option<TokenGas>) {
+        self.assert_role(Role::Operator);
+        match &gas {
+            Some(gas) => {
+                for call_gas in [gas.ft_transfer, gas.ft_transfer_call, gas.ft_balance_of].iter() {
+                    assert!(
+                        (MIN_TOKEN_GAS..=MAX_TOKEN_GAS).contains(&call_gas.0),
+                        "Token gas must be between {} and {}",
+                        MIN_TOKEN_GAS,
+                        MAX_TOKEN_GAS
+                    );
+                }
+                self.token_gas.insert(token_id.as_ref(), gas);
+            }
+            None => {
+                self.token_gas.remove(token_id.as_ref());
+            }
+        }
+        emit_event("set_token_gas", json!({ "token_id": token_id, "gas": gas }));
+    }
+
+    pub fn set_promise_gas_margin(&mut self, margin: U64) {
+        self.assert_role(Role::Operator);
+        assert!(
+            margin.0 <= MAX_PROMISE_GAS_MARGIN,
+            "Gas margin must be at most {}",
+            MAX_PROMISE_GAS_MARGIN
+        );
+        self.promise_gas_margin = margin.0;
+        emit_event("set_promise_gas_margin", json!({ "margin": margin }));
+    }
+
+    pub fn get_promise_gas_margin(&self) -> U64 {
+        self.promise_gas_margin.into()
+    }
+
+    /// Gas used for calls into `token_id`, overridden or default.
+    pub fn get_token_gas(&self, token_id: ValidAccountId) -> TokenGas {
+        self.token_gas(token_id.as_ref())
+    }
+
+    pub fn get_token_gas_overrides(&self) -> Vec<(AccountId, TokenGas)> {
+        self.token_gas.to_vec()
+    }
+
+    /// Last recorded operations, oldest first.
+    pub fn get_gas_profile(&self) -> Vec<GasProfile> {
+        let len = self.gas_profiles.len();
+        if len == 0 {
+            return vec![];
+        }
+        let oldest = self.gas_profiles_recorded % len;
+        (0..len).map(|i| self.gas_profiles.get((oldest + i) % len).unwrap()).collect()
+    }
+}
+
+impl Farm {
+    pub(crate) fn token_gas(&self, token_id: &AccountId) -> TokenGas {
+        self.token_gas.get(token_id).unwrap_or_default()
+    }
+
+    /// Fails with `INSUFFICIENT_GAS` unless the prepaid gas covers what the call used so
+    /// far, the `promises` it is about to schedule and the margin.
+    pub(crate) fn assert_prepaid_gas(&self, promises: Gas) {
+        let required = env::used_gas() + promises + self.promise_gas_margin;
+        let prepaid = env::prepaid_gas();
+        if prepaid < required {
+            fail(
+                ErrorCode::InsufficientGas,
+                json!({ "required": U64(required), "prepaid": U64(prepaid) }),
+            );
+        }
+    }
+
+    /// gas used so far, to be passed back to `record_gas` once the phase is over.
+    pub(crate) fn gas_mark(&self) -> Gas {
+        if self.gas_profiling {
+            env::used_gas()
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn record_gas(&mut self, phase: GasPhase, mark: Gas) {
+        if !self.gas_profiling {
+            return;
+        }
+        let spent = env::used_gas() - mark;
+        match phase {
+            GasPhase::Accrual => self.gas_meter.accrual += spent,
+            GasPhase::Storage => self.gas_meter.storage += spent,
+            GasPhase::Promises => self.gas_meter.promises += spent,
+        }
+    }
+
+    /// logs and stores the measurements of the finished `operation`.
+    pub(crate) fn flush_gas_profile(&mut self, operation: &str) {
+        if !self.gas_profiling {
+            return;
+        }
+        let meter = std::mem::take(&mut self.gas_meter);
+        let profile = GasProfile {
+            operation: operation.to_string(),
+            block_index: env::block_index().into(),
+            accrual: meter.accrual.into(),
+            storage: meter.storage.into(),
+            promises: meter.promises.into(),
+            total: env::used_gas().into(),
+        };
+        log!(
+            "Gas used by {}: accrual {}, storage {}, promises {}, total {}",
+            operation,
+            meter.accrual,
+            meter.storage,
+            meter.promises,
+            profile.total.0
+        );
+        if self.gas_profiles.len() < GAS_PROFILE_SIZE {
+            self.gas_profiles.push(&profile);
+        } else {
+            self.gas_profiles.replace(self.gas_profiles_recorded % GAS_PROFILE_SIZE, &profile);
+        }
+        self.gas_profiles_recorded += 1;
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use near_contract_standards::storage_management::StorageManagement;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, obs, reward};
+
+    #[test]
+    fn test_gas_profile_keeps_last_operations() {
+        let mut context = get_context(alice(), 0, 100);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.set_gas_profiling(true);
+        for _ in 0..GAS_PROFILE_SIZE + 3 {
+            contract.storage_deposit(None, None);
+        }
+        let profile = contract.get_gas_profile();
+        assert_eq!(profile.len() as u64, GAS_PROFILE_SIZE);
+        assert!(profile.iter().all(|p| p.operation == "storage_deposit" && p.storage.0 > 0));
+    }
+
+    #[test]
+    fn test_token_gas_override() {
+        testing_env!(get_context(alice(), 0, 100));
+        let mut contract = new_farm();
+        let gas = TokenGas { ft_transfer: U64(3 * BASE_GAS), ..Default::default() };
+        contract.set_token_gas(obs(), Some(gas.clone()));
+        assert_eq!(contract.token_gas(&obs().into()), gas);
+        assert_eq!(contract.get_token_gas(reward()), TokenGas::default());
+        contract.set_token_gas(obs(), None);
+        assert_eq!(contract.get_token_gas(obs()), TokenGas::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Token gas must be between")]
+    fn test_token_gas_bounds() {
+        testing_env!(get_context(alice(), 0, 100));
+        let mut contract = new_farm();
+        let gas = TokenGas { ft_balance_of: U64(MAX_TOKEN_GAS + 1), ..Default::default() };
+        contract.set_token_gas(obs(), Some(gas));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an account with role Operator")]
+    fn test_gas_profiling_needs_operator() {
+        testing_env!(get_context(crate::tests::bob(), 0, 100));
+        let mut contract = new_farm();
+        contract.set_gas_profiling(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "INSUFFICIENT_GAS")]
+    fn test_claim_fails_fast_without_gas() {
+        let mut contract = crate::tests::staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.attached_deposit = 1;
+        context.prepaid_gas = GAS_FOR_ON_TRANSFER;
+        testing_env!(context);
+        contract.claim_all();
+    }
+}
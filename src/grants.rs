@@ -0,0 +1,142 @@
+//! Off-chain reward grants. A signer key configured by the owner, typically held by a
+//! rewards backend, authorizes ad-hoc bonuses such as quest or campaign rewards, which the
+//! receiving account redeems from the reserve with `claim_granted`.
+//!
+//! The signed message is the UTF-8 string
+//! `<farm_id>:<account_id>:<amount>:<nonce>:<expiry>`, amounts and timestamps in decimal
+//! and `expiry` in nanoseconds. Each nonce can be redeemed once across all accounts.
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use near_sdk::json_types::{Base58PublicKey, Base64VecU8, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, near_bindgen};
+use std::convert::TryFrom;
+
+use crate::events::emit_event;
+use crate::*;
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the ed25519 key signing grants, `None` disabling them.
+    pub fn set_grant_signer(&mut self, public_key: Option<Base58PublicKey>) {
+        self.assert_owner();
+        let public_key = public_key.map(|public_key| {
+            let public_key: Vec<u8> = public_key.into();
+            assert_eq!(public_key[0], 0, "Grant signer must be an ed25519 key");
+            public_key[1..].to_vec()
+        });
+        self.grant_signer = public_key;
+        emit_event("set_grant_signer", json!({ "enabled": self.grant_signer.is_some() }));
+    }
+
+    pub fn get_grant_signer(&self) -> Option<Base58PublicKey> {
+        self.grant_signer
+            .as_ref()
+            .map(|public_key| Base58PublicKey::try_from([&[0], &public_key[..]].concat()).unwrap())
+    }
+
+    /// Pays out a grant of `amount` to the predecessor, signed by the grant signer, or queues
+    /// it if the reserve is short.
+    #[payable]
+    pub fn claim_granted(
+        &mut self,
+        amount: U128,
+        nonce: U64,
+        expiry: U64,
+        signature: Base64VecU8,
+    ) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_frozen(&account_id);
+        self.assert_not_contract(&account_id);
+        assert!(self.now() <= expiry.0, "Grant has expired");
+        let signer = self.grant_signer.as_ref().expect("Grants are disabled");
+        let message = format!(
+            "{}:{}:{}:{}:{}",
+            env::current_account_id(),
+            account_id,
+            amount.0,
+            nonce.0,
+            expiry.0
+        );
+        let public_key = PublicKey::from_bytes(signer).expect("Invalid grant signer");
+        let signature = Signature::try_from(&signature.0[..]).expect("Invalid signature");
+        assert!(public_key.verify(message.as_bytes(), &signature).is_ok(), "Invalid signature");
+        assert!(self.grant_nonces.insert(&nonce.0), "Grant was already claimed");
+
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        account.reward_claimed += amount.0;
+        self.save_account(&account_id_hash, &account);
+        self.notify_position_hook(&account_id, &account, PositionEvent::RewardClaimed, amount.0);
+        emit_event(
+            "claim_granted",
+            json!({ "account_id": account_id, "amount": amount, "nonce": nonce }),
+        );
+        let promise = self.pay_or_queue_reward(&account_id, amount.0, |farm| {
+            farm.internal_send_reward(&account_id, amount.0)
+        });
+        promise.map_or(PromiseOrValue::Value(U128(0)), PromiseOrValue::Promise)
+    }
+
+    pub fn is_grant_claimed(&self, nonce: U64) -> bool {
+        self.grant_nonces.contains(&nonce.0)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn signer() -> Keypair {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        Keypair { public: (&secret).into(), secret }
+    }
+
+    fn grant(amount: u128, nonce: u64, expiry: u64) -> Base64VecU8 {
+        let message =
+            format!("{}:{}:{}:{}:{}", env::current_account_id(), alice(), amount, nonce, expiry);
+        signer().sign(message.as_bytes()).to_bytes().to_vec().into()
+    }
+
+    fn granting_farm() -> Farm {
+        let mut contract = staked_farm();
+        let public_key = [&[0], signer().public.as_bytes().as_ref()].concat();
+        contract.set_grant_signer(Some(public_key.try_into().unwrap()));
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract
+    }
+
+    #[test]
+    fn test_claim_granted() {
+        let mut contract = granting_farm();
+        contract.claim_granted(U128(500), U64(1), U64(100), grant(500, 1, 100));
+        assert!(contract.is_grant_claimed(U64(1)));
+        assert_eq!(
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_claimed.0,
+            500
+        );
+        assert_eq!(contract.reward_reserve(), 10u128.pow(30) - 500);
+    }
+
+    #[test]
+    #[should_panic(expected = "Grant was already claimed")]
+    fn test_grant_replay() {
+        let mut contract = granting_farm();
+        contract.claim_granted(U128(500), U64(1), U64(100), grant(500, 1, 100));
+        contract.claim_granted(U128(500), U64(1), U64(100), grant(500, 1, 100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid signature")]
+    fn test_tampered_grant() {
+        let mut contract = granting_farm();
+        contract.claim_granted(U128(5000), U64(1), U64(100), grant(500, 1, 100));
+    }
+}
@@ -0,0 +1,396 @@
+//! Guardians are owner-appointed accounts that can freeze a specific account pending
+//! investigation, e.g. after a stolen-funds report. A frozen account neither accrues
+//! rewards nor withdraws; the frozen period's rewards can be restored when unfreezing.
+//! With a guardian threshold above one, a freeze is only proposed by the first guardian
+//! and executes once enough guardians confirm it within `GUARDIAN_ACTION_WINDOW`.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// maximum length of a freeze reason or an appeal, in bytes.
+pub const MAX_FREEZE_REASON_LEN: usize = 256;
+
+/// time guardians have to confirm a proposed action, in nanoseconds.
+pub const GUARDIAN_ACTION_WINDOW: Timestamp = 24 * 60 * 60 * 1_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FrozenAccount {
+    pub reason: String,
+    pub frozen_by: AccountId,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub frozen_at: Timestamp,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum GuardianAction {
+    Freeze { account_id: AccountId, reason: String },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingGuardianAction {
+    pub action: GuardianAction,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub proposed_at: Timestamp,
+    /// guardians who confirmed, the proposer first.
+    pub confirmations: Vec<AccountId>,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn add_guardian(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.guardians.insert(account_id.as_ref());
+        emit_event("add_guardian", json!({ "account_id": account_id }));
+    }
+
+    pub fn remove_guardian(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.internal_remove_guardian(account_id.as_ref());
+        emit_event("remove_guardian", json!({ "account_id": account_id }));
+    }
+
+    /// Sets how many guardians must confirm a freeze. The owner still acts alone.
+    pub fn set_guardian_threshold(&mut self, threshold: u32) {
+        self.assert_owner();
+        assert!(
+            threshold >= 1 && u64::from(threshold) <= self.guardians.len().max(1),
+            "Threshold must be between 1 and the number of guardians"
+        );
+        self.guardian_threshold = threshold;
+        emit_event("set_guardian_threshold", json!({ "threshold": threshold }));
+    }
+
+    pub fn get_guardian_threshold(&self) -> u32 {
+        self.guardian_threshold
+    }
+
+    pub fn get_guardians(&self) -> Vec<AccountId> {
+        self.guardians.to_vec()
+    }
+
+    /// Halts reward accrual and withdrawals of `account_id`. Below the owner, and with a
+    /// guardian threshold above one, this only proposes the freeze and returns the id of
+    /// the action the other guardians confirm.
+    pub fn freeze_account(&mut self, account_id: ValidAccountId, reason: String) -> Option<U64> {
+        self.assert_guardian();
+        assert!(reason.len() <= MAX_FREEZE_REASON_LEN, "Reason is too long");
+        let account_id: AccountId = account_id.into();
+        assert!(self.frozen_accounts.get(&account_id).is_none(), "Account is already frozen");
+        let predecessor_id = env::predecessor_account_id();
+        if predecessor_id == self.owner_id || self.guardian_threshold <= 1 {
+            self.internal_freeze(account_id, reason, predecessor_id);
+            return None;
+        }
+        let action_id = self.next_guardian_action_id;
+        self.next_guardian_action_id += 1;
+        let pending = PendingGuardianAction {
+            action: GuardianAction::Freeze { account_id, reason },
+            proposed_at: self.now(),
+            confirmations: vec![predecessor_id],
+        };
+        emit_event(
+            "propose_guardian_action",
+            json!({ "action_id": U64(action_id), "action": pending.action }),
+        );
+        self.guardian_actions.insert(&action_id, &pending);
+        Some(action_id.into())
+    }
+
+    /// Confirms a proposed guardian action, executing it once enough guardians confirmed.
+    /// Returns whether it was executed.
+    pub fn confirm_action(&mut self, action_id: U64) -> bool {
+        self.assert_guardian();
+        let mut pending = self.guardian_actions.get(&action_id.0).expect("No such action");
+        if self.now() > pending.proposed_at + GUARDIAN_ACTION_WINDOW {
+            self.guardian_actions.remove(&action_id.0);
+            log!("Guardian action {} expired", action_id.0);
+            return false;
+        }
+        let predecessor_id = env::predecessor_account_id();
+        assert!(!pending.confirmations.contains(&predecessor_id), "Already confirmed");
+        pending.confirmations.push(predecessor_id.clone());
+        // confirmations of removed guardians don't count
+        let confirmations = pending
+            .confirmations
+            .iter()
+            .filter(|guardian_id| self.guardians.contains(guardian_id))
+            .count();
+        emit_event(
+            "confirm_guardian_action",
+            json!({ "action_id": action_id, "guardian_id": predecessor_id }),
+        );
+        if predecessor_id != self.owner_id && confirmations < self.guardian_threshold as usize {
+            self.guardian_actions.insert(&action_id.0, &pending);
+            return false;
+        }
+        self.guardian_actions.remove(&action_id.0);
+        match pending.action {
+            GuardianAction::Freeze { account_id, reason } => {
+                if self.is_frozen(&account_id) {
+                    log!("@{} is already frozen", account_id);
+                    return false;
+                }
+                self.internal_freeze(account_id, reason, pending.confirmations.remove(0));
+            }
+        }
+        true
+    }
+
+    /// Proposed guardian actions that haven't expired, by id.
+    pub fn get_pending_guardian_actions(&self) -> Vec<(U64, PendingGuardianAction)> {
+        let now = self.now();
+        self.guardian_actions
+            .iter()
+            .filter(|(_, pending)| now <= pending.proposed_at + GUARDIAN_ACTION_WINDOW)
+            .map(|(action_id, pending)| (action_id.into(), pending))
+            .collect()
+    }
+
+    /// Lifts a freeze. With `restore_accrual` (the default) the rewards of the frozen period
+    /// are credited on the account's next interaction, otherwise they are forfeited.
+    /// Guardians and the owner can unfreeze, the owner being the appeal path.
+    pub fn unfreeze_account(&mut self, account_id: ValidAccountId, restore_accrual: Option<bool>) {
+        self.assert_guardian();
+        let account_id: AccountId = account_id.into();
+        let frozen = self.frozen_accounts.remove(&account_id).expect("Account is not frozen");
+        let restore_accrual = restore_accrual.unwrap_or(true);
+        if !restore_accrual {
+            let (account_id_hash, account) = self.get_internal_account(&account_id);
+            if let Some(mut account) = account {
+                account.last_accrual_time = self.now();
+                self.save_account(&account_id_hash, &account);
+            }
+        }
+        emit_event(
+            "unfreeze_account",
+            json!({
+                "account_id": account_id,
+                "unfrozen_by": env::predecessor_account_id(),
+                "frozen_at": TimestampView::from(frozen.frozen_at),
+                "restore_accrual": restore_accrual,
+            }),
+        );
+    }
+
+    /// Lets a frozen account contest its freeze; the owner reviews appeals from the events.
+    pub fn appeal_freeze(&mut self, message: String) {
+        assert!(message.len() <= MAX_FREEZE_REASON_LEN, "Appeal is too long");
+        let account_id = env::predecessor_account_id();
+        self.assert_frozen(&account_id);
+        emit_event("appeal_freeze", json!({ "account_id": account_id, "message": message }));
+    }
+
+    pub fn get_frozen_account(&self, account_id: ValidAccountId) -> Option<FrozenAccount> {
+        self.frozen_accounts.get(account_id.as_ref())
+    }
+}
+
+impl Farm {
+    fn internal_freeze(&mut self, account_id: AccountId, reason: String, frozen_by: AccountId) {
+        // settle the rewards earned before the freeze
+        let (account_id_hash, account) = self.get_mut_account(&account_id);
+        self.save_account(&account_id_hash, &account);
+        self.notify_position_hook(
+            &account_id,
+            &account,
+            PositionEvent::Frozen,
+            account.obs_balance,
+        );
+        let frozen = FrozenAccount { reason, frozen_by, frozen_at: self.now() };
+        emit_event("freeze_account", json!({ "account_id": account_id, "freeze": frozen }));
+        self.frozen_accounts.insert(&account_id, &frozen);
+    }
+
+    /// Removes a guardian. With the default threshold of one the last guardian can go too,
+    /// leaving freezes to the owner.
+    pub(crate) fn internal_remove_guardian(&mut self, account_id: &AccountId) {
+        assert!(self.guardians.contains(account_id), "Not a guardian");
+        assert!(
+            self.guardian_threshold <= 1
+                || self.guardians.len() > u64::from(self.guardian_threshold),
+            "Removing the guardian would leave fewer guardians than the threshold"
+        );
+        self.guardians.remove(account_id);
+    }
+
+    pub(crate) fn assert_guardian(&self) {
+        let predecessor_id = env::predecessor_account_id();
+        assert!(
+            predecessor_id == self.owner_id || self.guardians.contains(&predecessor_id),
+            "Only a guardian can call this method"
+        );
+    }
+
+    fn assert_frozen(&self, account_id: &AccountId) {
+        assert!(self.is_frozen(account_id), "Account is not frozen");
+    }
+
+    pub(crate) fn is_frozen(&self, account_id: &AccountId) -> bool {
+        self.frozen_accounts.contains_key(account_id)
+    }
+
+    pub(crate) fn assert_not_frozen(&self, account_id: &AccountId) {
+        if self.is_frozen(account_id) {
+            fail(ErrorCode::AccountFrozen, json!({ "account_id": account_id }));
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn frozen_farm() -> Farm {
+        let mut contract = staked_farm();
+        contract.add_guardian(bob().try_into().unwrap());
+        testing_env!(get_context(bob(), env::storage_usage(), contract.cliff_time + 1));
+        contract.freeze_account(alice().try_into().unwrap(), "stolen keys".to_string());
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is frozen")]
+    fn test_frozen_account_cannot_claim() {
+        let mut contract = frozen_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.claim_all();
+    }
+
+    #[test]
+    fn test_unfreeze_restores_accrual() {
+        let mut contract = frozen_farm();
+        let frozen_at = contract.cliff_time + 1;
+        let at_freeze = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+
+        testing_env!(get_context(bob(), env::storage_usage(), 2 * frozen_at));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance, at_freeze);
+        contract.unfreeze_account(alice().try_into().unwrap(), None);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(account.reward_balance.0 > at_freeze.0);
+    }
+
+    #[test]
+    fn test_unfreeze_forfeits_accrual() {
+        let mut contract = frozen_farm();
+        let frozen_at = contract.cliff_time + 1;
+        let at_freeze = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+
+        testing_env!(get_context(bob(), env::storage_usage(), 2 * frozen_at));
+        contract.unfreeze_account(alice().try_into().unwrap(), Some(false));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance, at_freeze);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is frozen")]
+    fn test_frozen_account_cannot_top_up() {
+        let mut contract = frozen_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.stake_my_obs(U128(10u128.pow(6)));
+    }
+
+    #[test]
+    fn test_top_up_after_forfeit_keeps_forfeit() {
+        let mut contract = frozen_farm();
+        let frozen_at = contract.cliff_time + 1;
+        let at_freeze = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+
+        testing_env!(get_context(bob(), env::storage_usage(), 2 * frozen_at));
+        contract.unfreeze_account(alice().try_into().unwrap(), Some(false));
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * frozen_at));
+        contract.stake_my_obs(U128(10u128.pow(6)));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance, at_freeze);
+    }
+
+    #[test]
+    fn test_freeze_needs_guardian_confirmations() {
+        let mut contract = staked_farm();
+        contract.add_guardian(bob().try_into().unwrap());
+        contract.add_guardian("carol.near".try_into().unwrap());
+        contract.set_guardian_threshold(2);
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        let action_id = contract
+            .freeze_account(alice().try_into().unwrap(), "stolen keys".to_string())
+            .unwrap();
+        assert!(!contract.is_frozen(&alice()));
+        assert_eq!(contract.get_pending_guardian_actions().len(), 1);
+
+        testing_env!(get_context("carol.near".to_string(), env::storage_usage(), 0));
+        assert!(contract.confirm_action(action_id));
+        assert_eq!(
+            contract.get_frozen_account(alice().try_into().unwrap()).unwrap().frozen_by,
+            bob()
+        );
+        assert!(contract.get_pending_guardian_actions().is_empty());
+    }
+
+    #[test]
+    fn test_guardian_action_expires() {
+        let mut contract = staked_farm();
+        contract.add_guardian(bob().try_into().unwrap());
+        contract.add_guardian("carol.near".try_into().unwrap());
+        contract.set_guardian_threshold(2);
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        let action_id = contract
+            .freeze_account(alice().try_into().unwrap(), "stolen keys".to_string())
+            .unwrap();
+
+        let expired_at = GUARDIAN_ACTION_WINDOW + 1;
+        testing_env!(get_context("carol.near".to_string(), env::storage_usage(), expired_at));
+        assert!(contract.get_pending_guardian_actions().is_empty());
+        assert!(!contract.confirm_action(action_id));
+        assert!(!contract.is_frozen(&alice()));
+    }
+
+    #[test]
+    fn test_remove_last_guardian() {
+        let mut contract = staked_farm();
+        contract.add_guardian(bob().try_into().unwrap());
+        contract.remove_guardian(bob().try_into().unwrap());
+        assert!(contract.get_guardians().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Not a guardian")]
+    fn test_remove_non_guardian() {
+        let mut contract = staked_farm();
+        contract.add_guardian(bob().try_into().unwrap());
+        contract.remove_guardian("carol.near".try_into().unwrap());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Removing the guardian would leave fewer guardians than the threshold"
+    )]
+    fn test_remove_guardian_below_threshold() {
+        let mut contract = staked_farm();
+        contract.add_guardian(bob().try_into().unwrap());
+        contract.add_guardian("carol.near".try_into().unwrap());
+        contract.set_guardian_threshold(2);
+        contract.remove_guardian(bob().try_into().unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only a guardian can call this method")]
+    fn test_freeze_guardian_only() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.freeze_account(alice().try_into().unwrap(), String::new());
+    }
+}
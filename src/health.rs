@@ -0,0 +1,232 @@
+//! Watchdog on the token contracts. With the watchdog set, a keeper with the `Health` scope
+//! calls `health_check`, which reads `ft_metadata` and the farm's `ft_balance_of` from the
+//! obs and reward tokens. A token that doesn't answer, or a balance short of what the
+//! ledger says the farm holds by more than `max_shortfall_bps`, is an incident: stakes are
+//! refused until an operator resumes them, so no more principal goes into a token that
+//! upgraded badly. Claims and unstakes are left alone. A balance above the ledger's, e.g.
+//! from tokens sent without a msg, is no incident.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, json};
+use near_sdk::{env, near_bindgen, Promise, PromiseResult};
+
+use crate::bots::BotScope;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HealthWatchdog {
+    /// largest shortfall of a token balance against the ledger, in basis points.
+    pub max_shortfall_bps: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HealthView {
+    pub watchdog: Option<HealthWatchdog>,
+    pub checked_at: Option<TimestampView>,
+    /// when stakes were paused by an incident, `None` while they are accepted.
+    pub deposits_paused_at: Option<TimestampView>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the health watchdog, `None` to stop checking the token contracts.
+    pub fn set_health_watchdog(&mut self, watchdog: Option<HealthWatchdog>) {
+        self.assert_role(Role::Operator);
+        if let Some(watchdog) = &watchdog {
+            assert!(watchdog.max_shortfall_bps <= BPS_DENOM as u32, "Shortfall is above 100%");
+        }
+        self.health_watchdog = watchdog.clone();
+        emit_event("set_health_watchdog", json!({ "watchdog": watchdog }));
+    }
+
+    pub fn get_health(&self) -> HealthView {
+        HealthView {
+            watchdog: self.health_watchdog.clone(),
+            checked_at: self.health_checked_at.map(TimestampView::from),
+            deposits_paused_at: self.deposits_paused_at.map(TimestampView::from),
+        }
+    }
+
+    /// Reads the metadata and the farm's balance from both token contracts.
+    pub fn health_check(&mut self) -> Promise {
+        self.assert_automation(BotScope::Health);
+        assert!(self.health_watchdog.is_some(), "No health watchdog is set");
+        let token_ids = self.health_token_ids();
+        self.assert_prepaid_gas(self.health_check_gas() - BASE_GAS);
+        let reads = token_ids
+            .iter()
+            .map(|token_id| {
+                ext_fungible_token::ft_metadata(token_id, NO_DEPOSIT, BASE_GAS).and(
+                    ext_fungible_token::ft_balance_of(
+                        env::current_account_id(),
+                        token_id,
+                        NO_DEPOSIT,
+                        self.token_gas(token_id).ft_balance_of.0,
+                    ),
+                )
+            })
+            .reduce(Promise::and)
+            .unwrap();
+        reads.then(ext_self::on_health_check(
+            token_ids,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Pauses stakes if a token didn't answer or holds too little for the farm.
+    #[private]
+    pub fn on_health_check(&mut self, token_ids: Vec<AccountId>) {
+        self.health_checked_at = Some(self.now());
+        let max_shortfall_bps = match &self.health_watchdog {
+            Some(watchdog) => watchdog.max_shortfall_bps,
+            None => return,
+        };
+        let expected = self.get_token_balances();
+        for (i, token_id) in token_ids.into_iter().enumerate() {
+            let responded = matches!(
+                env::promise_result(2 * i as u64),
+                PromiseResult::Successful(metadata)
+                    if serde_json::from_slice::<FungibleTokenMetadata>(&metadata).is_ok()
+            );
+            let balance = match env::promise_result(2 * i as u64 + 1) {
+                PromiseResult::Successful(value) => {
+                    serde_json::from_slice::<U128>(&value).ok().map(|balance| balance.0)
+                }
+                _ => None,
+            };
+            let expected = expected
+                .iter()
+                .find(|balances| balances.token_id == token_id)
+                .map_or(0, |balances| balances.total.0);
+            let reason = match balance {
+                _ if !responded => "metadata",
+                None => "balance",
+                Some(balance)
+                    if U256::from(expected.saturating_sub(balance)) * U256::from(BPS_DENOM)
+                        > U256::from(expected) * U256::from(max_shortfall_bps) =>
+                {
+                    "shortfall"
+                }
+                Some(_) => continue,
+            };
+            if self.deposits_paused_at.is_none() {
+                self.deposits_paused_at = Some(self.now());
+            }
+            emit_event(
+                "health_incident",
+                json!({
+                    "token_id": token_id,
+                    "reason": reason,
+                    "balance": balance.map(U128),
+                    "expected": U128(expected),
+                }),
+            );
+        }
+    }
+
+    /// Accepts stakes again after an incident.
+    pub fn resume_deposits(&mut self) {
+        self.assert_role(Role::Operator);
+        assert!(self.deposits_paused_at.take().is_some(), "Deposits are not paused");
+        emit_event("resume_deposits", json!({}));
+    }
+}
+
+impl Farm {
+    fn health_token_ids(&self) -> Vec<AccountId> {
+        let mut token_ids = vec![self.obs_token_account_id.clone()];
+        if !self.is_single_token() {
+            token_ids.push(self.reward_token_account_id.clone());
+        }
+        token_ids
+    }
+
+    /// gas to attach to `health_check`: a metadata and a balance read per token.
+    pub(crate) fn health_check_gas(&self) -> Gas {
+        let reads = self
+            .health_token_ids()
+            .iter()
+            .map(|token_id| BASE_GAS + self.token_gas(token_id).ft_balance_of.0)
+            .sum::<Gas>();
+        BASE_GAS + reads + GAS_FOR_RESOLVE_TRANSFER
+    }
+
+    pub(crate) fn assert_deposits_open(&self) {
+        assert!(self.deposits_paused_at.is_none(), "Deposits are paused after a token incident");
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+
+    fn check_health(contract: &mut Farm, obs_balance: u128, reward_balance: Option<u128>) {
+        let metadata = json!({
+            "spec": "ft-1.0.0",
+            "name": "Token",
+            "symbol": "TKN",
+            "icon": null,
+            "reference": null,
+            "reference_hash": null,
+            "decimals": 24,
+        });
+        let balance = |balance: Option<u128>| match balance {
+            Some(balance) => PromiseResult::Successful(json!(U128(balance)).to_string().into()),
+            None => PromiseResult::Failed,
+        };
+        let results = vec![
+            PromiseResult::Successful(metadata.to_string().into_bytes()),
+            balance(Some(obs_balance)),
+            PromiseResult::Successful(metadata.to_string().into_bytes()),
+            balance(reward_balance),
+        ];
+        testing_env!(
+            get_context(env::current_account_id(), env::storage_usage(), 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            results
+        );
+        contract.on_health_check(vec![
+            contract.obs_token_account_id.clone(),
+            contract.reward_token_account_id.clone(),
+        ]);
+    }
+
+    #[test]
+    fn test_incident_pauses_deposits() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_health_watchdog(Some(HealthWatchdog { max_shortfall_bps: 100 }));
+        let reserve = contract.get_token_balances()[1].total.0;
+        check_health(&mut contract, 995, Some(reserve));
+        assert!(contract.get_health().deposits_paused_at.is_none());
+        // the reward token didn't return the balance
+        check_health(&mut contract, 1000, None);
+        assert!(contract.get_health().deposits_paused_at.is_some());
+
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.resume_deposits();
+        check_health(&mut contract, 980, Some(reserve));
+        assert!(contract.get_health().deposits_paused_at.is_some());
+    }
+
+    #[test]
+    #[should_panic(expected = "Deposits are paused after a token incident")]
+    fn test_no_stakes_while_paused() {
+        let mut contract = staked_farm();
+        contract.deposits_paused_at = Some(0);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+    }
+}
@@ -0,0 +1,135 @@
+//! Archival of old payouts to a side contract, so the farm's storage and the gas of its
+//! history views stay bounded. Once an operator names the archive contract, anyone can
+//! page an account's payouts older than its latest `RECENT_CLAIM_RECORDS` into it with
+//! `archive_claims`; they are dropped here once the archive took them. Claim reports tell
+//! which payouts only the archive holds. The archive keeps records by account and index,
+//! so a page sent twice is stored once. Records already archived stay in the archive they
+//! were sent to.
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{env, ext_contract, is_promise_success, near_bindgen, Gas, Promise};
+
+use crate::claim_history::ClaimRecord;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// payouts of each account that are never archived.
+pub const RECENT_CLAIM_RECORDS: u64 = 100;
+
+/// most payouts archived at once.
+pub const MAX_ARCHIVE_PAGE: u64 = 100;
+
+/// gas attached to the archive's `archive_claims`.
+pub const GAS_FOR_ARCHIVE: Gas = 4 * BASE_GAS;
+
+#[ext_contract(ext_history_archive)]
+pub trait HistoryArchive {
+    /// Stores `records` of `account_id`, the first of them at index `from_index`.
+    fn archive_claims(&mut self, account_id: AccountId, from_index: U64, records: Vec<ClaimRecord>);
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn set_history_archive(&mut self, archive_id: Option<ValidAccountId>) {
+        self.assert_role(Role::Operator);
+        self.history_archive = archive_id.map(|archive_id| archive_id.into());
+        emit_event("set_history_archive", json!({ "archive_id": self.history_archive }));
+    }
+
+    pub fn get_history_archive(&self) -> Option<AccountId> {
+        self.history_archive.clone()
+    }
+
+    /// Sends the next page of the account's old payouts to the archive.
+    pub fn archive_claims(&mut self, account_id: ValidAccountId) -> Promise {
+        let archive_id = self.history_archive.clone().expect("No history archive is set");
+        let account_id: AccountId = account_id.into();
+        let account_id_hash = ShortAccountHash::from(&account_id);
+        let history = &self.claim_history;
+        let from_index = history.first_local(&account_id_hash);
+        let archivable = history.recorded(&account_id_hash).saturating_sub(RECENT_CLAIM_RECORDS);
+        assert!(from_index < archivable, "No payouts to archive");
+        let through_index = archivable.min(from_index + MAX_ARCHIVE_PAGE);
+        let records =
+            (from_index..through_index).map(|index| history.get(&account_id_hash, index)).collect();
+        self.assert_prepaid_gas(GAS_FOR_ARCHIVE + GAS_FOR_RESOLVE_TRANSFER);
+        ext_history_archive::archive_claims(
+            account_id.clone(),
+            from_index.into(),
+            records,
+            &archive_id,
+            NO_DEPOSIT,
+            GAS_FOR_ARCHIVE,
+        )
+        .then(ext_self::on_claims_archived(
+            account_id,
+            archive_id,
+            from_index.into(),
+            through_index.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Drops the payouts the archive took.
+    #[private]
+    pub fn on_claims_archived(
+        &mut self,
+        account_id: AccountId,
+        archive_id: AccountId,
+        from_index: U64,
+        through_index: U64,
+    ) {
+        if !is_promise_success() {
+            log!("Archiving payouts of @{} failed, they are kept", account_id);
+            return;
+        }
+        self.claim_history.drop_archived(&(&account_id).into(), through_index.0);
+        emit_event(
+            "archive_claims",
+            json!({
+                "account_id": account_id,
+                "archive_id": archive_id,
+                "from_index": from_index,
+                "through_index": through_index,
+            }),
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_archive_claims() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_history_archive(Some(bob().try_into().unwrap()));
+        for day in 0..RECENT_CLAIM_RECORDS + 30 {
+            testing_env!(get_context(alice(), env::storage_usage(), day));
+            contract.record_claim(&alice(), 1, "reward.near".to_string(), None, None);
+        }
+        contract.archive_claims(alice().try_into().unwrap());
+
+        testing_env!(
+            get_context(env::current_account_id(), env::storage_usage(), 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(vec![])]
+        );
+        contract.on_claims_archived(alice(), bob(), U64(0), U64(30));
+        let report =
+            contract.get_claims_report(alice().try_into().unwrap(), U64(0), U64(1_000), None, None);
+        assert_eq!(report.claims.len(), RECENT_CLAIM_RECORDS as usize);
+        assert_eq!(report.archived_before, Some(U64(30)));
+        assert_eq!(report.archive_id, Some(bob()));
+    }
+}
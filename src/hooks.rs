@@ -0,0 +1,117 @@
+//! Position hooks. An account can name a contract, e.g. a lending protocol holding its
+//! position as collateral or a smart wallet automating it, that is notified whenever the
+//! position changes. Notifications are detached calls with fixed gas, so a failing hook
+//! never blocks the operation that triggered it.
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, ext_contract, near_bindgen, Gas};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// gas attached to every hook notification.
+pub const GAS_FOR_POSITION_HOOK: Gas = BASE_GAS;
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum PositionEvent {
+    /// a stake was credited to the account.
+    Staked,
+    /// rewards were paid out, or sent on to another farm, for the account.
+    RewardClaimed,
+    /// the principal left the farm through `unstake_my_obs` or `exit`.
+    Unstaked,
+    /// a guardian froze the account.
+    Frozen,
+}
+
+#[ext_contract(ext_position_hook)]
+pub trait PositionHook {
+    fn on_position_event(&mut self, account_id: AccountId, event: PositionEvent, amount: U128);
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets or removes the predecessor's position hook. The hook id is charged to the
+    /// account's storage deposit.
+    pub fn set_position_hook(&mut self, hook_id: Option<ValidAccountId>) {
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        account.preferences.notification_hook = hook_id;
+        self.save_account(&account_id_hash, &account);
+        emit_event(
+            "set_position_hook",
+            json!({ "account_id": account_id, "hook_id": account.preferences.notification_hook }),
+        );
+    }
+}
+
+impl Farm {
+    /// Notifies the account's hook, if any, without waiting for or depending on the result.
+    pub(crate) fn notify_position_hook(
+        &self,
+        account_id: &AccountId,
+        account: &Account,
+        event: PositionEvent,
+        amount: Balance,
+    ) {
+        if let Some(hook_id) = &account.preferences.notification_hook {
+            log!("Notifying @{} of {:?} of @{}", hook_id, event, account_id);
+            ext_position_hook::on_position_event(
+                account_id.clone(),
+                event,
+                amount.into(),
+                hook_id,
+                NO_DEPOSIT,
+                GAS_FOR_POSITION_HOOK,
+            );
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_stake_and_claim_notify_hook() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        contract.set_position_hook(Some(bob().try_into().unwrap()));
+
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(500));
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.claim_all();
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs
+            .iter()
+            .any(|log| log == "Notifying @bob.near of RewardClaimed of @alice.near"));
+    }
+
+    #[test]
+    fn test_unstake_notifies_hook() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        contract.set_position_hook(Some(bob().try_into().unwrap()));
+
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs.iter().any(|log| log == "Notifying @bob.near of Unstaked of @alice.near"));
+    }
+}
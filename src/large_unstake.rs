@@ -0,0 +1,164 @@
+//! Opt-in protection against a single compromised key draining a large stake. Once an
+//! account sets a threshold, unstaking more than it takes a `request_large_unstake`
+//! followed by `confirm_large_unstake` at least `large_unstake_delay` later. Loosening the
+//! threshold goes through the same request, so the key alone can't switch it off.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// default delay between a large unstake request and its confirmation, in nanoseconds.
+pub const DEFAULT_LARGE_UNSTAKE_DELAY: Timestamp = 24 * 60 * 60 * 1_000_000_000;
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the predecessor's large unstake threshold, `None` turning the protection off.
+    /// Raising or removing an active threshold needs a matured request.
+    #[payable]
+    pub fn set_large_unstake_threshold(&mut self, threshold: Option<U128>) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        let threshold = threshold.map(|t| t.0);
+        let loosened = match (account.large_unstake_threshold, threshold) {
+            (Some(current), Some(new)) => new > current,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        if loosened {
+            self.consume_large_unstake_request(&mut account);
+        }
+        account.large_unstake_threshold = threshold;
+        self.save_account(&account_id_hash, &account);
+        emit_event(
+            "set_large_unstake_threshold",
+            json!({ "account_id": account_id, "threshold": threshold.map(U128) }),
+        );
+    }
+
+    /// Starts the delay after which a large unstake or a looser threshold is allowed.
+    pub fn request_large_unstake(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        assert!(account.large_unstake_threshold.is_some(), "Large unstake protection is off");
+        let now = self.now();
+        account.large_unstake_requested_at = Some(now);
+        self.save_account(&account_id_hash, &account);
+        emit_event(
+            "request_large_unstake",
+            json!({
+                "account_id": account_id,
+                "confirmable_at": U64(now + self.large_unstake_delay),
+            }),
+        );
+    }
+
+    pub fn cancel_large_unstake(&mut self) {
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        assert!(account.large_unstake_requested_at.take().is_some(), "No large unstake requested");
+        self.save_account(&account_id_hash, &account);
+        emit_event("cancel_large_unstake", json!({ "account_id": account_id }));
+    }
+
+    /// Unstakes the whole balance once a requested large unstake has matured.
+    #[payable]
+    pub fn confirm_large_unstake(&mut self) {
+        let (_, account) = self.get_registered_account(&env::predecessor_account_id());
+        self.assert_large_unstake_matured(&account);
+        self.unstake_my_obs();
+    }
+
+    pub fn set_large_unstake_delay(&mut self, delay: U64) {
+        self.assert_owner();
+        self.large_unstake_delay = delay.into();
+        log!("Large unstake delay set to {}", self.large_unstake_delay);
+    }
+
+    pub fn get_large_unstake_delay(&self) -> U64 {
+        self.large_unstake_delay.into()
+    }
+}
+
+impl Farm {
+    /// Lets `principal` leave the account, consuming a matured request when the amount is
+    /// above the account's threshold.
+    pub(crate) fn check_large_unstake(&self, account: &mut Account, principal: Balance) {
+        match account.large_unstake_threshold {
+            Some(threshold) if principal > threshold => self.consume_large_unstake_request(account),
+            _ => {}
+        }
+    }
+
+    fn assert_large_unstake_matured(&self, account: &Account) {
+        let requested_at = account
+            .large_unstake_requested_at
+            .expect("Unstakes above the threshold need request_large_unstake first");
+        assert!(
+            self.now() >= requested_at + self.large_unstake_delay,
+            "Large unstake can't be confirmed yet"
+        );
+    }
+
+    fn consume_large_unstake_request(&self, account: &mut Account) {
+        self.assert_large_unstake_matured(account);
+        account.large_unstake_requested_at = None;
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn guarded_farm() -> Farm {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_large_unstake_threshold(Some(U128(500)));
+        contract.set_large_unstake_delay(U64(1_000));
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Unstakes above the threshold need request_large_unstake first")]
+    fn test_large_unstake_needs_request() {
+        let mut contract = guarded_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.unstake_my_obs();
+    }
+
+    #[test]
+    #[should_panic(expected = "Large unstake can't be confirmed yet")]
+    fn test_large_unstake_delay() {
+        let mut contract = guarded_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.request_large_unstake();
+        contract.confirm_large_unstake();
+    }
+
+    #[test]
+    fn test_confirm_large_unstake() {
+        let mut contract = guarded_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.request_large_unstake();
+
+        let now = contract.cliff_time + 1 + contract.large_unstake_delay;
+        let mut context = get_context(alice(), env::storage_usage(), now);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.confirm_large_unstake();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 0);
+        assert!(account.large_unstake_confirmable_at.is_none());
+    }
+}
@@ -0,0 +1,293 @@
+//! Double-entry bookkeeping of the tokens held by the farm. Every movement is posted as a
+//! credit to the account the tokens leave and a debit to the one they enter, and no holding
+//! account can go below zero. The ledger is reconciled against records kept apart from it:
+//! `Staked` against the principal of the accounts, summed as they are saved, and the tokens
+//! held against the token contracts' `ft_balance_of` by `health_check`.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum LedgerAccount {
+    /// obs received from stakers, the source of `Staked` and `Fees`.
+    Deposits,
+    /// obs held on behalf of the stakers.
+    Staked,
+    /// staking fees kept by the farm.
+    Fees,
+    /// obs returned to the stakers.
+    Withdrawals,
+    /// obs bid in the VIP auction, held until settled and refunded, see `vip_auction`.
+    Escrow,
+    /// reward tokens sent to the farm, the source of `Reserve`.
+    Funding,
+    /// reward tokens available for payouts.
+    Reserve,
+    /// reward tokens paid out of the reserve.
+    Claimed,
+    /// reward tokens lent to strategies, see `strategy`.
+    Deployed,
+    /// reward tokens of closed reward periods, held for their claims, see `reward_periods`.
+    Settled,
+    /// reward tokens taken by the token contract as fees on payouts, see `transfer_fees`.
+    TransferFees,
+    /// reward tokens funded ahead and not released to the reserve yet, see `drip`.
+    Locked,
+    /// staking fees burned, held by the farm and never paid out, see `fee_destination`.
+    BurnedFees,
+    /// staking and registration fees sent to the treasury.
+    SweptFees,
+}
+
+impl LedgerAccount {
+    pub const ALL: [LedgerAccount; 14] = [
+        LedgerAccount::Deposits,
+        LedgerAccount::Staked,
+        LedgerAccount::Fees,
+        LedgerAccount::Withdrawals,
+        LedgerAccount::Escrow,
+        LedgerAccount::Funding,
+        LedgerAccount::Reserve,
+        LedgerAccount::Claimed,
+        LedgerAccount::Deployed,
+        LedgerAccount::Settled,
+        LedgerAccount::TransferFees,
+        LedgerAccount::Locked,
+        LedgerAccount::BurnedFees,
+        LedgerAccount::SweptFees,
+    ];
+
+    /// source accounts stand for tokens outside of the farm and carry a credit balance.
+    fn is_source(self) -> bool {
+        matches!(self, LedgerAccount::Deposits | LedgerAccount::Funding)
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Ledger {
+    debits: [Balance; 14],
+    credits: [Balance; 14],
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LedgerEntry {
+    pub account: LedgerAccount,
+    pub debit: U128,
+    pub credit: U128,
+}
+
+impl Ledger {
+    /// Moves `amount` from the `from` account to the `to` account.
+    pub fn post(&mut self, from: LedgerAccount, to: LedgerAccount, amount: Balance) {
+        if amount == 0 {
+            return;
+        }
+        self.credits[from as usize] += amount;
+        self.debits[to as usize] += amount;
+        self.assert_not_overdrawn();
+    }
+
+    /// Tokens held in a non-source account.
+    pub fn balance(&self, account: LedgerAccount) -> Balance {
+        self.debits[account as usize] - self.credits[account as usize]
+    }
+
+    fn assert_not_overdrawn(&self) {
+        for account in LedgerAccount::ALL.iter().filter(|account| !account.is_source()) {
+            assert!(
+                self.debits[*account as usize] >= self.credits[*account as usize],
+                "Ledger account {:?} is overdrawn",
+                account
+            );
+        }
+    }
+}
+
+/// tokens the farm should hold of one token contract, to reconcile with its `ft_balance_of`.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenBalances {
+    pub token_id: AccountId,
+    pub principal: U128,
+    pub fees: U128,
+    pub reserve: U128,
+    pub total: U128,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// One entry per token contract, a single one when the obs token is also the reward token.
+    /// Reward tokens of closed reward periods are listed with the reserve held for their
+    /// claims.
+    pub fn get_token_balances(&self) -> Vec<TokenBalances> {
+        let mut token_ids = vec![self.obs_token_account_id.clone()];
+        if !self.is_single_token() {
+            token_ids.push(self.reward_token_account_id.clone());
+        }
+        for period in self.reward_periods.iter() {
+            if !token_ids.contains(&period.token_id) {
+                token_ids.push(period.token_id);
+            }
+        }
+        token_ids
+            .into_iter()
+            .map(|token_id| {
+                let (principal, fees) = self.principal_held(&token_id);
+                let mut reserve = if token_id == self.reward_token_account_id {
+                    self.reward_reserve() + self.ledger.balance(LedgerAccount::Locked)
+                } else {
+                    0
+                };
+                reserve += self
+                    .reward_periods
+                    .iter()
+                    .filter(|period| period.token_id == token_id)
+                    .map(|period| period.reserve)
+                    .sum::<Balance>();
+                TokenBalances {
+                    token_id,
+                    principal: principal.into(),
+                    fees: fees.into(),
+                    reserve: reserve.into(),
+                    total: (principal + fees + reserve).into(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn get_ledger(&self) -> Vec<LedgerEntry> {
+        LedgerAccount::ALL
+            .iter()
+            .map(|account| LedgerEntry {
+                account: *account,
+                debit: self.ledger.debits[*account as usize].into(),
+                credit: self.ledger.credits[*account as usize].into(),
+            })
+            .collect()
+    }
+}
+
+impl Farm {
+    /// Adds the change of an account's principal from `old` to `new` to the principal of
+    /// all accounts.
+    pub(crate) fn record_account_principal(
+        &mut self,
+        old: Option<&Account>,
+        new: Option<&Account>,
+    ) {
+        let principal =
+            |account: Option<&Account>| account.map_or(0, |account| account.obs_balance);
+        self.staked_by_accounts = self.staked_by_accounts + principal(new) - principal(old);
+    }
+
+    /// Checked on every account save. Unregistered accounts hold no principal, but for
+    /// positions consolidated into one saved after them.
+    pub(crate) fn assert_ledger_reconciled(&self) {
+        assert_eq!(
+            self.ledger.balance(LedgerAccount::Staked),
+            self.staked_by_accounts,
+            "Staked ledger balance doesn't match the accounts"
+        );
+    }
+
+    /// reward tokens available for payouts.
+    pub(crate) fn reward_reserve(&self) -> Balance {
+        self.ledger.balance(LedgerAccount::Reserve)
+    }
+
+    /// whether the obs token is also the reward token, so one balance holds both.
+    pub(crate) fn is_single_token(&self) -> bool {
+        self.obs_token_account_id == self.reward_token_account_id
+    }
+
+    /// staked and escrowed principal and fees held in `token_id`, none of which may pay
+    /// rewards.
+    pub(crate) fn principal_held(&self, token_id: &AccountId) -> (Balance, Balance) {
+        if *token_id != self.obs_token_account_id {
+            return (0, 0);
+        }
+        let principal =
+            self.ledger.balance(LedgerAccount::Staked) + self.ledger.balance(LedgerAccount::Escrow);
+        let fees = self.ledger.balance(LedgerAccount::Fees)
+            + self.ledger.balance(LedgerAccount::BurnedFees);
+        (principal, fees)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, obs, staked_farm};
+    use std::convert::TryInto;
+
+    /// farm paying rewards in obs, with alice staking 1300 and a reserve of 500.
+    fn single_token_farm() -> Farm {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = Farm::new(FarmConfig::new(alice().try_into().unwrap(), obs(), obs()));
+        contract.register_account();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(300), "Stake".to_string());
+        contract.ft_on_transfer(alice(), U128(500), FUND_RESERVE_MSG.to_string());
+        contract
+    }
+
+    #[test]
+    fn test_single_token_balances() {
+        let contract = single_token_farm();
+        let balances = contract.get_token_balances();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].principal.0, 1300);
+        assert_eq!(balances[0].reserve.0, 500);
+        assert_eq!(balances[0].total.0, 1300 + balances[0].fees.0 + 500);
+    }
+
+    #[test]
+    #[should_panic(expected = "Sweep would dip into staked principal")]
+    fn test_sweep_keeps_principal() {
+        let mut contract = single_token_farm();
+        let total = contract.get_token_balances()[0].total.0;
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.on_sweep_balance(U128(total - 1));
+    }
+
+    #[test]
+    fn test_ledger_balances() {
+        let mut ledger = Ledger::default();
+        ledger.post(LedgerAccount::Deposits, LedgerAccount::Staked, 1000);
+        ledger.post(LedgerAccount::Deposits, LedgerAccount::Fees, 25);
+        ledger.post(LedgerAccount::Staked, LedgerAccount::Withdrawals, 400);
+        assert_eq!(ledger.balance(LedgerAccount::Staked), 600);
+        assert_eq!(ledger.balance(LedgerAccount::Fees), 25);
+        assert_eq!(ledger.balance(LedgerAccount::Withdrawals), 400);
+    }
+
+    #[test]
+    #[should_panic(expected = "Ledger account Reserve is overdrawn")]
+    fn test_ledger_overdraft() {
+        let mut ledger = Ledger::default();
+        ledger.post(LedgerAccount::Funding, LedgerAccount::Reserve, 100);
+        ledger.post(LedgerAccount::Reserve, LedgerAccount::Claimed, 101);
+    }
+    #[test]
+    #[should_panic(expected = "Staked ledger balance doesn't match the accounts")]
+    fn test_ledger_reconciles_with_accounts() {
+        let mut contract = staked_farm();
+        // a stake posted to the ledger without reaching an account
+        contract.ledger.post(LedgerAccount::Deposits, LedgerAccount::Staked, 1);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_label(None);
+    }
+}
@@ -1,19 +1,143 @@
+use near_contract_standards::fungible_token::metadata::FungibleTokenMetadata;
+use near_contract_standards::storage_management::{StorageBalance, StorageManagement};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LookupMap;
-use near_sdk::json_types::{ValidAccountId, U128};
-use near_sdk::serde::Serialize;
+#[cfg(feature = "grants")]
+use near_sdk::collections::LookupSet;
+use near_sdk::collections::{LazyOption, LookupMap, TreeMap, UnorderedMap, UnorderedSet, Vector};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Serialize, Serializer};
 use near_sdk::utils::assert_one_yocto;
 use near_sdk::{
-    env, ext_contract, log, near_bindgen, AccountId, Balance, PanicOnDefault, PromiseOrValue,
-    Timestamp,
+    env, ext_contract, log, near_bindgen, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault,
+    PromiseOrValue, StorageUsage, Timestamp,
 };
 use uint::construct_uint;
 
+mod account;
+mod admin;
+mod apr;
+mod apr_history;
+mod autocompound;
+mod batch_claim;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod bench;
+#[cfg(feature = "bonus")]
+mod bonus;
+mod bots;
+mod breaker;
+mod build_info;
+mod campaigns;
+mod capabilities;
+mod changes;
+mod claim_history;
+mod claim_queue;
+mod claim_sponsorship;
+mod cliff;
+mod config;
+mod config_history;
+mod contract_gate;
+mod conversion;
+mod dashboard;
+mod decimals;
+mod decommission;
+mod drip;
+mod dust;
+mod emission;
+mod errors;
+mod events;
+mod exit;
+mod fee_destination;
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod fuzz;
+mod gas;
+#[cfg(feature = "grants")]
+mod grants;
+mod guardian;
+mod health;
+mod history_archive;
+mod hooks;
+mod large_unstake;
+mod ledger;
+#[cfg(feature = "liens")]
+mod lien;
+mod metadata;
+mod multiplier;
+mod onboarding;
+mod operations;
+mod partner_boost;
+mod positions;
+mod preferences;
+mod rate_controller;
+mod registration;
+mod replica;
+mod reward_periods;
+mod rewards;
+mod roles;
+mod runway;
+mod simulation;
+mod snapshot;
+mod storage;
+mod strategy;
+mod streak;
+#[cfg(feature = "test-hooks")]
+mod test_hooks;
+mod transfer_fees;
+mod unlock_schedule;
+mod vip_auction;
+mod warm_up;
+mod weight;
+
+use crate::apr::CompoundingFrequency;
+use crate::apr_history::AprHistory;
+use crate::bots::Bot;
+use crate::campaigns::CampaignStats;
+use crate::changes::ScheduledChange;
+use crate::claim_history::ClaimHistory;
+use crate::claim_queue::ClaimQueue;
+use crate::claim_sponsorship::{ClaimSponsorship, SponsoredClaims};
+use crate::cliff::CliffScope;
+use crate::config::FarmConfig;
+use crate::config_history::ConfigHistory;
+use crate::contract_gate::ContractListing;
+use crate::conversion::ClaimRoute;
+use crate::decimals::FundingBounds;
+use crate::drip::Drip;
+use crate::dust::DustPolicy;
+use crate::emission::EmissionSchedule;
+use crate::errors::{fail, ErrorCode};
+use crate::fee_destination::{fee_of, FeeDestination};
+use crate::gas::{GasMeter, GasPhase, GasProfile, TokenGas, DEFAULT_PROMISE_GAS_MARGIN};
+use crate::guardian::{FrozenAccount, PendingGuardianAction};
+use crate::health::HealthWatchdog;
+use crate::hooks::PositionEvent;
+use crate::large_unstake::DEFAULT_LARGE_UNSTAKE_DELAY;
+use crate::ledger::{Ledger, LedgerAccount};
+#[cfg(feature = "liens")]
+use crate::lien::Lien;
+use crate::metadata::FarmMetadata;
+use crate::operations::{Operation, OperationKind, OperationStatus};
+use crate::partner_boost::PartnerBoost;
+use crate::positions::{position_id, DEFAULT_MAX_POSITIONS};
+use crate::preferences::Preferences;
+use crate::rate_controller::RateController;
+use crate::replica::AccountLog;
+use crate::reward_periods::RewardPeriod;
+use crate::roles::Role;
+use crate::runway::RunwayAlert;
+use crate::snapshot::Snapshot;
+use crate::strategy::Strategy;
+use crate::streak::StreakBonus;
+use crate::vip_auction::VipAuction;
+use crate::weight::RewardWeight;
+
 const NO_DEPOSIT: Balance = 0;
 const BASE_GAS: u64 = 5_000_000_000_000;
 const PROMISE_CALL: u64 = 5_000_000_000_000;
 const GAS_FOR_ACCOUNT_REGISTRATION: u64 = BASE_GAS;
 const GAS_FOR_ON_TRANSFER: u64 = BASE_GAS + PROMISE_CALL;
+const GAS_FOR_RESOLVE_TRANSFER: u64 = BASE_GAS;
+/// `ft_transfer_call` including the receiver's `ft_on_transfer` and the token's resolution.
+const GAS_FOR_FT_TRANSFER_CALL: u64 = 7 * BASE_GAS;
 
 construct_uint! {
     pub struct U256(8);
@@ -25,15 +149,105 @@ pub struct Account {
     pub reward_balance: Balance,
     pub reward_claimed: Balance,
     pub last_obs_per_reward_rate: Balance,
+    /// when the account last staked from nothing; top-ups keep it, see `cliff`.
     pub deposit_time: Timestamp,
+    /// time up to which rewards were added to `reward_balance`.
+    pub last_accrual_time: Timestamp,
+    /// rewards earned during a retroactive reward cliff or a tripped breaker before a top-up,
+    /// credited once the cliff ends or the breaker is reset, see `cliff` and `breaker`.
+    pub vesting_reward: Balance,
+    /// part of `obs_balance` staked within the warm-up, earning from `warm_until`, see
+    /// `warm_up`.
+    pub warming_balance: Balance,
+    pub warm_until: Timestamp,
+    /// NEAR attached by the account to pay for its storage, see NEP-145.
+    pub storage_deposit: Balance,
+    /// part of `storage_deposit` paid by the farm, never withdrawable or refunded.
+    pub storage_sponsored: Balance,
+    /// bytes of records owned by the account, other than the account record itself.
+    pub storage_usage: StorageUsage,
+    /// short self-describing label chosen by the account owner.
+    pub label: Option<String>,
+    /// obs ever staked by the account.
+    pub total_staked: Balance,
+    /// obs ever unstaked by the account.
+    pub total_withdrawn: Balance,
+    pub stake_count: u64,
+    /// meaningful only once `stake_count` is non-zero.
+    pub first_stake_time: Timestamp,
+    /// unstakes above it must be requested ahead, see `large_unstake`.
+    pub large_unstake_threshold: Option<Balance>,
+    pub large_unstake_requested_at: Option<Timestamp>,
+    /// partner token balance as of `partner_balance_checked_at`, see `partner_boost`.
+    pub partner_balance: Balance,
+    pub partner_balance_checked_at: Timestamp,
+    /// lienholder allowed to lock the position, see `lien`.
+    pub approved_lienholder: Option<AccountId>,
+    /// latest VIP round the account won a slot in, 0 for none, see `vip_auction`.
+    pub vip_round: u64,
+    /// when the account last staked from nothing, see `streak`.
+    pub streak_started_at: Timestamp,
+    /// closed reward periods the account was settled for, see `reward_periods`.
+    pub reward_period: u64,
+    /// staker fee index the account was credited up to, see `fee_destination`.
+    pub staker_fee_index: Balance,
+    /// campaign of the account's first tagged deposit, see `campaigns`.
+    pub campaign: Option<String>,
+    /// reward multiplier last announced for the account, see `multiplier`.
+    pub multiplier_bps: u32,
+    /// claims the relayer made for the account, once it opted in, see `claim_sponsorship`.
+    pub sponsored_claims: Option<SponsoredClaims>,
+    /// settings of the account, see `preferences`.
+    pub preferences: Preferences,
 }
 
 #[derive(Serialize)]
 #[serde(crate = "near_sdk::serde")]
-pub struct FarmerAccount {
+pub struct AccountView {
     pub obs_balance: U128,
     pub reward_balance: U128,
     pub reward_claimed: U128,
+    pub label: Option<String>,
+    pub total_staked: U128,
+    pub total_withdrawn: U128,
+    pub stake_count: U64,
+    pub first_stake_time: Option<TimestampView>,
+    pub large_unstake_threshold: Option<U128>,
+    pub large_unstake_confirmable_at: Option<TimestampView>,
+    pub reward_multiplier_bps: u32,
+    /// when unclaimed rewards expire, once the farm is decommissioned with a claim window.
+    pub reward_expires_at: Option<TimestampView>,
+    /// part of `obs_balance` earning nothing until `warm_until`, see `warm_up`.
+    pub warming_balance: U128,
+    pub warm_until: Option<TimestampView>,
+    /// consecutive epochs the account kept its stake over, see `streak`.
+    pub streak_epochs: U64,
+    pub auto_compound: bool,
+    pub campaign: Option<String>,
+    pub preferences: Preferences,
+}
+
+/// Timestamp as returned by views: nanoseconds as a string, so JS clients don't lose
+/// precision, and whole seconds, which fit in a JS number.
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TimestampView {
+    pub nanoseconds: U64,
+    pub seconds: u64,
+}
+
+impl From<Timestamp> for TimestampView {
+    fn from(timestamp: Timestamp) -> Self {
+        Self { nanoseconds: timestamp.into(), seconds: timestamp / 1_000_000_000 }
+    }
+}
+
+/// `serialize_with` helper for stored timestamps that are also returned by views.
+pub(crate) fn serialize_timestamp<S: Serializer>(
+    timestamp: &Timestamp,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    TimestampView::from(*timestamp).serialize(serializer)
 }
 
 #[derive(Serialize)]
@@ -42,35 +256,357 @@ pub struct FarmStats {
     pub total_obs_balance: U128,
     pub total_reward_claimed: U128,
     pub total_reward_received: U128,
+    pub accounts_registered: U64,
+    pub active_stakers: U64,
+    pub sponsored_accounts: U64,
+    pub storage_sponsor_pool: U128,
 }
 
 // using 10**18 for precision
 pub const OBS_PER_REWARD_DENOM: Balance = 1_000_000_000_000_000_000;
 
+/// `ft_on_transfer` message staking the transferred obs, for the sender or, followed by
+/// `:<account_id>`, for that account. Followed by `#<label>`, it stakes into a position of
+/// the sender, see `positions`. Any of them can end with `@<campaign>`, see `campaigns`.
+pub const STAKE_MSG: &str = "Stake";
+
+/// separates the campaign a stake msg is tagged with, see `campaigns`.
+pub const CAMPAIGN_SEPARATOR: char = '@';
+
+/// `ft_on_transfer` message funding the reserve when the obs token is also the reward token.
+pub const FUND_RESERVE_MSG: &str = "fund_reserve";
+
+/// `ft_on_transfer` message funding the locked reserve released by the drip, see `drip`.
+pub const FUND_LOCKED_MSG: &str = "fund_locked";
+
+/// `ft_on_transfer` message bidding the transferred obs for a VIP slot, see `vip_auction`.
+pub const VIP_BID_MSG: &str = "vip_bid";
+
+/// `ft_on_transfer` message a strategy returns recalled reserve with, see `strategy`.
+pub const STRATEGY_RETURN_MSG: &str = "strategy_return";
+
+/// denominator of rates expressed in basis points.
+pub const BPS_DENOM: u128 = 10_000;
+
+/// Prefixes of the farm's persistent collections. Collections nested under another one get
+/// a variant carrying the parent's id, so keys can never collide.
+#[derive(BorshSerialize, BorshStorageKey)]
+pub enum StorageKey {
+    Accounts,
+    GasProfiles,
+    Metadata,
+    Guardians,
+    FrozenAccounts,
+    Lienholders,
+    Liens,
+    GrantNonces,
+    TokenGas,
+    BonusQueue,
+    Snapshots,
+    BalanceCheckpoints,
+    ClaimQueue,
+    ClaimQueuePositions,
+    GuardianActions,
+    VipBids,
+    VipRounds,
+    VipRefunds,
+    AprHistory,
+    Strategies,
+    RewardPeriods,
+    SettledRewards,
+    ClaimRoutes,
+    ClaimRecords,
+    ClaimRecordCounts,
+    AutoCompounders,
+    Bots,
+    AccountChanges,
+    FeeOnTransferTokens,
+    RoleGrants,
+    PositionCounts,
+    UnlockCalendar,
+    CampaignStats,
+    Operations,
+    ArchivedClaimCounts,
+    ContractListings,
+    ConfigVersions,
+    ClaimManagers,
+    RegisteredAccounts,
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Farm {
+    pub owner_id: AccountId,
+
+    /// receives the reserve left over after a decommission.
+    pub treasury_id: AccountId,
+
     pub obs_token_account_id: AccountId,
 
     pub reward_token_account_id: AccountId,
 
+    /// decimals of the obs token, cached by `refresh_token_decimals`.
+    pub obs_decimals: Option<u8>,
+
+    pub reward_decimals: Option<u8>,
+
+    /// accepted size of reserve fundings, checked against `reward_decimals`.
+    pub funding_bounds: Option<FundingBounds>,
+
     pub accounts: LookupMap<ShortAccountHash, Account>,
 
+    /// ids of the registered accounts, paged through by `retire_accounts`.
+    pub registered_accounts: UnorderedSet<AccountId>,
+
     pub reward_rate: Balance,
 
+    /// how the reward rate changes over time, `reward_rate` being the constant one.
+    pub emission_schedule: EmissionSchedule,
+
+    /// announced parameter changes ordered by the time they take effect, see `changes`.
+    pub scheduled_changes: Vec<ScheduledChange>,
+
+    /// versions of the reward parameters, see `config_history`.
+    pub config_history: ConfigHistory,
+
+    /// controller pricing the reward rate, see `rate_controller`.
+    pub rate_controller: Option<RateController>,
+    pub rate_updated_at: Option<Timestamp>,
+
+    /// reward multiplier for holders of a partner token.
+    pub partner_boost: Option<PartnerBoost>,
+
+    /// reward multiplier growing with consecutive epochs staked, see `streak`.
+    pub streak_bonus: Option<StreakBonus>,
+
+    /// how stakes are weighted for rewards, see `weight`.
+    pub reward_weight: RewardWeight,
+
+    pub streak_epochs_start: Timestamp,
+
     pub obs_per_reward_rate: Balance,
 
-    pub staking_fee_rate: Balance,
+    /// fees on stakes and unstakes, in basis points, see `fee_destination`.
+    pub entry_fee_bps: u32,
+    pub exit_fee_bps: u32,
 
     pub cliff_time: Timestamp,
 
+    /// whether `cliff_time` locks the rewards, the principal or both.
+    pub cliff_scope: CliffScope,
+
+    /// cliff of the rewards if it differs from `cliff_time`, see `cliff`.
+    pub reward_cliff: Option<Timestamp>,
+    pub reward_cliff_retroactive: bool,
+
+    /// time between a large unstake request and its confirmation.
+    pub large_unstake_delay: Timestamp,
+
     pub reward_interval: Timestamp,
 
-    pub total_obs_balance: Balance,
+    /// token balances held by the farm, see `ledger`.
+    pub ledger: Ledger,
+    /// principal of all accounts, to reconcile the ledger with, see `ledger`.
+    pub staked_by_accounts: Balance,
 
     pub total_reward_farmed: Balance,
 
-    pub total_reward_claimed: Balance,
+    /// accounts ever registered, including the ones unregistered since.
+    pub accounts_registered: u64,
+
+    /// accounts with a non-zero stake.
+    pub active_stakers: u64,
+
+    /// how often rewards are assumed to be reinvested by `get_apy`.
+    pub compounding_frequency: CompoundingFrequency,
+
+    /// share of harvested rewards paid to whoever calls `harvest_for`, in basis points.
+    pub harvest_bounty_bps: u32,
+
+    /// smallest reward balance that can be claimed, smaller balances keep accruing.
+    pub min_claim: Balance,
+
+    /// time after a stake before it earns, see `warm_up`.
+    pub stake_warm_up: Timestamp,
+
+    /// positions an owner can hold, see `positions`.
+    pub max_positions: u64,
+
+    /// positions held by each owner.
+    pub position_counts: LookupMap<AccountId, u64>,
+
+    /// accounts `force_settle` can remove, see `dust`.
+    pub dust_policy: Option<DustPolicy>,
+
+    /// where entry and exit fees go, see `fee_destination`.
+    pub fee_destination: FeeDestination,
+    pub exit_fee_destination: FeeDestination,
+    /// entry and exit fees ever charged.
+    pub entry_fees: Balance,
+    pub exit_fees: Balance,
+
+    /// staking fees credited to stakers per staked obs, see `fee_destination`.
+    pub staker_fee_index: Balance,
+
+    /// principal unlocking by day, see `unlock_schedule`.
+    pub unlock_calendar: TreeMap<u64, Balance>,
+
+    /// deposits by campaign tag, see `campaigns`.
+    pub campaign_stats: LookupMap<String, CampaignStats>,
+
+    /// relayer claiming for opted-in accounts, see `claim_sponsorship`.
+    pub claim_sponsorship: Option<ClaimSponsorship>,
+    pub sponsored_claims: u64,
+
+    /// custodians approved to claim for the accounts delegating to them, see `batch_claim`.
+    pub claim_managers: UnorderedSet<AccountId>,
+
+    /// contract old payouts are archived to, see `history_archive`.
+    pub history_archive: Option<AccountId>,
+
+    /// whether stakes and claims from contract accounts are refused, see `contract_gate`.
+    pub reject_contracts: bool,
+    pub contract_listings: UnorderedMap<AccountId, ContractListing>,
+
+    /// watchdog on the token contracts pausing stakes on incidents, see `health`.
+    pub health_watchdog: Option<HealthWatchdog>,
+    pub health_checked_at: Option<Timestamp>,
+    pub deposits_paused_at: Option<Timestamp>,
+
+    /// latest operations by op id, see `operations`.
+    pub operations: LookupMap<u64, Operation>,
+    pub last_op_id: u64,
+
+    /// obs kept from the first stake of an unregistered account to register it.
+    pub registration_fee: Option<Balance>,
+
+    /// NEAR given by sponsors to pay for the registration of new stakers.
+    pub storage_sponsor_pool: Balance,
+
+    pub sponsored_accounts_limit: u64,
+
+    pub sponsored_accounts: u64,
+
+    pub metadata: LazyOption<FarmMetadata>,
+
+    pub guardians: UnorderedSet<AccountId>,
+
+    pub frozen_accounts: LookupMap<AccountId, FrozenAccount>,
+
+    /// guardians who must confirm a freeze, see `guardian`.
+    pub guardian_threshold: u32,
+
+    pub guardian_actions: UnorderedMap<u64, PendingGuardianAction>,
+
+    pub next_guardian_action_id: u64,
+
+    /// contracts the owner allows to place liens on positions.
+    #[cfg(feature = "liens")]
+    pub lienholders: UnorderedSet<AccountId>,
+
+    #[cfg(feature = "liens")]
+    pub liens: UnorderedMap<AccountId, Lien>,
+
+    /// ed25519 key signing reward grants, see `grants`.
+    #[cfg(feature = "grants")]
+    pub grant_signer: Option<Vec<u8>>,
+
+    #[cfg(feature = "grants")]
+    pub grant_nonces: LookupSet<u64>,
+
+    pub decommission_scheduled_at: Option<Timestamp>,
+
+    /// time after the decommission during which rewards can still be claimed.
+    pub claim_window: Option<Timestamp>,
+
+    /// once set, the farm is withdraw-only and nothing accrues past this time.
+    pub decommissioned_at: Option<Timestamp>,
+
+    /// whether mutating calls record their gas usage.
+    pub gas_profiling: bool,
+
+    /// gas required on top of the promises a call schedules, see `gas`.
+    pub promise_gas_margin: Gas,
+
+    /// ring buffer of the last `GAS_PROFILE_SIZE` gas measurements.
+    pub gas_profiles: Vector<GasProfile>,
+
+    pub gas_profiles_recorded: u64,
+
+    /// per-token overrides of the gas attached to token calls.
+    pub token_gas: UnorderedMap<AccountId, TokenGas>,
+
+    /// recipients of the bonus distribution in progress, next one last.
+    #[cfg(feature = "bonus")]
+    pub bonus_queue: Vector<(AccountId, Balance)>,
+
+    pub runway_alert: Option<RunwayAlert>,
+
+    /// whether the runway alert fired since the runway last was above its threshold.
+    pub runway_alert_sent: bool,
+
+    pub snapshots: Vector<Snapshot>,
+
+    /// stakes of accounts changed since a snapshot, as (snapshots taken, stake) pairs.
+    pub balance_checkpoints: LookupMap<AccountId, Vec<(u64, Balance)>>,
+
+    /// largest reward a single accrual may credit before the breaker trips, see `breaker`.
+    pub accrual_bound: Option<Balance>,
+
+    pub breaker_tripped_at: Option<Timestamp>,
+
+    /// claims waiting for the reserve to cover them, see `claim_queue`.
+    pub claim_queue: ClaimQueue,
+
+    /// auction of VIP boost slots, see `vip_auction`.
+    pub vip_auction: VipAuction,
+
+    /// largest stakes, highest first, see `dashboard`.
+    pub top_stakers: Vec<(AccountId, Balance)>,
+
+    /// APR recorded over time, see `apr_history`.
+    pub apr_history: AprHistory,
+
+    /// external contracts idle reserve can be lent to, see `strategy`.
+    pub strategies: UnorderedMap<AccountId, Strategy>,
+
+    /// reward periods closed by switching the reward token, see `reward_periods`.
+    pub reward_periods: Vector<RewardPeriod>,
+    /// reward payouts sent and not yet resolved, which would restore into the current token.
+    pub reward_payouts_in_flight: u64,
+    /// rewards of closed periods by period and account, not yet claimed.
+    pub settled_rewards: LookupMap<(u64, ShortAccountHash), Balance>,
+
+    /// DEX pools claims can be swapped through, by output token, see `conversion`.
+    pub claim_routes: UnorderedMap<AccountId, ClaimRoute>,
+
+    /// resolved reward payouts by account, see `claim_history`.
+    pub claim_history: ClaimHistory,
+
+    /// accounts whose rewards keepers restake, see `autocompound`.
+    pub auto_compounders: UnorderedSet<AccountId>,
+
+    /// automation accounts and their scopes, see `bots`.
+    pub bots: UnorderedMap<AccountId, Bot>,
+
+    /// log of account writes for indexers, see `replica`.
+    pub account_log: AccountLog,
+
+    /// reward tokens whose payouts are checked for transfer fees, see `transfer_fees`.
+    pub fee_on_transfer_tokens: UnorderedSet<AccountId>,
+
+    /// treasurer and operator roles by account, see `roles`.
+    pub role_grants: UnorderedMap<AccountId, Vec<Role>>,
+
+    /// release schedule of the locked reserve, see `drip`.
+    pub drip: Option<Drip>,
+
+    #[borsh_skip]
+    pub gas_meter: GasMeter,
+
+    /// shifts the clock seen by the farm, sandbox builds only.
+    #[cfg(feature = "test-hooks")]
+    pub block_timestamp_offset: Timestamp,
 }
 
 trait FungibleTokenReceiver {
@@ -90,15 +626,69 @@ impl FungibleTokenReceiver for Farm {
         amount: U128,
         msg: String,
     ) -> PromiseOrValue<U128> {
+        // recalled reserve is taken back even after decommissioning
+        if env::predecessor_account_id() == self.reward_token_account_id
+            && msg == STRATEGY_RETURN_MSG
+        {
+            self.book_strategy_return(&sender_id, amount.0);
+            return PromiseOrValue::Value(U128::from(0));
+        }
+        self.assert_not_decommissioned();
+        if env::predecessor_account_id() == self.reward_token_account_id && msg == FUND_LOCKED_MSG {
+            assert!(self.drip.is_some(), "Set a drip schedule before funding the locked reserve");
+            self.ledger.post(LedgerAccount::Funding, LedgerAccount::Locked, amount.0);
+            log!("Locked reserve funded with {} by @{}", amount.0, sender_id);
+            return PromiseOrValue::Value(U128::from(0));
+        }
+        // in a single-token farm only transfers marked as funding go to the reserve
+        let funds_reserve = !self.is_single_token() || msg == FUND_RESERVE_MSG;
+        if env::predecessor_account_id() == self.reward_token_account_id && funds_reserve {
+            self.assert_funding_in_bounds(amount.0);
+            self.ledger.post(LedgerAccount::Funding, LedgerAccount::Reserve, amount.0);
+            log!("Reward reserve funded with {} by @{}", amount.0, sender_id);
+            self.pay_queued_claims();
+            self.check_runway();
+            return PromiseOrValue::Value(U128::from(0));
+        }
         // Verifying that we were called by fungible token contract that we expect.
         assert_eq!(
             &env::predecessor_account_id(),
             &self.obs_token_account_id,
-            "Only supports the one fungible token contract"
+            "Only supports the obs and reward token contracts"
         );
         log!("in {} tokens from @{} ft_on_transfer, msg = {}", amount.0, sender_id, msg);
+        let (msg, campaign) = match msg.split_once(CAMPAIGN_SEPARATOR) {
+            Some((stake_msg, tag)) if stake_msg.starts_with(STAKE_MSG) => {
+                (stake_msg.to_string(), Some(tag.to_string()))
+            }
+            _ => (msg, None),
+        };
         match msg.as_str() {
-            "Stake" => PromiseOrValue::Value(U128::from(0)),
+            STAKE_MSG => {
+                let amount = self.register_implicitly(&sender_id, amount.0, true);
+                self.internal_stake(&sender_id, amount, campaign);
+                self.flush_gas_profile("ft_on_transfer");
+                PromiseOrValue::Value(U128::from(0))
+            }
+            _ if msg.starts_with(&format!("{}:", STAKE_MSG)) => {
+                let beneficiary_id = msg[STAKE_MSG.len() + 1..].to_string();
+                assert!(env::is_valid_account_id(beneficiary_id.as_bytes()), "Invalid account id");
+                let amount = self.register_implicitly(&beneficiary_id, amount.0, false);
+                self.internal_stake(&beneficiary_id, amount, campaign);
+                self.flush_gas_profile("ft_on_transfer");
+                PromiseOrValue::Value(U128::from(0))
+            }
+            _ if msg.starts_with(&format!("{}#", STAKE_MSG)) => {
+                let position_id = position_id(&sender_id, &msg[STAKE_MSG.len() + 1..]);
+                let amount = self.register_implicitly(&position_id, amount.0, false);
+                self.internal_stake(&position_id, amount, campaign);
+                self.flush_gas_profile("ft_on_transfer");
+                PromiseOrValue::Value(U128::from(0))
+            }
+            VIP_BID_MSG => {
+                self.place_vip_bid(&sender_id, amount.0);
+                PromiseOrValue::Value(U128::from(0))
+            }
             _ => ext_self::on_transfer(
                 self.obs_token_account_id.clone(),
                 env::predecessor_account_id(),
@@ -121,13 +711,56 @@ pub trait ExtFarm {
         receiver: AccountId,
         amount: Balance,
     ) -> PromiseOrValue<()>;
-    fn register_account(&mut self, account_id: AccountId);
+    fn on_reward_transfer(&mut self, account_id: AccountId, amount: U128, op_id: U64) -> U128;
+    fn on_bounty_transfer(&mut self, amount: U128);
+    fn on_exit(&mut self, account_id: AccountId, principal: U128, reward: U128, unregister: bool);
+    fn on_sweep_balance(&mut self, #[callback] balance: U128) -> Promise;
+    fn on_sweep_transfer(&mut self, amount: U128);
+    fn on_claim_and_stake(&mut self, account_id: AccountId, amount: U128, op_id: U64) -> U128;
+    fn on_partner_balance(&mut self, account_id: AccountId, #[callback] balance: U128);
+    fn on_token_metadata(
+        &mut self,
+        token_id: AccountId,
+        #[callback] metadata: FungibleTokenMetadata,
+    );
+    fn on_register_on_tokens(&mut self, token_ids: Vec<AccountId>) -> bool;
+    fn on_vip_proceeds(&mut self, amount: U128);
+    fn on_vip_refund(&mut self, account_id: AccountId, amount: U128);
+    fn on_strategy_deposit(&mut self, strategy_id: AccountId, amount: U128) -> U128;
+    fn on_strategy_report(&mut self, strategy_id: AccountId) -> U128;
+    fn on_period_reward_transfer(&mut self, account_id: AccountId, period: U64, amount: U128);
+    fn on_claim_conversion(
+        &mut self,
+        account_id: AccountId,
+        amount: U128,
+        token_id: AccountId,
+    ) -> U128;
+    fn on_payout_balance(&mut self, token_id: AccountId, #[callback] balance: U128);
+    fn on_fees_swept(&mut self, amount: U128);
+    fn on_claims_archived(
+        &mut self,
+        account_id: AccountId,
+        archive_id: AccountId,
+        from_index: U64,
+        through_index: U64,
+    );
+    fn on_prices(&mut self, #[callback] prices: Vec<U128>);
+    fn on_health_check(&mut self, token_ids: Vec<AccountId>);
 }
 
 // interface for external call
 #[ext_contract(ext_fungible_token)]
 pub trait FungibleToken {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_transfer_call(
+        &mut self,
+        receiver_id: AccountId,
+        amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128>;
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+    fn ft_metadata(&self) -> FungibleTokenMetadata;
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Clone, PartialEq)]
@@ -143,122 +776,164 @@ impl From<&AccountId> for ShortAccountHash {
 
 #[near_bindgen]
 impl Farm {
+    /// Initializes the farm. It still has to be registered on its token contracts with
+    /// `register_on_tokens` before it can receive them.
     #[init]
-    pub fn new(
-        obs_token_account_id: ValidAccountId,
-        reward_token_account_id: ValidAccountId,
-    ) -> Self {
-        // to allow access to obs and reward token contract
-        ext_self::register_account(
-            env::current_account_id(),
-            obs_token_account_id.as_ref(),
-            NO_DEPOSIT,
-            GAS_FOR_ACCOUNT_REGISTRATION,
-        );
-        ext_self::register_account(
-            env::current_account_id(),
-            reward_token_account_id.as_ref(),
-            NO_DEPOSIT,
-            GAS_FOR_ACCOUNT_REGISTRATION,
-        );
+    pub fn new(config: FarmConfig) -> Self {
         assert!(!env::state_exists(), "Already initialized");
-        Self {
-            obs_token_account_id: obs_token_account_id.into(),
-            reward_token_account_id: reward_token_account_id.into(),
-            accounts: LookupMap::new(b"a".to_vec()),
-            reward_rate: 1800,
+        config.validate();
+        let mut farm = Self {
+            owner_id: config.owner_id.clone().into(),
+            treasury_id: config.owner_id.clone().into(),
+            obs_token_account_id: config.obs_token_account_id.clone().into(),
+            reward_token_account_id: config.reward_token_account_id.clone().into(),
+            obs_decimals: None,
+            reward_decimals: None,
+            funding_bounds: None,
+            accounts: LookupMap::new(StorageKey::Accounts),
+            registered_accounts: UnorderedSet::new(StorageKey::RegisteredAccounts),
+            reward_rate: config.reward_rate(),
+            emission_schedule: EmissionSchedule::Constant,
+            rate_controller: None,
+            rate_updated_at: None,
+            scheduled_changes: Vec::new(),
+            config_history: ConfigHistory::new(),
+            partner_boost: None,
+            streak_bonus: None,
+            reward_weight: config.reward_weight(),
+            streak_epochs_start: 0,
             obs_per_reward_rate: 0,
-            staking_fee_rate: 25,
-            cliff_time: 60 * 60 * 24 * 10,
-            reward_interval: 60 * 60 * 24 * 365,
-            total_obs_balance: 0,
+            entry_fee_bps: config.entry_fee_bps(),
+            exit_fee_bps: config.exit_fee_bps(),
+            cliff_time: config.cliff_time(),
+            cliff_scope: CliffScope::default(),
+            reward_cliff: None,
+            reward_cliff_retroactive: true,
+            large_unstake_delay: DEFAULT_LARGE_UNSTAKE_DELAY,
+            reward_interval: config.reward_interval(),
+            ledger: Ledger::default(),
+            staked_by_accounts: 0,
             total_reward_farmed: 0,
-            total_reward_claimed: 0,
-        }
+            accounts_registered: 0,
+            active_stakers: 0,
+            compounding_frequency: CompoundingFrequency::Daily,
+            harvest_bounty_bps: 0,
+            min_claim: 0,
+            stake_warm_up: 0,
+            max_positions: DEFAULT_MAX_POSITIONS,
+            position_counts: LookupMap::new(StorageKey::PositionCounts),
+            dust_policy: None,
+            fee_destination: config.fee_destination(),
+            exit_fee_destination: FeeDestination::Treasury,
+            entry_fees: 0,
+            exit_fees: 0,
+            staker_fee_index: 0,
+            unlock_calendar: TreeMap::new(StorageKey::UnlockCalendar),
+            campaign_stats: LookupMap::new(StorageKey::CampaignStats),
+            claim_sponsorship: None,
+            sponsored_claims: 0,
+            claim_managers: UnorderedSet::new(StorageKey::ClaimManagers),
+            history_archive: None,
+            reject_contracts: false,
+            contract_listings: UnorderedMap::new(StorageKey::ContractListings),
+            health_watchdog: None,
+            health_checked_at: None,
+            deposits_paused_at: None,
+            operations: LookupMap::new(StorageKey::Operations),
+            last_op_id: 0,
+            registration_fee: None,
+            storage_sponsor_pool: 0,
+            sponsored_accounts_limit: 0,
+            sponsored_accounts: 0,
+            metadata: LazyOption::new(StorageKey::Metadata, None),
+            guardians: UnorderedSet::new(StorageKey::Guardians),
+            frozen_accounts: LookupMap::new(StorageKey::FrozenAccounts),
+            guardian_threshold: 1,
+            guardian_actions: UnorderedMap::new(StorageKey::GuardianActions),
+            next_guardian_action_id: 0,
+            #[cfg(feature = "liens")]
+            lienholders: UnorderedSet::new(StorageKey::Lienholders),
+            #[cfg(feature = "liens")]
+            liens: UnorderedMap::new(StorageKey::Liens),
+            #[cfg(feature = "grants")]
+            grant_signer: None,
+            #[cfg(feature = "grants")]
+            grant_nonces: LookupSet::new(StorageKey::GrantNonces),
+            decommission_scheduled_at: None,
+            claim_window: None,
+            decommissioned_at: None,
+            gas_profiling: false,
+            promise_gas_margin: DEFAULT_PROMISE_GAS_MARGIN,
+            gas_profiles: Vector::new(StorageKey::GasProfiles),
+            gas_profiles_recorded: 0,
+            token_gas: UnorderedMap::new(StorageKey::TokenGas),
+            #[cfg(feature = "bonus")]
+            bonus_queue: Vector::new(StorageKey::BonusQueue),
+            runway_alert: None,
+            runway_alert_sent: false,
+            snapshots: Vector::new(StorageKey::Snapshots),
+            balance_checkpoints: LookupMap::new(StorageKey::BalanceCheckpoints),
+            accrual_bound: None,
+            breaker_tripped_at: None,
+            claim_queue: ClaimQueue::new(),
+            vip_auction: VipAuction::new(),
+            top_stakers: Vec::new(),
+            apr_history: AprHistory::new(),
+            strategies: UnorderedMap::new(StorageKey::Strategies),
+            reward_periods: Vector::new(StorageKey::RewardPeriods),
+            reward_payouts_in_flight: 0,
+            settled_rewards: LookupMap::new(StorageKey::SettledRewards),
+            claim_routes: UnorderedMap::new(StorageKey::ClaimRoutes),
+            claim_history: ClaimHistory::new(),
+            auto_compounders: UnorderedSet::new(StorageKey::AutoCompounders),
+            bots: UnorderedMap::new(StorageKey::Bots),
+            account_log: AccountLog::new(),
+            fee_on_transfer_tokens: UnorderedSet::new(StorageKey::FeeOnTransferTokens),
+            role_grants: UnorderedMap::new(StorageKey::RoleGrants),
+            drip: None,
+            gas_meter: GasMeter::default(),
+            #[cfg(feature = "test-hooks")]
+            block_timestamp_offset: 0,
+        };
+        farm.record_config_version(farm.now());
+        farm
     }
 
     #[payable]
-    pub fn stake_my_obs(&mut self, amount: Balance) {
+    pub fn stake_my_obs(&mut self, amount: U128) {
         assert_one_yocto();
-        assert!(amount > 0, "Amount must be greater than 0",);
-        let fee = amount * self.staking_fee_rate * OBS_PER_REWARD_DENOM;
-        let attached_deposit = amount + fee;
-        let account_id = env::predecessor_account_id();
-        let (_account_id_hash, mut account) = self.get_mut_account(&account_id);
-
-        account.obs_balance = amount;
-        account.reward_balance = 0;
-        account.reward_claimed = 0;
-        account.last_obs_per_reward_rate = self.touch(&mut account);
-        account.deposit_time = env::block_timestamp();
-
-        let current_time = env::block_timestamp();
-        let time_diff = current_time - account.deposit_time;
-        let obs_per_reward = (((U256::from(attached_deposit)
-            * U256::from(time_diff)
-            * U256::from(self.reward_rate))
-            / U256::from(self.reward_interval))
-            * U256::from(OBS_PER_REWARD_DENOM))
-        .as_u128();
-
-        self.obs_per_reward_rate = obs_per_reward;
-        self.total_obs_balance += attached_deposit;
+        self.assert_not_decommissioned();
+        self.internal_stake(&env::predecessor_account_id(), amount.into(), None);
+        self.assert_prepaid_gas(
+            self.token_gas(&self.obs_token_account_id).ft_transfer.0 + GAS_FOR_ON_TRANSFER,
+        );
 
+        let gas_mark = self.gas_mark();
         ext_fungible_token::ft_transfer(
             env::current_account_id(),
-            attached_deposit.into(),
+            amount,
             None,
             &self.obs_token_account_id.clone(),
             1,
-            GAS_FOR_ON_TRANSFER,
+            self.token_gas(&self.obs_token_account_id).ft_transfer.0,
         )
         .then(ext_self::on_transfer(
             self.obs_token_account_id.clone(),
             env::predecessor_account_id(),
-            attached_deposit,
+            amount.0,
             &env::current_account_id(),
             NO_DEPOSIT,
             GAS_FOR_ON_TRANSFER,
         ));
+        self.record_gas(GasPhase::Promises, gas_mark);
+        self.flush_gas_profile("stake_my_obs");
     }
 
     #[payable]
     pub fn unstake_my_obs(&mut self) {
         assert_one_yocto();
-        let (_account_id_hash, mut account) = self.get_mut_account(&env::predecessor_account_id());
-        assert!(
-            env::block_timestamp() - account.deposit_time >= self.cliff_time,
-            "You can unstake only after the 10 days of deposit"
-        );
-
-        self.touch(&mut account);
-        let fee = account.obs_balance * self.staking_fee_rate * OBS_PER_REWARD_DENOM;
-        let attached_deposit = account.obs_balance + fee;
-
-        self.total_obs_balance -= account.obs_balance;
-        self.total_reward_claimed += account.obs_balance;
-        self.total_reward_claimed += account.reward_claimed;
-
-        account.obs_balance = 0;
-        account.reward_claimed = account.reward_balance;
-        account.reward_balance = 0;
-
-        ext_fungible_token::ft_transfer(
-            env::predecessor_account_id(),
-            attached_deposit.into(),
-            None,
-            &self.obs_token_account_id.clone(),
-            1,
-            GAS_FOR_ON_TRANSFER,
-        )
-        .then(ext_fungible_token::ft_transfer(
-            env::predecessor_account_id(),
-            attached_deposit.into(),
-            None,
-            &self.reward_token_account_id.clone(),
-            1,
-            GAS_FOR_ON_TRANSFER,
-        ));
+        self.internal_unstake(&env::predecessor_account_id());
+        self.flush_gas_profile("unstake_my_obs");
     }
 
     pub fn on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) {
@@ -270,9 +945,10 @@ impl Farm {
         );
         log!("{} tokens from @{} on_transfer, msg = {}", amount.0, sender_id, msg);
     }
-    pub fn register_account(&mut self) {
-        let (account_id_hash, account) = self.get_mut_account(&env::predecessor_account_id());
-        self.save_account(&account_id_hash, &account);
+    /// Registers the predecessor, same as `storage_deposit` with `registration_only`.
+    #[payable]
+    pub fn register_account(&mut self) -> StorageBalance {
+        self.storage_deposit(None, Some(true))
     }
 
     pub fn account_exists(&self, account_id: ValidAccountId) -> bool {
@@ -283,6 +959,7 @@ impl Farm {
         self.get_internal_account(account_id.as_ref())
             .1
             .map(|mut account| {
+                self.split_closed_periods(&mut account);
                 self.touch(&mut account);
                 account.reward_balance
             })
@@ -292,52 +969,287 @@ impl Farm {
 
     pub fn get_stats(&self) -> FarmStats {
         FarmStats {
-            total_obs_balance: self.total_obs_balance.into(),
-            total_reward_claimed: self.total_reward_claimed.into(),
+            total_obs_balance: self.ledger.balance(LedgerAccount::Staked).into(),
+            total_reward_claimed: self.ledger.balance(LedgerAccount::Claimed).into(),
             total_reward_received: self.total_reward_farmed.into(),
+            accounts_registered: self.accounts_registered.into(),
+            active_stakers: self.active_stakers.into(),
+            sponsored_accounts: self.sponsored_accounts.into(),
+            storage_sponsor_pool: self.storage_sponsor_pool.into(),
         }
     }
 }
 
 impl Farm {
+    fn assert_owner(&self) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only the owner can call this method"
+        );
+    }
+
     fn get_internal_account(&self, account_id: &AccountId) -> (ShortAccountHash, Option<Account>) {
         let account_id_hash: ShortAccountHash = account_id.into();
         let account = self.accounts.get(&account_id_hash);
         (account_id_hash, account)
     }
 
-    /// updating inner pool balances.
+    /// current block timestamp, shifted by the test-hooks offset in sandbox builds.
+    fn now(&self) -> Timestamp {
+        #[cfg(feature = "test-hooks")]
+        return env::block_timestamp() + self.block_timestamp_offset;
+        #[cfg(not(feature = "test-hooks"))]
+        env::block_timestamp()
+    }
+
+    /// time up to which rewards accrue, frozen at the decommission.
+    fn accrual_time(&self) -> Timestamp {
+        let now = self.now();
+        self.decommissioned_at.map_or(now, |decommissioned_at| now.min(decommissioned_at))
+    }
+
+    /// whether the account's principal can be unstaked.
+    fn is_unlocked(&self, account: &Account) -> bool {
+        self.decommissioned_at.is_some()
+            || !self.cliff_scope.principal
+            || self.now() - account.deposit_time >= self.cliff_time
+    }
+
+    /// rewards earned by the account since its last accrual, not yet added to its balance.
+    fn pending_reward(&self, account: &Account) -> Balance {
+        let now = self.accrual_time();
+        if self.rewards_locked(account, now) || self.breaker_tripped_at.is_some() {
+            return 0;
+        }
+        self.reward_earned(account, now).saturating_add(account.vesting_reward)
+    }
+
+    /// rewards the account's stake earns between its last accrual and `now`, locked or not.
+    fn reward_earned(&self, account: &Account, now: Timestamp) -> Balance {
+        let start = self.accrual_start(account);
+        if now <= start {
+            return 0;
+        }
+        let boost = U256::from(
+            self.partner_boost_bps(account, now) + self.streak_boost_bps(account, start),
+        );
+        // the stake still warming up joins at `warm_until`
+        let warm_from = account.warm_until.max(start).min(now);
+        let warm_balance = account.obs_balance.saturating_sub(account.warming_balance);
+        self.stake_earned(account, warm_balance, start, warm_from, boost)
+            .saturating_add(self.stake_earned(account, account.obs_balance, warm_from, now, boost))
+    }
+
+    /// rewards `balance` of the account's stake earns between `from` and `to`.
+    fn stake_earned(
+        &self,
+        account: &Account,
+        balance: Balance,
+        from: Timestamp,
+        to: Timestamp,
+        boost_bps: U256,
+    ) -> Balance {
+        if from >= to || balance == 0 {
+            return 0;
+        }
+        let emission = self.emission_between(from, to);
+        self.accrued(self.reward_weight.weight(balance), emission, boost_bps)
+            .saturating_add(self.vip_reward(account, balance, from, to))
+    }
+
+    /// rewards `weight` earns over `emission` with `boost_bps`. Past the bounds
+    /// `MAX_REWARD_RATE` is set for, they saturate rather than fail every accrual.
+    pub(crate) fn accrued(&self, weight: U256, emission: U256, boost_bps: U256) -> Balance {
+        let accrued = weight
+            .checked_mul(emission)
+            .and_then(|earned| earned.checked_mul(U256::from(OBS_PER_REWARD_DENOM)))
+            .and_then(|earned| earned.checked_mul(boost_bps))
+            .map(|earned| earned / U256::from(self.reward_interval) / U256::from(BPS_DENOM));
+        match accrued {
+            Some(accrued) if accrued <= U256::from(u128::MAX) => accrued.as_u128(),
+            _ => u128::MAX,
+        }
+    }
+
+    /// updating inner pool balances. Rewards of closed reward periods are settled first.
     fn touch(&mut self, account: &mut Account) -> Balance {
-        let current_time = env::block_timestamp();
-        let time_diff = current_time - account.deposit_time;
-        let earned_balance = (((U256::from(account.obs_balance)
-            * U256::from(time_diff)
-            * U256::from(self.reward_rate))
-            / U256::from(self.reward_interval))
-            * U256::from(OBS_PER_REWARD_DENOM))
-        .as_u128();
-        if time_diff > self.cliff_time.into() {
-            account.reward_balance += earned_balance;
-            self.total_reward_farmed += earned_balance;
-        };
-        return account.last_obs_per_reward_rate;
+        let gas_mark = self.gas_mark();
+        account.reward_balance += self.staker_fees_owed(account);
+        account.staker_fee_index = self.staker_fee_index;
+        let earned_balance = self.pending_reward(account);
+        if earned_balance > 0 && self.accrual_allowed(earned_balance) {
+            account.reward_balance = account.reward_balance.saturating_add(earned_balance);
+            account.last_accrual_time = self.accrual_time();
+            account.vesting_reward = 0;
+            self.total_reward_farmed = self.total_reward_farmed.saturating_add(earned_balance);
+        }
+        self.record_gas(GasPhase::Accrual, gas_mark);
+        account.last_obs_per_reward_rate
+    }
+
+    /// Stakes `deposit` for the registered `account_id`, less the entry fee.
+    fn internal_stake(
+        &mut self,
+        account_id: &AccountId,
+        deposit: Balance,
+        campaign: Option<String>,
+    ) {
+        assert!(deposit > 0, "Amount must be greater than 0",);
+        self.assert_not_frozen(account_id);
+        self.assert_not_contract(account_id);
+        self.assert_deposits_open();
+        self.apply_due_changes();
+        let fee = fee_of(deposit, self.entry_fee_bps);
+        let amount = deposit - fee;
+        let (account_id_hash, mut account) = self.get_mut_account(account_id);
+        self.checkpoint_balance(account_id, &account);
+        let deposit_time = self.now();
+        if account.obs_balance == 0 {
+            self.active_stakers += 1;
+            account.streak_started_at = deposit_time;
+            // the cliff starts with the stake; rewards vesting before an exit are forfeited
+            account.deposit_time = deposit_time;
+            account.vesting_reward = 0;
+        } else {
+            self.settle_vesting_reward(&mut account);
+        }
+
+        account.obs_balance += amount;
+        self.rank_staker(account_id, account.obs_balance);
+        account.last_accrual_time = deposit_time;
+        self.warm_up_stake(&mut account, amount, deposit_time + self.stake_warm_up);
+        account.total_staked += amount;
+        if account.stake_count == 0 {
+            account.first_stake_time = deposit_time;
+        }
+        account.stake_count += 1;
+        self.record_campaign(account_id, &mut account, amount, campaign);
+        self.start_operation(OperationKind::Stake, account_id, amount, OperationStatus::Settled);
+        self.announce_multiplier(account_id, &mut account);
+        self.refresh_partner_balance_if_stale(account_id, &account);
+        self.record_apr(false);
+        self.notify_position_hook(account_id, &account, PositionEvent::Staked, amount);
+
+        let current_time = self.now();
+        let time_diff = current_time - deposit_time;
+        let obs_per_reward =
+            (((U256::from(deposit) * U256::from(time_diff) * U256::from(self.reward_rate))
+                / U256::from(self.reward_interval))
+                * U256::from(OBS_PER_REWARD_DENOM))
+            .as_u128();
+
+        self.obs_per_reward_rate = obs_per_reward;
+        self.ledger.post(LedgerAccount::Deposits, LedgerAccount::Staked, amount);
+        self.ledger.post(LedgerAccount::Deposits, LedgerAccount::Fees, fee);
+        self.entry_fees += fee;
+        self.route_fee(fee, account.obs_balance, self.fee_destination);
+        account.staker_fee_index = self.staker_fee_index;
+        self.save_account(&account_id_hash, &account);
+        self.check_runway();
+    }
+
+    /// positions can't be locked without the `liens` feature.
+    #[cfg(not(feature = "liens"))]
+    fn assert_not_liened(&self, _account_id: &AccountId) {}
+
+    #[cfg(not(feature = "liens"))]
+    fn is_liened(&self, _account_id: &AccountId) -> bool {
+        false
     }
 
+    /// Withdraws the whole stake of `account_id` along with its rewards. Rewards of an
+    /// account refused by the contract gate stay in its balance.
+    fn internal_unstake(&mut self, account_id: &AccountId) {
+        self.assert_not_frozen(account_id);
+        self.assert_not_liened(account_id);
+        let (account_id_hash, mut account) = self.get_mut_account(account_id);
+        self.assert_cliff_reached(&account);
+        self.checkpoint_balance(account_id, &account);
+
+        let principal = account.obs_balance;
+        let reward = if self.refuses_contract(account_id) { 0 } else { account.reward_balance };
+        self.check_large_unstake(&mut account, principal);
+        if principal > 0 {
+            self.active_stakers -= 1;
+        }
+        self.ledger.post(LedgerAccount::Staked, LedgerAccount::Withdrawals, principal);
+        self.apply_due_changes();
+        let fee = fee_of(principal, self.exit_fee_bps);
+        self.charge_exit_fee(fee);
+
+        account.total_withdrawn += principal;
+        account.obs_balance = 0;
+        account.warming_balance = 0;
+        account.reward_claimed += reward;
+        account.reward_balance -= reward;
+        self.announce_multiplier(account_id, &mut account);
+        self.save_account(&account_id_hash, &account);
+        self.rank_staker(account_id, 0);
+        if principal > 0 {
+            let status = OperationStatus::Settled;
+            self.start_operation(OperationKind::Unstake, account_id, principal, status);
+            self.notify_position_hook(account_id, &account, PositionEvent::Unstaked, principal);
+        }
+        if reward > 0 {
+            self.notify_position_hook(account_id, &account, PositionEvent::RewardClaimed, reward);
+        }
+        let mut promises = self.token_gas(&self.obs_token_account_id).ft_transfer.0;
+        if reward > 0 {
+            promises += self.token_gas(&self.reward_token_account_id).ft_transfer.0
+                + GAS_FOR_RESOLVE_TRANSFER
+                + self.payout_check_gas();
+        }
+        self.assert_prepaid_gas(promises);
+
+        let gas_mark = self.gas_mark();
+        let withdraw = ext_fungible_token::ft_transfer(
+            self.payout_receiver(account_id),
+            (principal - fee).into(),
+            None,
+            &self.obs_token_account_id.clone(),
+            1,
+            self.token_gas(&self.obs_token_account_id).ft_transfer.0,
+        );
+        self.record_gas(GasPhase::Promises, gas_mark);
+        if reward > 0 {
+            if let Some(promise) = self.pay_or_queue_reward(account_id, reward, |farm| {
+                farm.internal_send_reward(account_id, reward)
+            }) {
+                withdraw.then(promise);
+            }
+        }
+    }
+
+    /// loads a registered account with its rewards accrued, unless it is frozen.
     fn get_mut_account(&mut self, account_id: &AccountId) -> (ShortAccountHash, Account) {
-        let (account_id_hash, account) = self.get_internal_account(&account_id);
-        let mut account = account.unwrap_or_else(|| Account {
-            last_obs_per_reward_rate: self.obs_per_reward_rate,
-            obs_balance: 0,
-            reward_balance: 0,
-            reward_claimed: 0,
-            deposit_time: 0,
-        });
-        self.touch(&mut account);
+        self.release_drip_until(self.now());
+        let (account_id_hash, mut account) = self.get_registered_account(account_id);
+        if !self.is_frozen(account_id) {
+            self.settle_reward_periods(&account_id_hash, &mut account);
+            self.touch(&mut account);
+        }
+        self.expire_rewards(account_id, &mut account);
+        self.announce_multiplier(account_id, &mut account);
         (account_id_hash, account)
     }
 
+    /// Persists the account, refusing the write if its storage deposit doesn't cover the
+    /// bytes it would take.
     fn save_account(&mut self, account_id_hash: &ShortAccountHash, account: &Account) {
-        self.accounts.insert(account_id_hash, account);
+        let gas_mark = self.gas_mark();
+        let storage_cost = Balance::from(account.storage_used()) * env::storage_byte_cost();
+        assert!(
+            storage_cost <= account.storage_deposit,
+            "Insufficient storage deposit, {} yoctoNEAR is required",
+            storage_cost
+        );
+        let old = self.accounts.insert(account_id_hash, account);
+        self.account_log.record(account_id_hash, old.as_ref(), Some(account));
+        self.record_unlock(old.as_ref(), Some(account));
+        self.record_account_principal(old.as_ref(), Some(account));
+        self.assert_ledger_reconciled();
+        self.record_gas(GasPhase::Storage, gas_mark);
     }
 }
 
@@ -351,23 +1263,43 @@ mod tests {
     use super::*;
     use std::convert::TryInto;
 
-    fn alice() -> AccountId {
+    pub(crate) fn alice() -> AccountId {
         "alice.near".to_string()
     }
-    fn bob() -> AccountId {
+    pub(crate) fn bob() -> AccountId {
         "bob.near".to_string()
     }
-    fn obs() -> ValidAccountId {
+    pub(crate) fn obs() -> ValidAccountId {
         "obs.near".try_into().unwrap()
     }
-    fn reward() -> ValidAccountId {
+    pub(crate) fn reward() -> ValidAccountId {
         "reward.near".try_into().unwrap()
     }
     fn farm() -> AccountId {
         "farm.near".to_string()
     }
 
-    fn get_context(
+    pub(crate) fn new_farm() -> Farm {
+        Farm::new(FarmConfig::new(alice().try_into().unwrap(), obs(), reward()))
+    }
+
+    /// farm with 10**30 rewards in the reserve and alice staking 1000 obs since genesis.
+    pub(crate) fn staked_farm() -> Farm {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(10u128.pow(30)), String::new());
+
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+        contract
+    }
+
+    pub(crate) fn get_context(
         predecessor_account_id: String,
         storage_usage: u64,
         block_timestamp: u64,
@@ -397,31 +1329,64 @@ mod tests {
     fn test_fail_deploy_farm() {
         let context = get_context(farm(), 0, 100);
         testing_env!(context);
-        let _contract = Farm::new(obs(), reward());
+        let _contract = new_farm();
         assert!(env::state_exists());
     }
 
     #[test]
     fn test_obs_staking() {
-        let context = get_context(alice(), 0, 101);
+        let mut context = get_context(alice(), 0, 101);
+        context.attached_deposit = 10u128.pow(24);
         testing_env!(context);
-        let mut contract = Farm::new(obs(), reward());
-        contract.stake_my_obs(1000);
+        let mut contract = new_farm();
+        contract.register_account();
+
+        let context = get_context(alice(), env::storage_usage(), 101);
+        testing_env!(context);
+        contract.stake_my_obs(U128(1000));
         // let (_hash, account) = contract.get_mut_account(&mut alice());
         // assert_eq!(account.obs_balance, 1000);
     }
 
+    #[test]
+    fn test_stake_for_beneficiary() {
+        let mut context = get_context(bob(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(1000), format!("{}:{}", STAKE_MSG, bob()));
+        let account = contract.get_account(bob().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 1000);
+    }
+
+    #[test]
+    fn test_staker_counters() {
+        let mut contract = staked_farm();
+        let stats = contract.get_stats();
+        assert_eq!((stats.accounts_registered.0, stats.active_stakers.0), (1, 1));
+
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let stats = contract.get_stats();
+        assert_eq!((stats.accounts_registered.0, stats.active_stakers.0), (1, 0));
+    }
+
     #[test]
     #[should_panic]
     fn test_fail_unstaking() {
         let context = get_context(alice(), 0, 102);
         testing_env!(context);
-        let mut contract = Farm::new(obs(), reward());
+        let mut contract = new_farm();
         contract.unstake_my_obs();
 
         let context = get_context(bob(), 0, contract.cliff_time + 101);
         testing_env!(context);
-        let mut contract = Farm::new(obs(), reward());
+        let mut contract = new_farm();
         contract.unstake_my_obs();
     }
 
@@ -429,8 +1394,8 @@ mod tests {
     // fn test_unstaking() {
     //     let context = get_context(alice(), 0, 101);
     //     testing_env!(context);
-    //     let mut contract = Farm::new(obs(), reward());
-    //     contract.stake_my_obs(1000);
+    //     let mut contract = new_farm();
+    //     contract.stake_my_obs(U128(1000));
 
     //     let context = get_context(alice(), 0, contract.cliff_time + 101);
     //     testing_env!(context);
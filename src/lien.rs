@@ -0,0 +1,154 @@
+//! Liens let lending protocols accept farm positions as collateral. The owner approves
+//! lienholder contracts, an account consents to one of them, and that contract can then
+//! lock the account's position: unstaking, exiting and unregistering are refused until
+//! the lienholder releases it.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Lien {
+    pub lienholder: AccountId,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub locked_at: Timestamp,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn add_lienholder(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        self.lienholders.insert(account_id.as_ref());
+        emit_event("add_lienholder", json!({ "account_id": account_id }));
+    }
+
+    /// Existing liens of the lienholder stay in place until it releases them.
+    pub fn remove_lienholder(&mut self, account_id: ValidAccountId) {
+        self.assert_owner();
+        assert!(self.lienholders.remove(account_id.as_ref()), "Not a lienholder");
+        emit_event("remove_lienholder", json!({ "account_id": account_id }));
+    }
+
+    pub fn get_lienholders(&self) -> Vec<AccountId> {
+        self.lienholders.to_vec()
+    }
+
+    /// Allows `lienholder` to lock the predecessor's position, `None` withdrawing consent.
+    pub fn approve_lienholder(&mut self, lienholder: Option<ValidAccountId>) {
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        account.approved_lienholder = lienholder.map(|lienholder| lienholder.into());
+        self.save_account(&account_id_hash, &account);
+    }
+
+    /// Places a lien of the predecessor, which must be `lienholder`, on the position.
+    pub fn lock_position(&mut self, position_id: ValidAccountId, lienholder: ValidAccountId) {
+        let lienholder: AccountId = lienholder.into();
+        assert_eq!(env::predecessor_account_id(), lienholder, "Only the lienholder can lock");
+        assert!(self.lienholders.contains(&lienholder), "Not an approved lienholder");
+        let position_id: AccountId = position_id.into();
+        let (_, account) = self.get_registered_account(&position_id);
+        assert_eq!(
+            account.approved_lienholder.as_ref(),
+            Some(&lienholder),
+            "The account didn't approve this lienholder"
+        );
+        assert!(self.liens.get(&position_id).is_none(), "Position is already locked");
+        let lien = Lien { lienholder, locked_at: self.now() };
+        emit_event("lock_position", json!({ "position_id": position_id, "lien": lien }));
+        self.liens.insert(&position_id, &lien);
+    }
+
+    pub fn release_position(&mut self, position_id: ValidAccountId) {
+        let lien = self.liens.get(position_id.as_ref()).expect("Position is not locked");
+        assert_eq!(
+            env::predecessor_account_id(),
+            lien.lienholder,
+            "Only the lienholder can release"
+        );
+        self.liens.remove(position_id.as_ref());
+        emit_event(
+            "release_position",
+            json!({ "position_id": position_id, "lienholder": lien.lienholder }),
+        );
+    }
+
+    pub fn get_lien(&self, position_id: ValidAccountId) -> Option<Lien> {
+        self.liens.get(position_id.as_ref())
+    }
+
+    /// Liens ordered by insertion, paginated.
+    pub fn get_liens(&self, from_index: U64, limit: U64) -> Vec<(AccountId, Lien)> {
+        self.liens.iter().skip(from_index.0 as usize).take(limit.0 as usize).collect()
+    }
+}
+
+impl Farm {
+    pub(crate) fn is_liened(&self, account_id: &AccountId) -> bool {
+        self.liens.get(account_id).is_some()
+    }
+
+    pub(crate) fn assert_not_liened(&self, account_id: &AccountId) {
+        if let Some(lien) = self.liens.get(account_id) {
+            fail(
+                ErrorCode::PositionLocked,
+                json!({ "account_id": account_id, "lienholder": lien.lienholder }),
+            );
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    /// staked farm with bob, an approved lienholder, holding a lien on alice's position.
+    fn liened_farm() -> Farm {
+        let mut contract = staked_farm();
+        contract.add_lienholder(bob().try_into().unwrap());
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        contract.approve_lienholder(Some(bob().try_into().unwrap()));
+
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.lock_position(alice().try_into().unwrap(), bob().try_into().unwrap());
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Position is locked by a lien")]
+    fn test_lien_blocks_unstake() {
+        let mut contract = liened_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.unstake_my_obs();
+    }
+
+    #[test]
+    fn test_release_position() {
+        let mut contract = liened_farm();
+        assert_eq!(contract.get_liens(U64(0), U64(10)).len(), 1);
+        contract.release_position(alice().try_into().unwrap());
+        assert!(contract.get_lien(alice().try_into().unwrap()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The account didn't approve this lienholder")]
+    fn test_lock_needs_consent() {
+        let mut contract = staked_farm();
+        contract.add_lienholder(bob().try_into().unwrap());
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.lock_position(alice().try_into().unwrap(), bob().try_into().unwrap());
+    }
+}
@@ -0,0 +1,85 @@
+//! Farm metadata for discovery by aggregators and dashboards.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::roles::Role;
+use crate::*;
+
+/// maximum length of any single metadata string, in bytes.
+pub const MAX_METADATA_FIELD_LEN: usize = 1024;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmMetadata {
+    pub title: String,
+    pub description: Option<String>,
+    pub icon: Option<String>,
+    pub website: Option<String>,
+    pub social_links: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl FarmMetadata {
+    pub fn assert_valid(&self) {
+        assert!(!self.title.is_empty(), "Farm title is required");
+        let fields = [&self.description, &self.icon, &self.website];
+        let texts = std::iter::once(&self.title)
+            .chain(fields.iter().filter_map(|field| field.as_ref()))
+            .chain(self.social_links.iter())
+            .chain(self.tags.iter());
+        for text in texts {
+            assert!(text.len() <= MAX_METADATA_FIELD_LEN, "Farm metadata field is too long");
+        }
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn set_farm_metadata(&mut self, metadata: FarmMetadata) {
+        self.assert_role(Role::Operator);
+        metadata.assert_valid();
+        self.metadata.set(&metadata);
+    }
+
+    pub fn get_farm_metadata(&self) -> Option<FarmMetadata> {
+        self.metadata.get()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm};
+
+    fn metadata() -> FarmMetadata {
+        FarmMetadata {
+            title: "OBS farm".to_string(),
+            description: Some("Stake OBS, earn STR".to_string()),
+            icon: None,
+            website: Some("https://example.com".to_string()),
+            social_links: vec![],
+            tags: vec!["staking".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_set_farm_metadata() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        assert!(contract.get_farm_metadata().is_none());
+        contract.set_farm_metadata(metadata());
+        assert_eq!(contract.get_farm_metadata().unwrap().title, "OBS farm");
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner or an account with role Operator")]
+    fn test_set_farm_metadata_needs_operator() {
+        testing_env!(get_context(bob(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_farm_metadata(metadata());
+    }
+}
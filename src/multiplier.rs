@@ -0,0 +1,71 @@
+//! Events for changes of an account's reward multiplier, so boost marketplaces and analytics
+//! can follow weights without polling every account. The multiplier adds up the partner
+//! boost, the VIP slot and the streak bonus; each account keeps the multiplier last
+//! announced, and a `multiplier_changed` event with the old and new value is emitted when
+//! the account is written with another one. Changes that come with time, like a streak
+//! growing by an epoch or a VIP slot starting, are announced when the account is next
+//! touched.
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+impl Farm {
+    /// Reward multiplier of the account at `time`, in basis points.
+    pub(crate) fn multiplier_bps(&self, account: &Account, time: Timestamp) -> u32 {
+        self.partner_boost_bps(account, time)
+            + self.vip_boost_bps(account, time)
+            + self.streak_boost_bps(account, time)
+    }
+
+    /// Announces the account's multiplier if it changed since last announced.
+    pub(crate) fn announce_multiplier(&self, account_id: &AccountId, account: &mut Account) {
+        let multiplier_bps = self.multiplier_bps(account, self.now());
+        if multiplier_bps == account.multiplier_bps {
+            return;
+        }
+        emit_event(
+            "multiplier_changed",
+            json!({
+                "account_id": account_id,
+                "old_multiplier_bps": account.multiplier_bps,
+                "new_multiplier_bps": multiplier_bps,
+            }),
+        );
+        account.multiplier_bps = multiplier_bps;
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::U64;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::streak::StreakBonus;
+    use crate::tests::{alice, get_context, staked_farm};
+
+    const EPOCH: Timestamp = 1_000_000_000;
+
+    #[test]
+    fn test_multiplier_changes_are_announced() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_streak_bonus(Some(StreakBonus {
+            epoch_length: U64(EPOCH),
+            bonus_bps_per_epoch: 1_000,
+            max_bonus_bps: 2_500,
+        }));
+        let (_, account) = contract.get_mut_account(&alice());
+        assert_eq!(account.multiplier_bps, BPS_DENOM as u32);
+
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * EPOCH));
+        let (_, account) = contract.get_mut_account(&alice());
+        assert_eq!(account.multiplier_bps, 12_000);
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(logs.iter().any(|log| log.contains("multiplier_changed")
+            && log.contains("\"old_multiplier_bps\":10000")
+            && log.contains("\"new_multiplier_bps\":12000")));
+    }
+}
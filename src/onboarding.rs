@@ -0,0 +1,108 @@
+//! Transactions a new staker sends, for wallets to batch. `get_onboarding_plan` lists them
+//! in order: registering on the reward token so payouts can land, registering on the farm
+//! unless the sponsorship pool covers it, and the staking transfer itself. The farm can't
+//! see token registrations from a view, so the reward token registration is always listed;
+//! with `registration_only` it refunds the deposit of accounts already registered.
+use near_contract_standards::storage_management::StorageManagement;
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::{json, Value};
+use near_sdk::{near_bindgen, Gas};
+
+use crate::*;
+
+/// NEAR attached to register on the reward token, above the usual token storage bounds.
+pub const TOKEN_STORAGE_DEPOSIT: Balance = 12_500_000_000_000_000_000_000;
+
+/// gas of a storage deposit transaction.
+pub const STORAGE_DEPOSIT_GAS: Gas = 30_000_000_000_000;
+
+/// gas of the staking transfer, covering the token, `ft_on_transfer` and the resolve.
+pub const STAKE_TRANSFER_GAS: Gas = 100_000_000_000_000;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OnboardingTransaction {
+    pub receiver_id: AccountId,
+    pub method_name: String,
+    pub args: Value,
+    pub deposit: U128,
+    pub gas: U64,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Transactions `account_id` still has to send, in order, to stake `amount` of obs.
+    pub fn get_onboarding_plan(
+        &self,
+        account_id: ValidAccountId,
+        amount: U128,
+    ) -> Vec<OnboardingTransaction> {
+        self.assert_not_decommissioned();
+        assert!(amount.0 > 0, "Amount must be greater than 0");
+        let storage_deposit = |receiver_id: &AccountId, deposit: Balance| OnboardingTransaction {
+            receiver_id: receiver_id.clone(),
+            method_name: "storage_deposit".to_string(),
+            args: json!({ "account_id": account_id, "registration_only": true }),
+            deposit: deposit.into(),
+            gas: STORAGE_DEPOSIT_GAS.into(),
+        };
+        let mut plan = vec![];
+        if !self.is_single_token() {
+            plan.push(storage_deposit(&self.reward_token_account_id, TOKEN_STORAGE_DEPOSIT));
+        }
+        if self.get_internal_account(account_id.as_ref()).1.is_none() {
+            let min_balance = self.storage_balance_bounds().min.0;
+            let sponsored = self.sponsored_accounts < self.sponsored_accounts_limit
+                && self.storage_sponsor_pool >= min_balance;
+            if !sponsored {
+                plan.push(storage_deposit(&env::current_account_id(), min_balance));
+            }
+        }
+        plan.push(OnboardingTransaction {
+            receiver_id: self.obs_token_account_id.clone(),
+            method_name: "ft_transfer_call".to_string(),
+            args: json!({
+                "receiver_id": env::current_account_id(),
+                "amount": amount,
+                "msg": STAKE_MSG,
+            }),
+            deposit: U128(1),
+            gas: STAKE_TRANSFER_GAS.into(),
+        });
+        plan
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_onboarding_plan() {
+        let contract = staked_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        let plan = contract.get_onboarding_plan(bob().try_into().unwrap(), U128(100));
+        let methods: Vec<_> =
+            plan.iter().map(|tx| (tx.receiver_id.as_str(), tx.method_name.as_str())).collect();
+        assert_eq!(
+            methods,
+            vec![
+                ("reward.near", "storage_deposit"),
+                (env::current_account_id().as_str(), "storage_deposit"),
+                ("obs.near", "ft_transfer_call"),
+            ]
+        );
+        assert_eq!(plan[2].args["msg"], STAKE_MSG);
+        assert_eq!(plan[2].args["amount"], "100");
+
+        // a registered staker only needs the transfer and the reward token registration
+        let plan = contract.get_onboarding_plan(alice().try_into().unwrap(), U128(100));
+        assert_eq!(plan.len(), 2);
+    }
+}
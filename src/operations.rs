@@ -0,0 +1,148 @@
+//! Operation ids for support tooling. Stakes, unstakes and reward payouts get an op id from
+//! a farm-wide counter, logged in the `operation` event and kept with the payout in the
+//! account's claim history, so a user reporting a missing claim can be traced with
+//! `get_operation`. Stakes and unstakes settle at once; payouts stay pending until their
+//! transfer resolves, then settle or roll back to the account's balance. The latest
+//! `MAX_OPERATIONS` operations are kept.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+/// operations kept for lookup.
+pub const MAX_OPERATIONS: u64 = 10_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OperationKind {
+    Stake,
+    Unstake,
+    Claim,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum OperationStatus {
+    Pending,
+    Settled,
+    RolledBack,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Operation {
+    pub kind: OperationKind,
+    pub account_id: AccountId,
+    pub amount: Balance,
+    pub status: OperationStatus,
+    pub started_at: Timestamp,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OperationView {
+    pub op_id: U64,
+    pub kind: OperationKind,
+    pub account_id: AccountId,
+    pub amount: U128,
+    pub status: OperationStatus,
+    pub started_at: TimestampView,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// The operation `op_id`, unless it is older than the latest `MAX_OPERATIONS`.
+    pub fn get_operation(&self, op_id: U64) -> Option<OperationView> {
+        self.operations.get(&op_id.0).map(|operation| OperationView {
+            op_id,
+            kind: operation.kind,
+            account_id: operation.account_id,
+            amount: operation.amount.into(),
+            status: operation.status,
+            started_at: operation.started_at.into(),
+        })
+    }
+}
+
+impl Farm {
+    /// Records an operation of `kind` on `account_id`, returning its op id.
+    pub(crate) fn start_operation(
+        &mut self,
+        kind: OperationKind,
+        account_id: &AccountId,
+        amount: Balance,
+        status: OperationStatus,
+    ) -> u64 {
+        self.last_op_id += 1;
+        let op_id = self.last_op_id;
+        let operation = Operation {
+            kind,
+            account_id: account_id.clone(),
+            amount,
+            status,
+            started_at: self.now(),
+        };
+        self.operations.insert(&op_id, &operation);
+        if op_id > MAX_OPERATIONS {
+            self.operations.remove(&(op_id - MAX_OPERATIONS));
+        }
+        emit_event(
+            "operation",
+            json!({
+                "op_id": U64(op_id),
+                "kind": kind,
+                "account_id": account_id,
+                "amount": U128(amount),
+                "status": status,
+            }),
+        );
+        op_id
+    }
+
+    /// Settles the pending operation `op_id`, or rolls it back.
+    pub(crate) fn resolve_operation(&mut self, op_id: u64, settled: bool) {
+        let mut operation = match self.operations.get(&op_id) {
+            Some(operation) if operation.status == OperationStatus::Pending => operation,
+            _ => return,
+        };
+        operation.status =
+            if settled { OperationStatus::Settled } else { OperationStatus::RolledBack };
+        self.operations.insert(&op_id, &operation);
+        emit_event("resolve_operation", json!({ "op_id": U64(op_id), "status": operation.status }));
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+
+    #[test]
+    fn test_operation_lifecycle() {
+        let mut contract = staked_farm();
+        let stake = contract.get_operation(U64(1)).unwrap();
+        assert_eq!((stake.kind, stake.status), (OperationKind::Stake, OperationStatus::Settled));
+        assert_eq!(stake.amount.0, 1000);
+
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.claim_all();
+        let claim = contract.get_operation(U64(2)).unwrap();
+        assert_eq!((claim.kind, claim.status), (OperationKind::Claim, OperationStatus::Pending));
+
+        testing_env!(
+            get_context(alice(), env::storage_usage(), contract.cliff_time + 1),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_reward_transfer(alice(), claim.amount, U64(2));
+        assert_eq!(contract.get_operation(U64(2)).unwrap().status, OperationStatus::RolledBack);
+        assert!(contract.get_operation(U64(3)).is_none());
+    }
+}
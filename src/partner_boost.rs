@@ -0,0 +1,180 @@
+//! Reward boost for holders of a partner token. The farm caches each account's partner
+//! token balance, refreshed through an `ft_balance_of` call when the account interacts
+//! with a stale cache or asks for it. Rewards are multiplied by the best tier the cached
+//! balance reaches, and a cache older than `max_staleness` gives no boost.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Promise};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// highest reward multiplier a tier can grant, in basis points.
+pub const MAX_PARTNER_BOOST_BPS: u32 = 30_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BoostTier {
+    pub min_balance: U128,
+    /// reward multiplier, in basis points.
+    pub multiplier_bps: u32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PartnerBoost {
+    pub token_id: AccountId,
+    pub tiers: Vec<BoostTier>,
+    /// how long a cached balance keeps granting its boost, in nanoseconds.
+    pub max_staleness: U64,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets or removes the partner token boost.
+    pub fn set_partner_boost(&mut self, boost: Option<PartnerBoost>) {
+        self.assert_owner();
+        if let Some(boost) = &boost {
+            assert!(env::is_valid_account_id(boost.token_id.as_bytes()), "Invalid token id");
+            for tier in boost.tiers.iter() {
+                assert!(
+                    (BPS_DENOM as u32..=MAX_PARTNER_BOOST_BPS).contains(&tier.multiplier_bps),
+                    "Boost multiplier out of range"
+                );
+            }
+        }
+        emit_event("set_partner_boost", json!({ "boost": boost }));
+        self.partner_boost = boost;
+    }
+
+    pub fn get_partner_boost(&self) -> Option<PartnerBoost> {
+        self.partner_boost.clone()
+    }
+
+    /// Refreshes the predecessor's cached partner token balance.
+    pub fn refresh_partner_boost(&mut self) -> Promise {
+        let account_id = env::predecessor_account_id();
+        self.get_registered_account(&account_id);
+        self.internal_refresh_partner_balance(&account_id)
+    }
+
+    #[private]
+    pub fn on_partner_balance(&mut self, account_id: AccountId, #[callback] balance: U128) {
+        let (account_id_hash, account) = self.get_internal_account(&account_id);
+        let mut account = match account {
+            Some(account) => account,
+            None => return,
+        };
+        // settle the rewards earned at the previous multiplier first
+        if !self.is_frozen(&account_id) {
+            self.settle_reward_periods(&account_id_hash, &mut account);
+            self.touch(&mut account);
+        }
+        account.partner_balance = balance.0;
+        account.partner_balance_checked_at = self.now();
+        self.announce_multiplier(&account_id, &mut account);
+        self.save_account(&account_id_hash, &account);
+    }
+}
+
+impl Farm {
+    /// reward multiplier of the account at `time`, in basis points.
+    pub(crate) fn partner_boost_bps(&self, account: &Account, time: Timestamp) -> u32 {
+        let boost = match &self.partner_boost {
+            Some(boost) => boost,
+            None => return BPS_DENOM as u32,
+        };
+        if time.saturating_sub(account.partner_balance_checked_at) > boost.max_staleness.0 {
+            return BPS_DENOM as u32;
+        }
+        boost
+            .tiers
+            .iter()
+            .filter(|tier| account.partner_balance >= tier.min_balance.0)
+            .map(|tier| tier.multiplier_bps)
+            .max()
+            .unwrap_or(BPS_DENOM as u32)
+    }
+
+    /// Schedules a balance refresh if the account's cache is too old to grant a boost.
+    pub(crate) fn refresh_partner_balance_if_stale(
+        &self,
+        account_id: &AccountId,
+        account: &Account,
+    ) {
+        if let Some(boost) = &self.partner_boost {
+            if self.now().saturating_sub(account.partner_balance_checked_at) > boost.max_staleness.0
+            {
+                self.internal_refresh_partner_balance(account_id);
+            }
+        }
+    }
+
+    fn internal_refresh_partner_balance(&self, account_id: &AccountId) -> Promise {
+        let boost = self.partner_boost.as_ref().expect("Partner boost is not set");
+        ext_fungible_token::ft_balance_of(
+            account_id.clone(),
+            &boost.token_id,
+            NO_DEPOSIT,
+            self.token_gas(&boost.token_id).ft_balance_of.0,
+        )
+        .then(ext_self::on_partner_balance(
+            account_id.clone(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            BASE_GAS,
+        ))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    fn boosted_farm() -> Farm {
+        let mut contract = staked_farm();
+        contract.set_partner_boost(Some(PartnerBoost {
+            token_id: "partner.near".to_string(),
+            tiers: vec![
+                BoostTier { min_balance: U128(100), multiplier_bps: 15_000 },
+                BoostTier { min_balance: U128(1_000), multiplier_bps: 20_000 },
+            ],
+            max_staleness: U64(contract.cliff_time * 4),
+        }));
+        contract
+    }
+
+    #[test]
+    fn test_partner_boost_multiplies_rewards() {
+        let mut contract = boosted_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let unboosted = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        assert!(unboosted > 0);
+
+        let (account_id_hash, account) = contract.get_internal_account(&alice());
+        let mut account = account.unwrap();
+        account.partner_balance = 500;
+        account.partner_balance_checked_at = contract.now();
+        contract.accounts.insert(&account_id_hash, &account);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_multiplier_bps, 15_000);
+        assert_eq!(account.reward_balance.0, unboosted * 3 / 2);
+    }
+
+    #[test]
+    fn test_stale_partner_balance_gives_no_boost() {
+        let mut contract = boosted_farm();
+        contract.on_partner_balance(alice(), U128(5_000));
+        let (_, account) = contract.get_internal_account(&alice());
+        let account = account.unwrap();
+        assert_eq!(contract.partner_boost_bps(&account, 0), 20_000);
+        assert_eq!(contract.partner_boost_bps(&account, contract.cliff_time * 4 + 1), 10_000);
+    }
+}
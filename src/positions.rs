@@ -0,0 +1,206 @@
+//! Positions held by contracts on behalf of others. A contract staker, such as a DAO or a
+//! vault, stakes with the msg `Stake#<label>` to keep the stake apart from its own under
+//! the key `<owner>#<label>`, like `dao.near#grant-42`. Each position is an account of its
+//! own, with independent accounting and views. `#` can't appear in account ids, so
+//! positions never clash with accounts. Only the owner manages a position, and payouts
+//! from it go to the owner.
+//!
+//! An owner holds at most `max_positions` positions, so its state stays bounded. Positions
+//! past the cliff can be merged with `consolidate_positions`, freeing their slots; the
+//! merged position keeps the target's deposit time, hook and bonuses.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, near_bindgen, PromiseOrValue};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// maximum length of a position label, in bytes.
+pub const MAX_POSITION_LABEL_LEN: usize = 64;
+
+/// positions an owner can hold unless set otherwise.
+pub const DEFAULT_MAX_POSITIONS: u64 = 32;
+
+#[near_bindgen]
+impl Farm {
+    pub fn get_position(&self, owner_id: ValidAccountId, label: String) -> Option<AccountView> {
+        self.account_view(&position_id(owner_id.as_ref(), &label))
+    }
+
+    /// Withdraws the whole stake of the predecessor's position `label` with its rewards.
+    #[payable]
+    pub fn unstake_position(&mut self, label: String) {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        self.assert_not_frozen(&owner_id);
+        self.internal_unstake(&position_id(&owner_id, &label));
+        self.flush_gas_profile("unstake_position");
+    }
+
+    /// Pays out all of the rewards of the predecessor's position `label`.
+    #[payable]
+    pub fn claim_position(&mut self, label: String) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        self.assert_not_frozen(&owner_id);
+        let promise = self.internal_claim(&position_id(&owner_id, &label));
+        self.flush_gas_profile("claim_position");
+        promise
+    }
+
+    /// Merges the predecessor's positions `labels` into the first of them, with their
+    /// stakes and rewards. All of them must be past the cliff.
+    #[payable]
+    pub fn consolidate_positions(&mut self, labels: Vec<String>) -> AccountView {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        self.assert_not_frozen(&owner_id);
+        assert!(labels.len() > 1, "Nothing to consolidate");
+        let target_id = position_id(&owner_id, &labels[0]);
+        let (target_hash, mut target) = self.get_mut_account(&target_id);
+        self.assert_mature_position(&target_id, &target);
+        self.checkpoint_balance(&target_id, &target);
+        for label in &labels[1..] {
+            let source_id = position_id(&owner_id, label);
+            assert_ne!(source_id, target_id, "Positions must be distinct");
+            let (source_hash, source) = self.get_mut_account(&source_id);
+            self.assert_mature_position(&source_id, &source);
+            self.checkpoint_balance(&source_id, &source);
+            if source.obs_balance > 0 && target.obs_balance > 0 {
+                self.active_stakers -= 1;
+            }
+            target.obs_balance += source.obs_balance;
+            if source.warm_until > self.now() {
+                self.warm_up_stake(&mut target, source.warming_balance, source.warm_until);
+            }
+            target.reward_balance += source.reward_balance;
+            target.total_staked += source.obs_balance;
+            self.rank_staker(&source_id, 0);
+            self.internal_unregister(&source_id, &source_hash, &source);
+        }
+        self.save_account(&target_hash, &target);
+        self.rank_staker(&target_id, target.obs_balance);
+        let obs_balance = U128(target.obs_balance);
+        emit_event(
+            "consolidate_positions",
+            json!({ "owner_id": owner_id, "labels": labels, "obs_balance": obs_balance }),
+        );
+        self.flush_gas_profile("consolidate_positions");
+        self.account_view(&target_id).unwrap()
+    }
+
+    pub fn set_max_positions(&mut self, max_positions: U64) {
+        self.assert_role(Role::Operator);
+        assert!(max_positions.0 > 0, "Owners must be able to hold a position");
+        self.max_positions = max_positions.into();
+        emit_event("set_max_positions", json!({ "max_positions": max_positions }));
+    }
+
+    pub fn get_position_count(&self, owner_id: ValidAccountId) -> U64 {
+        self.position_counts.get(owner_id.as_ref()).unwrap_or_default().into()
+    }
+}
+
+impl Farm {
+    /// Counts a new position of `owner_id`, up to `max_positions`.
+    pub(crate) fn open_position(&mut self, owner_id: &AccountId) {
+        let count = self.position_counts.get(owner_id).unwrap_or_default();
+        assert!(count < self.max_positions, "Too many positions, consolidate them first");
+        self.position_counts.insert(owner_id, &(count + 1));
+    }
+
+    pub(crate) fn close_position(&mut self, owner_id: &AccountId) {
+        let count = self.position_counts.get(owner_id).unwrap_or_default();
+        self.position_counts.insert(owner_id, &count.saturating_sub(1));
+    }
+
+    fn assert_mature_position(&self, position_id: &AccountId, position: &Account) {
+        self.assert_not_liened(position_id);
+        assert!(self.is_unlocked(position), "Only positions past the cliff can be consolidated");
+    }
+
+    /// Account tokens of `account_id` are paid to: its owner for a position.
+    pub(crate) fn payout_receiver(&self, account_id: &AccountId) -> AccountId {
+        account_id.split('#').next().unwrap().to_string()
+    }
+}
+
+/// Key of the position `label` of `owner_id`.
+pub(crate) fn position_id(owner_id: &AccountId, label: &str) -> AccountId {
+    assert!(
+        !label.is_empty()
+            && label.len() <= MAX_POSITION_LABEL_LEN
+            && label.bytes().all(|c| c.is_ascii_alphanumeric() || b"-_.".contains(&c)),
+        "Invalid position label"
+    );
+    format!("{}#{}", owner_id, label)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm, obs};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_positions_are_independent() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_registration_fee(Some(U128(10)));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(1010), format!("{}#grant-42", STAKE_MSG));
+        contract.ft_on_transfer(bob(), U128(510), format!("{}#grant-43", STAKE_MSG));
+
+        let bob_id: ValidAccountId = bob().try_into().unwrap();
+        let grant = contract.get_position(bob_id.clone(), "grant-42".to_string()).unwrap();
+        assert_eq!(grant.obs_balance.0, 1000);
+        let other = contract.get_position(bob_id.clone(), "grant-43".to_string()).unwrap();
+        assert_eq!(other.obs_balance.0, 500);
+        assert!(contract.get_account(bob_id).is_none());
+        assert_eq!(contract.payout_receiver(&position_id(&bob(), "grant-42")), bob());
+    }
+
+    #[test]
+    fn test_consolidate_positions() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_registration_fee(Some(U128(10)));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(1010), format!("{}#a", STAKE_MSG));
+        contract.ft_on_transfer(bob(), U128(510), format!("{}#b", STAKE_MSG));
+
+        let mut context = get_context(bob(), env::storage_usage(), contract.cliff_time + 1);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        let merged = contract.consolidate_positions(vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(merged.obs_balance.0, 1500);
+        let bob_id: ValidAccountId = bob().try_into().unwrap();
+        assert!(contract.get_position(bob_id.clone(), "b".to_string()).is_none());
+        assert_eq!(contract.get_position_count(bob_id).0, 1);
+        assert_eq!(contract.active_stakers, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Too many positions, consolidate them first")]
+    fn test_positions_are_capped() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_registration_fee(Some(U128(10)));
+        contract.set_max_positions(U64(1));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(1010), format!("{}#a", STAKE_MSG));
+        contract.ft_on_transfer(bob(), U128(1010), format!("{}#b", STAKE_MSG));
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid position label")]
+    fn test_position_label_is_checked() {
+        testing_env!(get_context(obs().into(), 0, 0));
+        let mut contract = new_farm();
+        contract.ft_on_transfer(bob(), U128(1000), format!("{}#a#b", STAKE_MSG));
+    }
+}
@@ -0,0 +1,112 @@
+//! Account preferences, kept in one struct on the account so integrations don't need a
+//! preferences service of their own: restaking of rewards (`autocompound`), the account
+//! claimed rewards are paid to, the manager claiming for the account (`batch_claim`), the
+//! contract notified of position changes (`hooks`) and a locale hint for frontends.
+//! `set_preferences` replaces them all at once, they are charged to the account's storage
+//! deposit and `get_account` returns them.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// maximum length of a locale hint, in bytes.
+pub const MAX_LOCALE_LEN: usize = 16;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Preferences {
+    /// whether keepers restake the account's rewards, see `autocompound`.
+    pub auto_compound: bool,
+    /// account claimed rewards are paid to instead of the account.
+    pub claim_receiver: Option<ValidAccountId>,
+    /// custodian allowed to claim for the account, see `batch_claim`.
+    pub claim_manager: Option<ValidAccountId>,
+    /// contract notified of changes to the position, see `hooks`.
+    pub notification_hook: Option<ValidAccountId>,
+    /// language tag for frontends, e.g. "en-US".
+    pub locale: Option<String>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Replaces the predecessor's preferences.
+    pub fn set_preferences(&mut self, preferences: Preferences) {
+        if let Some(locale) = &preferences.locale {
+            assert!(
+                !locale.is_empty()
+                    && locale.len() <= MAX_LOCALE_LEN
+                    && locale.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-'),
+                "Invalid locale"
+            );
+        }
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        self.set_auto_compounder(&account_id, preferences.auto_compound);
+        account.preferences = preferences;
+        self.save_account(&account_id_hash, &account);
+        emit_event(
+            "set_preferences",
+            json!({ "account_id": account_id, "preferences": account.preferences }),
+        );
+    }
+}
+
+impl Farm {
+    /// Account the rewards of `account_id` are paid to.
+    pub(crate) fn reward_receiver(&self, account_id: &AccountId) -> AccountId {
+        self.get_internal_account(account_id)
+            .1
+            .and_then(|account| account.preferences.claim_receiver)
+            .map_or_else(|| self.payout_receiver(account_id), |receiver_id| receiver_id.into())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_set_preferences() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.storage_deposit(None, None);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        let preferences = Preferences {
+            auto_compound: false,
+            claim_receiver: Some(bob().try_into().unwrap()),
+            claim_manager: None,
+            notification_hook: None,
+            locale: Some("en-US".to_string()),
+        };
+        contract.set_preferences(preferences.clone());
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.preferences, preferences);
+        assert_eq!(contract.reward_receiver(&alice()), bob());
+        assert_eq!(contract.reward_receiver(&bob()), bob());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid locale")]
+    fn test_locale_is_a_language_tag() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_preferences(Preferences {
+            locale: Some("en US".to_string()),
+            ..Default::default()
+        });
+    }
+}
@@ -0,0 +1,236 @@
+//! Oracle-priced reward rate. Fixed emissions overpay or underpay as the token prices
+//! move, so a treasurer can hand the reward rate to a controller aiming at a target APR
+//! in value. Once per `epoch`, a keeper with the `RewardRate` scope calls
+//! `update_reward_rate`, which reads the prices of the reward and obs tokens from the
+//! oracle and switches the emission to the rate worth `target_apr_bps` of the TVL, kept
+//! within `[min_rate, max_rate]`. The rate is per staked obs, so the TVL in value scales
+//! the emission and the APR alike and the rate only depends on the price ratio. Prices are
+//! quoted per whole token, so the decimals of both tokens must be cached.
+//!
+//! A farm is a single pool, so there is one controller per deployment. Each update is an
+//! emission change in effect right away, see `changes`; setting an emission schedule by
+//! hand takes over until the next update.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, ext_contract, near_bindgen, Promise};
+
+use crate::apr::YEAR_NANOS;
+use crate::bots::BotScope;
+use crate::changes::ParameterChange;
+use crate::config::assert_reward_rate;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[ext_contract(ext_price_oracle)]
+pub trait PriceOracle {
+    /// Prices of one whole token of each of `token_ids`, in the same quote and scale.
+    fn get_prices(&self, token_ids: Vec<AccountId>) -> Vec<U128>;
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateController {
+    pub oracle_id: ValidAccountId,
+    /// APR in value the rate aims at, in basis points.
+    pub target_apr_bps: u32,
+    pub min_rate: U128,
+    pub max_rate: U128,
+    /// least time between two updates, in nanoseconds.
+    pub epoch: U64,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateControllerView {
+    pub controller: RateController,
+    pub updated_at: Option<TimestampView>,
+    pub next_update_at: TimestampView,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Hands the reward rate to `controller`, `None` to keep the rate it set last.
+    pub fn set_rate_controller(&mut self, controller: Option<RateController>) {
+        self.assert_role(Role::Treasurer);
+        if let Some(controller) = &controller {
+            assert!(controller.min_rate.0 <= controller.max_rate.0, "Rate bounds are inverted");
+            assert_reward_rate(controller.max_rate.0);
+            assert!(controller.target_apr_bps > 0, "Target APR must be positive");
+            assert!(controller.epoch.0 > 0, "Epoch must be positive");
+            assert!(
+                self.obs_decimals.is_some() && self.reward_decimals.is_some(),
+                "Token decimals aren't cached"
+            );
+        }
+        self.rate_controller = controller.clone();
+        emit_event("set_rate_controller", json!({ "controller": controller }));
+    }
+
+    pub fn get_rate_controller(&self) -> Option<RateControllerView> {
+        self.rate_controller.clone().map(|controller| RateControllerView {
+            next_update_at: self.rate_updated_at.map_or(0, |at| at + controller.epoch.0).into(),
+            updated_at: self.rate_updated_at.map(TimestampView::from),
+            controller,
+        })
+    }
+
+    /// Reads the token prices from the oracle to set the reward rate of this epoch.
+    pub fn update_reward_rate(&mut self) -> Promise {
+        self.assert_automation(BotScope::RewardRate);
+        let controller = self.rate_controller.clone().expect("No rate controller is set");
+        let now = self.now();
+        if let Some(updated_at) = self.rate_updated_at {
+            assert!(now >= updated_at + controller.epoch.0, "Reward rate was updated this epoch");
+        }
+        self.rate_updated_at = Some(now);
+        self.assert_prepaid_gas(BASE_GAS + GAS_FOR_RESOLVE_TRANSFER);
+        ext_price_oracle::get_prices(
+            vec![self.reward_token_account_id.clone(), self.obs_token_account_id.clone()],
+            controller.oracle_id.as_ref(),
+            NO_DEPOSIT,
+            BASE_GAS,
+        )
+        .then(ext_self::on_prices(
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Switches the emission to the rate the prices call for.
+    #[private]
+    pub fn on_prices(&mut self, #[callback] prices: Vec<U128>) {
+        let controller = match &self.rate_controller {
+            Some(controller) => controller.clone(),
+            None => return,
+        };
+        assert_eq!(prices.len(), 2, "Oracle returned the wrong number of prices");
+        let (reward_price, obs_price) = (prices[0].0, prices[1].0);
+        assert!(reward_price > 0 && obs_price > 0, "Oracle returned a zero price");
+        let rate = controller_rate(
+            &controller,
+            reward_price,
+            obs_price,
+            self.reward_decimals.unwrap_or_default(),
+            self.obs_decimals.unwrap_or_default(),
+            self.reward_interval,
+        );
+        self.schedule_change(
+            None,
+            ParameterChange::EmissionSchedule(EmissionSchedule::Controlled { rate: rate.into() }),
+        );
+        let tvl = U256::from(self.ledger.balance(LedgerAccount::Staked)) * U256::from(obs_price)
+            / U256::from(10u128.pow(self.obs_decimals.unwrap_or_default().into()));
+        emit_event(
+            "update_reward_rate",
+            json!({
+                "rate": U128(rate),
+                "reward_price": U128(reward_price),
+                "obs_price": U128(obs_price),
+                "tvl": U128(tvl.min(U256::from(u128::MAX)).as_u128()),
+            }),
+        );
+        self.record_apr(true);
+    }
+}
+
+/// Rate earning `target_apr_bps` a year in value, within the controller's bounds. A staked
+/// obs unit accrues `rate × OBS_PER_REWARD_DENOM` reward units per `reward_interval`.
+fn controller_rate(
+    controller: &RateController,
+    reward_price: u128,
+    obs_price: u128,
+    reward_decimals: u8,
+    obs_decimals: u8,
+    reward_interval: u64,
+) -> Balance {
+    let scale = |decimals: u8| U256::from(10u128.pow(decimals.into()));
+    let rate = U256::from(controller.target_apr_bps)
+        * U256::from(reward_interval)
+        * U256::from(obs_price)
+        * scale(reward_decimals)
+        / (U256::from(YEAR_NANOS)
+            * U256::from(reward_price)
+            * scale(obs_decimals)
+            * U256::from(OBS_PER_REWARD_DENOM)
+            * U256::from(BPS_DENOM));
+    rate.min(U256::from(controller.max_rate.0)).as_u128().max(controller.min_rate.0)
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::config::MAX_REWARD_RATE;
+    use crate::tests::{alice, bob, get_context, new_farm};
+    use std::convert::TryInto;
+
+    fn controlled_farm() -> Farm {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.obs_decimals = Some(0);
+        contract.reward_decimals = Some(24);
+        contract.set_rate_controller(Some(RateController {
+            oracle_id: bob().try_into().unwrap(),
+            target_apr_bps: 1000,
+            min_rate: U128(1),
+            max_rate: U128(MAX_REWARD_RATE),
+            epoch: U64(100),
+        }));
+        contract
+    }
+
+    fn resolve_prices(contract: &mut Farm, time: Timestamp, reward_price: u128, obs_price: u128) {
+        let prices = json!([U128(reward_price), U128(obs_price)]).to_string();
+        testing_env!(
+            get_context(env::current_account_id(), env::storage_usage(), time),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(prices.into_bytes())]
+        );
+        contract.on_prices(vec![U128(reward_price), U128(obs_price)]);
+    }
+
+    #[test]
+    fn test_rate_follows_prices() {
+        let mut contract = controlled_farm();
+        contract.update_reward_rate();
+        resolve_prices(&mut contract, 0, 2, 1);
+        let apr = contract.get_apr().0;
+        // the APR in value is the token APR at the price ratio, over the decimals
+        assert_eq!(apr * 2 / 10u128.pow(24), 1000);
+
+        // the reward token halving in price doubles the rate
+        testing_env!(get_context(alice(), env::storage_usage(), 100));
+        contract.update_reward_rate();
+        resolve_prices(&mut contract, 100, 1, 1);
+        assert_eq!(contract.get_apr().0, apr * 2);
+    }
+
+    #[test]
+    fn test_rate_is_bounded() {
+        let controller = RateController {
+            oracle_id: bob().try_into().unwrap(),
+            target_apr_bps: 1000,
+            min_rate: U128(10),
+            max_rate: U128(20),
+            epoch: U64(100),
+        };
+        assert_eq!(controller_rate(&controller, 1, 1_000_000, 24, 0, YEAR_NANOS), 20);
+        assert_eq!(controller_rate(&controller, 1_000_000, 1, 0, 0, YEAR_NANOS), 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "Reward rate was updated this epoch")]
+    fn test_one_update_per_epoch() {
+        let mut contract = controlled_farm();
+        contract.update_reward_rate();
+        testing_env!(get_context(alice(), env::storage_usage(), 99));
+        contract.update_reward_rate();
+    }
+}
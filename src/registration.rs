@@ -0,0 +1,179 @@
+//! Implicit registration of first-time stakers. An unregistered account can stake straight
+//! through `ft_transfer_call`: its minimum storage deposit is covered by the sponsorship
+//! pool for the first `sponsored_accounts_limit` accounts staking for themselves, and
+//! otherwise by the farm in exchange for the registration fee kept from the transferred
+//! obs. Stakes for a beneficiary or a position never draw on the pool, and the owner can
+//! withdraw what the pool still holds.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Promise};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::storage::registration_deposit;
+use crate::*;
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the obs kept from a first stake to register its sender, `None` turning implicit
+    /// registration off.
+    pub fn set_registration_fee(&mut self, fee: Option<U128>) {
+        self.assert_owner();
+        self.registration_fee = fee.map(|fee| fee.0);
+        emit_event("set_registration_fee", json!({ "fee": fee }));
+    }
+
+    pub fn get_registration_fee(&self) -> Option<U128> {
+        self.registration_fee.map(U128)
+    }
+
+    /// Adds the attached NEAR to the pool paying the registration of new stakers.
+    #[payable]
+    pub fn sponsor_storage(&mut self) {
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "Attach NEAR to sponsor storage");
+        self.storage_sponsor_pool += amount;
+        emit_event(
+            "sponsor_storage",
+            json!({ "sponsor_id": env::predecessor_account_id(), "amount": U128(amount) }),
+        );
+    }
+
+    /// Withdraws `amount` of the sponsorship pool to the owner, all of it if `None`.
+    pub fn withdraw_storage_sponsorship(&mut self, amount: Option<U128>) -> Promise {
+        self.assert_owner();
+        let amount = amount.map_or(self.storage_sponsor_pool, |amount| amount.0);
+        assert!(amount > 0, "Nothing to withdraw");
+        assert!(amount <= self.storage_sponsor_pool, "The sponsorship pool holds less");
+        self.storage_sponsor_pool -= amount;
+        emit_event("withdraw_storage_sponsorship", json!({ "amount": U128(amount) }));
+        Promise::new(self.owner_id.clone()).transfer(amount)
+    }
+
+    /// Sets how many new stakers in total the sponsorship pool registers.
+    pub fn set_sponsored_accounts_limit(&mut self, limit: U64) {
+        self.assert_role(Role::Operator);
+        self.sponsored_accounts_limit = limit.into();
+        emit_event("set_sponsored_accounts_limit", json!({ "limit": limit }));
+    }
+}
+
+impl Farm {
+    /// Registers `account_id` if it isn't yet, returning the part of `amount` left to stake.
+    /// Only an account staking for itself is `sponsorable` by the sponsorship pool.
+    pub fn register_implicitly(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+        sponsorable: bool,
+    ) -> Balance {
+        let (account_id_hash, account) = self.get_internal_account(account_id);
+        if account.is_some() {
+            return amount;
+        }
+        if account_id.contains('#') {
+            self.open_position(&self.payout_receiver(account_id));
+        }
+        let min_balance = registration_deposit(account_id.len());
+        let sponsored = sponsorable
+            && self.sponsored_accounts < self.sponsored_accounts_limit
+            && self.storage_sponsor_pool >= min_balance;
+        let fee = if sponsored {
+            self.storage_sponsor_pool -= min_balance;
+            self.sponsored_accounts += 1;
+            0
+        } else {
+            self.registration_fee.expect("Account is not registered")
+        };
+        assert!(amount > fee, "The amount doesn't cover the registration fee");
+        let mut account = Account::new(self.obs_per_reward_rate, min_balance);
+        account.storage_sponsored = min_balance;
+        self.save_new_account(account_id, &account_id_hash, &mut account);
+        self.ledger.post(LedgerAccount::Deposits, LedgerAccount::Fees, fee);
+        emit_event(
+            "register_implicitly",
+            json!({ "account_id": account_id, "fee": U128(fee), "sponsored": sponsored }),
+        );
+        amount - fee
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm, obs};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_stake_registers_implicitly() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.set_registration_fee(Some(U128(100)));
+
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(1100), "Stake".to_string());
+        let account = contract.get_account(bob().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 1000);
+        let balance = contract.storage_balance_of(bob().try_into().unwrap()).unwrap();
+        assert_eq!(balance.available.0, 0);
+    }
+
+    #[test]
+    fn test_sponsored_registration() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.sponsor_storage();
+        contract.set_sponsored_accounts_limit(U64(1));
+
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(1000), "Stake".to_string());
+        let account = contract.get_account(bob().try_into().unwrap()).unwrap();
+        assert_eq!(account.obs_balance.0, 1000);
+        let stats = contract.get_stats();
+        assert_eq!(stats.sponsored_accounts.0, 1);
+        let min_balance = contract.storage_balance_bounds().min.0;
+        assert_eq!(stats.storage_sponsor_pool.0, 10u128.pow(24) - min_balance);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not registered")]
+    fn test_beneficiary_stake_is_not_sponsored() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.sponsor_storage();
+        contract.set_sponsored_accounts_limit(U64(1));
+
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(1000), format!("{}:{}", STAKE_MSG, bob()));
+    }
+
+    #[test]
+    fn test_withdraw_storage_sponsorship() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.sponsor_storage();
+        contract.withdraw_storage_sponsorship(Some(U128(10u128.pow(23))));
+        assert_eq!(contract.get_stats().storage_sponsor_pool.0, 9 * 10u128.pow(23));
+        contract.withdraw_storage_sponsorship(None);
+        assert_eq!(contract.get_stats().storage_sponsor_pool.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Account is not registered")]
+    fn test_implicit_registration_off() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(1100), "Stake".to_string());
+    }
+}
@@ -0,0 +1,158 @@
+//! Account state for indexers keeping a replica. Every account write is logged, so after
+//! downtime an indexer fetches the accounts changed since its cursor with
+//! `export_accounts_since` rather than re-scanning everything. `state_root` commits to the
+//! state of all accounts: the XOR of `sha256(account_id_hash ++ borsh(account))` over them,
+//! which a replica recomputes from the exported records to check it is consistent.
+//!
+//! The log keeps the latest `MAX_ACCOUNT_CHANGES` writes; an indexer further behind has to
+//! rebuild its replica.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{Base64VecU8, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+
+use crate::*;
+
+/// account writes kept in the log.
+pub const MAX_ACCOUNT_CHANGES: u64 = 10_000;
+
+/// most accounts `export_accounts_since` returns at once.
+const MAX_EXPORT_PAGE: u64 = 100;
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountRecord {
+    pub account_id_hash: Base64VecU8,
+    /// borsh-serialized account, `None` once it was unregistered.
+    pub account: Option<Base64VecU8>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountExport {
+    /// current state of the accounts written from the cursor on, in the order of the log.
+    pub accounts: Vec<AccountRecord>,
+    /// cursor of the next page; the replica matches `state_root` once it reaches the end.
+    pub next_cursor: U64,
+    pub end_cursor: U64,
+    pub state_root: Base64VecU8,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountLog {
+    /// written accounts by write index modulo `MAX_ACCOUNT_CHANGES`.
+    changes: LookupMap<u64, ShortAccountHash>,
+    /// account writes ever logged.
+    recorded: u64,
+    state_root: [u8; 32],
+}
+
+impl AccountLog {
+    pub fn new() -> Self {
+        Self {
+            changes: LookupMap::new(StorageKey::AccountChanges),
+            recorded: 0,
+            state_root: [0; 32],
+        }
+    }
+
+    /// Logs a write of the account at `account_id_hash` from `old` to `new`.
+    pub(crate) fn record(
+        &mut self,
+        account_id_hash: &ShortAccountHash,
+        old: Option<&Account>,
+        new: Option<&Account>,
+    ) {
+        for account in old.into_iter().chain(new) {
+            let leaf = leaf_hash(account_id_hash, account);
+            self.state_root.iter_mut().zip(leaf).for_each(|(root, leaf)| *root ^= leaf);
+        }
+        self.changes.insert(&(self.recorded % MAX_ACCOUNT_CHANGES), account_id_hash);
+        self.recorded += 1;
+    }
+}
+
+fn leaf_hash(account_id_hash: &ShortAccountHash, account: &Account) -> Vec<u8> {
+    let mut bytes = account_id_hash.0.to_vec();
+    bytes.extend(account.try_to_vec().unwrap());
+    env::sha256(&bytes)
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn state_root(&self) -> Base64VecU8 {
+        self.account_log.state_root.to_vec().into()
+    }
+
+    /// Accounts written from `cursor` on, up to `limit` of them. An account written several
+    /// times is listed at each write, always with its current state.
+    pub fn export_accounts_since(&self, cursor: U64, limit: Option<u64>) -> AccountExport {
+        let log = &self.account_log;
+        assert!(
+            cursor.0 + MAX_ACCOUNT_CHANGES >= log.recorded,
+            "Changes since the cursor were dropped, the replica has to be rebuilt"
+        );
+        let limit = limit.unwrap_or(MAX_EXPORT_PAGE).min(MAX_EXPORT_PAGE);
+        let next_cursor = (cursor.0 + limit).min(log.recorded);
+        let accounts = (cursor.0..next_cursor)
+            .map(|index| {
+                let account_id_hash = log
+                    .changes
+                    .get(&(index % MAX_ACCOUNT_CHANGES))
+                    .expect("Account change missing");
+                AccountRecord {
+                    account: self
+                        .accounts
+                        .get(&account_id_hash)
+                        .map(|account| account.try_to_vec().unwrap().into()),
+                    account_id_hash: account_id_hash.0.to_vec().into(),
+                }
+            })
+            .collect();
+        AccountExport {
+            accounts,
+            next_cursor: next_cursor.into(),
+            end_cursor: log.recorded.into(),
+            state_root: self.state_root(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_replica_matches_state_root() {
+        let contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+
+        let mut replica = HashMap::new();
+        let mut cursor = U64(0);
+        loop {
+            let export = contract.export_accounts_since(cursor, Some(1));
+            for record in export.accounts {
+                replica.insert(record.account_id_hash.0, record.account.map(|account| account.0));
+            }
+            cursor = export.next_cursor;
+            if cursor == export.end_cursor {
+                break;
+            }
+        }
+        let mut root = [0u8; 32];
+        for (account_id_hash, account) in replica {
+            if let Some(account) = account {
+                let leaf = env::sha256(&[account_id_hash, account].concat());
+                root.iter_mut().zip(leaf).for_each(|(root, leaf)| *root ^= leaf);
+            }
+        }
+        assert_eq!(contract.state_root().0, root.to_vec());
+        assert_ne!(root, [0; 32]);
+    }
+}
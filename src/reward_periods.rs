@@ -0,0 +1,475 @@
+//! Switching the reward token between emission periods. The switch closes the current
+//! period: the reserve left in the old token is set aside for the period's claims, and
+//! emissions go on in the new token. Accounts are settled lazily, the first time they are
+//! touched after the switch: rewards earned up to the close move to a per-period claimable
+//! balance, paid in the old token by `claim_period_rewards`, until the period's claims expire
+//! if it has an expiry. Rewards still vesting under the cliff at the close vest in the new
+//! token.
+//!
+//! Settling walks every period closed since the account was last touched, so a call settles
+//! at most `MAX_PERIODS_PER_SETTLE` of them. An account further behind is refused until
+//! `settle_periods` caught it up, one bounded chunk per call.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, is_promise_success, log, near_bindgen, Promise};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// closed periods settled for an account in a single call.
+pub const MAX_PERIODS_PER_SETTLE: u64 = 16;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct RewardPeriod {
+    pub token_id: AccountId,
+    pub closed_at: Timestamp,
+    pub claims_expire_at: Option<Timestamp>,
+    /// old reward tokens held for the period's claims.
+    pub reserve: Balance,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RewardPeriodView {
+    pub period: U64,
+    pub token_id: AccountId,
+    pub closed_at: TimestampView,
+    pub claims_expire_at: Option<TimestampView>,
+    pub reserve: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PeriodReward {
+    pub period: U64,
+    pub token_id: AccountId,
+    pub amount: U128,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Closes the current reward period and pays rewards in `token_id` from now on.
+    /// Claims of the closed period expire at `claims_expire_at`, never if `None`. Queued
+    /// claims must be paid, payouts resolved and strategies recalled first, as all are in
+    /// the old token.
+    pub fn switch_reward_token(&mut self, token_id: ValidAccountId, claims_expire_at: Option<U64>) {
+        self.assert_owner();
+        self.assert_not_decommissioned();
+        let token_id: AccountId = token_id.into();
+        assert_ne!(token_id, self.reward_token_account_id, "Already the reward token");
+        assert!(self.claim_queue.is_empty(), "Pay the queued claims first");
+        assert_eq!(self.reward_payouts_in_flight, 0, "Reward payouts are still in flight");
+        assert_eq!(self.deployed_reserve(), 0, "Recall the deployed reserve first");
+        assert!(self.drip.is_none(), "The locked reserve is in the current reward token");
+        let now = self.now();
+        if let Some(expires_at) = claims_expire_at {
+            assert!(expires_at.0 > now, "Claims must expire in the future");
+        }
+        let reserve = self.reward_reserve();
+        self.ledger.post(LedgerAccount::Reserve, LedgerAccount::Settled, reserve);
+        let old_token_id = std::mem::replace(&mut self.reward_token_account_id, token_id.clone());
+        self.reward_periods.push(&RewardPeriod {
+            token_id: old_token_id.clone(),
+            closed_at: now,
+            claims_expire_at: claims_expire_at.map(|expires_at| expires_at.0),
+            reserve,
+        });
+        // decimals of the new token are fetched again
+        self.reward_decimals = None;
+        self.record_apr(true);
+        emit_event(
+            "switch_reward_token",
+            json!({
+                "period": U64(self.reward_periods.len() - 1),
+                "old_token_id": old_token_id,
+                "token_id": token_id,
+                "reserve": U128(reserve),
+                "claims_expire_at": claims_expire_at,
+            }),
+        );
+    }
+
+    pub fn get_reward_periods(&self) -> Vec<RewardPeriodView> {
+        self.reward_periods
+            .iter()
+            .enumerate()
+            .map(|(period, reward_period)| RewardPeriodView {
+                period: U64(period as u64),
+                token_id: reward_period.token_id,
+                closed_at: reward_period.closed_at.into(),
+                claims_expire_at: reward_period.claims_expire_at.map(TimestampView::from),
+                reserve: reward_period.reserve.into(),
+            })
+            .collect()
+    }
+
+    /// Rewards of `account_id` in closed periods, not yet claimed.
+    pub fn get_period_rewards(&self, account_id: ValidAccountId) -> Vec<PeriodReward> {
+        let (account_id_hash, account) = self.get_internal_account(account_id.as_ref());
+        let mut unsettled = match account {
+            Some(mut account) if !self.is_frozen(account_id.as_ref()) => {
+                self.split_closed_periods(&mut account)
+            }
+            _ => vec![],
+        };
+        (0..self.reward_periods.len())
+            .filter_map(|period| {
+                let settled = self.settled_rewards.get(&(period, account_id_hash.clone()));
+                let position = unsettled.iter().position(|(unsettled, _)| *unsettled == period);
+                let amount = settled.unwrap_or(0)
+                    + position.map_or(0, |position| unsettled.swap_remove(position).1);
+                if amount == 0 {
+                    return None;
+                }
+                let token_id = self.reward_periods.get(period).unwrap().token_id;
+                Some(PeriodReward { period: period.into(), token_id, amount: amount.into() })
+            })
+            .collect()
+    }
+
+    /// Settles up to `MAX_PERIODS_PER_SETTLE` closed periods of `account_id`, for accounts
+    /// too far behind to be settled along with another call. Anyone can call it. Returns
+    /// how many closed periods are still to settle.
+    pub fn settle_periods(&mut self, account_id: ValidAccountId) -> U64 {
+        let account_id: AccountId = account_id.into();
+        self.assert_not_frozen(&account_id);
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        self.settle_reward_period_chunk(&account_id_hash, &mut account, MAX_PERIODS_PER_SETTLE);
+        self.save_account(&account_id_hash, &account);
+        let left = self.reward_periods.len() - account.reward_period;
+        emit_event("settle_periods", json!({ "account_id": account_id, "left": U64(left) }));
+        left.into()
+    }
+
+    /// Pays the predecessor's rewards of the closed `period`, in that period's token.
+    #[payable]
+    pub fn claim_period_rewards(&mut self, period: U64) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_frozen(&account_id);
+        self.assert_not_contract(&account_id);
+        let (account_id_hash, account) = self.get_mut_account(&account_id);
+        self.save_account(&account_id_hash, &account);
+        let mut reward_period = self.reward_periods.get(period.0).expect("Unknown reward period");
+        assert!(
+            !matches!(reward_period.claims_expire_at, Some(expires_at) if self.now() >= expires_at),
+            "Claims of this reward period have expired"
+        );
+        let key = (period.0, account_id_hash);
+        let amount = self.settled_rewards.remove(&key).unwrap_or(0);
+        if amount == 0 {
+            fail(ErrorCode::NothingToClaim, json!({}));
+        }
+        if amount > reward_period.reserve {
+            fail(
+                ErrorCode::InsufficientReserve,
+                json!({ "amount": U128(amount), "reserve": U128(reward_period.reserve) }),
+            );
+        }
+        let gas = self.token_gas(&reward_period.token_id).ft_transfer.0;
+        self.assert_prepaid_gas(gas + GAS_FOR_RESOLVE_TRANSFER);
+        reward_period.reserve -= amount;
+        self.reward_periods.replace(period.0, &reward_period);
+        self.ledger.post(LedgerAccount::Settled, LedgerAccount::Claimed, amount);
+        emit_event(
+            "claim_period_rewards",
+            json!({ "account_id": account_id, "period": period, "amount": U128(amount) }),
+        );
+        ext_fungible_token::ft_transfer(
+            account_id.clone(),
+            amount.into(),
+            None,
+            &reward_period.token_id,
+            1,
+            gas,
+        )
+        .then(ext_self::on_period_reward_transfer(
+            account_id,
+            period,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Sends what is left of the reserve of `period` to the treasury, once its claims have
+    /// expired.
+    pub fn sweep_reward_period(&mut self, period: U64) -> Promise {
+        self.assert_role(Role::Treasurer);
+        let mut reward_period = self.reward_periods.get(period.0).expect("Unknown reward period");
+        assert!(
+            matches!(reward_period.claims_expire_at, Some(expires_at) if self.now() >= expires_at),
+            "Claims of this reward period have not expired"
+        );
+        let amount = reward_period.reserve;
+        assert!(amount > 0, "Reward period reserve is empty");
+        reward_period.reserve = 0;
+        self.reward_periods.replace(period.0, &reward_period);
+        self.ledger.post(LedgerAccount::Settled, LedgerAccount::Claimed, amount);
+        let treasury_id = self.treasury_id.clone();
+        emit_event(
+            "sweep_reward_period",
+            json!({ "treasury_id": treasury_id, "period": period, "amount": U128(amount) }),
+        );
+        ext_fungible_token::ft_transfer(
+            treasury_id.clone(),
+            amount.into(),
+            None,
+            &reward_period.token_id,
+            1,
+            self.token_gas(&reward_period.token_id).ft_transfer.0,
+        )
+        .then(ext_self::on_period_reward_transfer(
+            treasury_id,
+            period,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Returns a failed payout of `period` to its reserve, and to the account's claimable
+    /// balance if it was a claim.
+    #[private]
+    pub fn on_period_reward_transfer(&mut self, account_id: AccountId, period: U64, amount: U128) {
+        let mut reward_period = self.reward_periods.get(period.0).unwrap();
+        if is_promise_success() {
+            self.record_claim(&account_id, amount.0, reward_period.token_id, None, None);
+            return;
+        }
+        reward_period.reserve += amount.0;
+        self.reward_periods.replace(period.0, &reward_period);
+        self.ledger.post(LedgerAccount::Claimed, LedgerAccount::Settled, amount.0);
+        if account_id != self.treasury_id {
+            let key = (period.0, ShortAccountHash::from(&account_id));
+            let settled = self.settled_rewards.get(&key).unwrap_or(0);
+            self.settled_rewards.insert(&key, &(settled + amount.0));
+        }
+        log!("Transfer of {} period {} rewards to @{} failed", amount.0, period.0, account_id);
+    }
+}
+
+impl Farm {
+    /// Moves `amount` out of the reserve for a payout whose callback calls
+    /// `resolve_reward_payout`.
+    pub(crate) fn start_reward_payout(&mut self, amount: Balance) {
+        self.ledger.post(LedgerAccount::Reserve, LedgerAccount::Claimed, amount);
+        self.reward_payouts_in_flight += 1;
+    }
+
+    pub(crate) fn resolve_reward_payout(&mut self) {
+        self.reward_payouts_in_flight = self.reward_payouts_in_flight.saturating_sub(1);
+    }
+
+    /// Splits off the rewards `account` holds and earned in periods closed since it was
+    /// last settled, leaving it with rewards in the current token only. Returns them by
+    /// period.
+    pub(crate) fn split_closed_periods(&self, account: &mut Account) -> Vec<(u64, Balance)> {
+        self.split_closed_period_chunk(account, u64::MAX)
+    }
+
+    /// Same as `split_closed_periods` for the first `max_periods` periods to settle.
+    fn split_closed_period_chunk(
+        &self,
+        account: &mut Account,
+        max_periods: u64,
+    ) -> Vec<(u64, Balance)> {
+        let mut settled = vec![];
+        let end = self.reward_periods.len().min(account.reward_period.saturating_add(max_periods));
+        while account.reward_period < end {
+            let closed_at = self.reward_periods.get(account.reward_period).unwrap().closed_at;
+            let mut amount = account.reward_balance;
+            if account.last_accrual_time < closed_at
+                && !self.rewards_locked(account, closed_at)
+                && self.breaker_tripped_at.is_none()
+            {
+                amount += self.reward_earned(account, closed_at) + account.vesting_reward;
+                account.last_accrual_time = closed_at;
+                account.vesting_reward = 0;
+            }
+            if amount > 0 {
+                settled.push((account.reward_period, amount));
+            }
+            account.reward_balance = 0;
+            account.reward_period += 1;
+        }
+        settled
+    }
+
+    /// Moves the rewards of closed periods out of `account` into their claimable balances.
+    /// Runs before the account accrues in the current period, and refuses accounts with
+    /// more than `MAX_PERIODS_PER_SETTLE` periods to settle.
+    pub(crate) fn settle_reward_periods(
+        &mut self,
+        account_id_hash: &ShortAccountHash,
+        account: &mut Account,
+    ) {
+        let behind = self.reward_periods.len() - account.reward_period;
+        assert!(
+            behind <= MAX_PERIODS_PER_SETTLE,
+            "The account has {} reward periods to settle, call settle_periods first",
+            behind
+        );
+        self.settle_reward_period_chunk(account_id_hash, account, MAX_PERIODS_PER_SETTLE);
+    }
+
+    fn settle_reward_period_chunk(
+        &mut self,
+        account_id_hash: &ShortAccountHash,
+        account: &mut Account,
+        max_periods: u64,
+    ) {
+        for (period, amount) in self.split_closed_period_chunk(account, max_periods) {
+            let key = (period, account_id_hash.clone());
+            let settled = self.settled_rewards.get(&key).unwrap_or(0);
+            self.settled_rewards.insert(&key, &(settled + amount));
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, reward, staked_farm};
+    use std::convert::TryInto;
+
+    fn new_token() -> ValidAccountId {
+        "new_reward.near".try_into().unwrap()
+    }
+
+    #[test]
+    fn test_switch_reward_token() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(
+            alice(),
+            env::storage_usage(),
+            contract.cliff_time + 1_000_000_000
+        ));
+        let reserve = contract.reward_reserve();
+        let before = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance;
+        assert!(before.0 > 0);
+        contract.switch_reward_token(new_token(), None);
+        assert_eq!(contract.reward_reserve(), 0);
+        assert_eq!(contract.get_reward_periods()[0].reserve.0, reserve);
+
+        testing_env!(get_context(
+            alice(),
+            env::storage_usage(),
+            contract.cliff_time + 2_000_000_000
+        ));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(account.reward_balance.0 > 0);
+        let period_rewards = contract.get_period_rewards(alice().try_into().unwrap());
+        assert_eq!(period_rewards[0].amount, before);
+        assert_eq!(period_rewards[0].token_id, "reward.near");
+
+        testing_env!(get_context(
+            alice(),
+            env::storage_usage(),
+            contract.cliff_time + 2_000_000_000
+        ));
+        contract.claim_period_rewards(U64(0));
+        assert_eq!(contract.get_reward_periods()[0].reserve.0, reserve - before.0);
+        assert!(contract.get_period_rewards(alice().try_into().unwrap()).is_empty());
+        assert_eq!(
+            contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance,
+            account.reward_balance
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Reward payouts are still in flight")]
+    fn test_switch_with_payout_in_flight() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.claim_all();
+        contract.switch_reward_token(new_token(), None);
+    }
+
+    #[test]
+    fn test_switch_after_failed_payout() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.claim_all();
+        let claimed = contract.get_account(alice().try_into().unwrap()).unwrap().reward_claimed;
+        testing_env!(
+            get_context(alice(), env::storage_usage(), contract.cliff_time + 1),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        contract.on_reward_transfer(alice(), claimed, U64(2));
+        // the failed payout is back in the old token's reserve before the period closes
+        let reserve = contract.reward_reserve();
+        contract.switch_reward_token(new_token(), None);
+        assert_eq!(contract.get_reward_periods()[0].reserve.0, reserve);
+        let rewards = contract.get_period_rewards(alice().try_into().unwrap());
+        assert_eq!(rewards[0].amount, claimed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Claims of this reward period have expired")]
+    fn test_claim_expired_period() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(
+            alice(),
+            env::storage_usage(),
+            contract.cliff_time + 1_000_000_000
+        ));
+        contract.switch_reward_token(new_token(), Some(U64(contract.cliff_time + 1_500_000_000)));
+        testing_env!(get_context(
+            alice(),
+            env::storage_usage(),
+            contract.cliff_time + 2_000_000_000
+        ));
+        contract.claim_period_rewards(U64(0));
+    }
+
+    #[test]
+    fn test_settle_periods_in_chunks() {
+        let mut contract = staked_farm();
+        for switch in 0..=MAX_PERIODS_PER_SETTLE {
+            testing_env!(get_context(
+                alice(),
+                env::storage_usage(),
+                contract.cliff_time + 1_000_000_000 + switch
+            ));
+            let token_id = if switch % 2 == 0 { new_token() } else { reward() };
+            contract.switch_reward_token(token_id, None);
+        }
+        testing_env!(get_context(bob(), env::storage_usage(), contract.cliff_time + 2_000_000_000));
+        assert_eq!(contract.settle_periods(alice().try_into().unwrap()).0, 1);
+        assert_eq!(contract.settle_periods(alice().try_into().unwrap()).0, 0);
+        let rewards = contract.get_period_rewards(alice().try_into().unwrap());
+        assert_eq!(rewards[0].period.0, 0);
+        assert!(rewards[0].amount.0 > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account has 17 reward periods to settle")]
+    fn test_too_far_behind_to_touch() {
+        let mut contract = staked_farm();
+        for switch in 0..=MAX_PERIODS_PER_SETTLE {
+            testing_env!(get_context(
+                alice(),
+                env::storage_usage(),
+                contract.cliff_time + 1_000_000_000 + switch
+            ));
+            let token_id = if switch % 2 == 0 { new_token() } else { reward() };
+            contract.switch_reward_token(token_id, None);
+        }
+        contract.get_mut_account(&alice());
+    }
+}
@@ -0,0 +1,478 @@
+//! Reward payouts. Rewards leave the reserve when a transfer is scheduled and are credited
+//! back to the account if the reward token transfer fails.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{
+    assert_one_yocto, env, is_promise_success, log, near_bindgen, Promise, PromiseResult,
+};
+
+use crate::changes::ParameterChange;
+use crate::events::emit_event;
+use crate::operations::{OperationKind, OperationStatus};
+use crate::roles::Role;
+use crate::*;
+
+/// upper bound of the `harvest_for` bounty, in basis points.
+pub const MAX_HARVEST_BOUNTY_BPS: u32 = 100;
+
+/// Whether `claim_all` would go through for an account, and what stands in the way if not.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimReadiness {
+    pub claimable: U128,
+    /// smallest balance `claim_all` pays out, see `set_min_claim`.
+    pub min_claim: U128,
+    pub ready: bool,
+    /// errors `claim_all` would fail with, see `get_error_catalog`. With
+    /// `INSUFFICIENT_RESERVE` the claim is queued rather than failing.
+    pub blockers: Vec<ErrorCode>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Accrues all of the predecessor's rewards and pays them out in a single transfer,
+    /// returning the amount paid. The claim is queued instead if the reserve is short.
+    #[payable]
+    pub fn claim_all(&mut self) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let promise = self.internal_claim(&env::predecessor_account_id());
+        self.flush_gas_profile("claim_all");
+        promise
+    }
+
+    /// Claims all of the predecessor's rewards and stakes them for it in `target_farm`
+    /// through `ft_transfer_call`. Rewards the target farm doesn't take are restored. A
+    /// claim that has to wait is queued and paid out like `claim_all`.
+    #[payable]
+    pub fn claim_and_stake_into(&mut self, target_farm: ValidAccountId) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        self.assert_not_frozen(&account_id);
+        self.assert_not_contract(&account_id);
+        let (account_id_hash, mut account) = self.get_mut_account(&account_id);
+        let amount = account.reward_balance;
+        if amount == 0 {
+            fail(ErrorCode::NothingToClaim, json!({}));
+        }
+        self.assert_min_claim(amount);
+        account.reward_balance = 0;
+        account.reward_claimed += amount;
+        self.save_account(&account_id_hash, &account);
+        self.notify_position_hook(&account_id, &account, PositionEvent::RewardClaimed, amount);
+        let promise = self.pay_or_queue_reward(&account_id, amount, |farm| {
+            farm.start_reward_payout(amount);
+            let op_id = farm.start_operation(
+                OperationKind::Claim,
+                &account_id,
+                amount,
+                OperationStatus::Pending,
+            );
+            let gas = farm.token_gas(&farm.reward_token_account_id).ft_transfer_call.0;
+            farm.assert_prepaid_gas(gas + GAS_FOR_RESOLVE_TRANSFER);
+
+            let gas_mark = farm.gas_mark();
+            let promise = ext_fungible_token::ft_transfer_call(
+                target_farm.into(),
+                amount.into(),
+                None,
+                format!("{}:{}", STAKE_MSG, account_id),
+                &farm.reward_token_account_id,
+                1,
+                gas,
+            )
+            .then(ext_self::on_claim_and_stake(
+                account_id.clone(),
+                amount.into(),
+                op_id.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ));
+            farm.record_gas(GasPhase::Promises, gas_mark);
+            promise
+        });
+        self.flush_gas_profile("claim_and_stake_into");
+        promise.map_or(PromiseOrValue::Value(U128(0)), PromiseOrValue::Promise)
+    }
+
+    /// Accrues and pays out the rewards of `account_id` to that account. Anyone can call it;
+    /// a caller other than the account itself earns `harvest_bounty_bps` of the harvest. A
+    /// harvest that has to wait is queued whole, earning no bounty.
+    pub fn harvest_for(&mut self, account_id: ValidAccountId) -> PromiseOrValue<U128> {
+        let account_id: AccountId = account_id.into();
+        let caller_id = env::predecessor_account_id();
+        self.assert_not_frozen(&account_id);
+        self.assert_not_contract(&account_id);
+        self.apply_due_changes();
+        let (account_id_hash, mut account) = self.get_mut_account(&account_id);
+        let amount = account.reward_balance;
+        if amount == 0 {
+            fail(ErrorCode::NothingToHarvest, json!({}));
+        }
+        self.assert_min_claim(amount);
+        let bounty = if caller_id == account_id || self.must_queue_claim(amount) {
+            0
+        } else {
+            amount * u128::from(self.harvest_bounty_bps) / BPS_DENOM
+        };
+        account.reward_balance = 0;
+        account.reward_claimed += amount - bounty;
+        self.save_account(&account_id_hash, &account);
+        self.notify_position_hook(
+            &account_id,
+            &account,
+            PositionEvent::RewardClaimed,
+            amount - bounty,
+        );
+
+        // the bounty is only paid with the harvest, so both are covered or both queued
+        let promise = self.pay_or_queue_reward(&account_id, amount - bounty, |farm| {
+            let promise = farm.internal_send_reward(&account_id, amount - bounty);
+            if bounty == 0 {
+                return promise;
+            }
+            farm.start_reward_payout(bounty);
+            let gas_mark = farm.gas_mark();
+            let promise = promise.and(
+                ext_fungible_token::ft_transfer(
+                    caller_id.clone(),
+                    bounty.into(),
+                    None,
+                    &farm.reward_token_account_id,
+                    1,
+                    farm.token_gas(&farm.reward_token_account_id).ft_transfer.0,
+                )
+                .then(ext_self::on_bounty_transfer(
+                    bounty.into(),
+                    &env::current_account_id(),
+                    NO_DEPOSIT,
+                    GAS_FOR_RESOLVE_TRANSFER,
+                )),
+            );
+            farm.record_gas(GasPhase::Promises, gas_mark);
+            promise
+        });
+        log!("@{} harvested {} for @{}, bounty {}", caller_id, amount, account_id, bounty);
+        self.flush_gas_profile("harvest_for");
+        promise.map_or(PromiseOrValue::Value(U128(0)), PromiseOrValue::Promise)
+    }
+
+    /// Dry run of `claim_all` for `account_id`. The farm doesn't know whether the account
+    /// is registered on the reward token, so a claim reported ready can still bounce; its
+    /// rewards are then restored.
+    pub fn can_claim(&self, account_id: ValidAccountId) -> ClaimReadiness {
+        let mut blockers = Vec::new();
+        let claimable = match self.get_account(account_id.clone()) {
+            Some(account) => account.reward_balance.0,
+            None => {
+                blockers.push(ErrorCode::AccountNotRegistered);
+                0
+            }
+        };
+        if self.is_frozen(account_id.as_ref()) {
+            blockers.push(ErrorCode::AccountFrozen);
+        }
+        if claimable == 0 && blockers.is_empty() {
+            blockers.push(ErrorCode::NothingToClaim);
+        } else if claimable > 0 && claimable < self.min_claim {
+            blockers.push(ErrorCode::BelowMinClaim);
+        }
+        if self.must_queue_claim(claimable) {
+            blockers.push(ErrorCode::InsufficientReserve);
+        }
+        ClaimReadiness {
+            claimable: claimable.into(),
+            min_claim: self.min_claim.into(),
+            ready: blockers.is_empty(),
+            blockers,
+        }
+    }
+
+    /// Sets the smallest reward balance that can be claimed, so dust isn't paid out one
+    /// transfer at a time. Unstaking still pays out any balance.
+    pub fn set_min_claim(&mut self, min_claim: U128) {
+        self.assert_role(Role::Operator);
+        self.min_claim = min_claim.into();
+        emit_event("set_min_claim", json!({ "min_claim": min_claim }));
+    }
+
+    /// Sets the harvest bounty at `effective_at`, right away if `None`.
+    pub fn set_harvest_bounty(&mut self, bounty_bps: u32, effective_at: Option<U64>) {
+        self.assert_owner();
+        assert!(bounty_bps <= MAX_HARVEST_BOUNTY_BPS, "Harvest bounty is too high");
+        let effective_at =
+            self.schedule_change(effective_at, ParameterChange::HarvestBounty(bounty_bps));
+        emit_event(
+            "set_harvest_bounty",
+            json!({ "bounty_bps": bounty_bps, "effective_at": U64(effective_at) }),
+        );
+    }
+
+    /// Resolves a reward transfer, returning the amount actually paid out.
+    #[private]
+    pub fn on_reward_transfer(&mut self, account_id: AccountId, amount: U128, op_id: U64) -> U128 {
+        self.resolve_reward_payout();
+        if is_promise_success() {
+            let token_id = self.reward_token_account_id.clone();
+            self.record_claim(&account_id, amount.0, token_id, None, Some(op_id.0));
+            self.resolve_operation(op_id.0, true);
+            self.check_payout_balance();
+            return amount;
+        }
+        self.restore_reward(&account_id, amount.0);
+        self.resolve_operation(op_id.0, false);
+        U128(0)
+    }
+
+    /// Resolves `claim_and_stake_into`, restoring whatever the target farm didn't take.
+    #[private]
+    pub fn on_claim_and_stake(&mut self, account_id: AccountId, amount: U128, op_id: U64) -> U128 {
+        self.resolve_reward_payout();
+        let used = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).map_or(0, |used| used.0)
+            }
+            _ => 0,
+        };
+        let used = used.min(amount.0);
+        if used < amount.0 {
+            self.restore_reward(&account_id, amount.0 - used);
+        }
+        let token_id = self.reward_token_account_id.clone();
+        self.record_claim(&account_id, used, token_id, None, Some(op_id.0));
+        self.resolve_operation(op_id.0, used > 0);
+        used.into()
+    }
+
+    /// Returns a harvest bounty that failed to transfer to the reserve.
+    #[private]
+    pub fn on_bounty_transfer(&mut self, amount: U128) {
+        self.resolve_reward_payout();
+        if !is_promise_success() {
+            self.ledger.post(LedgerAccount::Claimed, LedgerAccount::Reserve, amount.0);
+            log!("Harvest bounty of {} failed, returned to the reserve", amount.0);
+        }
+    }
+}
+
+impl Farm {
+    /// Credits back a payout that didn't reach `account_id`.
+    fn restore_reward(&mut self, account_id: &AccountId, amount: Balance) {
+        self.ledger.post(LedgerAccount::Claimed, LedgerAccount::Reserve, amount);
+        let (account_id_hash, account) = self.get_internal_account(account_id);
+        match account {
+            Some(mut account) => {
+                account.reward_balance += amount;
+                account.reward_claimed -= amount;
+                self.save_account(&account_id_hash, &account);
+                log!("Reward transfer of {} to @{} failed, balance restored", amount, account_id);
+            }
+            None => log!(
+                "Reward transfer of {} to unregistered @{} failed, returned to the reserve",
+                amount,
+                account_id
+            ),
+        }
+    }
+
+    /// Fails unless `amount` reaches the minimum claim; the rewards keep accruing.
+    pub(crate) fn assert_min_claim(&self, amount: Balance) {
+        if amount < self.min_claim {
+            fail(
+                ErrorCode::BelowMinClaim,
+                json!({ "claimable": U128(amount), "min_claim": U128(self.min_claim) }),
+            );
+        }
+    }
+
+    /// Pays out all of the rewards of `account_id`, or queues the claim if the reserve is
+    /// short.
+    pub(crate) fn internal_claim(&mut self, account_id: &AccountId) -> PromiseOrValue<U128> {
+        self.assert_not_frozen(account_id);
+        self.assert_not_contract(account_id);
+        let (account_id_hash, mut account) = self.get_mut_account(account_id);
+        let amount = account.reward_balance;
+        if amount == 0 {
+            fail(ErrorCode::NothingToClaim, json!({}));
+        }
+        self.assert_min_claim(amount);
+        self.refresh_partner_balance_if_stale(account_id, &account);
+        account.reward_balance = 0;
+        account.reward_claimed += amount;
+        self.save_account(&account_id_hash, &account);
+        self.notify_position_hook(account_id, &account, PositionEvent::RewardClaimed, amount);
+        let promise = self.pay_or_queue_reward(account_id, amount, |farm| {
+            farm.assert_prepaid_gas(
+                farm.token_gas(&farm.reward_token_account_id).ft_transfer.0
+                    + GAS_FOR_RESOLVE_TRANSFER
+                    + farm.payout_check_gas(),
+            );
+            farm.internal_send_reward(account_id, amount)
+        });
+        promise.map_or(PromiseOrValue::Value(U128(0)), PromiseOrValue::Promise)
+    }
+
+    /// Takes `amount` out of the reserve and transfers it to `account_id`, to the owning
+    /// contract for a position or to the claim receiver the account prefers.
+    pub(crate) fn internal_send_reward(
+        &mut self,
+        account_id: &AccountId,
+        amount: Balance,
+    ) -> Promise {
+        self.assert_reserve_covers(amount);
+        self.start_reward_payout(amount);
+        let op_id = self.start_operation(
+            OperationKind::Claim,
+            account_id,
+            amount,
+            OperationStatus::Pending,
+        );
+        self.check_runway();
+        self.record_apr(false);
+        let gas_mark = self.gas_mark();
+        let promise = ext_fungible_token::ft_transfer(
+            self.reward_receiver(account_id),
+            amount.into(),
+            None,
+            &self.reward_token_account_id,
+            1,
+            self.token_gas(&self.reward_token_account_id).ft_transfer.0,
+        )
+        .then(ext_self::on_reward_transfer(
+            account_id.clone(),
+            amount.into(),
+            op_id.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER + self.payout_check_gas(),
+        ));
+        self.record_gas(GasPhase::Promises, gas_mark);
+        promise
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::{testing_env, MockedBlockchain, PromiseResult};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, new_farm, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_claim_all() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let pending = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        assert!(pending > 0);
+        contract.claim_all();
+
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, 0);
+        assert_eq!(account.reward_claimed.0, pending);
+        assert_eq!(contract.reward_reserve(), 10u128.pow(30) - pending);
+    }
+
+    #[test]
+    fn test_can_claim() {
+        let contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let readiness = contract.can_claim(alice().try_into().unwrap());
+        assert!(readiness.ready && readiness.claimable.0 > 0);
+        let readiness = contract.can_claim(bob().try_into().unwrap());
+        assert_eq!(readiness.blockers, vec![ErrorCode::AccountNotRegistered]);
+    }
+
+    #[test]
+    fn test_min_claim() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        let pending = contract.can_claim(alice().try_into().unwrap()).claimable.0;
+        contract.set_min_claim(U128(pending + 1));
+        let readiness = contract.can_claim(alice().try_into().unwrap());
+        assert_eq!(readiness.blockers, vec![ErrorCode::BelowMinClaim]);
+        assert_eq!(readiness.min_claim.0, pending + 1);
+
+        contract.set_min_claim(U128(pending));
+        assert!(contract.can_claim(alice().try_into().unwrap()).ready);
+    }
+
+    #[test]
+    fn test_failed_reward_transfer_is_restored() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.claim_all();
+        let claimed = contract.get_account(alice().try_into().unwrap()).unwrap().reward_claimed;
+
+        testing_env!(
+            get_context(alice(), env::storage_usage(), contract.cliff_time + 1),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Failed]
+        );
+        assert_eq!(contract.on_reward_transfer(alice(), claimed, U64(2)).0, 0);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance, claimed);
+        assert_eq!(account.reward_claimed.0, 0);
+        assert_eq!(contract.reward_reserve(), 10u128.pow(30));
+    }
+
+    #[test]
+    fn test_harvest_for_pays_bounty() {
+        let mut contract = staked_farm();
+        contract.set_harvest_bounty(MAX_HARVEST_BOUNTY_BPS, None);
+        let mut context = get_context(bob(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context);
+        let pending = contract.get_account(alice().try_into().unwrap()).unwrap().reward_balance.0;
+        contract.harvest_for(alice().try_into().unwrap());
+
+        let bounty = pending / 100;
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, 0);
+        assert_eq!(account.reward_claimed.0, pending - bounty);
+        assert_eq!(contract.reward_reserve(), 10u128.pow(30) - pending);
+    }
+
+    #[test]
+    fn test_claim_and_stake_restores_unused() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context.clone());
+        contract.claim_and_stake_into(bob().try_into().unwrap());
+        let claimed = contract.get_account(alice().try_into().unwrap()).unwrap().reward_claimed.0;
+
+        let used = b"\"100\"".to_vec();
+        testing_env!(
+            context,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(used)]
+        );
+        assert_eq!(contract.on_claim_and_stake(alice(), U128(claimed), U64(2)).0, 100);
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, claimed - 100);
+        assert_eq!(contract.reward_reserve(), 10u128.pow(30) - 100);
+    }
+
+    #[test]
+    fn test_claim_beyond_reserve_is_queued() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+        // pays for the queue entry
+        contract.storage_deposit(None, None);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        contract.claim_all();
+        assert_eq!(contract.get_claim_queue_length().0, 1);
+    }
+}
@@ -0,0 +1,140 @@
+//! Admin roles below the owner. Treasurers manage the reward money: emission, funding
+//! bounds, strategies, closed reward periods and bonuses. Operators manage the farm's
+//! running: gas settings, metadata, sponsorship and alerts. Guardians freeze accounts, see
+//! `guardian`. The owner holds every role and alone grants and revokes them, so a treasury
+//! multisig can manage rewards without being able to change fees. Each admin method lists
+//! the role it requires in `get_admin_interface`.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::ValidAccountId;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen};
+
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq, Debug,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum Role {
+    Owner,
+    Treasurer,
+    Operator,
+    Guardian,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn grant_role(&mut self, account_id: ValidAccountId, role: Role) {
+        self.assert_owner();
+        match role {
+            Role::Owner => env::panic(b"The owner role can't be granted"),
+            Role::Guardian => {
+                self.guardians.insert(account_id.as_ref());
+            }
+            Role::Treasurer | Role::Operator => {
+                let mut roles = self.role_grants.get(account_id.as_ref()).unwrap_or_default();
+                if !roles.contains(&role) {
+                    roles.push(role);
+                    self.role_grants.insert(account_id.as_ref(), &roles);
+                }
+            }
+        }
+        emit_event("grant_role", json!({ "account_id": account_id, "role": role }));
+    }
+
+    pub fn revoke_role(&mut self, account_id: ValidAccountId, role: Role) {
+        self.assert_owner();
+        match role {
+            Role::Owner => env::panic(b"The owner role can't be revoked"),
+            Role::Guardian => self.internal_remove_guardian(account_id.as_ref()),
+            Role::Treasurer | Role::Operator => {
+                let mut roles = self.role_grants.get(account_id.as_ref()).unwrap_or_default();
+                assert!(roles.contains(&role), "The account doesn't have the role");
+                roles.retain(|granted| *granted != role);
+                if roles.is_empty() {
+                    self.role_grants.remove(account_id.as_ref());
+                } else {
+                    self.role_grants.insert(account_id.as_ref(), &roles);
+                }
+            }
+        }
+        emit_event("revoke_role", json!({ "account_id": account_id, "role": role }));
+    }
+
+    pub fn get_roles(&self, account_id: ValidAccountId) -> Vec<Role> {
+        let account_id: AccountId = account_id.into();
+        let mut roles = vec![];
+        if account_id == self.owner_id {
+            roles.push(Role::Owner);
+        }
+        roles.extend(self.role_grants.get(&account_id).unwrap_or_default());
+        if self.guardians.contains(&account_id) {
+            roles.push(Role::Guardian);
+        }
+        roles
+    }
+}
+
+impl Farm {
+    /// Lets through the owner and accounts granted `role`.
+    pub(crate) fn assert_role(&self, role: Role) {
+        let predecessor_id = env::predecessor_account_id();
+        let allowed = predecessor_id == self.owner_id
+            || match role {
+                Role::Owner => false,
+                Role::Guardian => self.guardians.contains(&predecessor_id),
+                Role::Treasurer | Role::Operator => {
+                    self.role_grants.get(&predecessor_id).unwrap_or_default().contains(&role)
+                }
+            };
+        if !allowed {
+            env::panic(
+                format!("Only the owner or an account with role {:?} can call this method", role)
+                    .as_bytes(),
+            );
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::decimals::FundingBounds;
+    use crate::tests::{alice, bob, get_context, new_farm};
+    use std::convert::TryInto;
+
+    fn treasurer_farm() -> Farm {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.grant_role(bob().try_into().unwrap(), Role::Treasurer);
+        contract
+    }
+
+    #[test]
+    fn test_treasurer_role() {
+        let mut contract = treasurer_farm();
+        assert_eq!(contract.get_roles(bob().try_into().unwrap()), vec![Role::Treasurer]);
+        assert_eq!(contract.get_roles(alice().try_into().unwrap()), vec![Role::Owner]);
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        let bounds = FundingBounds { min_tokens: U128(1), max_tokens: U128(100) };
+        contract.set_funding_bounds(Some(bounds));
+
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.revoke_role(bob().try_into().unwrap(), Role::Treasurer);
+        assert!(contract.get_roles(bob().try_into().unwrap()).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner can call this method")]
+    fn test_treasurer_cannot_change_fees() {
+        let mut contract = treasurer_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.set_registration_fee(Some(U128(1)));
+    }
+}
@@ -0,0 +1,137 @@
+//! Runway alerts. The runway is how long the reward reserve lasts at the current emission.
+//! When it drops below the owner's threshold the farm logs a `runway_low` event and, for
+//! teams without a log indexer, can also send one yoctoNEAR to an ops account. The alert
+//! fires once and re-arms when the runway is back above the threshold.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{near_bindgen, Promise};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RunwayAlert {
+    /// runway below which the alert fires, in nanoseconds.
+    pub threshold: U64,
+    /// account receiving one yoctoNEAR when the alert fires.
+    pub ops_account_id: Option<AccountId>,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the runway alert, `None` disabling it.
+    pub fn set_runway_alert(&mut self, alert: Option<RunwayAlert>) {
+        self.assert_role(Role::Treasurer);
+        if let Some(alert) = &alert {
+            if let Some(ops_account_id) = &alert.ops_account_id {
+                assert!(env::is_valid_account_id(ops_account_id.as_bytes()), "Invalid ops account");
+            }
+        }
+        emit_event("set_runway_alert", json!({ "alert": alert }));
+        self.runway_alert = alert;
+        self.runway_alert_sent = false;
+    }
+
+    pub fn get_runway_alert(&self) -> Option<RunwayAlert> {
+        self.runway_alert.clone()
+    }
+
+    /// Nanoseconds the reserve lasts at the current emission, ignoring partner boosts, or
+    /// `None` while nothing is emitted.
+    pub fn get_runway(&self) -> Option<U64> {
+        self.runway().map(U64)
+    }
+
+    /// Fires the runway alert if it is due. Stakes, fundings and reward transfers check it on
+    /// their own; this lets keepers catch the runway running down while nobody calls the farm.
+    pub fn check_runway(&mut self) {
+        let (threshold, ops_account_id) = match &self.runway_alert {
+            Some(alert) => (alert.threshold.0, alert.ops_account_id.clone()),
+            None => return,
+        };
+        let runway = match self.runway() {
+            Some(runway) => runway,
+            None => return,
+        };
+        if runway >= threshold {
+            self.runway_alert_sent = false;
+            return;
+        }
+        if self.runway_alert_sent {
+            return;
+        }
+        self.runway_alert_sent = true;
+        emit_event(
+            "runway_low",
+            json!({ "runway": U64(runway), "reward_reserve": U128(self.reward_reserve()) }),
+        );
+        if let Some(ops_account_id) = ops_account_id {
+            Promise::new(ops_account_id).transfer(1);
+        }
+    }
+}
+
+impl Farm {
+    fn runway(&self) -> Option<Timestamp> {
+        if self.decommissioned_at.is_some() {
+            return None;
+        }
+        let rate = self.reward_rate_at(self.accrual_time());
+        let staked = self.ledger.balance(LedgerAccount::Staked);
+        let emission = U256::from(staked) * U256::from(rate) * U256::from(OBS_PER_REWARD_DENOM);
+        if emission.is_zero() {
+            return None;
+        }
+        let runway =
+            U256::from(self.reward_reserve()) * U256::from(self.reward_interval) / emission;
+        Some(runway.min(U256::from(Timestamp::MAX)).as_u64())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, staked_farm};
+
+    #[test]
+    fn test_runway_alert_fires_once() {
+        let mut contract = staked_farm();
+        // leaves a reserve that runs out within a year
+        contract.ledger.post(
+            LedgerAccount::Reserve,
+            LedgerAccount::Claimed,
+            10u128.pow(30) - 10u128.pow(24),
+        );
+        let runway = contract.get_runway().unwrap().0;
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_runway_alert(Some(RunwayAlert {
+            threshold: U64(runway + 1),
+            ops_account_id: Some("ops.near".to_string()),
+        }));
+        let receipts = get_created_receipts().len();
+        contract.check_runway();
+        assert!(contract.runway_alert_sent);
+        assert_eq!(get_created_receipts().len(), receipts + 1);
+        contract.check_runway();
+        assert_eq!(get_created_receipts().len(), receipts + 1);
+
+        contract
+            .set_runway_alert(Some(RunwayAlert { threshold: U64(runway), ops_account_id: None }));
+        contract.check_runway();
+        assert!(!contract.runway_alert_sent);
+    }
+
+    #[test]
+    fn test_no_runway_without_stakes() {
+        testing_env!(get_context(alice(), 0, 0));
+        assert!(new_farm().get_runway().is_none());
+    }
+}
@@ -0,0 +1,161 @@
+//! Emission budgeting. `simulate_emissions` projects the rewards the current stake earns
+//! over the next `horizon_days`, the runway of the reserve and the APR under a hypothetical
+//! emission, without changing state, so token economics teams can weigh a change against
+//! the current TVL before proposing it. Left out, the override fields take the farm's
+//! values and the announced changes apply; an overridden rate or schedule replaces them
+//! from now on. Like `get_runway`, the projection weighs every stake as itself and ignores
+//! boosts.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::apr::YEAR_NANOS;
+use crate::config::assert_reward_rate;
+use crate::*;
+
+/// longest horizon simulated, ten years.
+pub const MAX_SIMULATION_DAYS: u32 = 3650;
+
+/// one day in nanoseconds.
+const DAY: Timestamp = 24 * 3600 * 1_000_000_000;
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EmissionOverride {
+    pub reward_rate: Option<U128>,
+    pub emission_schedule: Option<EmissionSchedule>,
+    pub reward_reserve: Option<U128>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EmissionSimulation {
+    pub horizon_end: TimestampView,
+    /// rewards the current stake earns until the end of the horizon.
+    pub rewards_owed: U128,
+    pub reward_reserve: U128,
+    /// rewards owed beyond the reserve.
+    pub shortfall: U128,
+    /// nanoseconds the reserve lasts, `None` if it lasts the whole horizon.
+    pub runway: Option<U64>,
+    /// APR now and at the end of the horizon, in basis points.
+    pub apr: U128,
+    pub final_apr: U128,
+}
+
+#[near_bindgen]
+impl Farm {
+    pub fn simulate_emissions(
+        &self,
+        config_override: Option<EmissionOverride>,
+        horizon_days: u32,
+    ) -> EmissionSimulation {
+        assert!(
+            horizon_days > 0 && horizon_days <= MAX_SIMULATION_DAYS,
+            "Horizon must be 1 to {} days",
+            MAX_SIMULATION_DAYS
+        );
+        let config_override = config_override.unwrap_or_default();
+        if let Some(reward_rate) = config_override.reward_rate {
+            assert_reward_rate(reward_rate.0);
+        }
+        if let Some(schedule) = &config_override.emission_schedule {
+            schedule.assert_valid();
+        }
+        let start = self.accrual_time();
+        let end = start + Timestamp::from(horizon_days) * DAY;
+        let overridden =
+            config_override.reward_rate.is_some() || config_override.emission_schedule.is_some();
+        let reward_rate = config_override.reward_rate.map_or(self.reward_rate, |rate| rate.0);
+        let schedule =
+            config_override.emission_schedule.unwrap_or_else(|| self.get_emission_schedule());
+        let emission_between = |from: Timestamp, to: Timestamp| {
+            if overridden {
+                schedule.emission_between(reward_rate, from, to)
+            } else {
+                self.emission_between(from, to)
+            }
+        };
+        let staked = U256::from(self.ledger.balance(LedgerAccount::Staked));
+        let owed_until = |time: Timestamp| {
+            let emission = emission_between(start, time);
+            staked * emission * U256::from(OBS_PER_REWARD_DENOM) / U256::from(self.reward_interval)
+        };
+        let apr = |time: Timestamp| self.apr_of(emission_between(time, time + YEAR_NANOS));
+
+        let reserve = config_override.reward_reserve.map_or(self.reward_reserve(), |r| r.0);
+        let owed = owed_until(end);
+        let runway = if owed <= U256::from(reserve) {
+            None
+        } else {
+            // rewards owed grow with time, so the time they pass the reserve is bisected
+            let (mut low, mut high) = (start, end);
+            while low < high {
+                let middle = low + (high - low) / 2;
+                if owed_until(middle) > U256::from(reserve) {
+                    high = middle;
+                } else {
+                    low = middle + 1;
+                }
+            }
+            Some(U64(low - start))
+        };
+        let owed = owed.min(U256::from(Balance::MAX)).as_u128();
+        EmissionSimulation {
+            horizon_end: end.into(),
+            rewards_owed: owed.into(),
+            reward_reserve: reserve.into(),
+            shortfall: owed.saturating_sub(reserve).into(),
+            runway,
+            apr: apr(start).into(),
+            final_apr: apr(end).into(),
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+
+    #[test]
+    fn test_simulate_current_emission() {
+        let mut contract = staked_farm();
+        // leaves a reserve that runs out within the month
+        contract.ledger.post(
+            LedgerAccount::Reserve,
+            LedgerAccount::Claimed,
+            10u128.pow(30) - 10u128.pow(22),
+        );
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        let simulation = contract.simulate_emissions(None, 30);
+        let expected = U256::from(1000 * 1800 * 30 * DAY as u128)
+            * U256::from(OBS_PER_REWARD_DENOM)
+            / U256::from(contract.reward_interval);
+        assert_eq!(simulation.rewards_owed.0, expected.as_u128());
+        assert_eq!(simulation.apr, contract.get_apr());
+        let runway = simulation.runway.unwrap().0;
+        assert!(runway.abs_diff(contract.get_runway().unwrap().0) <= 100, "{}", runway);
+    }
+
+    #[test]
+    fn test_simulate_override() {
+        let contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        let current = contract.simulate_emissions(None, 30);
+        let config_override = EmissionOverride {
+            reward_rate: Some(U128(3600)),
+            emission_schedule: None,
+            reward_reserve: Some(U128(current.rewards_owed.0)),
+        };
+        let simulation = contract.simulate_emissions(Some(config_override), 30);
+        assert!(simulation.rewards_owed.0.abs_diff(2 * current.rewards_owed.0) <= 1);
+        assert!(simulation.shortfall.0.abs_diff(current.rewards_owed.0) <= 1);
+        // twice the rate runs the reserve of a month down in half of it
+        let runway = simulation.runway.unwrap().0;
+        assert!(runway.abs_diff(15 * DAY) <= 100, "{}", runway);
+    }
+}
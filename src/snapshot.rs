@@ -0,0 +1,124 @@
+//! Point-in-time stake balances for governance and airdrops. `take_snapshot` records the
+//! total stake; account balances are checkpointed lazily, the first time an account's stake
+//! changes after a snapshot, so snapshots cost nothing per account until then.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+
+use crate::bots::BotScope;
+use crate::events::emit_event;
+use crate::*;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Snapshot {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub taken_at: Timestamp,
+    pub total_staked: U128,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Records the current balances as a new snapshot and returns its id. The owner and
+    /// snapshot bots can call it.
+    pub fn take_snapshot(&mut self) -> U64 {
+        self.assert_automation(BotScope::Snapshot);
+        let snapshot = Snapshot {
+            taken_at: self.now(),
+            total_staked: self.ledger.balance(LedgerAccount::Staked).into(),
+        };
+        let snapshot_id = self.snapshots.len();
+        emit_event(
+            "take_snapshot",
+            json!({ "snapshot_id": U64(snapshot_id), "snapshot": snapshot }),
+        );
+        self.snapshots.push(&snapshot);
+        snapshot_id.into()
+    }
+
+    pub fn get_snapshot(&self, snapshot_id: U64) -> Option<Snapshot> {
+        self.snapshots.get(snapshot_id.0)
+    }
+
+    pub fn get_snapshot_count(&self) -> U64 {
+        self.snapshots.len().into()
+    }
+
+    /// Stake of `account_id` when the snapshot was taken, `None` for unknown snapshots.
+    pub fn balance_at_snapshot(
+        &self,
+        account_id: ValidAccountId,
+        snapshot_id: U64,
+    ) -> Option<U128> {
+        if snapshot_id.0 >= self.snapshots.len() {
+            return None;
+        }
+        let checkpoint =
+            self.balance_checkpoints.get(account_id.as_ref()).and_then(|checkpoints| {
+                checkpoints.into_iter().find(|(snapshots, _)| *snapshots > snapshot_id.0)
+            });
+        let balance = match checkpoint {
+            Some((_, balance)) => balance,
+            // unchanged since the snapshot
+            None => self
+                .get_internal_account(account_id.as_ref())
+                .1
+                .map_or(0, |account| account.obs_balance),
+        };
+        Some(balance.into())
+    }
+}
+
+impl Farm {
+    /// Keeps the stake of `account` for the snapshots taken since its last change. Called
+    /// before every change of its stake.
+    pub(crate) fn checkpoint_balance(&mut self, account_id: &AccountId, account: &Account) {
+        let snapshots = self.snapshots.len();
+        if snapshots == 0 {
+            return;
+        }
+        let mut checkpoints = self.balance_checkpoints.get(account_id).unwrap_or_default();
+        // the snapshots that saw the current stake are already covered
+        if matches!(checkpoints.last(), Some((covered, _)) if *covered == snapshots) {
+            return;
+        }
+        checkpoints.push((snapshots, account.obs_balance));
+        self.balance_checkpoints.insert(account_id, &checkpoints);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_balance_at_snapshot() {
+        let mut contract = staked_farm();
+        let mut context = get_context(alice(), env::storage_usage(), contract.cliff_time + 1);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context.clone());
+        let first = contract.take_snapshot();
+        let second = contract.take_snapshot();
+        context.attached_deposit = 1;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let third = contract.take_snapshot();
+
+        let balance_at =
+            |snapshot_id| contract.balance_at_snapshot(alice().try_into().unwrap(), snapshot_id);
+        assert_eq!(balance_at(first), Some(U128(1000)));
+        assert_eq!(balance_at(second), Some(U128(1000)));
+        assert_eq!(balance_at(third), Some(U128(0)));
+        assert_eq!(balance_at(U64(3)), None);
+        assert_eq!(contract.get_snapshot(first).unwrap().total_staked.0, 1000);
+        assert_eq!(contract.get_snapshot(third).unwrap().total_staked.0, 0);
+    }
+}
@@ -0,0 +1,280 @@
+//! NEP-145 storage management. Every account pays for the bytes its data takes, and
+//! writes that would go beyond its storage deposit are refused.
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::{assert_one_yocto, env, log, near_bindgen, Promise, StorageUsage};
+
+use crate::*;
+
+/// bytes the runtime charges for every record on top of its key and value.
+const STORAGE_RECORD_OVERHEAD: StorageUsage = 40;
+
+/// length of the longest account id that can register with `storage_deposit`.
+const MAX_ACCOUNT_ID_LEN: usize = 64;
+
+/// length of an `accounts` key: the one byte `StorageKey` prefix and the account hash.
+const ACCOUNT_KEY_LEN: StorageUsage = 1 + 20;
+
+impl Account {
+    pub(crate) fn new(last_obs_per_reward_rate: Balance, storage_deposit: Balance) -> Self {
+        Self {
+            obs_balance: 0,
+            reward_balance: 0,
+            reward_claimed: 0,
+            last_obs_per_reward_rate,
+            deposit_time: 0,
+            last_accrual_time: 0,
+            vesting_reward: 0,
+            warming_balance: 0,
+            warm_until: 0,
+            storage_deposit,
+            storage_sponsored: 0,
+            storage_usage: 0,
+            label: None,
+            total_staked: 0,
+            total_withdrawn: 0,
+            stake_count: 0,
+            first_stake_time: 0,
+            large_unstake_threshold: None,
+            large_unstake_requested_at: None,
+            partner_balance: 0,
+            partner_balance_checked_at: 0,
+            approved_lienholder: None,
+            vip_round: 0,
+            streak_started_at: 0,
+            reward_period: 0,
+            staker_fee_index: 0,
+            campaign: None,
+            multiplier_bps: BPS_DENOM as u32,
+            sponsored_claims: None,
+            preferences: Preferences::default(),
+        }
+    }
+
+    /// Storage bytes charged to the account, including its own record.
+    pub fn storage_used(&self) -> StorageUsage {
+        let record_len = self.try_to_vec().unwrap().len() as StorageUsage;
+        ACCOUNT_KEY_LEN + record_len + STORAGE_RECORD_OVERHEAD + self.storage_usage
+    }
+
+    /// NEAR of the storage deposit not locked by the account's data nor sponsored.
+    pub fn storage_available(&self) -> Balance {
+        let locked = Balance::from(self.storage_used()) * env::storage_byte_cost();
+        self.storage_deposit - locked.max(self.storage_sponsored)
+    }
+
+    fn storage_balance(&self) -> StorageBalance {
+        StorageBalance {
+            total: self.storage_deposit.into(),
+            available: self.storage_available().into(),
+        }
+    }
+}
+
+#[near_bindgen]
+impl StorageManagement for Farm {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<ValidAccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let amount = env::attached_deposit();
+        let account_id = account_id.map(|a| a.into()).unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        let (account_id_hash, account) = self.get_internal_account(&account_id);
+        let account = match account {
+            Some(mut account) => {
+                if registration_only {
+                    log!("The account is already registered, refunding the deposit");
+                    if amount > 0 {
+                        Promise::new(env::predecessor_account_id()).transfer(amount);
+                    }
+                } else {
+                    account.storage_deposit += amount;
+                    self.save_account(&account_id_hash, &account);
+                }
+                account
+            }
+            None => {
+                let min_balance = self.storage_balance_bounds().min.0;
+                assert!(
+                    amount >= min_balance,
+                    "The attached deposit is less than the minimum storage balance"
+                );
+                let deposit = if registration_only { min_balance } else { amount };
+                let mut account = Account::new(self.obs_per_reward_rate, deposit);
+                self.save_new_account(&account_id, &account_id_hash, &mut account);
+                if amount > deposit {
+                    Promise::new(env::predecessor_account_id()).transfer(amount - deposit);
+                }
+                account
+            }
+        };
+        self.flush_gas_profile("storage_deposit");
+        account.storage_balance()
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, mut account) = self.get_registered_account(&account_id);
+        let available = account.storage_available();
+        let amount = amount.map(|a| a.0).unwrap_or(available);
+        assert!(amount <= available, "The amount is greater than the available storage balance");
+        if amount > 0 {
+            account.storage_deposit -= amount;
+            self.save_account(&account_id_hash, &account);
+            Promise::new(account_id).transfer(amount);
+        }
+        account.storage_balance()
+    }
+
+    /// Staked tokens must be withdrawn first; `force` only forfeits unclaimed rewards.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let (account_id_hash, account) = self.get_internal_account(&account_id);
+        let account = match account {
+            Some(account) => account,
+            None => {
+                log!("The account {} is not registered", account_id);
+                return false;
+            }
+        };
+        self.assert_not_frozen(&account_id);
+        self.assert_not_liened(&account_id);
+        assert_eq!(account.obs_balance, 0, "Can't unregister the account with staked tokens");
+        assert!(
+            account.reward_balance == 0 || force.unwrap_or(false),
+            "Can't unregister the account with unclaimed rewards without force"
+        );
+        self.internal_unregister(&account_id, &account_id_hash, &account);
+        true
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds { min: registration_deposit(MAX_ACCOUNT_ID_LEN).into(), max: None }
+    }
+
+    fn storage_balance_of(&self, account_id: ValidAccountId) -> Option<StorageBalance> {
+        self.get_internal_account(account_id.as_ref()).1.map(|account| account.storage_balance())
+    }
+}
+
+/// bytes of the `registered_accounts` entry of an id `account_id_len` long, the id keyed
+/// to its index and stored at it. Ids count as `MAX_ACCOUNT_ID_LEN` long at least, so every
+/// account pays the minimum storage balance and only longer position ids pay more.
+fn index_entry_len(account_id_len: usize) -> StorageUsage {
+    let account_id_len = account_id_len.max(MAX_ACCOUNT_ID_LEN) as StorageUsage;
+    2 * (STORAGE_RECORD_OVERHEAD + 2 + 4 + account_id_len + 8)
+}
+
+/// storage deposit registering an account with an id `account_id_len` long takes.
+pub(crate) fn registration_deposit(account_id_len: usize) -> Balance {
+    let mut account = Account::new(0, 0);
+    account.storage_usage = index_entry_len(account_id_len);
+    Balance::from(account.storage_used()) * env::storage_byte_cost()
+}
+
+impl Farm {
+    /// Saves a newly registered account, its storage deposit paying for its entry in
+    /// `registered_accounts`.
+    pub(crate) fn save_new_account(
+        &mut self,
+        account_id: &AccountId,
+        account_id_hash: &ShortAccountHash,
+        account: &mut Account,
+    ) {
+        self.registered_accounts.insert(account_id);
+        account.storage_usage += index_entry_len(account_id.len());
+        self.save_account(account_id_hash, account);
+        self.accounts_registered += 1;
+    }
+
+    /// Removes the account and refunds its storage deposit, forfeiting unclaimed rewards.
+    pub(crate) fn internal_unregister(
+        &mut self,
+        account_id: &AccountId,
+        account_id_hash: &ShortAccountHash,
+        account: &Account,
+    ) {
+        assert!(
+            !self.has_queued_claim(account_id),
+            "Can't unregister the account with a queued claim"
+        );
+        let old = self.accounts.remove(account_id_hash);
+        self.account_log.record(account_id_hash, old.as_ref(), None);
+        self.record_unlock(old.as_ref(), None);
+        self.record_account_principal(old.as_ref(), None);
+        self.registered_accounts.remove(account_id);
+        self.auto_compounders.remove(account_id);
+        if account_id.contains('#') {
+            self.close_position(&self.payout_receiver(account_id));
+        }
+        let refund = account.storage_deposit - account.storage_sponsored;
+        if refund > 0 {
+            Promise::new(account_id.clone()).transfer(refund);
+        }
+        log!("Unregistered @{}, forfeiting {} rewards", account_id, account.reward_balance);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_storage_deposit_and_withdraw() {
+        let mut context = get_context(alice(), 0, 100);
+        context.attached_deposit = 10u128.pow(24);
+        context.account_balance = 10u128.pow(25);
+        testing_env!(context.clone());
+        let mut contract = new_farm();
+        let min = contract.storage_balance_bounds().min.0;
+        let balance = contract.storage_deposit(None, None);
+        assert_eq!(balance.total.0, 10u128.pow(24));
+        assert_eq!(balance.available.0, 10u128.pow(24) - min);
+
+        context.attached_deposit = 1;
+        context.storage_usage = env::storage_usage();
+        testing_env!(context);
+        let balance = contract.storage_withdraw(None);
+        assert_eq!(balance.total.0, min);
+        assert_eq!(balance.available.0, 0);
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(alice().try_into().unwrap()).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit is less than the minimum storage balance")]
+    fn test_storage_deposit_below_minimum() {
+        testing_env!(get_context(alice(), 0, 100));
+        let mut contract = new_farm();
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage deposit")]
+    fn test_write_beyond_deposit() {
+        let mut context = get_context(alice(), 0, 100);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+        let (account_id_hash, account) = contract.get_internal_account(&alice());
+        let mut account = account.unwrap();
+        account.storage_usage += 1;
+        contract.save_account(&account_id_hash, &account);
+    }
+}
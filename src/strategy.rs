@@ -0,0 +1,423 @@
+//! Lending idle reward reserve to external strategy contracts, so it earns yield instead
+//! of sitting still. The owner whitelists strategies, each with hard caps, and deploys reserve
+//! to them through `ft_transfer_call`; deployed tokens are tracked per strategy, apart from
+//! the local reserve, which alone pays rewards. Recalling asks a strategy to send tokens back
+//! through `ft_transfer_call` with `STRATEGY_RETURN_MSG`, so the farm books what the token
+//! contract moved rather than what the strategy claims to have sent. Whatever comes back
+//! beyond what was deployed is yield, added to the reserve as funding. Guardians can recall
+//! too, in an emergency.
+//!
+//! Keepers call `report` to reconcile what a strategy holds for the farm with what the farm
+//! deployed. Every deployment or recall makes the last report stale, and no new reserve is
+//! deployed to a strategy until a fresh report shows it holding at least what it was lent.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{env, ext_contract, near_bindgen, Gas, Promise, PromiseResult};
+
+use crate::bots::BotScope;
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// `ft_transfer_call` message deploying reserve to a strategy.
+pub const STRATEGY_DEPOSIT_MSG: &str = "deposit";
+
+/// gas of a strategy's `withdraw`, including its `ft_transfer_call` back to the farm.
+const GAS_FOR_STRATEGY_WITHDRAW: Gas = BASE_GAS + GAS_FOR_FT_TRANSFER_CALL;
+
+/// gas of a strategy's `balance_of`.
+const GAS_FOR_STRATEGY_BALANCE: Gas = BASE_GAS;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StrategyLimits {
+    /// most reward tokens deployed to the strategy at once.
+    pub max_deployed: U128,
+    /// most of the reserve, deployed included, deployed to the strategy at once, in basis
+    /// points.
+    pub max_deployed_bps: u32,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Strategy {
+    pub limits: StrategyLimits,
+    pub deployed: Balance,
+    /// yield recalled from the strategy, ever.
+    pub realized_returns: Balance,
+    /// what the strategy held for the farm at the last report.
+    pub reported_balance: Balance,
+    pub last_report_at: Option<Timestamp>,
+    /// whether `deployed` changed since the last report.
+    pub stale: bool,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StrategyView {
+    pub strategy_id: AccountId,
+    pub limits: StrategyLimits,
+    pub deployed: U128,
+    pub realized_returns: U128,
+    pub reported_balance: U128,
+    pub last_report_at: Option<TimestampView>,
+    /// whether a report is due before reserve can be deployed again.
+    pub stale: bool,
+}
+
+#[ext_contract(ext_strategy)]
+pub trait RewardStrategy {
+    /// Sends up to `amount` reward tokens back to the farm with `ft_transfer_call` and
+    /// `STRATEGY_RETURN_MSG`.
+    fn withdraw(&mut self, amount: U128);
+    /// Reward tokens held for `account_id`, yield included.
+    fn balance_of(&self, account_id: AccountId) -> U128;
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Whitelists `strategy_id` with `limits`, or removes it. A strategy is only removed
+    /// once nothing is deployed to it.
+    pub fn set_strategy(&mut self, strategy_id: ValidAccountId, limits: Option<StrategyLimits>) {
+        self.assert_owner();
+        match &limits {
+            Some(limits) => {
+                assert!(
+                    limits.max_deployed_bps as u128 <= BPS_DENOM,
+                    "Deployment cap out of range"
+                );
+                let strategy = match self.strategies.get(strategy_id.as_ref()) {
+                    Some(strategy) => Strategy { limits: limits.clone(), ..strategy },
+                    None => Strategy {
+                        limits: limits.clone(),
+                        deployed: 0,
+                        realized_returns: 0,
+                        reported_balance: 0,
+                        last_report_at: None,
+                        stale: true,
+                    },
+                };
+                self.strategies.insert(strategy_id.as_ref(), &strategy);
+            }
+            None => {
+                let strategy = self.strategy(strategy_id.as_ref());
+                assert_eq!(strategy.deployed, 0, "Recall the deployed reserve first");
+                self.strategies.remove(strategy_id.as_ref());
+            }
+        }
+        emit_event("set_strategy", json!({ "strategy_id": strategy_id, "limits": limits }));
+    }
+
+    pub fn get_strategies(&self) -> Vec<StrategyView> {
+        self.strategies
+            .iter()
+            .map(|(strategy_id, strategy)| StrategyView {
+                strategy_id,
+                limits: strategy.limits,
+                deployed: strategy.deployed.into(),
+                realized_returns: strategy.realized_returns.into(),
+                reported_balance: strategy.reported_balance.into(),
+                last_report_at: strategy.last_report_at.map(TimestampView::from),
+                stale: strategy.stale,
+            })
+            .collect()
+    }
+
+    /// Asks `strategy_id` what it holds for the farm. The owner and reconcile bots can call
+    /// it.
+    pub fn report(&mut self, strategy_id: ValidAccountId) -> Promise {
+        self.assert_automation(BotScope::Reconcile);
+        self.strategy(strategy_id.as_ref());
+        self.assert_prepaid_gas(self.report_gas() - BASE_GAS);
+        ext_strategy::balance_of(
+            env::current_account_id(),
+            strategy_id.as_ref(),
+            NO_DEPOSIT,
+            GAS_FOR_STRATEGY_BALANCE,
+        )
+        .then(ext_self::on_strategy_report(
+            strategy_id.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Lends `amount` of the reserve to `strategy_id`, within its caps.
+    pub fn deploy_to_strategy(&mut self, strategy_id: ValidAccountId, amount: U128) -> Promise {
+        self.assert_role(Role::Treasurer);
+        self.assert_not_decommissioned();
+        let mut strategy = self.strategy(strategy_id.as_ref());
+        assert!(!strategy.stale, "Strategy must report before new deployments");
+        assert!(
+            strategy.reported_balance >= strategy.deployed,
+            "Strategy reported less than it was lent"
+        );
+        let deployed = strategy.deployed + amount.0;
+        assert!(deployed <= strategy.limits.max_deployed.0, "Deployment is above the strategy cap");
+        let total = self.reward_reserve() + self.deployed_reserve();
+        assert!(
+            deployed * BPS_DENOM <= total * strategy.limits.max_deployed_bps as u128,
+            "Deployment is above the share of the reserve allowed"
+        );
+        // queued claims are owed already
+        assert!(
+            amount.0 + self.claim_queue.outstanding() <= self.reward_reserve(),
+            "Not enough idle reserve"
+        );
+        self.assert_prepaid_gas(self.deploy_gas() - BASE_GAS);
+        self.ledger.post(LedgerAccount::Reserve, LedgerAccount::Deployed, amount.0);
+        strategy.deployed = deployed;
+        strategy.stale = true;
+        self.strategies.insert(strategy_id.as_ref(), &strategy);
+        emit_event("deploy_to_strategy", json!({ "strategy_id": strategy_id, "amount": amount }));
+        ext_fungible_token::ft_transfer_call(
+            strategy_id.clone().into(),
+            amount,
+            None,
+            STRATEGY_DEPOSIT_MSG.to_string(),
+            &self.reward_token_account_id,
+            1,
+            self.token_gas(&self.reward_token_account_id).ft_transfer_call.0,
+        )
+        .then(ext_self::on_strategy_deposit(
+            strategy_id.into(),
+            amount,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Asks `strategy_id` for `amount` back, everything deployed to it if `None`. The owner
+    /// and guardians can call it. Nothing is booked until the tokens arrive.
+    pub fn recall_from_strategy(
+        &mut self,
+        strategy_id: ValidAccountId,
+        amount: Option<U128>,
+    ) -> Promise {
+        self.assert_guardian();
+        let strategy = self.strategy(strategy_id.as_ref());
+        let amount = amount.map_or(strategy.deployed, |amount| amount.0);
+        assert!(amount > 0, "Nothing to recall");
+        self.assert_prepaid_gas(self.recall_gas() - BASE_GAS);
+        emit_event(
+            "recall_from_strategy",
+            json!({ "strategy_id": strategy_id, "amount": U128(amount) }),
+        );
+        ext_strategy::withdraw(
+            amount.into(),
+            strategy_id.as_ref(),
+            NO_DEPOSIT,
+            GAS_FOR_STRATEGY_WITHDRAW,
+        )
+    }
+
+    /// Returns to the reserve whatever the strategy didn't take.
+    #[private]
+    pub fn on_strategy_deposit(&mut self, strategy_id: AccountId, amount: U128) -> U128 {
+        let used = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).map_or(0, |used| used.0)
+            }
+            _ => 0,
+        };
+        let used = used.min(amount.0);
+        self.ledger.post(LedgerAccount::Deployed, LedgerAccount::Reserve, amount.0 - used);
+        // a strategy removed in between had nothing deployed, so there is nothing to return
+        if let Some(mut strategy) = self.strategies.get(&strategy_id) {
+            strategy.deployed -= (amount.0 - used).min(strategy.deployed);
+            self.strategies.insert(&strategy_id, &strategy);
+        }
+        used.into()
+    }
+
+    /// Records what the strategy holds for the farm. A failed report leaves it stale.
+    #[private]
+    pub fn on_strategy_report(&mut self, strategy_id: AccountId) -> U128 {
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<U128>(&value).ok().map(|balance| balance.0)
+            }
+            _ => None,
+        };
+        let mut strategy = self.strategy(&strategy_id);
+        if let Some(balance) = balance {
+            strategy.reported_balance = balance;
+            strategy.last_report_at = Some(self.now());
+            strategy.stale = false;
+            self.strategies.insert(&strategy_id, &strategy);
+            emit_event(
+                "strategy_report",
+                json!({
+                    "strategy_id": strategy_id,
+                    "balance": U128(balance),
+                    "deployed": U128(strategy.deployed),
+                }),
+            );
+        }
+        strategy.reported_balance.into()
+    }
+}
+
+impl Farm {
+    pub(crate) fn strategy(&self, strategy_id: &AccountId) -> Strategy {
+        self.strategies.get(strategy_id).expect("Unknown strategy")
+    }
+
+    /// reward tokens lent to strategies.
+    pub(crate) fn deployed_reserve(&self) -> Balance {
+        self.ledger.balance(LedgerAccount::Deployed)
+    }
+
+    /// Books `amount` reward tokens `strategy_id` returned with `STRATEGY_RETURN_MSG`,
+    /// beyond the deployed amount as yield.
+    pub fn book_strategy_return(&mut self, strategy_id: &AccountId, amount: Balance) {
+        let mut strategy = self
+            .strategies
+            .get(strategy_id)
+            .unwrap_or_else(|| env::panic(b"Only whitelisted strategies can return reserve"));
+        let principal = amount.min(strategy.deployed);
+        let returns = amount - principal;
+        self.ledger.post(LedgerAccount::Deployed, LedgerAccount::Reserve, principal);
+        self.ledger.post(LedgerAccount::Funding, LedgerAccount::Reserve, returns);
+        strategy.deployed -= principal;
+        strategy.realized_returns += returns;
+        strategy.stale = true;
+        self.strategies.insert(strategy_id, &strategy);
+        emit_event(
+            "strategy_withdrawn",
+            json!({
+                "strategy_id": strategy_id,
+                "principal": U128(principal),
+                "yield": U128(returns),
+            }),
+        );
+        if amount > 0 {
+            self.pay_queued_claims();
+        }
+    }
+
+    pub(crate) fn deploy_gas(&self) -> Gas {
+        BASE_GAS
+            + self.token_gas(&self.reward_token_account_id).ft_transfer_call.0
+            + GAS_FOR_RESOLVE_TRANSFER
+    }
+
+    pub(crate) fn report_gas(&self) -> Gas {
+        BASE_GAS + GAS_FOR_STRATEGY_BALANCE + GAS_FOR_RESOLVE_TRANSFER
+    }
+
+    pub(crate) fn recall_gas(&self) -> Gas {
+        BASE_GAS + GAS_FOR_STRATEGY_WITHDRAW
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::convert::TryInto;
+
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, reward, staked_farm};
+
+    fn strategy_id() -> ValidAccountId {
+        "strategy.near".try_into().unwrap()
+    }
+
+    fn strategy_farm() -> Farm {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_strategy(
+            strategy_id(),
+            Some(StrategyLimits { max_deployed: U128(10u128.pow(30)), max_deployed_bps: 5_000 }),
+        );
+        contract
+    }
+
+    fn resolve(result: U128) {
+        testing_env!(
+            get_context(env::current_account_id(), env::storage_usage(), 0),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![PromiseResult::Successful(near_sdk::serde_json::to_vec(&result).unwrap())]
+        );
+    }
+
+    #[test]
+    fn test_deploy_and_recall_with_yield() {
+        let mut contract = strategy_farm();
+        let reserve = contract.reward_reserve();
+        resolve(U128(0));
+        contract.on_strategy_report(strategy_id().into());
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.deploy_to_strategy(strategy_id(), U128(reserve / 4));
+        assert_eq!(contract.deployed_reserve(), reserve / 4);
+        assert_eq!(contract.reward_reserve(), reserve - reserve / 4);
+
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(
+            strategy_id().into(),
+            U128(reserve / 4 + 10),
+            STRATEGY_RETURN_MSG.to_string(),
+        );
+        assert_eq!(contract.deployed_reserve(), 0);
+        assert_eq!(contract.reward_reserve(), reserve + 10);
+        let strategies = contract.get_strategies();
+        assert_eq!(strategies[0].deployed.0, 0);
+        assert_eq!(strategies[0].realized_returns.0, 10);
+        assert!(strategies[0].stale);
+    }
+
+    #[test]
+    #[should_panic(expected = "Only whitelisted strategies can return reserve")]
+    fn test_return_from_stranger() {
+        let mut contract = strategy_farm();
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(bob(), U128(10), STRATEGY_RETURN_MSG.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Strategy must report before new deployments")]
+    fn test_deploy_without_report() {
+        let mut contract = strategy_farm();
+        contract.deploy_to_strategy(strategy_id(), U128(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Strategy reported less than it was lent")]
+    fn test_deploy_after_reported_loss() {
+        let mut contract = strategy_farm();
+        resolve(U128(0));
+        contract.on_strategy_report(strategy_id().into());
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.deploy_to_strategy(strategy_id(), U128(100));
+        resolve(U128(90));
+        contract.on_strategy_report(strategy_id().into());
+        assert_eq!(contract.get_strategies()[0].reported_balance.0, 90);
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.deploy_to_strategy(strategy_id(), U128(100));
+    }
+
+    #[test]
+    #[should_panic(expected = "Deployment is above the share of the reserve allowed")]
+    fn test_deploy_above_share() {
+        let mut contract = strategy_farm();
+        let reserve = contract.reward_reserve();
+        resolve(U128(0));
+        contract.on_strategy_report(strategy_id().into());
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.deploy_to_strategy(strategy_id(), U128(reserve / 2 + 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "Only a guardian can call this method")]
+    fn test_recall_by_stranger() {
+        let mut contract = strategy_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), 0));
+        contract.recall_from_strategy(strategy_id(), None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Streak bonus for accounts that keep a stake over consecutive epochs. An account's streak
+//! starts when it stakes from nothing and counts the epochs begun since; a full exit ends
+//! it, so staking again starts over. Each epoch of the streak adds to the reward multiplier,
+//! up to a cap. Rewards are multiplied by the streak as of their last accrual, so the bonus
+//! grows as the account interacts.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U64;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::*;
+
+/// highest multiplier a streak can add, in basis points.
+pub const MAX_STREAK_BONUS_BPS: u32 = 10_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StreakBonus {
+    /// in nanoseconds.
+    pub epoch_length: U64,
+    /// multiplier added per epoch of the streak, in basis points.
+    pub bonus_bps_per_epoch: u32,
+    /// cap of the added multiplier, in basis points.
+    pub max_bonus_bps: u32,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets or removes the streak bonus, counting epochs from the current time.
+    pub fn set_streak_bonus(&mut self, bonus: Option<StreakBonus>) {
+        self.assert_owner();
+        if let Some(bonus) = &bonus {
+            assert!(bonus.epoch_length.0 > 0, "Epoch length must be positive");
+            assert!(bonus.max_bonus_bps <= MAX_STREAK_BONUS_BPS, "Streak bonus out of range");
+        }
+        emit_event("set_streak_bonus", json!({ "bonus": bonus }));
+        self.streak_bonus = bonus;
+        self.streak_epochs_start = self.now();
+    }
+
+    pub fn get_streak_bonus(&self) -> Option<StreakBonus> {
+        self.streak_bonus.clone()
+    }
+}
+
+impl Farm {
+    /// Epochs the account's stake has been kept over at `time`.
+    pub(crate) fn streak_epochs(&self, account: &Account, time: Timestamp) -> u64 {
+        let bonus = match &self.streak_bonus {
+            Some(bonus) if account.obs_balance > 0 => bonus,
+            _ => return 0,
+        };
+        let epoch_at =
+            |time: Timestamp| time.saturating_sub(self.streak_epochs_start) / bonus.epoch_length.0;
+        epoch_at(time).saturating_sub(epoch_at(account.streak_started_at))
+    }
+
+    /// Multiplier the account's streak adds at `time`, in basis points.
+    pub(crate) fn streak_boost_bps(&self, account: &Account, time: Timestamp) -> u32 {
+        let bonus = match &self.streak_bonus {
+            Some(bonus) => bonus,
+            None => return 0,
+        };
+        let streak = self.streak_epochs(account, time);
+        (streak.saturating_mul(bonus.bonus_bps_per_epoch.into())).min(bonus.max_bonus_bps.into())
+            as u32
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, staked_farm};
+    use std::convert::TryInto;
+
+    const EPOCH: Timestamp = 1_000_000_000;
+
+    fn streak_farm() -> Farm {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_streak_bonus(Some(StreakBonus {
+            epoch_length: U64(EPOCH),
+            bonus_bps_per_epoch: 1_000,
+            max_bonus_bps: 2_500,
+        }));
+        contract
+    }
+
+    #[test]
+    fn test_streak_grows_to_cap() {
+        let contract = streak_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * EPOCH));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.streak_epochs.0, 2);
+        assert_eq!(account.reward_multiplier_bps, 12_000);
+
+        testing_env!(get_context(alice(), env::storage_usage(), 10 * EPOCH));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_multiplier_bps, 12_500);
+    }
+
+    #[test]
+    fn test_exit_resets_streak() {
+        let mut contract = streak_farm();
+        contract.cliff_time = EPOCH;
+        let mut context = get_context(alice(), env::storage_usage(), 2 * EPOCH);
+        context.account_balance = 10u128.pow(25);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.unstake_my_obs();
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.streak_epochs.0, 0);
+
+        let mut context = get_context(alice(), env::storage_usage(), 3 * EPOCH);
+        context.attached_deposit = 1;
+        testing_env!(context);
+        contract.stake_my_obs(U128(100));
+        testing_env!(get_context(alice(), env::storage_usage(), 4 * EPOCH));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.streak_epochs.0, 1);
+    }
+}
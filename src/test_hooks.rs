@@ -0,0 +1,52 @@
+//! Sandbox-only methods, compiled with the `test-hooks` feature. They let integration
+//! tests and QA environments drive the farm into states that can't be reached on mainnet.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+
+use crate::*;
+
+#[near_bindgen]
+impl Farm {
+    /// Shifts the clock seen by the farm by `offset` nanoseconds.
+    #[private]
+    pub fn set_block_timestamp_offset(&mut self, offset: U64) {
+        self.block_timestamp_offset = offset.into();
+        log!("Block timestamp offset set to {}", self.block_timestamp_offset);
+    }
+
+    /// Accrues rewards for `account_id` now and returns its reward balance.
+    #[private]
+    pub fn force_touch(&mut self, account_id: ValidAccountId) -> U128 {
+        let (account_id_hash, mut account) = self.get_registered_account(account_id.as_ref());
+        self.settle_reward_periods(&account_id_hash, &mut account);
+        self.touch(&mut account);
+        self.save_account(&account_id_hash, &account);
+        account.reward_balance.into()
+    }
+
+    /// Credits the reward reserve without an actual token transfer.
+    #[private]
+    pub fn inject_reward_reserve(&mut self, amount: U128) {
+        self.ledger.post(LedgerAccount::Funding, LedgerAccount::Reserve, amount.0);
+        log!("Injected {} into the reward reserve", amount.0);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_hooks() {
+        testing_env!(get_context(alice(), 0, 100));
+        let mut contract = new_farm();
+        contract.set_block_timestamp_offset(U64(1_000));
+        assert_eq!(contract.now(), 1_100);
+        contract.inject_reward_reserve(U128(500));
+        assert_eq!(contract.reward_reserve(), 500);
+    }
+}
@@ -0,0 +1,108 @@
+//! Reward tokens that take a fee on transfer. Incoming transfers are credited with the
+//! amount `ft_on_transfer` reports as received, never the amount the sender meant to send.
+//! For payouts in a token an operator marks as charging fees, the farm reads its own balance
+//! with `ft_balance_of` once the transfer resolved, and a balance below what the ledger
+//! says it holds is moved out of the reserve as `TransferFees`, so the reserve never counts
+//! tokens the fees took.
+use near_sdk::json_types::{ValidAccountId, U128};
+use near_sdk::serde_json::json;
+use near_sdk::{env, near_bindgen, Gas};
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+#[near_bindgen]
+impl Farm {
+    /// Marks `token_id` as taking fees on transfer, so payouts in it are checked.
+    pub fn set_fee_on_transfer(&mut self, token_id: ValidAccountId, enabled: bool) {
+        self.assert_role(Role::Operator);
+        if enabled {
+            self.fee_on_transfer_tokens.insert(token_id.as_ref());
+        } else {
+            self.fee_on_transfer_tokens.remove(token_id.as_ref());
+        }
+        emit_event("set_fee_on_transfer", json!({ "token_id": token_id, "enabled": enabled }));
+    }
+
+    pub fn get_fee_on_transfer_tokens(&self) -> Vec<AccountId> {
+        self.fee_on_transfer_tokens.to_vec()
+    }
+
+    /// Resolves the balance check after a payout in `token_id`, taking any shortfall out
+    /// of the reserve.
+    #[private]
+    pub fn on_payout_balance(&mut self, token_id: AccountId, #[callback] balance: U128) {
+        let expected = self
+            .get_token_balances()
+            .into_iter()
+            .find(|balances| balances.token_id == token_id)
+            .map_or(0, |balances| balances.total.0);
+        if token_id != self.reward_token_account_id || balance.0 >= expected {
+            return;
+        }
+        let shortfall = (expected - balance.0).min(self.reward_reserve());
+        self.ledger.post(LedgerAccount::Reserve, LedgerAccount::TransferFees, shortfall);
+        emit_event(
+            "transfer_fee_detected",
+            json!({ "token_id": token_id, "expected": U128(expected), "balance": balance }),
+        );
+        self.check_runway();
+    }
+}
+
+impl Farm {
+    /// gas of the balance check after a payout of the reward token, none unless it takes
+    /// fees.
+    pub(crate) fn payout_check_gas(&self) -> Gas {
+        if !self.fee_on_transfer_tokens.contains(&self.reward_token_account_id) {
+            return 0;
+        }
+        self.token_gas(&self.reward_token_account_id).ft_balance_of.0 + GAS_FOR_RESOLVE_TRANSFER
+    }
+
+    /// Schedules the balance check of a payout that went through.
+    pub(crate) fn check_payout_balance(&self) {
+        if self.payout_check_gas() == 0 {
+            return;
+        }
+        let token_id = self.reward_token_account_id.clone();
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id(),
+            &token_id,
+            NO_DEPOSIT,
+            self.token_gas(&token_id).ft_balance_of.0,
+        )
+        .then(ext_self::on_payout_balance(
+            token_id,
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::{testing_env, MockedBlockchain};
+
+    use super::*;
+    use crate::tests::{alice, get_context, reward, staked_farm};
+
+    #[test]
+    fn test_payout_shortfall_leaves_reserve() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_fee_on_transfer(reward(), true);
+        let reserve = contract.reward_reserve();
+
+        testing_env!(get_context(env::current_account_id(), env::storage_usage(), 0));
+        contract.on_payout_balance(reward().into(), U128(reserve - 7));
+        assert_eq!(contract.reward_reserve(), reserve - 7);
+        assert_eq!(contract.ledger.balance(LedgerAccount::TransferFees), 7);
+
+        // a balance at or above the ledger changes nothing
+        contract.on_payout_balance(reward().into(), U128(reserve));
+        assert_eq!(contract.reward_reserve(), reserve - 7);
+    }
+}
@@ -0,0 +1,197 @@
+//! Upcoming unlocks of an account in one view, for wallets rendering a calendar: the
+//! principal at the end of the cliff, rewards vesting under the cliff, and large unstakes
+//! once their request matured. Amounts are as of now; rewards keep vesting until the cliff.
+//!
+//! Operators get the principal unlocking across all accounts by day from
+//! `get_global_unlock_schedule`. The farm keeps the totals by day as accounts are saved, and
+//! drops a past day with each save.
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::near_bindgen;
+use near_sdk::serde::Serialize;
+use std::ops::Bound;
+
+use crate::*;
+
+/// length of a day of the global unlock calendar, in nanoseconds.
+pub const UNLOCK_DAY: Timestamp = 24 * 3600 * 1_000_000_000;
+
+/// principal unlocking across all accounts on one day.
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnlockBucket {
+    pub day_start: TimestampView,
+    pub amount: U128,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub enum UnlockKind {
+    /// the cliff ends and the principal can be unstaked.
+    Principal,
+    /// rewards earned under the cliff become claimable.
+    Rewards,
+    /// a requested large unstake can be confirmed, see `large_unstake`.
+    LargeUnstake,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UnlockEvent {
+    pub kind: UnlockKind,
+    pub unlocks_at: TimestampView,
+    pub amount: U128,
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Unlocks of `account_id` still ahead, in time order.
+    pub fn get_unlock_schedule(&self, account_id: ValidAccountId) -> Vec<UnlockEvent> {
+        let account = match self.get_internal_account(account_id.as_ref()).1 {
+            Some(account) => account,
+            None => return vec![],
+        };
+        let mut events = self.unlock_events(&account);
+        events.sort_by_key(|(unlocks_at, _, _)| *unlocks_at);
+        events
+            .into_iter()
+            .map(|(unlocks_at, kind, amount)| UnlockEvent {
+                kind,
+                unlocks_at: unlocks_at.into(),
+                amount: amount.into(),
+            })
+            .collect()
+    }
+
+    /// Principal unlocking by day between `from_ts` and `to_ts`, for at most `limit` days
+    /// with unlocks. Days already past are left out.
+    pub fn get_global_unlock_schedule(
+        &self,
+        from_ts: U64,
+        to_ts: U64,
+        limit: u64,
+    ) -> Vec<UnlockBucket> {
+        if !self.cliff_scope.principal || self.decommissioned_at.is_some() {
+            return vec![];
+        }
+        let from_day = from_ts.0.max(self.now()) / UNLOCK_DAY;
+        let to_day = to_ts.0 / UNLOCK_DAY;
+        if from_day > to_day {
+            return vec![];
+        }
+        self.unlock_calendar
+            .range((Bound::Included(from_day), Bound::Included(to_day)))
+            .take(limit as usize)
+            .map(|(day, amount)| UnlockBucket {
+                day_start: (day * UNLOCK_DAY).into(),
+                amount: amount.into(),
+            })
+            .collect()
+    }
+}
+
+impl Farm {
+    /// Moves an account's principal unlock in the global calendar from `old` to `new`.
+    pub(crate) fn record_unlock(&mut self, old: Option<&Account>, new: Option<&Account>) {
+        let (now, cliff_time) = (self.now(), self.cliff_time);
+        let unlock = |account: &Account| {
+            let unlocks_at = account.deposit_time + cliff_time;
+            if unlocks_at > now && account.obs_balance > 0 {
+                Some((unlocks_at / UNLOCK_DAY, account.obs_balance))
+            } else {
+                None
+            }
+        };
+        let (old, new) = (old.and_then(unlock), new.and_then(unlock));
+        if old != new {
+            if let Some((day, amount)) = old {
+                let total = self.unlock_calendar.get(&day).unwrap_or(0).saturating_sub(amount);
+                if total == 0 {
+                    self.unlock_calendar.remove(&day);
+                } else {
+                    self.unlock_calendar.insert(&day, &total);
+                }
+            }
+            if let Some((day, amount)) = new {
+                let total = self.unlock_calendar.get(&day).unwrap_or(0) + amount;
+                self.unlock_calendar.insert(&day, &total);
+            }
+        }
+        if let Some(day) = self.unlock_calendar.min() {
+            if day < now / UNLOCK_DAY {
+                self.unlock_calendar.remove(&day);
+            }
+        }
+    }
+
+    /// Unlocks of `account` after now, unordered.
+    pub(crate) fn unlock_events(&self, account: &Account) -> Vec<(Timestamp, UnlockKind, Balance)> {
+        let now = self.now();
+        let mut events = vec![];
+        let cliff_ends_at = account.deposit_time + self.cliff_time;
+        if account.obs_balance > 0 && !self.is_unlocked(account) {
+            events.push((cliff_ends_at, UnlockKind::Principal, account.obs_balance));
+        }
+        let accrual_time = self.accrual_time();
+        if self.rewards_locked(account, accrual_time) {
+            let vesting = self.vesting_reward(account, accrual_time);
+            if vesting > 0 {
+                let unlocks_at = self.reward_cliff_ends_at(account) + 1;
+                events.push((unlocks_at, UnlockKind::Rewards, vesting));
+            }
+        }
+        if let Some(requested_at) = account.large_unstake_requested_at {
+            let matures_at = requested_at + self.large_unstake_delay;
+            if matures_at > now {
+                events.push((matures_at, UnlockKind::LargeUnstake, account.obs_balance));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, obs, reward, staked_farm};
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_unlock_schedule() {
+        let contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time / 2));
+        let schedule = contract.get_unlock_schedule(alice().try_into().unwrap());
+        let kinds: Vec<UnlockKind> = schedule.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![UnlockKind::Principal, UnlockKind::Rewards]);
+        assert_eq!(schedule[0].amount.0, 1000);
+        assert_eq!(schedule[0].unlocks_at.nanoseconds.0, contract.cliff_time);
+
+        testing_env!(get_context(alice(), env::storage_usage(), contract.cliff_time + 1));
+        assert!(contract.get_unlock_schedule(alice().try_into().unwrap()).is_empty());
+        assert!(contract.get_unlock_schedule(bob().try_into().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_global_unlock_schedule() {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut config = FarmConfig::new(alice().try_into().unwrap(), obs(), reward());
+        config.cliff_time = Some(U64(3 * UNLOCK_DAY));
+        let mut contract = Farm::new(config);
+        contract.register_account();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+        testing_env!(get_context(alice(), env::storage_usage(), UNLOCK_DAY));
+        contract.stake_my_obs(U128(500));
+
+        // the top-up unlocks with the first stake
+        let schedule = contract.get_global_unlock_schedule(U64(0), U64(10 * UNLOCK_DAY), 10);
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule[0].day_start.nanoseconds.0, 3 * UNLOCK_DAY);
+        assert_eq!(schedule[0].amount.0, 1500);
+        assert!(contract.get_global_unlock_schedule(U64(0), U64(2 * UNLOCK_DAY), 10).is_empty());
+    }
+}
@@ -0,0 +1,432 @@
+//! Auction of a limited number of VIP boost slots per epoch. Registered accounts bid obs for
+//! the next epoch by transferring it with `VIP_BID_MSG`, and the bids are escrowed. Once that
+//! epoch starts anyone can settle the round: the highest bids win a slot and go to the
+//! treasury, the others become refunds their bidders withdraw. A slot multiplies the rewards
+//! earned during its epoch; a round settled late only boosts what is earned after settling.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::json_types::{ValidAccountId, U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use near_sdk::{assert_one_yocto, env, is_promise_success, log, near_bindgen, Promise};
+
+use crate::events::emit_event;
+use crate::*;
+
+/// highest number of slots per epoch, bounding the accounts a settlement updates.
+pub const MAX_VIP_SLOTS: u32 = 20;
+
+/// highest number of bidders per round, bounding the bids a settlement sorts.
+pub const MAX_VIP_BIDDERS: u64 = 100;
+
+/// highest multiplier a slot can grant, in basis points.
+pub const MAX_VIP_BOOST_BPS: u32 = 30_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VipAuctionConfig {
+    /// slots auctioned per epoch.
+    pub slots: u32,
+    /// in nanoseconds.
+    pub epoch_length: U64,
+    /// reward multiplier of a slot, in basis points.
+    pub multiplier_bps: u32,
+    /// smallest total bid of an account.
+    pub min_bid: U128,
+}
+
+/// Epoch auctioned by a settled round, and the accounts that won it.
+#[derive(BorshDeserialize, BorshSerialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VipRound {
+    pub epoch: U64,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub starts_at: Timestamp,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub ends_at: Timestamp,
+    pub multiplier_bps: u32,
+    pub winners: Vec<AccountId>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct VipAuction {
+    config: Option<VipAuctionConfig>,
+    /// start of epoch 0.
+    epochs_start: Timestamp,
+    /// epoch the open bids are for.
+    bidding_epoch: u64,
+    bids: UnorderedMap<AccountId, Balance>,
+    /// settled rounds by id, counted from 1.
+    rounds: LookupMap<u64, VipRound>,
+    rounds_settled: u64,
+    /// losing bids waiting to be withdrawn.
+    refunds: LookupMap<AccountId, Balance>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct VipAuctionView {
+    pub config: VipAuctionConfig,
+    pub epoch: U64,
+    pub bidding_epoch: U64,
+    /// when bidding for `bidding_epoch` closes and the round can be settled.
+    pub bidding_closes_at: TimestampView,
+    pub bidders: U64,
+    /// id of the latest settled round.
+    pub last_round_id: Option<U64>,
+}
+
+impl VipAuction {
+    pub fn new() -> Self {
+        Self {
+            config: None,
+            epochs_start: 0,
+            bidding_epoch: 0,
+            bids: UnorderedMap::new(StorageKey::VipBids),
+            rounds: LookupMap::new(StorageKey::VipRounds),
+            rounds_settled: 0,
+            refunds: LookupMap::new(StorageKey::VipRefunds),
+        }
+    }
+
+    fn config(&self) -> &VipAuctionConfig {
+        self.config.as_ref().expect("The VIP auction is disabled")
+    }
+
+    fn epoch_start(&self, epoch: u64) -> Timestamp {
+        self.epochs_start + epoch * self.config().epoch_length.0
+    }
+
+    fn epoch_at(&self, time: Timestamp) -> u64 {
+        (time - self.epochs_start) / self.config().epoch_length.0
+    }
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Sets or disables the VIP auction, restarting the epochs at the current time. Settled
+    /// slots keep their boost.
+    pub fn set_vip_auction(&mut self, config: Option<VipAuctionConfig>) {
+        self.assert_owner();
+        assert!(self.vip_auction.bids.is_empty(), "VIP bids are open");
+        if let Some(config) = &config {
+            assert!(
+                (1..=MAX_VIP_SLOTS).contains(&config.slots),
+                "Slots must be between 1 and {}",
+                MAX_VIP_SLOTS
+            );
+            assert!(config.epoch_length.0 > 0, "Epoch length must be positive");
+            assert!(
+                (BPS_DENOM as u32 + 1..=MAX_VIP_BOOST_BPS).contains(&config.multiplier_bps),
+                "Boost multiplier out of range"
+            );
+        }
+        emit_event("set_vip_auction", json!({ "config": config }));
+        self.vip_auction.config = config;
+        self.vip_auction.epochs_start = self.now();
+        // the first epoch started already
+        self.vip_auction.bidding_epoch = 1;
+    }
+
+    pub fn get_vip_auction(&self) -> Option<VipAuctionView> {
+        let auction = &self.vip_auction;
+        let config = auction.config.clone()?;
+        Some(VipAuctionView {
+            config,
+            epoch: auction.epoch_at(self.now()).into(),
+            bidding_epoch: auction.bidding_epoch.into(),
+            bidding_closes_at: auction.epoch_start(auction.bidding_epoch).into(),
+            bidders: auction.bids.len().into(),
+            last_round_id: Some(auction.rounds_settled).filter(|id| *id > 0).map(U64),
+        })
+    }
+
+    /// Open bids, highest first.
+    pub fn get_vip_bids(&self) -> Vec<(AccountId, U128)> {
+        self.sorted_vip_bids()
+            .into_iter()
+            .map(|(account_id, bid)| (account_id, bid.into()))
+            .collect()
+    }
+
+    pub fn get_vip_round(&self, round_id: U64) -> Option<VipRound> {
+        self.vip_auction.rounds.get(&round_id.0)
+    }
+
+    pub fn get_vip_refund(&self, account_id: ValidAccountId) -> U128 {
+        self.vip_auction.refunds.get(account_id.as_ref()).unwrap_or(0).into()
+    }
+
+    /// Awards the slots of the epoch the open bids are for, once it started. Anyone can
+    /// call it.
+    pub fn settle_vip_auction(&mut self) -> U64 {
+        let now = self.now();
+        let auction = &self.vip_auction;
+        let (slots, multiplier_bps) = (auction.config().slots, auction.config().multiplier_bps);
+        let epoch = auction.bidding_epoch;
+        assert!(now >= auction.epoch_start(epoch), "Bidding is still open");
+        let round_id = auction.rounds_settled + 1;
+        let mut round = VipRound {
+            epoch: epoch.into(),
+            starts_at: auction.epoch_start(epoch),
+            ends_at: auction.epoch_start(epoch + 1),
+            multiplier_bps,
+            winners: vec![],
+        };
+
+        let bids = self.sorted_vip_bids();
+        self.vip_auction.bids.clear();
+        let mut proceeds = 0;
+        for (account_id, bid) in bids {
+            if round.winners.len() < slots as usize {
+                if let (account_id_hash, Some(mut account)) = self.get_internal_account(&account_id)
+                {
+                    // accrue under the previous slot before replacing it
+                    if !self.is_frozen(&account_id) {
+                        self.settle_reward_periods(&account_id_hash, &mut account);
+                        self.touch(&mut account);
+                    }
+                    account.vip_round = round_id;
+                    self.save_account(&account_id_hash, &account);
+                    round.winners.push(account_id);
+                    proceeds += bid;
+                    continue;
+                }
+            }
+            let refund = self.vip_auction.refunds.get(&account_id).unwrap_or(0);
+            self.vip_auction.refunds.insert(&account_id, &(refund + bid));
+        }
+
+        emit_event(
+            "settle_vip_auction",
+            json!({ "round_id": U64(round_id), "round": round, "proceeds": U128(proceeds) }),
+        );
+        self.vip_auction.rounds.insert(&round_id, &round);
+        self.vip_auction.rounds_settled = round_id;
+        self.vip_auction.bidding_epoch = self.vip_auction.epoch_at(now) + 1;
+        if proceeds > 0 {
+            self.ledger.post(LedgerAccount::Escrow, LedgerAccount::Withdrawals, proceeds);
+            ext_fungible_token::ft_transfer(
+                self.treasury_id.clone(),
+                proceeds.into(),
+                None,
+                &self.obs_token_account_id,
+                1,
+                self.token_gas(&self.obs_token_account_id).ft_transfer.0,
+            )
+            .then(ext_self::on_vip_proceeds(
+                proceeds.into(),
+                &env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ));
+        }
+        round_id.into()
+    }
+
+    /// Sends the predecessor its losing bids.
+    #[payable]
+    pub fn withdraw_vip_refund(&mut self) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let amount = match self.vip_auction.refunds.remove(&account_id) {
+            Some(amount) => amount,
+            None => fail(ErrorCode::NothingToWithdraw, json!({})),
+        };
+        self.ledger.post(LedgerAccount::Escrow, LedgerAccount::Withdrawals, amount);
+        ext_fungible_token::ft_transfer(
+            account_id.clone(),
+            amount.into(),
+            None,
+            &self.obs_token_account_id,
+            1,
+            self.token_gas(&self.obs_token_account_id).ft_transfer.0,
+        )
+        .then(ext_self::on_vip_refund(
+            account_id,
+            amount.into(),
+            &env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ))
+    }
+
+    /// Keeps proceeds the treasury didn't take as fees.
+    #[private]
+    pub fn on_vip_proceeds(&mut self, amount: U128) {
+        if !is_promise_success() {
+            log!("Treasury refused {} of VIP proceeds, kept as fees", amount.0);
+            self.ledger.post(LedgerAccount::Withdrawals, LedgerAccount::Fees, amount.0);
+        }
+    }
+
+    #[private]
+    pub fn on_vip_refund(&mut self, account_id: AccountId, amount: U128) {
+        if !is_promise_success() {
+            self.ledger.post(LedgerAccount::Withdrawals, LedgerAccount::Escrow, amount.0);
+            let refund = self.vip_auction.refunds.get(&account_id).unwrap_or(0);
+            self.vip_auction.refunds.insert(&account_id, &(refund + amount.0));
+        }
+    }
+}
+
+impl Farm {
+    /// Escrows a bid of `amount` obs by `account_id`, adding to its open bid.
+    pub fn place_vip_bid(&mut self, account_id: &AccountId, amount: Balance) {
+        self.get_registered_account(account_id);
+        let now = self.now();
+        let auction = &mut self.vip_auction;
+        let min_bid = auction.config().min_bid.0;
+        if now >= auction.epoch_start(auction.bidding_epoch) {
+            // nobody bid on the epoch that started, move on to the next one
+            assert!(auction.bids.is_empty(), "The VIP auction must be settled first");
+            auction.bidding_epoch = auction.epoch_at(now) + 1;
+        }
+        let bid = auction.bids.get(account_id);
+        assert!(
+            bid.is_some() || auction.bids.len() < MAX_VIP_BIDDERS,
+            "Too many VIP bidders this round"
+        );
+        let bid = bid.unwrap_or(0) + amount;
+        assert!(bid >= min_bid, "Bid is below the minimum");
+        auction.bids.insert(account_id, &bid);
+        self.ledger.post(LedgerAccount::Deposits, LedgerAccount::Escrow, amount);
+        emit_event(
+            "vip_bid",
+            json!({
+                "account_id": account_id,
+                "epoch": U64(self.vip_auction.bidding_epoch),
+                "bid": U128(bid),
+            }),
+        );
+    }
+
+    /// Open bids, highest first, ties going to the lower account id.
+    fn sorted_vip_bids(&self) -> Vec<(AccountId, Balance)> {
+        let mut bids = self.vip_auction.bids.to_vec();
+        bids.sort_by(|(a_id, a_bid), (b_id, b_bid)| b_bid.cmp(a_bid).then(a_id.cmp(b_id)));
+        bids
+    }
+
+    /// Slot window and multiplier of the account's latest won round.
+    fn vip_slot(&self, account: &Account) -> Option<VipRound> {
+        if account.vip_round == 0 {
+            return None;
+        }
+        self.vip_auction.rounds.get(&account.vip_round)
+    }
+
+    /// Extra rewards the account's slot adds to what `balance` of its stake earned between
+    /// `from` and `to`.
+    pub(crate) fn vip_reward(
+        &self,
+        account: &Account,
+        balance: Balance,
+        from: Timestamp,
+        to: Timestamp,
+    ) -> Balance {
+        let slot = match self.vip_slot(account) {
+            Some(slot) => slot,
+            None => return 0,
+        };
+        let (from, to) = (from.max(slot.starts_at), to.min(slot.ends_at));
+        if from >= to {
+            return 0;
+        }
+        let extra_bps = U256::from(slot.multiplier_bps) - U256::from(BPS_DENOM);
+        let weight = self.reward_weight.weight(balance);
+        self.accrued(weight, self.emission_between(from, to), extra_bps)
+    }
+
+    /// Multiplier the account's slot adds at `time`, in basis points.
+    pub(crate) fn vip_boost_bps(&self, account: &Account, time: Timestamp) -> u32 {
+        match self.vip_slot(account) {
+            Some(slot) if (slot.starts_at..slot.ends_at).contains(&time) => {
+                slot.multiplier_bps - BPS_DENOM as u32
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, bob, get_context, obs, staked_farm};
+    use std::convert::TryInto;
+
+    const EPOCH: Timestamp = 1_000_000_000;
+
+    /// alice bids 100 and bob 50 for one slot of epoch 1.
+    fn auction_farm() -> Farm {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_vip_auction(Some(VipAuctionConfig {
+            slots: 1,
+            epoch_length: U64(EPOCH),
+            multiplier_bps: 20_000,
+            min_bid: U128(10),
+        }));
+        let mut context = get_context(bob(), env::storage_usage(), 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        contract.register_account();
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(100), VIP_BID_MSG.to_string());
+        contract.ft_on_transfer(bob(), U128(50), VIP_BID_MSG.to_string());
+        contract
+    }
+
+    #[test]
+    fn test_settle_vip_auction() {
+        let mut contract = auction_farm();
+        assert_eq!(contract.get_vip_bids()[0], (alice(), U128(100)));
+        testing_env!(get_context(bob(), env::storage_usage(), EPOCH));
+        let round_id = contract.settle_vip_auction();
+        let round = contract.get_vip_round(round_id).unwrap();
+        assert_eq!(round.winners, vec![alice()]);
+        assert_eq!(contract.get_vip_refund(bob().try_into().unwrap()).0, 50);
+        assert_eq!(contract.get_vip_refund(alice().try_into().unwrap()).0, 0);
+        assert_eq!(contract.ledger.balance(LedgerAccount::Escrow), 50);
+        assert_eq!(contract.get_vip_auction().unwrap().bidding_epoch.0, 2);
+    }
+
+    #[test]
+    fn test_vip_slot_boosts_its_epoch() {
+        let mut contract = auction_farm();
+        testing_env!(get_context(bob(), env::storage_usage(), EPOCH));
+        contract.settle_vip_auction();
+        let account = contract.get_internal_account(&alice()).1.unwrap();
+        let boosted = contract.vip_reward(&account, account.obs_balance, 0, 3 * EPOCH);
+        assert!(boosted > 0);
+        assert_eq!(boosted, contract.vip_reward(&account, account.obs_balance, EPOCH, 2 * EPOCH));
+        assert_eq!(contract.vip_reward(&account, account.obs_balance, 2 * EPOCH, 3 * EPOCH), 0);
+        assert_eq!(contract.vip_boost_bps(&account, EPOCH), 10_000);
+        assert_eq!(contract.vip_boost_bps(&account, 2 * EPOCH), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Bidding is still open")]
+    fn test_settle_before_epoch() {
+        let mut contract = auction_farm();
+        contract.settle_vip_auction();
+    }
+
+    #[test]
+    #[should_panic(expected = "Bid is below the minimum")]
+    fn test_vip_bid_below_minimum() {
+        let mut contract = staked_farm();
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.set_vip_auction(Some(VipAuctionConfig {
+            slots: 1,
+            epoch_length: U64(EPOCH),
+            multiplier_bps: 20_000,
+            min_bid: U128(10),
+        }));
+        testing_env!(get_context(obs().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(5), VIP_BID_MSG.to_string());
+    }
+}
@@ -0,0 +1,109 @@
+//! Warm-up of new stakes. A stake earns nothing for `stake_warm_up` after it lands, so a
+//! stake, claim and unstake within a block, or within an epoch, earns nothing. About a
+//! second covers a block and twelve hours an epoch. Only the new stake waits: it is kept
+//! apart as the account's `warming_balance` until `warm_until`, while the rest keeps
+//! earning. A top-up within a warm-up extends it for the stake still warming up;
+//! compounded rewards don't warm up.
+use near_sdk::json_types::U64;
+use near_sdk::near_bindgen;
+use near_sdk::serde_json::json;
+
+use crate::events::emit_event;
+use crate::roles::Role;
+use crate::*;
+
+/// longest warm-up, a week.
+pub const MAX_STAKE_WARM_UP: Timestamp = 7 * 24 * 3600 * 1_000_000_000;
+
+#[near_bindgen]
+impl Farm {
+    /// Sets the warm-up of stakes made from now on, in nanoseconds.
+    pub fn set_stake_warm_up(&mut self, warm_up: U64) {
+        self.assert_role(Role::Treasurer);
+        assert!(warm_up.0 <= MAX_STAKE_WARM_UP, "Warm-up is too long");
+        self.apply_due_changes();
+        self.stake_warm_up = warm_up.into();
+        self.record_config_version(self.now());
+        emit_event("set_stake_warm_up", json!({ "warm_up": warm_up }));
+    }
+}
+
+impl Farm {
+    /// Adds `amount` to the account's stake warming up until `warm_until`, once its
+    /// rewards are accrued up to now. Stake whose warm-up is over joins the rest.
+    pub(crate) fn warm_up_stake(
+        &self,
+        account: &mut Account,
+        amount: Balance,
+        warm_until: Timestamp,
+    ) {
+        if account.warm_until <= self.now() {
+            account.warming_balance = 0;
+        }
+        account.warming_balance += amount;
+        account.warm_until = account.warm_until.max(warm_until);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::json_types::U128;
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm, reward};
+    use std::convert::TryInto;
+
+    fn warm_up_farm(warm_up: Timestamp) -> Farm {
+        let mut context = get_context(alice(), 0, 0);
+        context.attached_deposit = 10u128.pow(24);
+        testing_env!(context);
+        let mut contract = new_farm();
+        contract.register_account();
+        // rewards unlock before the warm-up ends
+        contract.cliff_time = warm_up / 2;
+        contract.set_stake_warm_up(U64(warm_up));
+        testing_env!(get_context(reward().into(), env::storage_usage(), 0));
+        contract.ft_on_transfer(alice(), U128(10u128.pow(30)), String::new());
+        testing_env!(get_context(alice(), env::storage_usage(), 0));
+        contract.stake_my_obs(U128(1000));
+        contract
+    }
+
+    #[test]
+    fn test_stake_earns_after_warm_up() {
+        let warm_up = MAX_STAKE_WARM_UP;
+        let contract = warm_up_farm(warm_up);
+
+        testing_env!(get_context(alice(), env::storage_usage(), warm_up));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(account.reward_balance.0, 0);
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * warm_up));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert!(account.reward_balance.0 > 0);
+    }
+
+    #[test]
+    fn test_top_up_only_warms_up_new_stake() {
+        let warm_up = MAX_STAKE_WARM_UP;
+        let mut contract = warm_up_farm(warm_up);
+        testing_env!(get_context(alice(), env::storage_usage(), 2 * warm_up));
+        contract.stake_my_obs(U128(1000));
+        let topped_up = contract.get_account(alice().try_into().unwrap()).unwrap();
+        assert_eq!(topped_up.warming_balance.0, 1000);
+        assert_eq!(topped_up.warm_until.unwrap().nanoseconds.0, 3 * warm_up);
+
+        testing_env!(get_context(alice(), env::storage_usage(), 3 * warm_up));
+        let warmed_up = contract.get_account(alice().try_into().unwrap()).unwrap();
+        let earned = warmed_up.reward_balance.0 - topped_up.reward_balance.0;
+        assert!(earned > 0);
+        assert_eq!((warmed_up.warming_balance.0, warmed_up.warm_until), (0, None));
+
+        // the whole stake earns from then on, twice as much up to rounding
+        testing_env!(get_context(alice(), env::storage_usage(), 4 * warm_up));
+        let account = contract.get_account(alice().try_into().unwrap()).unwrap();
+        let doubled = account.reward_balance.0 - warmed_up.reward_balance.0;
+        assert!((doubled as i128 - 2 * earned as i128).abs() <= 1);
+    }
+}
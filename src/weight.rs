@@ -0,0 +1,104 @@
+//! Reward weight of a stake. Rewards are normally proportional to the stake; community farms
+//! can flatten whale dominance by weighting stakes with their square root, or by capping the
+//! weight of any one account. The weight function is part of the pool config, fixed at init.
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::json_types::U128;
+use near_sdk::near_bindgen;
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::apr::YEAR_NANOS;
+use crate::*;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, Default, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RewardWeight {
+    #[default]
+    Linear,
+    /// weight of `sqrt(stake × unit)`: a stake of one `unit` earns as in linear mode,
+    /// smaller stakes earn more per token and larger ones less.
+    SquareRoot { unit: U128 },
+    /// stakes above `max_weight` earn as if they were `max_weight`.
+    Capped { max_weight: U128 },
+}
+
+impl RewardWeight {
+    pub fn validate(&self) {
+        match self {
+            RewardWeight::Linear => {}
+            RewardWeight::SquareRoot { unit } => {
+                assert!(unit.0 > 0, "Weight unit must be positive")
+            }
+            RewardWeight::Capped { max_weight } => {
+                assert!(max_weight.0 > 0, "Weight cap must be positive")
+            }
+        }
+    }
+
+    /// weight `stake` earns rewards for.
+    pub fn weight(&self, stake: Balance) -> U256 {
+        match self {
+            RewardWeight::Linear => U256::from(stake),
+            RewardWeight::SquareRoot { unit } => sqrt(U256::from(stake) * U256::from(unit.0)),
+            RewardWeight::Capped { max_weight } => U256::from(stake.min(max_weight.0)),
+        }
+    }
+}
+
+/// integer square root, rounded down.
+fn sqrt(x: U256) -> U256 {
+    let (mut root, mut next) = (x, (x + 1) / 2);
+    while next < root {
+        root = next;
+        next = (x / next + next) / 2;
+    }
+    root
+}
+
+#[near_bindgen]
+impl Farm {
+    /// Simple annual rate a stake of `amount` earns under the reward weight, in basis points.
+    pub fn get_apr_at_stake(&self, amount: U128) -> U128 {
+        if amount.0 == 0 {
+            return self.get_apr();
+        }
+        let now = self.accrual_time();
+        let weight = self.reward_weight.weight(amount.0);
+        let earned = self.accrued(
+            weight,
+            self.emission_between(now, now + YEAR_NANOS),
+            U256::from(BPS_DENOM),
+        );
+        let apr = U256::from(earned) * U256::from(BPS_DENOM) / U256::from(amount.0);
+        apr.min(U256::from(u128::MAX)).as_u128().into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::testing_env;
+    use near_sdk::MockedBlockchain;
+
+    use super::*;
+    use crate::tests::{alice, get_context, new_farm};
+
+    #[test]
+    fn test_square_root_weight() {
+        let weight = RewardWeight::SquareRoot { unit: U128(100) };
+        assert_eq!(weight.weight(100), U256::from(100));
+        assert_eq!(weight.weight(400), U256::from(200));
+        assert_eq!(weight.weight(0), U256::zero());
+        assert_eq!(sqrt(U256::from(99)), U256::from(9));
+    }
+
+    #[test]
+    fn test_apr_at_stake() {
+        testing_env!(get_context(alice(), 0, 0));
+        let mut contract = new_farm();
+        contract.reward_weight = RewardWeight::Capped { max_weight: U128(1000) };
+        let apr = contract.get_apr().0;
+        assert_eq!(contract.get_apr_at_stake(U128(500)).0, apr);
+        assert_eq!(contract.get_apr_at_stake(U128(2000)).0, apr / 2);
+    }
+}